@@ -1,6 +1,8 @@
 use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
 use stone_kvs::wal::crc32c::{crc32c, crc32c_table, crc32c_slice8, crc32c_hw, crc32c_slice32, crc32c_slice16, crc32c_slice16_bt};
 
+type PatternFn = fn(usize) -> Vec<u8>;
+
 fn bench_crc32c(c: &mut Criterion) {
     let mut group = c.benchmark_group("crc32c");
 
@@ -11,7 +13,7 @@ fn bench_crc32c(c: &mut Criterion) {
         ("1MB", 1024 * 1024),
     ];
 
-    let patterns: &[(&str, fn(usize) -> Vec<u8>)] = &[
+    let patterns: &[(&str, PatternFn)] = &[
         ("zeros", |size| vec![0u8; size]),
         ("ones", |size| vec![0xFFu8; size]),
         ("sequential", |size| {
@@ -0,0 +1,45 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use stone_kvs::cache::BlockCache;
+use stone_kvs::env::{FileSystem, MemFileSystem};
+use stone_kvs::table::{DataBlockIndexType, TableBuilder, TableOptions, TableReader};
+use stone_kvs::wal::RecordType;
+
+fn bench_table_get(c: &mut Criterion) {
+    let fs = MemFileSystem::new();
+    fs.create_dir_all(Path::new("/bench")).unwrap();
+
+    let mut group = c.benchmark_group("table_get");
+    for (name, index_type) in [
+        ("binary_search", DataBlockIndexType::BinarySearch),
+        ("hash_index", DataBlockIndexType::BinaryAndHash),
+    ] {
+        // small values, every data block in the cache: the lookup inside the block dominates
+        let options = TableOptions {
+            data_block_index_type: index_type,
+            block_cache: Some(Arc::new(BlockCache::new(64 * 1024 * 1024))),
+            ..TableOptions::default()
+        };
+        let path = Path::new("/bench").join(format!("{name}.sst"));
+        let mut builder = TableBuilder::new(fs.create_new(&path).unwrap(), options.clone());
+        for n in 0..200_000u32 {
+            builder.add(format!("key{n:010}").as_bytes(), 1, RecordType::Put, &[0xab; 64]).unwrap();
+        }
+        builder.finish().unwrap();
+        let table = TableReader::open(&fs, &path, &options).unwrap();
+
+        group.bench_function(name, |b| {
+            let mut n = 0u32;
+            b.iter(|| {
+                n = n.wrapping_add(7919) % 200_000;
+                table.get(format!("key{n:010}").as_bytes(), u64::MAX).unwrap()
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_table_get);
+criterion_main!(benches);
@@ -0,0 +1,47 @@
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use stone_kvs::wal::{MmapWalReader, WalReader, WalWriter};
+
+fn bench_wal_replay(c: &mut Criterion) {
+    let dir = std::env::temp_dir().join(format!("stone-kvs-wal-replay-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("000001.log");
+    let _ = std::fs::remove_file(&path);
+
+    // ~64MB of records with values around the 1KB mark
+    let mut writer = WalWriter::create(&path).unwrap();
+    let value = vec![0xabu8; 1024];
+    for seq in 1..=60_000u64 {
+        writer.put(seq, format!("key{seq:012}").as_bytes(), &value).unwrap();
+    }
+    writer.sync().unwrap();
+    let size = std::fs::metadata(&path).unwrap().len();
+
+    let mut group = c.benchmark_group("wal_replay");
+    group.throughput(Throughput::Bytes(size));
+    group.sample_size(10);
+
+    group.bench_function("buffered", |b| {
+        b.iter(|| WalReader::open(&path).unwrap().map(|r| r.unwrap().value.len()).sum::<usize>());
+    });
+
+    group.bench_function("mmap_bytes", |b| {
+        b.iter(|| MmapWalReader::open(&path).unwrap().map(|r| r.value.len()).sum::<usize>());
+    });
+
+    group.bench_function("mmap_view", |b| {
+        b.iter(|| {
+            let mut reader = MmapWalReader::open(&path).unwrap();
+            let mut total = 0;
+            while let Some(view) = reader.next_view() {
+                total += view.value.len();
+            }
+            total
+        });
+    });
+
+    group.finish();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+criterion_group!(benches, bench_wal_replay);
+criterion_main!(benches);
@@ -0,0 +1,205 @@
+//! stone-wal: inspects WAL segments without writing ad-hoc code
+//!
+//! ```text
+//! stone-wal dump <segment> [--from SEQ] [--to SEQ] [--prefix PREFIX] [--hex] [--utf8]
+//! stone-wal verify <segment>...
+//! ```
+//! `PREFIX` is taken as UTF-8 unless it starts with `0x`, in that case it is decoded as hex.
+//! `verify` exits with status 1 when any record fails its CRC32C check.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::process::ExitCode;
+
+use stone_kvs::wal::record::{FILE_HEADER_SIZE, RECORD_HEADER_SIZE, RecordType};
+use stone_kvs::wal::{ScanEntry, WalReader};
+
+const USAGE: &str = "usage:
+  stone-wal dump <segment> [--from SEQ] [--to SEQ] [--prefix PREFIX] [--hex] [--utf8]
+  stone-wal verify <segment>...";
+
+#[derive(Default)]
+struct DumpOptions {
+    from: Option<u64>,
+    to: Option<u64>,
+    prefix: Option<Vec<u8>>,
+    hex: bool,
+    utf8: bool,
+}
+
+impl DumpOptions {
+    fn matches(&self, sequence: u64, key: &[u8]) -> bool {
+        self.from.is_none_or(|from| sequence >= from)
+            && self.to.is_none_or(|to| sequence <= to)
+            && self.prefix.as_ref().is_none_or(|prefix| key.starts_with(prefix))
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("dump") => parse_dump(&args[1..]).and_then(|(path, options)| dump(&path, &options)),
+        Some("verify") if args.len() > 1 => verify(&args[1..]),
+        Some("-h") | Some("--help") => {
+            println!("{USAGE}");
+            Ok(ExitCode::SUCCESS)
+        }
+        _ => Err(usage_error("missing or unknown command")),
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("stone-wal: {e}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn usage_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{message}\n{USAGE}"))
+}
+
+fn parse_dump(args: &[String]) -> io::Result<(String, DumpOptions)> {
+    let mut path = None;
+    let mut options = DumpOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--from" => options.from = Some(parse_sequence(args.next())?),
+            "--to" => options.to = Some(parse_sequence(args.next())?),
+            "--prefix" => {
+                let prefix = args.next().ok_or_else(|| usage_error("--prefix needs a value"))?;
+                options.prefix = Some(parse_prefix(prefix)?);
+            }
+            "--hex" => options.hex = true,
+            "--utf8" => options.utf8 = true,
+            flag if flag.starts_with("--") => return Err(usage_error(&format!("unknown option {flag}"))),
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return Err(usage_error("dump takes a single segment")),
+        }
+    }
+    let path = path.ok_or_else(|| usage_error("missing segment path"))?;
+    Ok((path, options))
+}
+
+fn parse_sequence(value: Option<&String>) -> io::Result<u64> {
+    let value = value.ok_or_else(|| usage_error("missing sequence number"))?;
+    value
+        .parse()
+        .map_err(|_| usage_error(&format!("invalid sequence number {value}")))
+}
+
+fn parse_prefix(prefix: &str) -> io::Result<Vec<u8>> {
+    let Some(hex) = prefix.strip_prefix("0x") else {
+        return Ok(prefix.as_bytes().to_vec());
+    };
+    if hex.len() % 2 != 0 {
+        return Err(usage_error("hex prefix must have an even number of digits"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| usage_error("invalid hex prefix")))
+        .collect()
+}
+
+fn dump(path: &str, options: &DumpOptions) -> io::Result<ExitCode> {
+    let mut header = [0u8; FILE_HEADER_SIZE];
+    File::open(path)?.read_exact(&mut header)?;
+    let mut reader = WalReader::new(BufReader::new(File::open(path)?))?;
+
+    let mut out = io::stdout().lock();
+    writeln!(out, "file: {path}")?;
+    writeln!(out, "magic: {}", String::from_utf8_lossy(&header[..4]))?;
+    writeln!(out, "version: {}", u32::from_le_bytes(header[4..8].try_into().unwrap()))?;
+    writeln!(out, "reserved: {}", to_hex(&header[8..16]))?;
+
+    while let Some(entry) = reader.next_entry()? {
+        match entry {
+            ScanEntry::Record { offset, record, .. } => {
+                if !options.matches(record.sequence, &record.key) {
+                    continue;
+                }
+                writeln!(
+                    out,
+                    "offset={offset} seq={} type={} key_size={} value_size={} crc=ok",
+                    record.sequence,
+                    type_name(record.record_type as u8),
+                    record.key.len(),
+                    record.value.len()
+                )?;
+                print_bytes(&mut out, "key", &record.key, options)?;
+                if record.record_type == RecordType::Put {
+                    print_bytes(&mut out, "value", &record.value, options)?;
+                }
+            }
+            ScanEntry::Corrupted { offset, raw } => {
+                // the header fields cannot be trusted, they are printed as found for investigation
+                let fields = (reader.version() == 1 && raw.len() >= RECORD_HEADER_SIZE).then(|| {
+                    (
+                        type_name(raw[0]),
+                        u64::from_le_bytes(raw[1..9].try_into().unwrap()),
+                        u32::from_le_bytes(raw[13..17].try_into().unwrap()),
+                        u32::from_le_bytes(raw[17..21].try_into().unwrap()),
+                    )
+                });
+                match fields {
+                    Some((record_type, sequence, key_size, value_size)) => writeln!(
+                        out,
+                        "offset={offset} seq={sequence} type={record_type} key_size={key_size} value_size={value_size} crc=MISMATCH"
+                    )?,
+                    None => writeln!(out, "offset={offset} len={} crc=MISMATCH", raw.len())?,
+                }
+            }
+            ScanEntry::TornTail { offset, len } => writeln!(out, "offset={offset} torn tail of {len} bytes")?,
+        }
+    }
+
+    let stats = reader.stats();
+    writeln!(
+        out,
+        "records={} corrupted={} torn_tail_bytes={}",
+        stats.records, stats.corrupted_records, stats.truncated_tail_bytes
+    )?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn verify(paths: &[String]) -> io::Result<ExitCode> {
+    let mut failed = false;
+    for path in paths {
+        let mut reader = WalReader::new(BufReader::new(File::open(path)?))?;
+        while reader.next_entry()?.is_some() {}
+        let stats = reader.stats();
+        let status = if stats.corrupted_records > 0 { "FAILED" } else { "OK" };
+        println!(
+            "{path}: {status} version={} records={} corrupted={} torn_tail_bytes={}",
+            reader.version(),
+            stats.records,
+            stats.corrupted_records,
+            stats.truncated_tail_bytes
+        );
+        failed |= stats.corrupted_records > 0;
+    }
+    Ok(if failed { ExitCode::FAILURE } else { ExitCode::SUCCESS })
+}
+
+fn type_name(byte: u8) -> String {
+    match RecordType::from_byte(byte) {
+        Some(RecordType::Put) => "PUT".to_string(),
+        Some(RecordType::Delete) => "DELETE".to_string(),
+        None => format!("0x{byte:02x}"),
+    }
+}
+
+fn print_bytes(out: &mut impl Write, label: &str, bytes: &[u8], options: &DumpOptions) -> io::Result<()> {
+    if options.utf8 {
+        writeln!(out, "  {label} (utf8): {}", String::from_utf8_lossy(bytes).escape_debug())?;
+    }
+    if options.hex {
+        writeln!(out, "  {label} (hex): {}", to_hex(bytes))?;
+    }
+    Ok(())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
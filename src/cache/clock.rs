@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::table::block::Block;
+
+use super::{CacheKey, Priority, is_pinned};
+
+/// Highest count of a slot: an entry survives up to that many sweeps of the hand without a hit
+const MAX_COUNT: u8 = 3;
+
+struct Slot {
+    key: CacheKey,
+    block: Arc<Block>,
+    charge: usize,
+    /// Decremented by each sweep of the hand, the entry is evicted at 0; bumped by every hit
+    count: AtomicU8,
+}
+
+/// One shard of a CLOCK cache
+///
+/// The entries sit in a ring of slots swept by a hand when the shard is over capacity: a slot
+/// with a count above 0 has it decremented, one at 0 is evicted, a pinned one is skipped. A hit
+/// only bumps the count of its slot, atomically, so that lookups share the lock of the shard
+/// instead of reordering a list. A high-priority entry starts at the highest count and survives
+/// more sweeps than a low-priority one.
+pub(super) struct ClockShard {
+    capacity: usize,
+    slots: Vec<Option<Slot>>,
+    free: Vec<usize>,
+    index: HashMap<CacheKey, usize>,
+    hand: usize,
+    usage: usize,
+}
+
+impl ClockShard {
+    pub(super) fn new(capacity: usize) -> Self {
+        ClockShard {
+            capacity,
+            slots: Vec::new(),
+            free: Vec::new(),
+            index: HashMap::new(),
+            hand: 0,
+            usage: 0,
+        }
+    }
+
+    pub(super) fn lookup(&self, key: CacheKey) -> Option<Arc<Block>> {
+        let slot = self.slots[*self.index.get(&key)?].as_ref().unwrap();
+        let _ = slot
+            .count
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| (count < MAX_COUNT).then_some(count + 1));
+        Some(Arc::clone(&slot.block))
+    }
+
+    /// Same contract as `LruShard::insert`
+    pub(super) fn insert(
+        &mut self,
+        key: CacheKey,
+        block: Arc<Block>,
+        charge: usize,
+        priority: Priority,
+        strict: bool,
+    ) -> (bool, u64) {
+        self.remove(key);
+        if charge > self.capacity {
+            return (false, 0);
+        }
+        let evicted = self.evict_for(charge);
+        if strict && self.usage + charge > self.capacity {
+            return (false, evicted);
+        }
+        let count = match priority {
+            Priority::High => MAX_COUNT,
+            Priority::Low => 1,
+        };
+        let slot = Slot {
+            key,
+            block,
+            charge,
+            count: AtomicU8::new(count),
+        };
+        let at = match self.free.pop() {
+            Some(at) => {
+                self.slots[at] = Some(slot);
+                at
+            }
+            None => {
+                self.slots.push(Some(slot));
+                self.slots.len() - 1
+            }
+        };
+        self.index.insert(key, at);
+        self.usage += charge;
+        (true, evicted)
+    }
+
+    /// Evicts the released entries while over capacity, returns the number of entries evicted
+    pub(super) fn evict(&mut self) -> u64 {
+        self.evict_for(0)
+    }
+
+    /// Sweeps the hand until `charge` more fits in the capacity or only pinned entries are left,
+    /// returns the number of entries evicted
+    fn evict_for(&mut self, charge: usize) -> u64 {
+        let mut evicted = 0;
+        // every count reaches 0 within `MAX_COUNT + 1` turns of the ring
+        let mut steps = self.slots.len() * (MAX_COUNT as usize + 1);
+        while self.usage + charge > self.capacity && steps > 0 {
+            steps -= 1;
+            let at = self.hand;
+            self.hand = (self.hand + 1) % self.slots.len();
+            let Some(slot) = &mut self.slots[at] else {
+                continue;
+            };
+            if is_pinned(&slot.block) {
+                continue;
+            }
+            let count = slot.count.get_mut();
+            if *count > 0 {
+                *count -= 1;
+                continue;
+            }
+            let key = slot.key;
+            self.remove(key);
+            evicted += 1;
+        }
+        evicted
+    }
+
+    pub(super) fn usage(&self) -> usize {
+        self.usage
+    }
+
+    pub(super) fn pinned_usage(&self) -> usize {
+        let pinned = self.slots.iter().flatten().filter(|slot| is_pinned(&slot.block));
+        pinned.map(|slot| slot.charge).sum()
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn remove(&mut self, key: CacheKey) {
+        if let Some(at) = self.index.remove(&key) {
+            let slot = self.slots[at].take().unwrap();
+            self.usage -= slot.charge;
+            self.free.push(at);
+        }
+    }
+}
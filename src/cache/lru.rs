@@ -0,0 +1,189 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use crate::table::block::Block;
+
+use super::{CacheKey, Priority, is_pinned};
+
+struct Entry {
+    block: Arc<Block>,
+    charge: usize,
+    priority: Priority,
+    /// Position in the recency order of its pool, the lowest is evicted first
+    tick: u64,
+    /// In the high-priority pool, or demoted to the low-priority one
+    high: bool,
+}
+
+/// One shard of an LRU cache, with a high-priority pool
+///
+/// High-priority entries are kept in their own recency list of up to `high_capacity` bytes, the
+/// least recently used of them are demoted to the most recently used end of the low-priority list
+/// when the pool overflows. Eviction takes the least recently used low-priority entries first,
+/// then the high-priority ones, and skips the pinned entries.
+pub(super) struct LruShard {
+    capacity: usize,
+    high_capacity: usize,
+    entries: HashMap<CacheKey, Entry>,
+    high: BTreeMap<u64, CacheKey>,
+    low: BTreeMap<u64, CacheKey>,
+    next_tick: u64,
+    usage: usize,
+    high_usage: usize,
+}
+
+impl LruShard {
+    pub(super) fn new(capacity: usize, high_capacity: usize) -> Self {
+        LruShard {
+            capacity,
+            high_capacity,
+            entries: HashMap::new(),
+            high: BTreeMap::new(),
+            low: BTreeMap::new(),
+            next_tick: 0,
+            usage: 0,
+            high_usage: 0,
+        }
+    }
+
+    pub(super) fn lookup(&mut self, key: CacheKey) -> Option<Arc<Block>> {
+        let priority = self.entries.get(&key)?.priority;
+        self.unlink(key);
+        self.push(key, priority == Priority::High);
+        Some(Arc::clone(&self.entries[&key].block))
+    }
+
+    /// Inserts a block, replacing the one with the same key, after evicting what it takes to fit it.
+    /// Returns whether the block is kept and the number of blocks evicted.
+    pub(super) fn insert(
+        &mut self,
+        key: CacheKey,
+        block: Arc<Block>,
+        charge: usize,
+        priority: Priority,
+        strict: bool,
+    ) -> (bool, u64) {
+        self.remove(key);
+        if charge > self.capacity {
+            return (false, 0);
+        }
+        let evicted = self.evict_for(charge);
+        if strict && self.usage + charge > self.capacity {
+            // only pinned blocks are left to evict
+            return (false, evicted);
+        }
+        self.entries.insert(
+            key,
+            Entry {
+                block,
+                charge,
+                priority,
+                tick: 0,
+                high: false,
+            },
+        );
+        self.usage += charge;
+        self.push(key, priority == Priority::High);
+        (true, evicted)
+    }
+
+    /// Evicts the released entries while over capacity, returns the number of entries evicted
+    pub(super) fn evict(&mut self) -> u64 {
+        self.evict_for(0)
+    }
+
+    /// Evicts the least recently used entries not pinned until `charge` more fits in the capacity,
+    /// returns the number of entries evicted
+    fn evict_for(&mut self, charge: usize) -> u64 {
+        let mut evicted = 0;
+        for high in [false, true] {
+            let list = match high {
+                true => &self.high,
+                false => &self.low,
+            };
+            let mut excess = (self.usage + charge).saturating_sub(self.capacity);
+            let mut victims = Vec::new();
+            for key in list.values() {
+                if excess == 0 {
+                    break;
+                }
+                let entry = &self.entries[key];
+                if !is_pinned(&entry.block) {
+                    excess = excess.saturating_sub(entry.charge);
+                    victims.push(*key);
+                }
+            }
+            for key in victims {
+                self.remove(key);
+                evicted += 1;
+            }
+        }
+        evicted
+    }
+
+    pub(super) fn usage(&self) -> usize {
+        self.usage
+    }
+
+    pub(super) fn high_priority_usage(&self) -> usize {
+        self.high_usage
+    }
+
+    pub(super) fn pinned_usage(&self) -> usize {
+        let pinned = self.entries.values().filter(|entry| is_pinned(&entry.block));
+        pinned.map(|entry| entry.charge).sum()
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn remove(&mut self, key: CacheKey) {
+        if self.entries.contains_key(&key) {
+            self.unlink(key);
+            self.usage -= self.entries.remove(&key).unwrap().charge;
+        }
+    }
+
+    /// Takes an entry out of its recency list
+    fn unlink(&mut self, key: CacheKey) {
+        let entry = &self.entries[&key];
+        match entry.high {
+            true => {
+                self.high.remove(&entry.tick);
+                self.high_usage -= entry.charge;
+            }
+            false => {
+                self.low.remove(&entry.tick);
+            }
+        }
+    }
+
+    /// Puts an entry at the most recently used end of a pool, then demotes the least recently
+    /// used high-priority entries while the pool is over its capacity
+    fn push(&mut self, key: CacheKey, high: bool) {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        let entry = self.entries.get_mut(&key).unwrap();
+        entry.tick = tick;
+        entry.high = high;
+        match high {
+            true => {
+                self.high.insert(tick, key);
+                self.high_usage += entry.charge;
+            }
+            false => {
+                self.low.insert(tick, key);
+            }
+        }
+        while self.high_usage > self.high_capacity {
+            let (_, oldest) = self.high.pop_first().expect("high-priority usage without entries");
+            let entry = self.entries.get_mut(&oldest).unwrap();
+            self.high_usage -= entry.charge;
+            entry.high = false;
+            entry.tick = self.next_tick;
+            self.next_tick += 1;
+            self.low.insert(entry.tick, oldest);
+        }
+    }
+}
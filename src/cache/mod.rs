@@ -0,0 +1,281 @@
+mod clock;
+mod lru;
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::table::block::Block;
+
+use clock::ClockShard;
+use lru::LruShard;
+
+/// A block is identified by the cache id of its table and its offset in the file
+pub type CacheKey = (u64, u64);
+
+/// Smallest capacity of a shard when the number of shards is chosen by the cache
+const MIN_SHARD_CAPACITY: usize = 512 * 1024;
+
+/// Most shard bits chosen by the cache, 64 shards
+const MAX_AUTO_SHARD_BITS: u32 = 6;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub inserts: u64,
+    pub evictions: u64,
+}
+
+/// How a shard picks the blocks to evict
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Least recently used first, with a pool for high-priority blocks. A hit reorders the
+    /// recency list under the lock of the shard.
+    #[default]
+    Lru,
+    /// A hand sweeping the blocks, evicting those not hit since its last turn. A hit only bumps a
+    /// counter and shares the lock of the shard with the other hits, for read-heavy loads with
+    /// many threads.
+    Clock,
+}
+
+/// Priority of a block in the cache: index partitions are high, data blocks low
+///
+/// Filters never go through the cache, each reader holds its own (see `TableReader::filter_memory_usage`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Priority {
+    High,
+    #[default]
+    Low,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockCacheOptions {
+    /// Total charge of the blocks held, split evenly between the shards
+    pub capacity: usize,
+    /// The cache has `2^num_shard_bits` shards, `None` picks as many as fit shards of 512KB, up to
+    /// 64. More shards are less contention on their locks and a less precise eviction.
+    pub num_shard_bits: Option<u32>,
+    pub eviction: EvictionPolicy,
+    /// Share of the capacity of a shard reserved to high-priority blocks with `EvictionPolicy::Lru`:
+    /// low-priority blocks never evict them while they fit in it
+    pub high_priority_ratio: f64,
+    /// Refuses a block that only fits by evicting pinned blocks, instead of going over capacity
+    /// until they are released
+    pub strict_capacity_limit: bool,
+}
+
+impl Default for BlockCacheOptions {
+    fn default() -> Self {
+        BlockCacheOptions {
+            capacity: 8 * 1024 * 1024,
+            num_shard_bits: None,
+            eviction: EvictionPolicy::Lru,
+            high_priority_ratio: 0.5,
+            strict_capacity_limit: false,
+        }
+    }
+}
+
+enum Shard {
+    Lru(Mutex<LruShard>),
+    Clock(RwLock<ClockShard>),
+}
+
+/// Blocks read from tables, shared by every table of a store
+///
+/// The blocks are spread over shards by the hash of their key, each with its own lock and an
+/// even share of the capacity, and evicted by the policy of the options once the charge of a
+/// shard exceeds its share. A block handed out stays valid after its eviction, the cache only
+/// drops its own reference.
+///
+/// A block is pinned while a reference handed out by the cache is alive, e.g. held by the cursor
+/// of an iterator: it is not evicted and still counts in the usage, which may exceed the capacity
+/// until it is released. A released block is evicted by the next insert into its shard, or by
+/// `usage()`.
+pub struct BlockCache {
+    capacity: usize,
+    shard_bits: u32,
+    strict_capacity_limit: bool,
+    shards: Vec<Shard>,
+    next_id: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl BlockCache {
+    /// An LRU cache holding up to `capacity` bytes of blocks, with the default options
+    pub fn new(capacity: usize) -> Self {
+        Self::with_options(BlockCacheOptions {
+            capacity,
+            ..BlockCacheOptions::default()
+        })
+    }
+
+    pub fn with_options(options: BlockCacheOptions) -> Self {
+        let shard_bits = options.num_shard_bits.unwrap_or_else(|| {
+            let shards = (options.capacity / MIN_SHARD_CAPACITY).max(1);
+            shards.ilog2().min(MAX_AUTO_SHARD_BITS)
+        });
+        let shard_capacity = options.capacity.div_ceil(1 << shard_bits);
+        let high_capacity = (shard_capacity as f64 * options.high_priority_ratio.clamp(0.0, 1.0)) as usize;
+        let shards = (0..1 << shard_bits)
+            .map(|_| match options.eviction {
+                EvictionPolicy::Lru => Shard::Lru(Mutex::new(LruShard::new(shard_capacity, high_capacity))),
+                EvictionPolicy::Clock => Shard::Clock(RwLock::new(ClockShard::new(shard_capacity))),
+            })
+            .collect();
+        BlockCache {
+            capacity: options.capacity,
+            shard_bits,
+            strict_capacity_limit: options.strict_capacity_limit,
+            shards,
+            next_id: AtomicU64::new(1),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            inserts: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// A new id for a table, the first part of the keys of its blocks
+    ///
+    /// The ids stay below `1 << 63`, the ids the table cache of a store derives from the file
+    /// numbers have the top bit set.
+    pub fn new_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn get(&self, key: CacheKey) -> Option<Arc<Block>> {
+        let block = match self.shard(key) {
+            Shard::Lru(shard) => shard.lock().unwrap().lookup(key),
+            Shard::Clock(shard) => shard.read().unwrap().lookup(key),
+        };
+        let counter = match block {
+            Some(_) => &self.hits,
+            None => &self.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        block
+    }
+
+    /// Inserts a low-priority block charged its size, see `insert_with`
+    pub fn insert(&self, key: CacheKey, block: Arc<Block>) {
+        let charge = block.size();
+        self.insert_with(key, block, charge, Priority::Low);
+    }
+
+    /// Inserts a block, replacing the one with the same key, then evicts blocks of its shard
+    /// while the shard is over capacity. Returns false when the block is not kept: its charge is
+    /// larger than the capacity of a shard, or it only fits by evicting pinned blocks with
+    /// `strict_capacity_limit`.
+    pub fn insert_with(&self, key: CacheKey, block: Arc<Block>, charge: usize, priority: Priority) -> bool {
+        self.inserts.fetch_add(1, Ordering::Relaxed);
+        let strict = self.strict_capacity_limit;
+        let (kept, evicted) = match self.shard(key) {
+            Shard::Lru(shard) => shard.lock().unwrap().insert(key, block, charge, priority, strict),
+            Shard::Clock(shard) => shard.write().unwrap().insert(key, block, charge, priority, strict),
+        };
+        self.evictions.fetch_add(evicted, Ordering::Relaxed);
+        kept
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Charge of the blocks held, after evicting the blocks released since the last insert
+    pub fn usage(&self) -> usize {
+        let mut usage = 0;
+        for shard in &self.shards {
+            let (evicted, shard_usage) = match shard {
+                Shard::Lru(shard) => {
+                    let mut shard = shard.lock().unwrap();
+                    (shard.evict(), shard.usage())
+                }
+                Shard::Clock(shard) => {
+                    let mut shard = shard.write().unwrap();
+                    (shard.evict(), shard.usage())
+                }
+            };
+            self.evictions.fetch_add(evicted, Ordering::Relaxed);
+            usage += shard_usage;
+        }
+        usage
+    }
+
+    /// Charge of the blocks pinned by a reference handed out by the cache
+    pub fn pinned_usage(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| match shard {
+                Shard::Lru(shard) => shard.lock().unwrap().pinned_usage(),
+                Shard::Clock(shard) => shard.read().unwrap().pinned_usage(),
+            })
+            .sum()
+    }
+
+    /// Charge of the high-priority blocks in their pool, always 0 with `EvictionPolicy::Clock`
+    pub fn high_priority_usage(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| match shard {
+                Shard::Lru(shard) => shard.lock().unwrap().high_priority_usage(),
+                Shard::Clock(_) => 0,
+            })
+            .sum()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| match shard {
+                Shard::Lru(shard) => shard.lock().unwrap().len(),
+                Shard::Clock(shard) => shard.read().unwrap().len(),
+            })
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            inserts: self.inserts.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    fn shard(&self, (id, offset): CacheKey) -> &Shard {
+        if self.shard_bits == 0 {
+            return &self.shards[0];
+        }
+        let hash = (id.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ offset).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        &self.shards[(hash >> (64 - self.shard_bits)) as usize]
+    }
+}
+
+impl fmt::Debug for BlockCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockCache")
+            .field("capacity", &self.capacity)
+            .field("shards", &self.shards.len())
+            .field("usage", &self.usage())
+            .finish()
+    }
+}
+
+/// A block is pinned while someone besides the cache holds it
+fn is_pinned(block: &Arc<Block>) -> bool {
+    Arc::strong_count(block) > 1
+}
@@ -0,0 +1,316 @@
+use std::io;
+use std::iter::Peekable;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::memtable::MemtableEntry;
+use crate::table::{TableEntry, TableIter};
+use crate::wal::RecordType;
+
+use super::fifo::pick_fifo_compaction;
+use super::flush::TableOutput;
+use super::universal::pick_universal_compaction;
+use super::{DbOptions, FileMetaData, NUM_LEVELS, Shared, Version, VersionEdit};
+
+/// How the tables are merged as they pile up, chosen per store
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompactionStyle {
+    /// Levels of growing target size, a table is merged with those of the next level it meets:
+    /// little space overhead and few tables per lookup, but each write is rewritten once per level
+    #[default]
+    Level,
+    /// Sorted runs of similar size merged together (size-tiered), see `UniversalCompactionOptions`:
+    /// fewer rewrites for write-heavy stores, at the cost of space and of more runs per lookup
+    Universal,
+    /// Nothing is merged, the oldest tables are deleted past a size or an age, see
+    /// `FifoCompactionOptions`: a ring buffer of the latest writes at almost no compaction cost
+    Fifo,
+}
+
+/// Work done by the compactions since the store was opened
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// Compactions installed, a table moved to an other level without being rewritten included
+    pub compactions: u64,
+    /// Size of the tables read
+    pub bytes_read: u64,
+    /// Size of the tables written, over the bytes flushed it is the write amplification
+    pub bytes_written: u64,
+    /// Tables deleted without being merged by the FIFO style
+    pub tables_dropped: u64,
+}
+
+/// Tables merged into one sorted run
+pub(super) struct Compaction {
+    /// The tables merged, with their level
+    pub(super) inputs: Vec<(usize, Arc<FileMetaData>)>,
+    pub(super) output_level: usize,
+    /// Tables left out that may hold older versions of the keys of the inputs, a tombstone is kept
+    /// while one of them may hold its key
+    pub(super) older: Vec<Arc<FileMetaData>>,
+    /// The inputs are deleted, nothing is read nor written
+    pub(super) deletion: bool,
+}
+
+impl Compaction {
+    /// A single table is moved to the output level without being rewritten
+    fn is_trivial_move(&self) -> bool {
+        !self.deletion && self.inputs.len() == 1 && self.inputs[0].0 != self.output_level
+    }
+}
+
+/// Background thread running the compactions `pick_compaction` finds, one at a time
+///
+/// A compaction reads the tables of the version it was picked from, then installs its output
+/// with a MANIFEST edit; its input files are deleted once no reader holds a version with them.
+/// After a failure the thread stops and the error is returned by every following write. With a FIFO
+/// TTL the idle thread wakes up every second, so that the tables expire without new writes.
+pub(super) fn run_compactor(shared: Arc<Shared>) {
+    loop {
+        let (compaction, version, smallest_snapshot) = {
+            let mut state = shared.state.lock().unwrap();
+            loop {
+                if state.background_error.is_some() || state.shutting_down {
+                    state.compacting = false;
+                    shared.compacted.notify_all();
+                    return;
+                }
+                let version = state.versions.current();
+                if !shared.options.disable_auto_compactions
+                    && let Some(compaction) =
+                        pick_compaction(&version, &shared.options, &mut state.compact_pointers)
+                {
+                    state.compacting = true;
+                    let oldest_snapshot = shared.snapshots.oldest().unwrap_or(u64::MAX);
+                    break (compaction, version, oldest_snapshot.min(state.last_sequence));
+                }
+                state.compacting = false;
+                shared.compacted.notify_all();
+                state = match shared.options.compaction_style {
+                    CompactionStyle::Fifo if shared.options.compaction_options_fifo.ttl > 0 => {
+                        shared.compact_work.wait_timeout(state, Duration::from_secs(1)).unwrap().0
+                    }
+                    _ => shared.compact_work.wait(state).unwrap(),
+                };
+            }
+        };
+
+        let result = run_compaction(&shared, &compaction, smallest_snapshot);
+        drop(version);
+        let mut state = shared.state.lock().unwrap();
+        match result.and_then(|(edit, stats)| state.versions.log_and_apply(edit).map(|()| stats)) {
+            Ok(stats) => {
+                state.compaction_stats.compactions += 1;
+                state.compaction_stats.bytes_read += stats.bytes_read;
+                state.compaction_stats.bytes_written += stats.bytes_written;
+                state.compaction_stats.tables_dropped += stats.tables_dropped;
+                shared.delete_obsolete_tables(&mut state);
+            }
+            Err(e) => {
+                state.background_error = Some((e.kind(), format!("background compaction failed: {e}")));
+                shared.flushed.notify_all();
+            }
+        }
+    }
+}
+
+/// Size the tables of `level` (1 and deeper) may reach before it is compacted
+pub fn max_bytes_for_level(options: &DbOptions, level: usize) -> f64 {
+    options.max_bytes_for_level_base as f64 * options.max_bytes_for_level_multiplier.powi(level as i32 - 1)
+}
+
+/// How much each level but the last needs a compaction, it does from 1 on
+///
+/// Level 0 is scored by its number of tables against `level0_file_num_compaction_trigger`, since
+/// every lookup reads all of them; the deeper levels by their size against their target size.
+pub fn compaction_scores(version: &Version, options: &DbOptions) -> Vec<f64> {
+    let level0 = version.files(0).len() as f64 / options.level0_file_num_compaction_trigger.max(1) as f64;
+    let deeper = (1..NUM_LEVELS - 1)
+        .map(|level| version.level_size(level) as f64 / max_bytes_for_level(options, level));
+    std::iter::once(level0).chain(deeper).collect()
+}
+
+/// The next compaction of the style of the store, `None` when the tables are in shape
+pub(super) fn pick_compaction(
+    version: &Version,
+    options: &DbOptions,
+    pointers: &mut [Vec<u8>],
+) -> Option<Compaction> {
+    match options.compaction_style {
+        CompactionStyle::Level => pick_level_compaction(version, options, pointers),
+        CompactionStyle::Universal => pick_universal_compaction(version, options),
+        CompactionStyle::Fifo => pick_fifo_compaction(version, options),
+    }
+}
+
+/// Whether the compaction thread has work, without picking it
+pub(super) fn needs_compaction(version: &Version, options: &DbOptions) -> bool {
+    match options.compaction_style {
+        CompactionStyle::Level => compaction_scores(version, options).iter().any(|&score| score >= 1.0),
+        CompactionStyle::Universal => pick_universal_compaction(version, options).is_some(),
+        CompactionStyle::Fifo => pick_fifo_compaction(version, options).is_some(),
+    }
+}
+
+/// The compaction of the level with the highest score, `None` when no score reaches 1
+///
+/// Level 0 is compacted whole, its tables overlap. In a deeper level the table after the last
+/// one compacted (`pointers[level]` is its largest key) is picked, so that compactions go round
+/// the key space.
+fn pick_level_compaction(version: &Version, options: &DbOptions, pointers: &mut [Vec<u8>]) -> Option<Compaction> {
+    let scores = compaction_scores(version, options);
+    let (level, _) = scores
+        .iter()
+        .enumerate()
+        .filter(|&(_, &score)| score >= 1.0)
+        .max_by(|a, b| a.1.total_cmp(b.1))?;
+    let inputs: Vec<Arc<FileMetaData>> = match level {
+        0 => version.files(0).to_vec(),
+        _ => {
+            let files = version.files(level);
+            let next = files
+                .iter()
+                .find(|file| file.smallest > pointers[level])
+                .unwrap_or(&files[0]);
+            vec![Arc::clone(next)]
+        }
+    };
+    let smallest = inputs.iter().map(|file| &file.smallest).min().unwrap().clone();
+    let largest = inputs.iter().map(|file| &file.largest).max().unwrap().clone();
+    pointers[level] = largest.clone();
+    let overlapping = version.overlapping_files(level + 1, &smallest, &largest);
+    let inputs = inputs.into_iter().map(|file| (level, file));
+    Some(Compaction {
+        inputs: inputs.chain(overlapping.into_iter().map(|file| (level + 1, file))).collect(),
+        output_level: level + 1,
+        older: (level + 2..NUM_LEVELS).flat_map(|level| version.files(level).to_vec()).collect(),
+        deletion: false,
+    })
+}
+
+/// Runs `compaction` and returns the edit installing its output, with the bytes read and written
+///
+/// The inputs are merged in the internal order. Of the versions of a key, those shadowed by a
+/// newer version every snapshot sees (at or below `smallest_snapshot`) are dropped, and so is a
+/// tombstone every snapshot sees once no older table may hold the key. The entries kept go straight
+/// to the table being written, none is held in between. The output is cut in tables of about
+/// `target_file_size_base`, never between two versions of a key, so that the tables of a level (or
+/// of a run of level 0) never share a key.
+pub(super) fn run_compaction(
+    shared: &Shared,
+    compaction: &Compaction,
+    smallest_snapshot: u64,
+) -> io::Result<(VersionEdit, CompactionStats)> {
+    let output_level = compaction.output_level;
+    let mut edit = VersionEdit {
+        deleted_files: compaction.inputs.iter().map(|(level, file)| (*level, file.number)).collect(),
+        ..VersionEdit::default()
+    };
+    let mut stats = CompactionStats::default();
+    if compaction.deletion {
+        stats.tables_dropped = compaction.inputs.len() as u64;
+        return Ok((edit, stats));
+    }
+    if compaction.is_trivial_move() {
+        let (_, file) = &compaction.inputs[0];
+        edit.new_files.push((output_level, (**file).clone()));
+        return Ok((edit, stats));
+    }
+
+    let tables = compaction
+        .inputs
+        .iter()
+        .map(|(_, file)| shared.table_cache.get(file.number))
+        .collect::<io::Result<Vec<_>>>()?;
+    stats.bytes_read = compaction.inputs.iter().map(|(_, file)| file.file_size).sum();
+    let mut merged = MergingIter::new(tables.iter().map(|table| table.iter()).collect());
+    // an older table holding the key may have a version the tombstone hides
+    let in_older_table = |key: &[u8]| compaction.older.iter().any(|file| file.contains(key));
+
+    let mut output: Option<TableOutput> = None;
+    let mut last_key: Option<Vec<u8>> = None;
+    let mut last_sequence_for_key = u64::MAX;
+    while let Some(entry) = merged.next().transpose()? {
+        if last_key.as_ref() != Some(&entry.key) {
+            let target = shared.options.target_file_size_base;
+            if let Some(full) = output.take_if(|output| output.estimated_size() >= target) {
+                edit.new_files.push((output_level, finish_output(shared, full)?));
+            }
+            last_key = Some(entry.key.clone());
+            last_sequence_for_key = u64::MAX;
+        }
+        let shadowed = last_sequence_for_key <= smallest_snapshot;
+        let obsolete_tombstone = entry.record_type == RecordType::Delete
+            && entry.sequence <= smallest_snapshot
+            && !in_older_table(&entry.key);
+        last_sequence_for_key = entry.sequence;
+        if shadowed || obsolete_tombstone {
+            continue;
+        }
+        let output = match &mut output {
+            Some(output) => output,
+            None => {
+                let number = shared.state.lock().unwrap().versions.new_file_number();
+                output.insert(TableOutput::create(shared, number, output_level)?)
+            }
+        };
+        output.add(MemtableEntry {
+            key: &entry.key,
+            sequence: entry.sequence,
+            record_type: entry.record_type,
+            value: &entry.value,
+        })?;
+    }
+    if let Some(output) = output {
+        edit.new_files.push((output_level, finish_output(shared, output)?));
+    }
+    if output_level == 0 {
+        // the tables of the run take its sequence range: they sort together in level 0, as one run
+        let smallest = edit.new_files.iter().map(|(_, file)| file.smallest_sequence).min();
+        let largest = edit.new_files.iter().map(|(_, file)| file.largest_sequence).max();
+        for (_, file) in &mut edit.new_files {
+            file.smallest_sequence = smallest.unwrap();
+            file.largest_sequence = largest.unwrap();
+        }
+    }
+    stats.bytes_written = edit.new_files.iter().map(|(_, file)| file.file_size).sum();
+    Ok((edit, stats))
+}
+
+fn finish_output(shared: &Shared, output: TableOutput) -> io::Result<FileMetaData> {
+    let (table, file) = output.finish()?;
+    shared.table_cache.insert(file.number, table);
+    Ok(file)
+}
+
+/// Entries of several tables merged in the internal order
+struct MergingIter<'a> {
+    sources: Vec<Peekable<TableIter<'a>>>,
+}
+
+impl<'a> MergingIter<'a> {
+    fn new(sources: Vec<TableIter<'a>>) -> Self {
+        MergingIter {
+            sources: sources.into_iter().map(Iterator::peekable).collect(),
+        }
+    }
+}
+
+impl Iterator for MergingIter<'_> {
+    type Item = io::Result<TableEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut next: Option<(usize, &TableEntry)> = None;
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            match source.peek() {
+                Some(Ok(entry)) if next.is_none_or(|(_, best)| entry.order() < best.order()) => {
+                    next = Some((i, entry));
+                }
+                Some(Err(_)) => return source.next(),
+                _ => {}
+            }
+        }
+        let (i, _) = next?;
+        self.sources[i].next()
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::compaction::Compaction;
+use super::{DbOptions, FileMetaData, NUM_LEVELS, Version};
+
+/// Tuning of `CompactionStyle::Fifo`
+///
+/// The tables are never merged: the oldest ones are deleted, so that the store is a ring buffer
+/// of its latest writes. The oldest writes are lost whatever their key, even one never written
+/// since: the style is meant for data written once, such as metrics or logs.
+#[derive(Debug, Clone)]
+pub struct FifoCompactionOptions {
+    /// Size of the tables past which the oldest ones are deleted
+    pub max_table_files_size: u64,
+    /// Age in seconds past which a table is deleted, from the creation time the MANIFEST holds;
+    /// 0 keeps the tables whatever their age
+    pub ttl: u64,
+}
+
+impl Default for FifoCompactionOptions {
+    fn default() -> Self {
+        FifoCompactionOptions {
+            max_table_files_size: 1024 * 1024 * 1024,
+            ttl: 0,
+        }
+    }
+}
+
+/// The oldest tables to delete, `None` when the tables are under the size limit and none expired
+///
+/// Tables are deleted oldest first (the deeper levels, left by an other style, before level 0)
+/// while the tables weigh more than `max_table_files_size` or the oldest left expired. A table
+/// without a creation time (logged by an older release) never expires. No table is read.
+pub(super) fn pick_fifo_compaction(version: &Version, options: &DbOptions) -> Option<Compaction> {
+    let fifo = &options.compaction_options_fifo;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    let expired = |file: &FileMetaData| {
+        fifo.ttl > 0 && file.creation_time > 0 && now.saturating_sub(file.creation_time) > fifo.ttl
+    };
+
+    let mut size: u64 = version.tables().map(|file| file.file_size).sum();
+    let mut inputs = Vec::new();
+    let oldest_first = (0..NUM_LEVELS)
+        .rev()
+        .flat_map(|level| version.files(level).iter().rev().map(move |file| (level, file)));
+    for (level, file) in oldest_first {
+        if size <= fifo.max_table_files_size && !expired(file) {
+            break;
+        }
+        size -= file.file_size;
+        inputs.push((level, Arc::clone(file)));
+    }
+    (!inputs.is_empty()).then_some(Compaction {
+        inputs,
+        output_level: 0,
+        older: Vec::new(),
+        deletion: true,
+    })
+}
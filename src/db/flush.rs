@@ -0,0 +1,156 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::memtable::MemtableEntry;
+use crate::table::{Table, TableSink, table_file_name};
+use crate::wal::segment::segment_path;
+
+use super::{FileMetaData, Shared, TEMP_SUFFIX, VersionEdit};
+
+/// Background thread writing the frozen memtables to tables, oldest first
+///
+/// A memtable stays readable in the queue until its table is logged to the MANIFEST, and its WAL
+/// segments are deleted only after that: at any time every write is in a WAL segment or in a synced
+/// table of the MANIFEST.
+/// After a failure the thread stops and the error is returned by every following write.
+pub(super) fn run_flusher(shared: Arc<Shared>) {
+    loop {
+        let (immutable, number) = {
+            let mut state = shared.state.lock().unwrap();
+            loop {
+                if state.background_error.is_some() {
+                    return;
+                }
+                if let Some(immutable) = state.immutables.front() {
+                    let immutable = Arc::clone(immutable);
+                    break (immutable, state.versions.new_file_number());
+                }
+                if state.shutting_down {
+                    return;
+                }
+                state = shared.work.wait(state).unwrap();
+            }
+        };
+
+        let result = write_table(&shared, number, 0, &mut immutable.memtable.iter());
+        let mut state = shared.state.lock().unwrap();
+        let result = result.and_then(|(table, file)| {
+            let edit = VersionEdit {
+                log_number: Some(state.next_log_number()),
+                last_sequence: Some(file.largest_sequence),
+                new_files: vec![(0, file)],
+                ..VersionEdit::default()
+            };
+            state.versions.log_and_apply(edit)?;
+            Ok(table)
+        });
+        match result {
+            Ok(table) => {
+                shared.table_cache.insert(number, table);
+                state.immutables.pop_front();
+                for &segment in &immutable.wal_segments {
+                    // a segment left behind is harmless, its records are skipped on the next open
+                    let _ = shared.fs.remove_file(&segment_path(&shared.dir, segment));
+                }
+                shared.delete_obsolete_tables(&mut state);
+            }
+            Err(e) => state.background_error = Some((e.kind(), format!("background flush failed: {e}"))),
+        }
+        shared.flushed.notify_all();
+        shared.compact_work.notify_one();
+    }
+}
+
+/// Writes `entries` to table `number` of `level`, see `TableOutput`
+pub(super) fn write_table(
+    shared: &Shared,
+    number: u64,
+    level: usize,
+    entries: &mut dyn Iterator<Item = MemtableEntry<'_>>,
+) -> io::Result<(Arc<dyn Table>, FileMetaData)> {
+    let mut output = TableOutput::create(shared, number, level)?;
+    for entry in entries {
+        output.add(entry)?;
+    }
+    output.finish()
+}
+
+/// A table being written, under a temporary name renamed once synced so that a table file found on
+/// open is always complete; dropped unfinished, the temporary file is removed
+///
+/// The time it is created is the creation time of the table.
+pub(super) struct TableOutput<'a> {
+    shared: &'a Shared,
+    path: PathBuf,
+    temp: PathBuf,
+    /// `None` once finished
+    builder: Option<Box<dyn TableSink>>,
+    file: FileMetaData,
+}
+
+impl<'a> TableOutput<'a> {
+    pub(super) fn create(shared: &'a Shared, number: u64, level: usize) -> io::Result<Self> {
+        let path = shared.dir.join(table_file_name(number));
+        let temp = PathBuf::from(format!("{}{TEMP_SUFFIX}", path.display()));
+        let builder = shared.options.table_factory.new_builder(shared.fs.as_ref(), &temp, level)?;
+        Ok(TableOutput {
+            shared,
+            path,
+            temp,
+            builder: Some(builder),
+            file: FileMetaData {
+                number,
+                smallest_sequence: u64::MAX,
+                creation_time: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs()),
+                ..FileMetaData::default()
+            },
+        })
+    }
+
+    /// Adds the next entry, sorted by key then newest first: the first and last ones bound the table
+    pub(super) fn add(&mut self, entry: MemtableEntry<'_>) -> io::Result<()> {
+        let file = &mut self.file;
+        if file.smallest_sequence == u64::MAX {
+            file.smallest = entry.key.to_vec();
+        }
+        file.largest.clear();
+        file.largest.extend_from_slice(entry.key);
+        file.smallest_sequence = file.smallest_sequence.min(entry.sequence);
+        file.largest_sequence = file.largest_sequence.max(entry.sequence);
+        self.builder.as_mut().unwrap().add(entry)
+    }
+
+    /// About the size of the table if it were finished now
+    pub(super) fn estimated_size(&self) -> u64 {
+        self.builder.as_ref().unwrap().estimated_size()
+    }
+
+    /// Syncs the table and moves it to its name, returns it opened with its metadata
+    pub(super) fn finish(mut self) -> io::Result<(Arc<dyn Table>, FileMetaData)> {
+        let fs = self.shared.fs.as_ref();
+        let built = self.builder.take().unwrap().finish();
+        self.file.file_size = match built {
+            Ok(file_size) => file_size,
+            Err(e) => {
+                let _ = fs.remove_file(&self.temp);
+                return Err(e);
+            }
+        };
+        self.file.smallest_sequence = self.file.smallest_sequence.min(self.file.largest_sequence);
+        fs.rename(&self.temp, &self.path)?;
+        fs.sync_dir(&self.shared.dir)?;
+        let table = self.shared.table_cache.open(self.file.number)?;
+        Ok((table, std::mem::take(&mut self.file)))
+    }
+}
+
+impl Drop for TableOutput<'_> {
+    fn drop(&mut self) {
+        // the file is closed first
+        if self.builder.take().is_some() {
+            let _ = self.shared.fs.remove_file(&self.temp);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Arc;
+
+use crate::table::{TableEntry, TableIter};
+use crate::wal::RecordType;
+
+use super::{ImmutableMemtable, Snapshot, TableCache, Version};
+
+/// Entries read from a table or a frozen memtable at a time, the iterator holds no table in
+/// between: the table cache may close it
+const TABLE_BATCH: usize = 256;
+
+/// What a source reads its batches from
+enum Origin {
+    /// File number of a table
+    Table(u64),
+    /// A frozen memtable, read without the store lock: nothing is added to it anymore
+    Memtable(Arc<ImmutableMemtable>),
+}
+
+/// Entries of one memtable or table, in the internal order
+struct Source {
+    buffered: VecDeque<TableEntry>,
+    /// Where the next batch is read from, `None` once it is exhausted (and for the active
+    /// memtable, copied when the iterator is created)
+    origin: Option<Origin>,
+    /// Last entry read, the next batch starts after it
+    resume: Option<(Vec<u8>, u64)>,
+}
+
+impl Source {
+    /// The next entry, reading a batch from the table when the buffer is empty
+    fn peek(&mut self, prefix: &[u8], tables: &TableCache) -> io::Result<Option<&TableEntry>> {
+        if self.buffered.is_empty() {
+            self.refill(prefix, tables)?;
+        }
+        Ok(self.buffered.front())
+    }
+
+    fn refill(&mut self, prefix: &[u8], tables: &TableCache) -> io::Result<()> {
+        let table;
+        let entries: TableIter<'_> = match &self.origin {
+            None => return Ok(()),
+            Some(Origin::Table(number)) => {
+                table = tables.get(*number)?;
+                match &self.resume {
+                    // the first batch goes through the prefix check of the table
+                    None => table.prefix_iter(prefix),
+                    Some((key, _)) => table.iter_from(key),
+                }
+            }
+            Some(Origin::Memtable(immutable)) => {
+                let from = self.resume.as_ref().map_or(prefix, |(key, _)| key.as_slice());
+                Box::new(immutable.memtable.iter_from(from).map(|entry| Ok(TableEntry::from_memtable(&entry))))
+            }
+        };
+        for entry in entries {
+            let entry = entry?;
+            if !entry.key.starts_with(prefix) {
+                break;
+            }
+            if let Some((key, sequence)) = &self.resume
+                && entry.order() <= (key.as_slice(), Reverse(*sequence))
+            {
+                continue;
+            }
+            self.buffered.push_back(entry);
+            if self.buffered.len() == TABLE_BATCH {
+                break;
+            }
+        }
+        match self.buffered.back() {
+            Some(last) if self.buffered.len() == TABLE_BATCH => {
+                self.resume = Some((last.key.clone(), last.sequence));
+            }
+            _ => self.origin = None,
+        }
+        Ok(())
+    }
+}
+
+/// Latest value of every key starting with a prefix, by key, as of the creation of the iterator
+///
+/// Merges the entries of the active memtable, the versions it sees copied when it is created,
+/// with those of the frozen memtables and of the tables, read in batches without the store lock.
+/// Tables whose filter rules the prefix out are never read, see
+/// `TableOptions::prefix_extractor`. Items are `(key, value)` pairs, deleted keys are skipped.
+/// The iterator holds a snapshot and the version of its tables: compactions keep both readable.
+pub struct PrefixIter {
+    prefix: Vec<u8>,
+    sequence: u64,
+    _snapshot: Snapshot,
+    _version: Arc<Version>,
+    /// Newest first, the memtables then the tables
+    sources: Vec<Source>,
+    tables: Arc<TableCache>,
+    done: bool,
+}
+
+impl PrefixIter {
+    pub(super) fn new(
+        prefix: &[u8],
+        snapshot: Snapshot,
+        active: Vec<TableEntry>,
+        frozen: Vec<Arc<ImmutableMemtable>>,
+        version: Arc<Version>,
+        tables: Arc<TableCache>,
+    ) -> Self {
+        let active = Source {
+            buffered: active.into(),
+            origin: None,
+            resume: None,
+        };
+        let frozen = frozen.into_iter().map(|immutable| Source {
+            buffered: VecDeque::new(),
+            origin: Some(Origin::Memtable(immutable)),
+            resume: None,
+        });
+        let table_sources = version.tables().map(|file| Source {
+            buffered: VecDeque::new(),
+            origin: Some(Origin::Table(file.number)),
+            resume: None,
+        });
+        let sources = std::iter::once(active).chain(frozen).chain(table_sources).collect();
+        PrefixIter {
+            prefix: prefix.to_vec(),
+            sequence: snapshot.sequence(),
+            _snapshot: snapshot,
+            _version: version,
+            sources,
+            tables,
+            done: false,
+        }
+    }
+
+    /// The newest version visible to the iterator of the next key, `None` at the end
+    fn next_version(&mut self) -> io::Result<Option<TableEntry>> {
+        loop {
+            let mut key: Option<Vec<u8>> = None;
+            for source in &mut self.sources {
+                if let Some(entry) = source.peek(&self.prefix, &self.tables)?
+                    && key.as_ref().is_none_or(|key| entry.key < *key)
+                {
+                    key = Some(entry.key.clone());
+                }
+            }
+            let Some(key) = key else {
+                return Ok(None);
+            };
+            let mut newest: Option<TableEntry> = None;
+            for source in &mut self.sources {
+                while source.peek(&self.prefix, &self.tables)?.is_some_and(|entry| entry.key == key) {
+                    let entry = source.buffered.pop_front().unwrap();
+                    if entry.sequence <= self.sequence
+                        && newest.as_ref().is_none_or(|newest| entry.sequence > newest.sequence)
+                    {
+                        newest = Some(entry);
+                    }
+                }
+            }
+            // every version of the key is newer than the iterator: try the next key
+            if newest.is_some() {
+                return Ok(newest);
+            }
+        }
+    }
+}
+
+impl Iterator for PrefixIter {
+    type Item = io::Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.next_version() {
+                Ok(Some(entry)) if entry.record_type == RecordType::Put => {
+                    return Some(Ok((entry.key, entry.value)));
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
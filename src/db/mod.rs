@@ -0,0 +1,644 @@
+mod compaction;
+mod fifo;
+mod flush;
+mod iter;
+mod snapshot;
+mod table_cache;
+mod universal;
+mod version_edit;
+mod version_set;
+
+use std::collections::{HashSet, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::env::{FileSystem, default_fs};
+use crate::memtable::{Memtable, MemtableEntry, MemtableRepFactory, SkipListRepFactory};
+use crate::table::{BlockBasedTableFactory, TableEntry, TableFactory, parse_table_file_name, table_file_name};
+use crate::wal::segment::{parse_segment_file_name, segment_path};
+use crate::wal::{DecoderRegistry, MmapWalReader, RecordType, WalReader, WalWriter};
+
+use snapshot::SnapshotList;
+
+pub use compaction::{CompactionStats, CompactionStyle, compaction_scores, max_bytes_for_level};
+pub use fifo::FifoCompactionOptions;
+pub use iter::PrefixIter;
+pub use snapshot::Snapshot;
+pub use table_cache::TableCache;
+pub use universal::{UniversalCompactionOptions, sorted_run_count};
+pub use version_edit::{FileMetaData, VersionEdit};
+pub use version_set::{CURRENT_FILE, NUM_LEVELS, Version, VersionSet, manifest_file_name, parse_manifest_file_name};
+
+/// Suffix of a table or `CURRENT` being written, such files are removed on open
+const TEMP_SUFFIX: &str = ".tmp";
+
+#[derive(Clone)]
+pub struct DbOptions {
+    /// Memory usage above which the active memtable is frozen and a new one (and WAL segment) started
+    pub write_buffer_size: usize,
+    /// Frozen memtables waiting for the background flush, writes stall while there are this many
+    pub max_immutable_memtables: usize,
+    /// When true every write is synced to the WAL before it returns, otherwise it is only flushed
+    /// to the OS and survives a process crash but not a power loss
+    pub sync: bool,
+    /// Format of the tables, block-based by default
+    pub table_factory: Arc<dyn TableFactory>,
+    /// Representation of the memtables, a skiplist by default
+    pub memtable_factory: Arc<dyn MemtableRepFactory>,
+    /// Tables kept open by the table cache, the least recently used one is closed past it
+    pub max_open_files: usize,
+    /// Size past which the MANIFEST is replaced by a new one holding a snapshot of the tables
+    pub max_manifest_file_size: u64,
+    /// When true no compaction runs, the flushed tables pile up in level 0
+    pub disable_auto_compactions: bool,
+    /// Leveled (default), universal or FIFO compaction
+    pub compaction_style: CompactionStyle,
+    /// Tables in level 0 from which it is compacted into level 1, sorted runs from which they are
+    /// merged with the universal style
+    pub level0_file_num_compaction_trigger: usize,
+    /// Target size of level 1, past it a table of the level is compacted into the next one
+    pub max_bytes_for_level_base: u64,
+    /// Growth of the target size from a level to the next
+    pub max_bytes_for_level_multiplier: f64,
+    /// Size of the tables written by a compaction
+    pub target_file_size_base: u64,
+    /// Tuning of the universal compaction, ignored by the other styles
+    pub compaction_options_universal: UniversalCompactionOptions,
+    /// Tuning of the FIFO compaction, ignored by the other styles
+    pub compaction_options_fifo: FifoCompactionOptions,
+}
+
+impl Default for DbOptions {
+    fn default() -> Self {
+        DbOptions {
+            write_buffer_size: 4 * 1024 * 1024,
+            max_immutable_memtables: 2,
+            sync: false,
+            table_factory: Arc::new(BlockBasedTableFactory::default()),
+            memtable_factory: Arc::new(SkipListRepFactory),
+            max_open_files: 1000,
+            max_manifest_file_size: 64 * 1024 * 1024,
+            disable_auto_compactions: false,
+            compaction_style: CompactionStyle::Level,
+            level0_file_num_compaction_trigger: 4,
+            max_bytes_for_level_base: 10 * 1024 * 1024,
+            max_bytes_for_level_multiplier: 10.0,
+            target_file_size_base: 2 * 1024 * 1024,
+            compaction_options_universal: UniversalCompactionOptions::default(),
+            compaction_options_fifo: FifoCompactionOptions::default(),
+        }
+    }
+}
+
+/// A memtable waiting to be written to a table, with the WAL segments holding its records
+struct ImmutableMemtable {
+    memtable: Memtable,
+    wal_segments: Vec<u64>,
+}
+
+struct State {
+    /// Number of the WAL segment the writes go to, its writer is `Shared::wal`
+    wal_number: u64,
+    memtable: Memtable,
+    /// Oldest first, the background thread flushes them in this order
+    immutables: VecDeque<Arc<ImmutableMemtable>>,
+    /// The tables by level, opened through the table cache, and the file numbers
+    versions: VersionSet,
+    last_sequence: u64,
+    /// Largest key of the last table compacted in each level, see `compaction::pick_compaction`
+    compact_pointers: Vec<Vec<u8>>,
+    compacting: bool,
+    compaction_stats: CompactionStats,
+    background_error: Option<(io::ErrorKind, String)>,
+    shutting_down: bool,
+}
+
+impl State {
+    fn check_background_error(&self) -> io::Result<()> {
+        match &self.background_error {
+            Some((kind, message)) => Err(io::Error::new(*kind, message.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// First WAL segment still needed once the oldest frozen memtable is flushed
+    fn next_log_number(&self) -> u64 {
+        self.immutables
+            .get(1)
+            .and_then(|immutable| immutable.wal_segments.first().copied())
+            .unwrap_or(self.wal_number)
+    }
+}
+
+struct Shared {
+    fs: Arc<dyn FileSystem>,
+    dir: PathBuf,
+    options: DbOptions,
+    table_cache: Arc<TableCache>,
+    /// The current WAL segment, held by a write for its I/O so that the readers of `state` never
+    /// wait behind an append or a sync. Taken before `state`, it also orders the writes.
+    wal: Mutex<WalWriter>,
+    state: Mutex<State>,
+    snapshots: Arc<SnapshotList>,
+    /// Wakes the flush thread up: a memtable to flush or a shutdown
+    work: Condvar,
+    /// Signals the end of a flush to stalled writers and to `flush`
+    flushed: Condvar,
+    /// Wakes the compaction thread up: a new table or a shutdown
+    compact_work: Condvar,
+    /// Signals that the compaction thread is idle to `wait_for_compactions`
+    compacted: Condvar,
+}
+
+impl Shared {
+    /// Deletes the files of the tables removed by compactions that no reader holds anymore
+    fn delete_obsolete_tables(&self, state: &mut State) {
+        for number in state.versions.take_obsolete_tables() {
+            self.table_cache.evict(number);
+            // a file left behind is not in the MANIFEST, it is deleted on the next open
+            let _ = self.fs.remove_file(&self.dir.join(table_file_name(number)));
+        }
+    }
+}
+
+/// Key-value store: a WAL, a memtable and the tables it is flushed to
+///
+/// Writes go to the current WAL segment, then to the active memtable. Once the memtable grows past
+/// `write_buffer_size` it is frozen, queued for the background flush thread, and a new memtable
+/// and WAL segment take its place. The flush thread writes the frozen memtables to tables, oldest
+/// first, then deletes the WAL segments they covered: from then on the table is their only copy.
+/// A compaction thread merges the tables, down the levels or by sorted runs, see `CompactionStyle`.
+pub struct Db {
+    shared: Arc<Shared>,
+    flusher: Option<JoinHandle<()>>,
+    compactor: Option<JoinHandle<()>>,
+}
+
+impl Db {
+    pub fn open(dir: impl AsRef<Path>, options: DbOptions) -> io::Result<Self> {
+        Self::open_in(default_fs(), dir, options)
+    }
+
+    /// Opens the store in `dir`, creating it if needed, and replays the WAL segments not flushed yet
+    pub fn open_in(fs: Arc<dyn FileSystem>, dir: impl AsRef<Path>, options: DbOptions) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let table_cache = Arc::new(TableCache::new(
+            Arc::clone(&fs),
+            dir.clone(),
+            Arc::clone(&options.table_factory),
+            options.max_open_files,
+        ));
+        let (state, wal) = recover(&fs, &dir, &options, &table_cache)?;
+        let shared = Arc::new(Shared {
+            fs,
+            dir,
+            options,
+            table_cache,
+            wal: Mutex::new(wal),
+            state: Mutex::new(state),
+            snapshots: Arc::default(),
+            work: Condvar::new(),
+            flushed: Condvar::new(),
+            compact_work: Condvar::new(),
+            compacted: Condvar::new(),
+        });
+        let flusher_shared = Arc::clone(&shared);
+        let flusher = thread::Builder::new()
+            .name("stone-flush".to_string())
+            .spawn(move || flush::run_flusher(flusher_shared))?;
+        let mut db = Db {
+            shared,
+            flusher: Some(flusher),
+            compactor: None,
+        };
+        let compactor_shared = Arc::clone(&db.shared);
+        db.compactor = Some(
+            thread::Builder::new()
+                .name("stone-compact".to_string())
+                .spawn(move || compaction::run_compactor(compactor_shared))?,
+        );
+        Ok(db)
+    }
+
+    /// Writes `key`, returns the sequence number of the write
+    pub fn put(&self, key: &[u8], value: &[u8]) -> io::Result<u64> {
+        self.write(RecordType::Put, key, value)
+    }
+
+    pub fn delete(&self, key: &[u8]) -> io::Result<u64> {
+        self.write(RecordType::Delete, key, &[])
+    }
+
+    /// Latest value of `key`
+    pub fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        self.get_at_sequence(key, None)
+    }
+
+    /// Value of `key` as of `snapshot`
+    pub fn get_at(&self, key: &[u8], snapshot: &Snapshot) -> io::Result<Option<Vec<u8>>> {
+        self.get_at_sequence(key, Some(snapshot.sequence()))
+    }
+
+    /// A read view of the store as it is now, the versions it sees are kept until it is dropped
+    pub fn snapshot(&self) -> Snapshot {
+        let state = self.shared.state.lock().unwrap();
+        self.shared.snapshots.acquire(state.last_sequence)
+    }
+
+    fn get_at_sequence(&self, key: &[u8], sequence: Option<u64>) -> io::Result<Option<Vec<u8>>> {
+        let (version, sequence) = {
+            let state = self.shared.state.lock().unwrap();
+            let sequence = sequence.unwrap_or(state.last_sequence);
+            let memtables = std::iter::once(&state.memtable).chain(state.immutables.iter().rev().map(|i| &i.memtable));
+            for memtable in memtables {
+                if let Some(entry) = memtable.get(key, sequence) {
+                    return Ok(value_of(&entry));
+                }
+            }
+            (state.versions.current(), sequence)
+        };
+        for file in version.tables().filter(|file| file.contains(key)) {
+            if let Some(entry) = self.shared.table_cache.get(file.number)?.get(key, sequence)? {
+                return Ok(match entry.record_type {
+                    RecordType::Put => Some(entry.value),
+                    RecordType::Delete => None,
+                });
+            }
+        }
+        Ok(None)
+    }
+
+    /// Latest value of every key starting with `prefix`, in key order
+    ///
+    /// The iterator sees the writes made before it was created. Only the latest version of each
+    /// key of the active memtable is copied, under the store lock; the frozen memtables and the
+    /// tables are read as it advances. With a prefix extractor the tables whose filter rules
+    /// `prefix` out are skipped without being read.
+    pub fn prefix_iter(&self, prefix: &[u8]) -> PrefixIter {
+        let state = self.shared.state.lock().unwrap();
+        // the iterator sees no later write: the newest version of a key is the one it reads
+        let mut active: Vec<TableEntry> = Vec::new();
+        for entry in state.memtable.iter_from(prefix).take_while(|entry| entry.key.starts_with(prefix)) {
+            if active.last().is_none_or(|last| last.key != entry.key) {
+                active.push(TableEntry::from_memtable(&entry));
+            }
+        }
+        let frozen = state.immutables.iter().rev().cloned().collect();
+        let snapshot = self.shared.snapshots.acquire(state.last_sequence);
+        let version = state.versions.current();
+        drop(state);
+        PrefixIter::new(prefix, snapshot, active, frozen, version, Arc::clone(&self.shared.table_cache))
+    }
+
+    /// Sequence of the last write
+    pub fn last_sequence(&self) -> u64 {
+        self.shared.state.lock().unwrap().last_sequence
+    }
+
+    /// Freezes the active memtable and waits until every frozen memtable is written to a table
+    pub fn flush(&self) -> io::Result<()> {
+        let mut wal = self.shared.wal.lock().unwrap();
+        let pending = {
+            let state = self.shared.state.lock().unwrap();
+            state.check_background_error()?;
+            !state.memtable.is_empty()
+        };
+        if pending {
+            self.switch_memtable(&mut wal)?;
+        }
+        drop(wal);
+        let mut state = self.shared.state.lock().unwrap();
+        while !state.immutables.is_empty() && state.background_error.is_none() {
+            state = self.shared.flushed.wait(state).unwrap();
+        }
+        state.check_background_error()
+    }
+
+    /// Waits until the frozen memtables are flushed and no compaction is running or needed
+    pub fn wait_for_compactions(&self) -> io::Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            state.check_background_error()?;
+            let shared = &self.shared;
+            let needed = !shared.options.disable_auto_compactions
+                && compaction::needs_compaction(&state.versions.current(), &shared.options);
+            if state.immutables.is_empty() && !state.compacting && !needed {
+                return Ok(());
+            }
+            state = self.shared.compacted.wait(state).unwrap();
+        }
+    }
+
+    /// Work done by the compactions since the store was opened
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.shared.state.lock().unwrap().compaction_stats
+    }
+
+    /// Numbers of the tables in the order a lookup reads them, see `Version::tables`
+    pub fn table_numbers(&self) -> Vec<u64> {
+        self.current_version().table_numbers()
+    }
+
+    /// The tables of the store by level
+    pub fn current_version(&self) -> Arc<Version> {
+        self.shared.state.lock().unwrap().versions.current()
+    }
+
+    /// The open tables, see `DbOptions::max_open_files`
+    pub fn table_cache(&self) -> &TableCache {
+        &self.shared.table_cache
+    }
+
+    /// Numbers of the WAL segments still needed, oldest first
+    pub fn wal_segment_numbers(&self) -> Vec<u64> {
+        let state = self.shared.state.lock().unwrap();
+        let mut numbers: Vec<u64> = state
+            .immutables
+            .iter()
+            .flat_map(|immutable| immutable.wal_segments.iter().copied())
+            .collect();
+        numbers.push(state.wal_number);
+        numbers
+    }
+
+    /// Frozen memtables not flushed yet
+    pub fn immutable_memtables(&self) -> usize {
+        self.shared.state.lock().unwrap().immutables.len()
+    }
+
+    /// Stops the background threads once the frozen memtables are flushed, the active one stays in
+    /// the WAL; a compaction in progress is finished, the others wait for the next open
+    pub fn close(mut self) -> io::Result<()> {
+        self.shutdown()
+    }
+
+    /// Appends the write to the WAL holding only the WAL lock, then takes the store lock to add it
+    /// to the memtable and publish its sequence: reads go on during the append and the sync
+    fn write(&self, record_type: RecordType, key: &[u8], value: &[u8]) -> io::Result<u64> {
+        let mut wal = self.shared.wal.lock().unwrap();
+        let sequence = {
+            let mut state = self.shared.state.lock().unwrap();
+            while state.immutables.len() >= self.shared.options.max_immutable_memtables.max(1)
+                && state.background_error.is_none()
+            {
+                state = self.shared.flushed.wait(state).unwrap();
+            }
+            state.check_background_error()?;
+            // no other write runs before this one publishes its sequence, the WAL lock is held
+            state.last_sequence + 1
+        };
+
+        let logged = wal.append(record_type, sequence, key, value).and_then(|()| match self.shared.options.sync {
+            true => wal.sync(),
+            false => wal.flush(),
+        });
+        let mut state = self.shared.state.lock().unwrap();
+        // the segment may end with part of the record: no write is appended behind it. A record the
+        // WAL rejects (a key or value past 4GB) is not written, only that write fails.
+        if let Err(e) = logged {
+            if e.kind() == io::ErrorKind::InvalidInput {
+                return Err(e);
+            }
+            state.background_error = Some((e.kind(), format!("WAL write failed: {e}")));
+            self.shared.flushed.notify_all();
+            return Err(e);
+        }
+        state.memtable.add(sequence, record_type, key, value);
+        state.last_sequence = sequence;
+        let full = state.memtable.memory_usage() >= self.shared.options.write_buffer_size;
+        drop(state);
+
+        // the write is applied and will be recovered whatever happens to the switch, a failure is
+        // returned by the next writes instead
+        if full && let Err(e) = self.switch_memtable(&mut wal) {
+            let mut state = self.shared.state.lock().unwrap();
+            state.background_error = Some((e.kind(), format!("memtable switch failed: {e}")));
+            self.shared.flushed.notify_all();
+        }
+        Ok(sequence)
+    }
+
+    /// Freezes the active memtable and starts a new one with a new WAL segment
+    ///
+    /// The current segment is synced and the new one created holding only the WAL lock, no write
+    /// comes in between; the store lock is taken to allocate its number and to swap the memtables.
+    fn switch_memtable(&self, wal: &mut WalWriter) -> io::Result<()> {
+        wal.sync()?;
+        let number = self.shared.state.lock().unwrap().versions.new_file_number();
+        *wal = create_wal(self.shared.fs.as_ref(), &self.shared.dir, number)?;
+
+        let mut state = self.shared.state.lock().unwrap();
+        let old_wal = std::mem::replace(&mut state.wal_number, number);
+        let mut memtable = std::mem::replace(&mut state.memtable, new_memtable(&self.shared.options));
+        memtable.mark_read_only();
+        state.immutables.push_back(Arc::new(ImmutableMemtable {
+            memtable,
+            wal_segments: vec![old_wal],
+        }));
+        self.shared.work.notify_one();
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        let Some(flusher) = self.flusher.take() else {
+            return Ok(());
+        };
+        self.shared.state.lock().unwrap().shutting_down = true;
+        self.shared.work.notify_one();
+        self.shared.compact_work.notify_one();
+        flusher
+            .join()
+            .map_err(|_| io::Error::other("flush thread panicked"))?;
+        if let Some(compactor) = self.compactor.take() {
+            compactor
+                .join()
+                .map_err(|_| io::Error::other("compaction thread panicked"))?;
+        }
+        self.shared.state.lock().unwrap().check_background_error()
+    }
+}
+
+impl Drop for Db {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+fn new_memtable(options: &DbOptions) -> Memtable {
+    Memtable::with_rep(options.memtable_factory.create())
+}
+
+fn value_of(entry: &MemtableEntry<'_>) -> Option<Vec<u8>> {
+    match entry.record_type {
+        RecordType::Put => Some(entry.value.to_vec()),
+        RecordType::Delete => None,
+    }
+}
+
+/// Creates a WAL segment whose header is durable, so that a crash never leaves an unreadable segment
+/// `None` for a segment created right before a crash, its header never made it to the disk
+fn skip_headerless<T>(opened: io::Result<T>) -> io::Result<Option<T>> {
+    match opened {
+        Ok(reader) => Ok(Some(reader)),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn create_wal(fs: &dyn FileSystem, dir: &Path, number: u64) -> io::Result<WalWriter> {
+    let mut wal = WalWriter::create_in(fs, segment_path(dir, number))?;
+    wal.sync()?;
+    fs.sync_dir(dir)?;
+    Ok(wal)
+}
+
+/// Replays the MANIFEST, replays the WAL segments past its checkpoint and starts a new segment
+///
+/// Files the MANIFEST does not know are removed: tables whose flush was not logged, segments
+/// below the checkpoint and old MANIFESTs. A store written before the MANIFEST has none, its
+/// tables are adopted at level 0.
+fn recover(
+    fs: &Arc<dyn FileSystem>,
+    dir: &Path,
+    options: &DbOptions,
+    table_cache: &TableCache,
+) -> io::Result<(State, WalWriter)> {
+    fs.create_dir_all(dir)?;
+    let mut segments = Vec::new();
+    let mut table_numbers = Vec::new();
+    let mut manifests = Vec::new();
+    let mut max_number = 0;
+    for name in fs.list_dir(dir)? {
+        if name.ends_with(TEMP_SUFFIX) {
+            // a table whose flush was interrupted, its records are still in the WAL
+            fs.remove_file(&dir.join(&name))?;
+        } else if let Some(number) = parse_segment_file_name(&name) {
+            segments.push(number);
+            max_number = max_number.max(number);
+        } else if let Some(number) = parse_table_file_name(&name) {
+            table_numbers.push(number);
+            max_number = max_number.max(number);
+        } else if let Some(number) = parse_manifest_file_name(&name) {
+            manifests.push(number);
+            max_number = max_number.max(number);
+        }
+    }
+    segments.sort_unstable();
+    table_numbers.sort_unstable();
+
+    let mut versions = VersionSet::recover(Arc::clone(fs), dir, max_number + 1, options.max_manifest_file_size)?;
+    let mut edit = VersionEdit::default();
+    if versions.manifest_number() == 0 {
+        for &number in &table_numbers {
+            edit.new_files.push((0, adopt_table(fs.as_ref(), dir, table_cache, number)?));
+        }
+    }
+    // the tables hold every write up to their highest sequence, the flushes go oldest first
+    let flushed_sequence = edit
+        .new_files
+        .iter()
+        .map(|(_, file)| file.largest_sequence)
+        .fold(versions.current().largest_sequence(), u64::max);
+
+    let (obsolete, segments): (Vec<u64>, Vec<u64>) =
+        segments.into_iter().partition(|&number| number < versions.log_number());
+    let mut memtable = new_memtable(options);
+    let mut last_sequence = flushed_sequence.max(versions.last_sequence());
+    let registry = DecoderRegistry::default();
+    let mut replay = |sequence: u64, record_type: RecordType, key: &[u8], value: &[u8]| {
+        last_sequence = last_sequence.max(sequence);
+        if sequence > flushed_sequence {
+            memtable.add(sequence, record_type, key, value);
+        }
+    };
+    for &number in &segments {
+        let path = segment_path(dir, number);
+        // a mapped segment is decoded in place, without copying its keys and values
+        if let Some(data) = fs.map(&path)? {
+            let Some(mut reader) = skip_headerless(MmapWalReader::from_bytes(data, &registry))? else {
+                continue;
+            };
+            while let Some(record) = reader.next_view() {
+                replay(record.sequence, record.record_type, record.key, record.value);
+            }
+        } else {
+            let Some(reader) = skip_headerless(WalReader::open_in(fs.as_ref(), &path, &registry))? else {
+                continue;
+            };
+            for record in reader {
+                let record = record?;
+                replay(record.sequence, record.record_type, &record.key, &record.value);
+            }
+        }
+    }
+
+    let wal_number = versions.new_file_number();
+    let wal = create_wal(fs.as_ref(), dir, wal_number)?;
+    let mut immutables = VecDeque::new();
+    let mut obsolete_segments = obsolete;
+    if memtable.is_empty() {
+        obsolete_segments.extend(segments);
+        edit.log_number = Some(wal_number);
+    } else {
+        // the recovered writes are flushed in the background like any frozen memtable
+        edit.log_number = segments.first().copied();
+        memtable.mark_read_only();
+        immutables.push_back(Arc::new(ImmutableMemtable {
+            memtable,
+            wal_segments: segments,
+        }));
+    }
+    edit.last_sequence = Some(last_sequence);
+    // always a new MANIFEST, the old one may end with a torn edit
+    versions.log_and_apply(edit)?;
+
+    for number in obsolete_segments {
+        fs.remove_file(&segment_path(dir, number))?;
+    }
+    let live: HashSet<u64> = versions.current().tables().map(|file| file.number).collect();
+    for number in table_numbers.into_iter().filter(|number| !live.contains(number)) {
+        fs.remove_file(&dir.join(table_file_name(number)))?;
+    }
+    for number in manifests.into_iter().filter(|&number| number != versions.manifest_number()) {
+        fs.remove_file(&dir.join(manifest_file_name(number)))?;
+    }
+
+    let state = State {
+        wal_number,
+        memtable: new_memtable(options),
+        immutables,
+        versions,
+        last_sequence,
+        compact_pointers: vec![Vec::new(); NUM_LEVELS],
+        compacting: false,
+        compaction_stats: CompactionStats::default(),
+        background_error: None,
+        shutting_down: false,
+    };
+    Ok((state, wal))
+}
+
+/// Key range and sequences of a table of a store without a MANIFEST, read from its entries
+fn adopt_table(fs: &dyn FileSystem, dir: &Path, table_cache: &TableCache, number: u64) -> io::Result<FileMetaData> {
+    let mut file = FileMetaData {
+        number,
+        file_size: fs.file_size(&dir.join(table_file_name(number)))?,
+        smallest_sequence: u64::MAX,
+        ..FileMetaData::default()
+    };
+    let table = table_cache.get(number)?;
+    file.creation_time = table.creation_time().unwrap_or(0);
+    for (n, entry) in table.iter().enumerate() {
+        let entry = entry?;
+        if n == 0 {
+            file.smallest = entry.key.clone();
+        }
+        file.smallest_sequence = file.smallest_sequence.min(entry.sequence);
+        file.largest_sequence = file.largest_sequence.max(entry.sequence);
+        file.largest = entry.key;
+    }
+    file.smallest_sequence = file.smallest_sequence.min(file.largest_sequence);
+    Ok(file)
+}
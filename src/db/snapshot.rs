@@ -0,0 +1,50 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// Sequences of the live snapshots, with how many snapshots share each
+#[derive(Default)]
+pub(super) struct SnapshotList {
+    sequences: Mutex<BTreeMap<u64, usize>>,
+}
+
+impl SnapshotList {
+    pub(super) fn acquire(self: &Arc<Self>, sequence: u64) -> Snapshot {
+        *self.sequences.lock().unwrap().entry(sequence).or_default() += 1;
+        Snapshot {
+            sequence,
+            list: Arc::clone(self),
+        }
+    }
+
+    /// Sequence of the oldest live snapshot, compactions keep every version it reads
+    pub(super) fn oldest(&self) -> Option<u64> {
+        self.sequences.lock().unwrap().keys().next().copied()
+    }
+}
+
+/// A read view of the store as of a sequence, see `Db::snapshot`
+///
+/// Until it is dropped, compactions keep the versions of the keys it sees.
+pub struct Snapshot {
+    sequence: u64,
+    list: Arc<SnapshotList>,
+}
+
+impl Snapshot {
+    /// Sequence of the last write the snapshot sees
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let mut sequences = self.list.sequences.lock().unwrap();
+        if let Some(count) = sequences.get_mut(&self.sequence) {
+            *count -= 1;
+            if *count == 0 {
+                sequences.remove(&self.sequence);
+            }
+        }
+    }
+}
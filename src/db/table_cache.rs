@@ -0,0 +1,163 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::cache::CacheStats;
+use crate::env::FileSystem;
+use crate::table::{Table, TableFactory, table_file_name};
+
+/// Ids of the stores of the process, the block cache keys of their tables start with them
+static NEXT_STORE_ID: AtomicU64 = AtomicU64::new(0);
+
+struct Entry {
+    table: Arc<dyn Table>,
+    /// Position in the recency order, the lowest is evicted first
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<u64, Entry>,
+    by_recency: BTreeMap<u64, u64>,
+    next_tick: u64,
+}
+
+impl Lru {
+    fn touch(&mut self, number: u64) -> Option<Arc<dyn Table>> {
+        let tick = self.next_tick;
+        let entry = self.entries.get_mut(&number)?;
+        self.by_recency.remove(&entry.tick);
+        entry.tick = tick;
+        self.by_recency.insert(tick, number);
+        self.next_tick += 1;
+        Some(Arc::clone(&entry.table))
+    }
+
+    fn remove(&mut self, number: u64) -> bool {
+        match self.entries.remove(&number) {
+            Some(entry) => {
+                self.by_recency.remove(&entry.tick);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// The open tables of a store, keyed by file number, up to `capacity` of them
+///
+/// A table is opened on its first lookup: its file is opened and its footer, index, properties
+/// and filter are read once and kept with the reader. Past the capacity the least recently used
+/// table is dropped, which closes its file once the lookups and iterators still holding it are
+/// done, so that a store with thousands of tables keeps a bounded number of descriptors.
+///
+/// A table is opened with the same block cache id every time, made of an id of the store and its
+/// file number: the blocks an evicted table left in the block cache serve it again once reopened.
+pub struct TableCache {
+    fs: Arc<dyn FileSystem>,
+    store_id: u64,
+    dir: PathBuf,
+    factory: Arc<dyn TableFactory>,
+    capacity: usize,
+    lru: Mutex<Lru>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl TableCache {
+    pub fn new(fs: Arc<dyn FileSystem>, dir: PathBuf, factory: Arc<dyn TableFactory>, capacity: usize) -> Self {
+        TableCache {
+            fs,
+            store_id: NEXT_STORE_ID.fetch_add(1, Ordering::Relaxed),
+            dir,
+            factory,
+            capacity: capacity.max(1),
+            lru: Mutex::new(Lru::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            inserts: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// The table of file `number`, opened if it is not in the cache
+    pub fn get(&self, number: u64) -> io::Result<Arc<dyn Table>> {
+        if let Some(table) = self.lru.lock().unwrap().touch(number) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(table);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        // opened without the lock, lookups of other tables go on meanwhile
+        let table = self.open(number)?;
+        Ok(self.insert(number, table))
+    }
+
+    /// Opens the table of file `number` without caching it, with the block cache id it always has
+    ///
+    /// The id is the top bit, 23 bits of the store id and 40 bits of the file number, above the ids
+    /// `BlockCache::new_id` hands out.
+    pub fn open(&self, number: u64) -> io::Result<Arc<dyn Table>> {
+        let cache_id = 1 << 63 | (self.store_id & ((1 << 23) - 1)) << 40 | (number & ((1 << 40) - 1));
+        self.factory
+            .open_with_id(self.fs.as_ref(), &self.dir.join(table_file_name(number)), cache_id)
+    }
+
+    /// Adds a table just opened, returns the one to use: an other thread may have opened the same
+    /// table in the meantime, the first one in the cache is kept
+    pub fn insert(&self, number: u64, table: Arc<dyn Table>) -> Arc<dyn Table> {
+        let mut lru = self.lru.lock().unwrap();
+        if let Some(cached) = lru.touch(number) {
+            return cached;
+        }
+        self.inserts.fetch_add(1, Ordering::Relaxed);
+        let tick = lru.next_tick;
+        lru.next_tick += 1;
+        lru.entries.insert(
+            number,
+            Entry {
+                table: Arc::clone(&table),
+                tick,
+            },
+        );
+        lru.by_recency.insert(tick, number);
+        while lru.entries.len() > self.capacity {
+            let (_, oldest) = lru.by_recency.pop_first().expect("entries without recency");
+            lru.entries.remove(&oldest);
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+        table
+    }
+
+    /// Drops the table of file `number`, before its file is deleted
+    pub fn evict(&self, number: u64) {
+        if self.lru.lock().unwrap().remove(number) {
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of tables open in the cache
+    pub fn len(&self) -> usize {
+        self.lru.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            inserts: self.inserts.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use super::compaction::Compaction;
+use super::{DbOptions, FileMetaData, NUM_LEVELS, Version};
+
+/// Tuning of `CompactionStyle::Universal`
+///
+/// The tables form sorted runs, newest first: each flush or merge output of level 0 is one, and
+/// so is each non-empty deeper level. Once there are `level0_file_num_compaction_trigger` runs they are
+/// merged, a whole run at a time, so that a write is rewritten about once per doubling of its run.
+#[derive(Debug, Clone)]
+pub struct UniversalCompactionOptions {
+    /// Slack in percent when comparing sizes: a run joins the newer runs picked when it is at most
+    /// this much larger than all of them together
+    pub size_ratio: u64,
+    /// Fewest runs merged by a size ratio compaction
+    pub min_merge_width: usize,
+    /// Most runs merged by a size ratio compaction
+    pub max_merge_width: usize,
+    /// Space overhead in percent tolerated: once the runs but the oldest weigh this much of the
+    /// oldest, all the runs are merged into the last level
+    pub max_size_amplification_percent: u64,
+}
+
+impl Default for UniversalCompactionOptions {
+    fn default() -> Self {
+        UniversalCompactionOptions {
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: usize::MAX,
+            max_size_amplification_percent: 200,
+        }
+    }
+}
+
+/// The tables of a flush or merge in level 0, or of a deeper level
+struct SortedRun<'a> {
+    level: usize,
+    files: &'a [Arc<FileMetaData>],
+    size: u64,
+}
+
+/// The sorted runs of `version`, newest first
+///
+/// The tables a merge writes to level 0 all carry the sequence range of the run, see
+/// `run_compaction`: they are next to each other, and two runs never share a range.
+fn sorted_runs(version: &Version) -> Vec<SortedRun<'_>> {
+    let same_run = |a: &Arc<FileMetaData>, b: &Arc<FileMetaData>| {
+        (a.smallest_sequence, a.largest_sequence) == (b.smallest_sequence, b.largest_sequence)
+    };
+    let level0 = version.files(0).chunk_by(same_run).map(|files| SortedRun {
+        level: 0,
+        files,
+        size: files.iter().map(|file| file.file_size).sum(),
+    });
+    let deeper = (1..NUM_LEVELS)
+        .filter(|&level| !version.files(level).is_empty())
+        .map(|level| SortedRun {
+            level,
+            files: version.files(level),
+            size: version.level_size(level),
+        });
+    level0.chain(deeper).collect()
+}
+
+/// Number of sorted runs of `version`, what a lookup reads at most with the universal style
+pub fn sorted_run_count(version: &Version) -> usize {
+    sorted_runs(version).len()
+}
+
+/// The next universal compaction, `None` while there are fewer runs than the trigger
+///
+/// Tried in turn:
+/// - size amplification: the runs but the oldest weigh `max_size_amplification_percent` of it,
+///   every run is merged
+/// - size ratio: from the newest run on, the first span of runs each at most `size_ratio` percent
+///   larger than the newer ones together, `min_merge_width` runs at least
+/// - else the newest runs are merged, down to one run under the trigger
+pub(super) fn pick_universal_compaction(version: &Version, options: &DbOptions) -> Option<Compaction> {
+    let runs = sorted_runs(version);
+    let trigger = options.level0_file_num_compaction_trigger.max(2);
+    if runs.len() < trigger {
+        return None;
+    }
+    let universal = &options.compaction_options_universal;
+
+    let (oldest, newer) = runs.split_last()?;
+    let newer_size: u64 = newer.iter().map(|run| run.size).sum();
+    if newer_size.saturating_mul(100) >= universal.max_size_amplification_percent.saturating_mul(oldest.size) {
+        return Some(compaction(&runs, 0..runs.len()));
+    }
+
+    let min_width = universal.min_merge_width.max(2);
+    let max_width = universal.max_merge_width.max(min_width);
+    for start in 0..runs.len() {
+        let mut candidate = runs[start].size;
+        let mut end = start + 1;
+        while end < runs.len()
+            && end - start < max_width
+            && candidate.saturating_mul(100 + universal.size_ratio) / 100 >= runs[end].size
+        {
+            candidate += runs[end].size;
+            end += 1;
+        }
+        if end - start >= min_width {
+            return Some(compaction(&runs, start..end));
+        }
+    }
+
+    let width = (runs.len() + 1 - trigger).max(2);
+    Some(compaction(&runs, 0..width))
+}
+
+/// Merges `runs[picked]` into one run: in the last level when the oldest run is part of it,
+/// otherwise in level 0 in place of the runs, the older runs keep their place
+fn compaction(runs: &[SortedRun<'_>], picked: Range<usize>) -> Compaction {
+    let output_level = if picked.end == runs.len() { NUM_LEVELS - 1 } else { 0 };
+    let files = |runs: &[SortedRun<'_>]| {
+        runs.iter()
+            .flat_map(|run| run.files.iter().map(move |file| (run.level, Arc::clone(file))))
+            .collect::<Vec<_>>()
+    };
+    Compaction {
+        inputs: files(&runs[picked.clone()]),
+        output_level,
+        older: files(&runs[picked.end..]).into_iter().map(|(_, file)| file).collect(),
+        deletion: false,
+    }
+}
@@ -0,0 +1,129 @@
+use std::io;
+
+use crate::table::format::{corruption, get_varint64, put_varint64};
+
+const TAG_LOG_NUMBER: u64 = 1;
+const TAG_NEXT_FILE_NUMBER: u64 = 2;
+const TAG_LAST_SEQUENCE: u64 = 3;
+const TAG_DELETED_FILE: u64 = 4;
+const TAG_NEW_FILE: u64 = 5;
+const TAG_NEW_FILE_WITH_TIME: u64 = 6;
+
+/// A table of the store: its file and the range of user keys and sequences it holds
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileMetaData {
+    pub number: u64,
+    pub file_size: u64,
+    pub smallest: Vec<u8>,
+    pub largest: Vec<u8>,
+    pub smallest_sequence: u64,
+    pub largest_sequence: u64,
+    /// Seconds since the Unix epoch when the table was written, 0 when unknown
+    pub creation_time: u64,
+}
+
+impl FileMetaData {
+    /// Whether `key` is in the range of user keys of the table
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.smallest.as_slice() <= key && key <= self.largest.as_slice()
+    }
+}
+
+/// A change to the files of the store and to its counters, one record of the MANIFEST
+///
+/// Encoded as a list of `[Tag(varint) | Fields]`: the counters are varints, a deleted file is its
+/// level and number, a new file its level, number and size, smallest and largest keys
+/// (length-prefixed) and sequences, followed by its creation time under its own tag when known.
+/// A tag the reader does not know is `InvalidData`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionEdit {
+    /// The WAL checkpoint: the segments below this number are in tables
+    pub log_number: Option<u64>,
+    pub next_file_number: Option<u64>,
+    pub last_sequence: Option<u64>,
+    /// `(level, file number)` of the tables removed from the store
+    pub deleted_files: Vec<(usize, u64)>,
+    /// `(level, file)` of the tables added to the store
+    pub new_files: Vec<(usize, FileMetaData)>,
+}
+
+impl VersionEdit {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let counters = [
+            (TAG_LOG_NUMBER, self.log_number),
+            (TAG_NEXT_FILE_NUMBER, self.next_file_number),
+            (TAG_LAST_SEQUENCE, self.last_sequence),
+        ];
+        for (tag, value) in counters {
+            if let Some(value) = value {
+                put_varint64(&mut buf, tag);
+                put_varint64(&mut buf, value);
+            }
+        }
+        for &(level, number) in &self.deleted_files {
+            put_varint64(&mut buf, TAG_DELETED_FILE);
+            put_varint64(&mut buf, level as u64);
+            put_varint64(&mut buf, number);
+        }
+        for (level, file) in &self.new_files {
+            let tag = if file.creation_time > 0 { TAG_NEW_FILE_WITH_TIME } else { TAG_NEW_FILE };
+            put_varint64(&mut buf, tag);
+            put_varint64(&mut buf, *level as u64);
+            put_varint64(&mut buf, file.number);
+            put_varint64(&mut buf, file.file_size);
+            for key in [&file.smallest, &file.largest] {
+                put_varint64(&mut buf, key.len() as u64);
+                buf.extend_from_slice(key);
+            }
+            put_varint64(&mut buf, file.smallest_sequence);
+            put_varint64(&mut buf, file.largest_sequence);
+            if file.creation_time > 0 {
+                put_varint64(&mut buf, file.creation_time);
+            }
+        }
+        buf
+    }
+
+    pub fn decode(mut input: &[u8]) -> io::Result<Self> {
+        let mut edit = VersionEdit::default();
+        let input = &mut input;
+        while !input.is_empty() {
+            match varint(input)? {
+                TAG_LOG_NUMBER => edit.log_number = Some(varint(input)?),
+                TAG_NEXT_FILE_NUMBER => edit.next_file_number = Some(varint(input)?),
+                TAG_LAST_SEQUENCE => edit.last_sequence = Some(varint(input)?),
+                TAG_DELETED_FILE => edit.deleted_files.push((varint(input)? as usize, varint(input)?)),
+                tag @ (TAG_NEW_FILE | TAG_NEW_FILE_WITH_TIME) => {
+                    let level = varint(input)? as usize;
+                    let mut file = FileMetaData {
+                        number: varint(input)?,
+                        file_size: varint(input)?,
+                        smallest: length_prefixed(input)?,
+                        largest: length_prefixed(input)?,
+                        smallest_sequence: varint(input)?,
+                        largest_sequence: varint(input)?,
+                        creation_time: 0,
+                    };
+                    if tag == TAG_NEW_FILE_WITH_TIME {
+                        file.creation_time = varint(input)?;
+                    }
+                    edit.new_files.push((level, file));
+                }
+                tag => return Err(corruption(format!("unknown version edit tag {tag}"))),
+            }
+        }
+        Ok(edit)
+    }
+}
+
+fn varint(input: &mut &[u8]) -> io::Result<u64> {
+    get_varint64(input).ok_or_else(|| corruption("truncated version edit"))
+}
+
+fn length_prefixed(input: &mut &[u8]) -> io::Result<Vec<u8>> {
+    let len = varint(input)? as usize;
+    let bytes = input.get(..len).ok_or_else(|| corruption("truncated version edit"))?;
+    *input = &input[len..];
+    Ok(bytes.to_vec())
+}
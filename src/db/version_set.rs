@@ -0,0 +1,314 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+
+use crate::env::{FileSystem, read_file, write_file};
+use crate::table::format::corruption;
+use crate::wal::{DecoderRegistry, RecordType, ScanEntry, WalReader, WalWriter};
+
+use super::version_edit::{FileMetaData, VersionEdit};
+
+/// Levels of the store, flushes write level 0
+pub const NUM_LEVELS: usize = 7;
+
+/// File naming the MANIFEST in use, replaced atomically by a rename
+pub const CURRENT_FILE: &str = "CURRENT";
+
+const MANIFEST_PREFIX: &str = "MANIFEST-";
+
+pub fn manifest_file_name(number: u64) -> String {
+    format!("{MANIFEST_PREFIX}{number:06}")
+}
+
+/// Returns the MANIFEST number encoded in a file name, `None` for any other file
+pub fn parse_manifest_file_name(name: &str) -> Option<u64> {
+    let digits = name.strip_prefix(MANIFEST_PREFIX)?;
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// The tables of the store at one point in time, by level
+///
+/// Level 0 holds the flushed tables (and the output of universal compactions), newest first by
+/// largest sequence, their key ranges may overlap. The deeper levels are sorted by smallest key.
+/// A version never changes, an edit makes a new one.
+#[derive(Debug, Clone, Default)]
+pub struct Version {
+    levels: [Vec<Arc<FileMetaData>>; NUM_LEVELS],
+}
+
+impl Version {
+    pub fn files(&self, level: usize) -> &[Arc<FileMetaData>] {
+        &self.levels[level]
+    }
+
+    pub fn num_files(&self) -> usize {
+        self.levels.iter().map(Vec::len).sum()
+    }
+
+    /// The tables in the order a lookup reads them: level 0 newest first, then each level
+    pub fn tables(&self) -> impl Iterator<Item = &Arc<FileMetaData>> {
+        self.levels.iter().flatten()
+    }
+
+    pub fn table_numbers(&self) -> Vec<u64> {
+        self.tables().map(|file| file.number).collect()
+    }
+
+    pub fn contains(&self, number: u64) -> bool {
+        self.tables().any(|file| file.number == number)
+    }
+
+    /// Total size of the tables of `level`
+    pub fn level_size(&self, level: usize) -> u64 {
+        self.levels[level].iter().map(|file| file.file_size).sum()
+    }
+
+    /// The tables of `level` whose key range meets `smallest..=largest`
+    pub fn overlapping_files(&self, level: usize, smallest: &[u8], largest: &[u8]) -> Vec<Arc<FileMetaData>> {
+        self.levels[level]
+            .iter()
+            .filter(|file| file.smallest.as_slice() <= largest && smallest <= file.largest.as_slice())
+            .cloned()
+            .collect()
+    }
+
+    /// Highest sequence in the tables, every older write is in them or was overwritten
+    pub fn largest_sequence(&self) -> u64 {
+        self.tables().map(|file| file.largest_sequence).max().unwrap_or(0)
+    }
+
+    fn apply(&self, edit: &VersionEdit) -> io::Result<Version> {
+        let mut version = self.clone();
+        for &(level, number) in &edit.deleted_files {
+            let files = version.level_mut(level)?;
+            let at = files
+                .iter()
+                .position(|file| file.number == number)
+                .ok_or_else(|| corruption(format!("deleted table {number} is not in level {level}")))?;
+            files.remove(at);
+        }
+        for (level, file) in &edit.new_files {
+            let files = version.level_mut(*level)?;
+            files.push(Arc::new(file.clone()));
+            match level {
+                // a compaction output keeps the age of its inputs, not of its file number
+                0 => files.sort_by_key(|file| std::cmp::Reverse((file.largest_sequence, file.number))),
+                _ => files.sort_by(|a, b| a.smallest.cmp(&b.smallest)),
+            }
+        }
+        Ok(version)
+    }
+
+    fn level_mut(&mut self, level: usize) -> io::Result<&mut Vec<Arc<FileMetaData>>> {
+        self.levels
+            .get_mut(level)
+            .ok_or_else(|| corruption(format!("level {level} out of range")))
+    }
+}
+
+/// The current `Version` of the store and the MANIFEST logging its edits
+///
+/// The MANIFEST is a log of `VersionEdit`s framed as WAL records (the edit is the value, the
+/// sequence counts the edits of the file), each synced before the new version is installed. The
+/// first record is a snapshot of the whole version. `CURRENT` holds the name of the MANIFEST in
+/// use: a new MANIFEST is written on open and once the current one grows past its size limit,
+/// then `CURRENT` is replaced by a rename.
+pub struct VersionSet {
+    fs: Arc<dyn FileSystem>,
+    dir: PathBuf,
+    current: Arc<Version>,
+    /// `None` until the first edit after `recover` starts a new MANIFEST
+    manifest: Option<WalWriter>,
+    manifest_number: u64,
+    max_manifest_file_size: u64,
+    edits: u64,
+    log_number: u64,
+    next_file_number: u64,
+    last_sequence: u64,
+    /// Every version handed out, a removed table stays on disk while one of them holds it
+    versions: Vec<Weak<Version>>,
+    /// Tables removed by an edit whose files are not deleted yet
+    removed: Vec<u64>,
+}
+
+impl VersionSet {
+    /// Replays the MANIFEST named by `CURRENT`, an empty version when there is none
+    ///
+    /// File numbers start at `min_file_number` at least, the caller passes one past the highest
+    /// number found in the directory. A torn or corrupted last edit was never acknowledged and is
+    /// ignored, a corrupted edit followed by valid ones is `InvalidData`.
+    pub fn recover(
+        fs: Arc<dyn FileSystem>,
+        dir: impl AsRef<Path>,
+        min_file_number: u64,
+        max_manifest_file_size: u64,
+    ) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let mut versions = VersionSet {
+            fs,
+            dir,
+            current: Arc::new(Version::default()),
+            manifest: None,
+            manifest_number: 0,
+            max_manifest_file_size,
+            edits: 0,
+            log_number: 0,
+            next_file_number: min_file_number,
+            last_sequence: 0,
+            versions: Vec::new(),
+            removed: Vec::new(),
+        };
+        let current_path = versions.dir.join(CURRENT_FILE);
+        if !versions.fs.exists(&current_path) {
+            return Ok(versions);
+        }
+        let contents = read_file(versions.fs.as_ref(), &current_path)?;
+        let name = String::from_utf8(contents).map_err(|_| corruption("CURRENT is not UTF-8"))?;
+        let name = name.strip_suffix('\n').unwrap_or(&name);
+        versions.manifest_number =
+            parse_manifest_file_name(name).ok_or_else(|| corruption(format!("CURRENT names {name:?}")))?;
+
+        let path = versions.dir.join(name);
+        let mut reader = WalReader::open_in(versions.fs.as_ref(), &path, &DecoderRegistry::default())?;
+        let mut version = Version::default();
+        let mut corrupted_at = None;
+        while let Some(entry) = reader.next_entry()? {
+            match entry {
+                ScanEntry::Record { offset, record, .. } => {
+                    if let Some(at) = corrupted_at {
+                        return Err(corruption(format!("{name}: corrupted edit at {at} before the edit at {offset}")));
+                    }
+                    let edit = VersionEdit::decode(&record.value)?;
+                    version = version.apply(&edit)?;
+                    versions.apply_counters(&edit);
+                }
+                ScanEntry::Corrupted { offset, .. } => corrupted_at = corrupted_at.or(Some(offset)),
+                ScanEntry::TornTail { .. } => break,
+            }
+        }
+        versions.current = Arc::new(version);
+        Ok(versions)
+    }
+
+    pub fn current(&self) -> Arc<Version> {
+        Arc::clone(&self.current)
+    }
+
+    /// Tables removed from the store that no version in use holds anymore: readers (e.g. a prefix
+    /// iterator) keep the version they started with, its files are deleted once they are done
+    pub fn take_obsolete_tables(&mut self) -> Vec<u64> {
+        self.versions.retain(|version| version.strong_count() > 0);
+        let live: Vec<Arc<Version>> = self.versions.iter().filter_map(Weak::upgrade).collect();
+        let (in_use, obsolete) = std::mem::take(&mut self.removed)
+            .into_iter()
+            .partition(|&number| live.iter().any(|version| version.contains(number)));
+        self.removed = in_use;
+        obsolete
+    }
+
+    pub fn new_file_number(&mut self) -> u64 {
+        self.next_file_number += 1;
+        self.next_file_number - 1
+    }
+
+    /// The WAL checkpoint, the segments below it are in tables
+    pub fn log_number(&self) -> u64 {
+        self.log_number
+    }
+
+    /// Highest sequence recorded by an edit
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    /// Number of the MANIFEST in use, 0 before the first edit of a new store
+    pub fn manifest_number(&self) -> u64 {
+        self.manifest_number
+    }
+
+    /// Logs `edit` to the MANIFEST, syncs it and installs the new version
+    ///
+    /// The edit records the next file number too. On error the current version is unchanged.
+    pub fn log_and_apply(&mut self, mut edit: VersionEdit) -> io::Result<()> {
+        let version = self.current.apply(&edit)?;
+        let roll = self
+            .manifest
+            .as_ref()
+            .is_none_or(|manifest| manifest.len() >= self.max_manifest_file_size);
+        if roll {
+            // the snapshot is the first edit of the new MANIFEST and takes the number it uses
+            let number = self.new_file_number();
+            let mut versions = self.snapshot(&version, &edit);
+            versions.next_file_number = Some(self.next_file_number);
+            self.write_manifest(number, &versions)?;
+        } else {
+            edit.next_file_number = Some(self.next_file_number);
+            let manifest = self.manifest.as_mut().unwrap();
+            manifest.append(RecordType::Put, self.edits + 1, &[], &edit.encode())?;
+            manifest.sync()?;
+            self.edits += 1;
+        }
+        self.apply_counters(&edit);
+        // a table moved to an other level is still in use
+        let added: Vec<u64> = edit.new_files.iter().map(|(_, file)| file.number).collect();
+        let removed = edit.deleted_files.iter().map(|&(_, number)| number);
+        self.removed.extend(removed.filter(|number| !added.contains(number)));
+        self.versions.push(Arc::downgrade(&self.current));
+        self.current = Arc::new(version);
+        Ok(())
+    }
+
+    fn apply_counters(&mut self, edit: &VersionEdit) {
+        if let Some(log_number) = edit.log_number {
+            self.log_number = self.log_number.max(log_number);
+        }
+        if let Some(next_file_number) = edit.next_file_number {
+            self.next_file_number = self.next_file_number.max(next_file_number);
+        }
+        if let Some(last_sequence) = edit.last_sequence {
+            self.last_sequence = self.last_sequence.max(last_sequence);
+        }
+    }
+
+    /// An edit rebuilding `version` from nothing, with the counters as updated by `edit`
+    fn snapshot(&self, version: &Version, edit: &VersionEdit) -> VersionEdit {
+        let new_files = (0..NUM_LEVELS)
+            .flat_map(|level| version.files(level).iter().map(move |file| (level, (**file).clone())))
+            .collect();
+        VersionEdit {
+            log_number: Some(edit.log_number.unwrap_or(0).max(self.log_number)),
+            next_file_number: None,
+            last_sequence: Some(edit.last_sequence.unwrap_or(0).max(self.last_sequence)),
+            deleted_files: Vec::new(),
+            new_files,
+        }
+    }
+
+    /// Writes a new MANIFEST holding `snapshot`, points `CURRENT` at it and deletes the old one
+    fn write_manifest(&mut self, number: u64, snapshot: &VersionEdit) -> io::Result<()> {
+        let fs = self.fs.as_ref();
+        let path = self.dir.join(manifest_file_name(number));
+        let mut manifest = WalWriter::create_in(fs, &path)?;
+        manifest.append(RecordType::Put, 1, &[], &snapshot.encode())?;
+        manifest.sync()?;
+        fs.sync_dir(&self.dir)?;
+
+        // a crash before the rename leaves the old MANIFEST in use, the new one is deleted on open
+        let temp = self.dir.join(format!("{CURRENT_FILE}.tmp"));
+        write_file(fs, &temp, format!("{}\n", manifest_file_name(number)).as_bytes())?;
+        fs.rename(&temp, &self.dir.join(CURRENT_FILE))?;
+        fs.sync_dir(&self.dir)?;
+
+        // the MANIFEST replayed on open is left to the caller, which removes every obsolete file
+        let old = std::mem::replace(&mut self.manifest_number, number);
+        if self.manifest.is_some() {
+            let _ = fs.remove_file(&self.dir.join(manifest_file_name(old)));
+        }
+        self.manifest = Some(manifest);
+        self.edits = 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::{FileSystem, RandomAccessFile, SequentialFile, WritableFile, read_file};
+
+/// The calls a fault can be attached to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileOp {
+    Create,
+    Open,
+    Append,
+    Flush,
+    Sync,
+    Read,
+    Remove,
+    Rename,
+    SyncDir,
+    ListDir,
+}
+
+#[derive(Debug, Clone)]
+struct Fault {
+    op: FileOp,
+    path_contains: Option<String>,
+    /// Matching calls to let through before failing
+    skip: u64,
+    /// Matching calls to fail once `skip` is exhausted, `None` for all of them
+    times: Option<u64>,
+    /// For appends: bytes actually written before the error is returned
+    torn_keep: Option<usize>,
+}
+
+struct TrackedFile {
+    path: PathBuf,
+    written: u64,
+    synced: u64,
+}
+
+#[derive(Default)]
+struct State {
+    faults: Vec<Fault>,
+    files: HashMap<u64, TrackedFile>,
+    next_file_id: u64,
+    /// Bumped by every simulated power loss, handles opened before it stop working
+    epoch: u64,
+    op_counts: HashMap<FileOp, u64>,
+}
+
+impl State {
+    /// Counts the call and returns the fault to apply to it, if any
+    fn check(&mut self, op: FileOp, path: &Path) -> Option<Fault> {
+        *self.op_counts.entry(op).or_default() += 1;
+        let path = path.to_string_lossy();
+        let index = self.faults.iter_mut().position(|fault| {
+            if fault.op != op || fault.path_contains.as_ref().is_some_and(|p| !path.contains(p.as_str())) {
+                return false;
+            }
+            if fault.skip > 0 {
+                fault.skip -= 1;
+                return false;
+            }
+            true
+        })?;
+        let fault = self.faults[index].clone();
+        match &mut self.faults[index].times {
+            Some(1) => {
+                self.faults.remove(index);
+            }
+            Some(times) => *times -= 1,
+            None => {}
+        }
+        Some(fault)
+    }
+
+    fn tracked_by_path(&mut self, path: &Path) -> Option<&mut TrackedFile> {
+        self.files.values_mut().find(|file| file.path == path)
+    }
+}
+
+fn injected_error(op: FileOp, path: &Path) -> io::Error {
+    io::Error::other(format!("injected I/O error (EIO) on {op:?} {}", path.display()))
+}
+
+fn stale_handle(path: &Path) -> io::Error {
+    io::Error::other(format!("{} was opened before a simulated power loss", path.display()))
+}
+
+/// Wraps a `FileSystem` to inject faults and simulate power losses
+///
+/// It remembers how many bytes of every file written through it have been synced, so that
+/// `drop_unsynced_data` can bring the files back to what a disk would hold after losing power.
+/// Files that existed before being wrapped are considered durable. Metadata operations (create,
+/// rename, remove) are durable as soon as they return.
+#[derive(Clone)]
+pub struct FaultInjectionFileSystem {
+    base: Arc<dyn FileSystem>,
+    state: Arc<Mutex<State>>,
+}
+
+impl FaultInjectionFileSystem {
+    pub fn new(base: Arc<dyn FileSystem>) -> Self {
+        FaultInjectionFileSystem {
+            base,
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    /// The `n`-th call (1-based, counted from now) to `op` fails with an I/O error
+    pub fn fail_nth(&self, op: FileOp, n: u64) {
+        self.add_fault(Fault {
+            op,
+            path_contains: None,
+            skip: n.saturating_sub(1),
+            times: Some(1),
+            torn_keep: None,
+        });
+    }
+
+    /// Like `fail_nth`, counting only the calls on paths containing `path_contains`
+    pub fn fail_nth_on_path(&self, op: FileOp, path_contains: &str, n: u64) {
+        self.add_fault(Fault {
+            op,
+            path_contains: Some(path_contains.to_string()),
+            skip: n.saturating_sub(1),
+            times: Some(1),
+            torn_keep: None,
+        });
+    }
+
+    /// Every call to `op` fails until `clear_faults`
+    pub fn fail_always(&self, op: FileOp) {
+        self.add_fault(Fault {
+            op,
+            path_contains: None,
+            skip: 0,
+            times: None,
+            torn_keep: None,
+        });
+    }
+
+    /// The `n`-th append writes only its first `keep` bytes, then fails with an I/O error
+    pub fn tear_nth_append(&self, n: u64, keep: usize) {
+        self.add_fault(Fault {
+            op: FileOp::Append,
+            path_contains: None,
+            skip: n.saturating_sub(1),
+            times: Some(1),
+            torn_keep: Some(keep),
+        });
+    }
+
+    pub fn clear_faults(&self) {
+        self.state.lock().unwrap().faults.clear();
+    }
+
+    /// Number of calls to `op` seen so far, failed ones included
+    pub fn op_count(&self, op: FileOp) -> u64 {
+        self.state.lock().unwrap().op_counts.get(&op).copied().unwrap_or(0)
+    }
+
+    /// Bytes appended to `path` and not synced yet
+    pub fn unsynced_bytes(&self, path: &Path) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.tracked_by_path(path).map_or(0, |file| file.written - file.synced)
+    }
+
+    /// Simulates a power loss: every file loses what was appended after its last sync
+    pub fn drop_unsynced_data(&self) -> io::Result<()> {
+        self.drop_unsynced_data_with(|_, synced, _| synced)
+    }
+
+    /// Simulates a power loss where part of the unsynced data made it to disk
+    ///
+    /// `keep(path, synced, written)` returns the length the file ends up with, clamped to
+    /// `synced..=written`, which lets a test reproduce any partially-synced state.
+    /// Handles opened before the call fail from now on, as the process they belong to is gone.
+    pub fn drop_unsynced_data_with(&self, mut keep: impl FnMut(&Path, u64, u64) -> u64) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.epoch += 1;
+        for file in state.files.values_mut() {
+            if file.written == file.synced || !self.base.exists(&file.path) {
+                continue;
+            }
+            let len = keep(&file.path, file.synced, file.written).clamp(file.synced, file.written);
+            let mut data = read_file(self.base.as_ref(), &file.path)?;
+            data.truncate(len as usize);
+            let mut rewritten = self.base.create(&file.path)?;
+            rewritten.append(&data)?;
+            rewritten.sync()?;
+            file.written = len;
+            file.synced = len;
+        }
+        Ok(())
+    }
+
+    fn add_fault(&self, fault: Fault) {
+        self.state.lock().unwrap().faults.push(fault);
+    }
+
+    fn check(&self, op: FileOp, path: &Path) -> io::Result<()> {
+        match self.state.lock().unwrap().check(op, path) {
+            Some(_) => Err(injected_error(op, path)),
+            None => Ok(()),
+        }
+    }
+
+    fn track(&self, path: &Path, file: Box<dyn WritableFile>) -> Box<dyn WritableFile> {
+        let mut state = self.state.lock().unwrap();
+        // a truncated or recreated file starts from scratch
+        state.files.retain(|_, tracked| tracked.path != path);
+        let id = state.next_file_id;
+        state.next_file_id += 1;
+        state.files.insert(
+            id,
+            TrackedFile {
+                path: path.to_path_buf(),
+                written: 0,
+                synced: 0,
+            },
+        );
+        Box::new(FaultWritableFile {
+            inner: file,
+            id,
+            epoch: state.epoch,
+            state: Arc::clone(&self.state),
+        })
+    }
+}
+
+struct FaultWritableFile {
+    inner: Box<dyn WritableFile>,
+    id: u64,
+    epoch: u64,
+    state: Arc<Mutex<State>>,
+}
+
+/// Checks the handle is still valid and applies the faults registered for `op`
+///
+/// The state stays locked until the write is done, so a power loss never lands in the middle of one.
+fn check_handle(
+    state: &Mutex<State>,
+    id: u64,
+    epoch: u64,
+    op: FileOp,
+) -> io::Result<(MutexGuard<'_, State>, PathBuf, Option<Fault>)> {
+    let mut state = state.lock().unwrap();
+    let path = match state.files.get(&id) {
+        Some(file) if state.epoch == epoch => file.path.clone(),
+        Some(file) => return Err(stale_handle(&file.path)),
+        None if state.epoch != epoch => return Err(stale_handle(Path::new("file"))),
+        // removed while open, writes still go to the unlinked file
+        None => PathBuf::new(),
+    };
+    let fault = state.check(op, &path);
+    Ok((state, path, fault))
+}
+
+impl WritableFile for FaultWritableFile {
+    fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        let (mut state, path, fault) = check_handle(&self.state, self.id, self.epoch, FileOp::Append)?;
+        let (written, torn) = match fault {
+            Some(Fault {
+                torn_keep: Some(keep), ..
+            }) => {
+                let keep = keep.min(data.len());
+                self.inner.append(&data[..keep])?;
+                self.inner.flush()?;
+                (keep, true)
+            }
+            Some(_) => return Err(injected_error(FileOp::Append, &path)),
+            None => {
+                self.inner.append(data)?;
+                (data.len(), false)
+            }
+        };
+        if let Some(file) = state.files.get_mut(&self.id) {
+            file.written += written as u64;
+        }
+        match torn {
+            true => Err(injected_error(FileOp::Append, &path)),
+            false => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match check_handle(&self.state, self.id, self.epoch, FileOp::Flush)? {
+            (_, path, Some(_)) => Err(injected_error(FileOp::Flush, &path)),
+            (_state, _, None) => self.inner.flush(),
+        }
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        match check_handle(&self.state, self.id, self.epoch, FileOp::Sync)? {
+            (_, path, Some(_)) => Err(injected_error(FileOp::Sync, &path)),
+            (mut state, _, None) => {
+                self.inner.sync()?;
+                if let Some(file) = state.files.get_mut(&self.id) {
+                    file.synced = file.written;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+struct FaultSequentialFile {
+    inner: Box<dyn SequentialFile>,
+    path: PathBuf,
+    epoch: u64,
+    state: Arc<Mutex<State>>,
+}
+
+impl Read for FaultSequentialFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.epoch != self.epoch {
+            return Err(stale_handle(&self.path));
+        }
+        if state.check(FileOp::Read, &self.path).is_some() {
+            return Err(injected_error(FileOp::Read, &self.path));
+        }
+        drop(state);
+        self.inner.read(buf)
+    }
+}
+
+struct FaultRandomAccessFile {
+    inner: Arc<dyn RandomAccessFile>,
+    path: PathBuf,
+    epoch: u64,
+    state: Arc<Mutex<State>>,
+}
+
+impl FaultRandomAccessFile {
+    fn check_epoch(&self, state: &State) -> io::Result<()> {
+        match state.epoch == self.epoch {
+            true => Ok(()),
+            false => Err(stale_handle(&self.path)),
+        }
+    }
+}
+
+impl RandomAccessFile for FaultRandomAccessFile {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        self.check_epoch(&state)?;
+        if state.check(FileOp::Read, &self.path).is_some() {
+            return Err(injected_error(FileOp::Read, &self.path));
+        }
+        drop(state);
+        self.inner.read_at(offset, buf)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        self.check_epoch(&self.state.lock().unwrap())?;
+        self.inner.size()
+    }
+}
+
+impl FileSystem for FaultInjectionFileSystem {
+    fn create_new(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        self.check(FileOp::Create, path)?;
+        let file = self.base.create_new(path)?;
+        Ok(self.track(path, file))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        self.check(FileOp::Create, path)?;
+        let file = self.base.create(path)?;
+        Ok(self.track(path, file))
+    }
+
+    fn open_sequential(&self, path: &Path) -> io::Result<Box<dyn SequentialFile>> {
+        self.check(FileOp::Open, path)?;
+        let inner = self.base.open_sequential(path)?;
+        Ok(Box::new(FaultSequentialFile {
+            inner,
+            path: path.to_path_buf(),
+            epoch: self.state.lock().unwrap().epoch,
+            state: Arc::clone(&self.state),
+        }))
+    }
+
+    fn open_random_access(&self, path: &Path) -> io::Result<Arc<dyn RandomAccessFile>> {
+        self.check(FileOp::Open, path)?;
+        let inner = self.base.open_random_access(path)?;
+        Ok(Arc::new(FaultRandomAccessFile {
+            inner,
+            path: path.to_path_buf(),
+            epoch: self.state.lock().unwrap().epoch,
+            state: Arc::clone(&self.state),
+        }))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.base.exists(path)
+    }
+
+    fn file_size(&self, path: &Path) -> io::Result<u64> {
+        self.base.file_size(path)
+    }
+
+    fn list_dir(&self, dir: &Path) -> io::Result<Vec<String>> {
+        self.check(FileOp::ListDir, dir)?;
+        self.base.list_dir(dir)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.check(FileOp::Remove, path)?;
+        self.base.remove_file(path)?;
+        self.state.lock().unwrap().files.retain(|_, file| file.path != path);
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.check(FileOp::Rename, from)?;
+        self.base.rename(from, to)?;
+        let mut state = self.state.lock().unwrap();
+        state.files.retain(|_, file| file.path != to);
+        if let Some(file) = state.tracked_by_path(from) {
+            file.path = to.to_path_buf();
+        }
+        Ok(())
+    }
+
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()> {
+        self.base.create_dir_all(dir)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        self.check(FileOp::SyncDir, dir)?;
+        self.base.sync_dir(dir)
+    }
+}
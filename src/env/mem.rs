@@ -0,0 +1,229 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use super::{FileSystem, RandomAccessFile, SequentialFile, WritableFile};
+
+type FileData = Arc<RwLock<Vec<u8>>>;
+
+#[derive(Default)]
+struct State {
+    files: HashMap<PathBuf, FileData>,
+    dirs: BTreeSet<PathBuf>,
+}
+
+/// In-memory `FileSystem` for tests
+///
+/// Like the OS page cache, written bytes are visible to readers immediately; there is no notion of
+/// durability here, `FaultInjectionFileSystem` adds it on top. An open file keeps its data when it
+/// is removed or renamed, as an open descriptor would.
+#[derive(Clone, Default)]
+pub struct MemFileSystem {
+    state: Arc<Mutex<State>>,
+}
+
+impl MemFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A deep copy of every file and directory, independent from `self` from now on
+    pub fn snapshot(&self) -> MemFileSystem {
+        let state = self.state.lock().unwrap();
+        let files = state
+            .files
+            .iter()
+            .map(|(path, data)| (path.clone(), Arc::new(RwLock::new(data.read().unwrap().clone()))))
+            .collect();
+        MemFileSystem {
+            state: Arc::new(Mutex::new(State {
+                files,
+                dirs: state.dirs.clone(),
+            })),
+        }
+    }
+
+    /// Paths of every file, sorted
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self.state.lock().unwrap().files.keys().cloned().collect();
+        files.sort();
+        files
+    }
+
+    /// Replaces the content of an existing file, used to simulate lost or corrupted data
+    pub fn set_contents(&self, path: &Path, data: Vec<u8>) -> io::Result<()> {
+        let file = self.file(path)?;
+        *file.write().unwrap() = data;
+        Ok(())
+    }
+
+    fn file(&self, path: &Path) -> io::Result<FileData> {
+        self.state
+            .lock()
+            .unwrap()
+            .files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn insert(&self, path: &Path, fail_if_exists: bool) -> io::Result<FileData> {
+        let mut state = self.state.lock().unwrap();
+        check_parent(&state, path)?;
+        if fail_if_exists && state.files.contains_key(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            ));
+        }
+        let data = FileData::default();
+        state.files.insert(path.to_path_buf(), Arc::clone(&data));
+        Ok(data)
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path.display()))
+}
+
+fn check_parent(state: &State, path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() && !state.dirs.contains(parent) => Err(not_found(parent)),
+        _ => Ok(()),
+    }
+}
+
+struct MemWritableFile {
+    data: FileData,
+}
+
+impl WritableFile for MemWritableFile {
+    fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        self.data.write().unwrap().extend_from_slice(data);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct MemSequentialFile {
+    data: FileData,
+    pos: usize,
+}
+
+impl Read for MemSequentialFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.data.read().unwrap();
+        let available = data.len().saturating_sub(self.pos);
+        let read = available.min(buf.len());
+        buf[..read].copy_from_slice(&data[self.pos..self.pos + read]);
+        self.pos += read;
+        Ok(read)
+    }
+}
+
+struct MemRandomAccessFile {
+    data: FileData,
+}
+
+impl RandomAccessFile for MemRandomAccessFile {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.data.read().unwrap();
+        let start = (offset as usize).min(data.len());
+        let read = (data.len() - start).min(buf.len());
+        buf[..read].copy_from_slice(&data[start..start + read]);
+        Ok(read)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.data.read().unwrap().len() as u64)
+    }
+}
+
+impl FileSystem for MemFileSystem {
+    fn create_new(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        Ok(Box::new(MemWritableFile {
+            data: self.insert(path, true)?,
+        }))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        Ok(Box::new(MemWritableFile {
+            data: self.insert(path, false)?,
+        }))
+    }
+
+    fn open_sequential(&self, path: &Path) -> io::Result<Box<dyn SequentialFile>> {
+        Ok(Box::new(MemSequentialFile {
+            data: self.file(path)?,
+            pos: 0,
+        }))
+    }
+
+    fn open_random_access(&self, path: &Path) -> io::Result<Arc<dyn RandomAccessFile>> {
+        Ok(Arc::new(MemRandomAccessFile { data: self.file(path)? }))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        let state = self.state.lock().unwrap();
+        state.files.contains_key(path) || state.dirs.contains(path)
+    }
+
+    fn file_size(&self, path: &Path) -> io::Result<u64> {
+        Ok(self.file(path)?.read().unwrap().len() as u64)
+    }
+
+    fn list_dir(&self, dir: &Path) -> io::Result<Vec<String>> {
+        let state = self.state.lock().unwrap();
+        if !state.dirs.contains(dir) {
+            return Err(not_found(dir));
+        }
+        let files = state.files.keys();
+        let dirs = state.dirs.iter();
+        Ok(files
+            .chain(dirs)
+            .filter(|path| path.parent() == Some(dir))
+            .filter_map(|path| path.file_name()?.to_str().map(str::to_string))
+            .collect())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .files
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        check_parent(&state, to)?;
+        let data = state.files.remove(from).ok_or_else(|| not_found(from))?;
+        state.files.insert(to.to_path_buf(), data);
+        Ok(())
+    }
+
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        for ancestor in dir.ancestors().filter(|a| !a.as_os_str().is_empty()) {
+            state.dirs.insert(ancestor.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        match self.state.lock().unwrap().dirs.contains(dir) {
+            true => Ok(()),
+            false => Err(not_found(dir)),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Filesystem abstraction every file access of the store goes through
+//!
+//! Production code uses `PosixFileSystem`, tests swap in `MemFileSystem` and wrap it in a
+//! `FaultInjectionFileSystem` to simulate power losses, I/O errors and torn writes deterministically.
+
+pub mod fault;
+pub mod mem;
+pub mod posix;
+
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+pub use fault::{FaultInjectionFileSystem, FileOp};
+pub use mem::MemFileSystem;
+pub use posix::PosixFileSystem;
+
+/// A file opened for appending, like the OS the data reaches stable storage only after `sync`
+pub trait WritableFile: Send {
+    fn append(&mut self, data: &[u8]) -> io::Result<()>;
+
+    /// Hands buffered data to the filesystem, readers see it but a power loss can still drop it
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Makes every appended byte durable
+    fn sync(&mut self) -> io::Result<()>;
+}
+
+/// A file read front to back, reading again after EOF returns what has been appended since
+pub trait SequentialFile: Read + Send {}
+
+impl<T: Read + Send> SequentialFile for T {}
+
+/// A file read at arbitrary offsets, shared between threads
+pub trait RandomAccessFile: Send + Sync {
+    /// Reads up to `buf.len()` bytes at `offset`, returns 0 at EOF
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
+
+    fn size(&self) -> io::Result<u64>;
+
+    fn read_exact_at(&self, mut offset: u64, mut buf: &mut [u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(offset, buf) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "read past end of file")),
+                Ok(read) => {
+                    offset += read as u64;
+                    buf = &mut buf[read..];
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+pub trait FileSystem: Send + Sync {
+    /// Creates a new file, failing with `AlreadyExists` if it is there
+    fn create_new(&self, path: &Path) -> io::Result<Box<dyn WritableFile>>;
+
+    /// Creates a file, truncating it if it exists
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>>;
+
+    fn open_sequential(&self, path: &Path) -> io::Result<Box<dyn SequentialFile>>;
+
+    fn open_random_access(&self, path: &Path) -> io::Result<Arc<dyn RandomAccessFile>>;
+
+    /// Maps the whole file read-only, `None` when the filesystem cannot map files and they are read
+    /// through `open_sequential` instead. The file must not be truncated while the bytes are alive.
+    fn map(&self, _path: &Path) -> io::Result<Option<Bytes>> {
+        Ok(None)
+    }
+
+    fn exists(&self, path: &Path) -> bool;
+
+    fn file_size(&self, path: &Path) -> io::Result<u64>;
+
+    /// Names of the entries of `dir`, in no particular order
+    fn list_dir(&self, dir: &Path) -> io::Result<Vec<String>>;
+
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Atomically replaces `to` with `from`
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()>;
+
+    /// Makes the creations, renames and removals inside `dir` durable
+    fn sync_dir(&self, dir: &Path) -> io::Result<()>;
+}
+
+/// The filesystem used when none is given
+pub fn default_fs() -> Arc<dyn FileSystem> {
+    Arc::new(PosixFileSystem)
+}
+
+/// Reads a whole file through `fs`
+pub fn read_file(fs: &dyn FileSystem, path: &Path) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    fs.open_sequential(path)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Writes a whole file through `fs`, replacing any previous content, and syncs it
+pub fn write_file(fs: &dyn FileSystem, path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = fs.create(path)?;
+    file.append(data)?;
+    file.sync()
+}
@@ -1 +1,6 @@
+pub mod cache;
+pub mod db;
+pub mod env;
+pub mod memtable;
+pub mod table;
 pub mod wal;
@@ -0,0 +1,87 @@
+/// Size of the blocks the arena carves allocations from
+pub const ARENA_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Location of an allocation: `block index << 32 | offset in the block`
+pub type ArenaAddr = u64;
+
+/// Bump allocator for the memtable
+///
+/// Allocations are carved from fixed-size blocks and never freed individually, the whole arena
+/// goes away with the memtable. An allocation larger than a quarter of a block gets a block of
+/// its own so that the remainder of the current block is not wasted.
+pub struct Arena {
+    blocks: Vec<Box<[u8]>>,
+    /// Free bytes left at the end of the last regular block
+    remaining: usize,
+    /// Index of the block regular allocations are carved from
+    current: usize,
+    memory_usage: usize,
+}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Arena {
+            blocks: Vec::new(),
+            remaining: 0,
+            current: 0,
+            memory_usage: 0,
+        }
+    }
+
+    /// Allocates `len` zeroed bytes
+    pub fn allocate(&mut self, len: usize) -> ArenaAddr {
+        assert!(len <= u32::MAX as usize, "arena allocation of {len} bytes");
+        if len > ARENA_BLOCK_SIZE / 4 {
+            return addr(self.new_block(len), 0);
+        }
+        if len > self.remaining {
+            self.current = self.new_block(ARENA_BLOCK_SIZE);
+            self.remaining = ARENA_BLOCK_SIZE;
+        }
+        let offset = ARENA_BLOCK_SIZE - self.remaining;
+        self.remaining -= len;
+        addr(self.current, offset)
+    }
+
+    /// Copies `data` into a new allocation
+    pub fn allocate_copy(&mut self, data: &[u8]) -> ArenaAddr {
+        let at = self.allocate(data.len());
+        self.bytes_mut(at, data.len()).copy_from_slice(data);
+        at
+    }
+
+    pub fn bytes(&self, at: ArenaAddr, len: usize) -> &[u8] {
+        let (block, offset) = split(at);
+        &self.blocks[block][offset..offset + len]
+    }
+
+    pub fn bytes_mut(&mut self, at: ArenaAddr, len: usize) -> &mut [u8] {
+        let (block, offset) = split(at);
+        &mut self.blocks[block][offset..offset + len]
+    }
+
+    /// Bytes reserved from the allocator, allocated or not
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage + self.blocks.capacity() * size_of::<Box<[u8]>>()
+    }
+
+    fn new_block(&mut self, len: usize) -> usize {
+        self.blocks.push(vec![0u8; len].into_boxed_slice());
+        self.memory_usage += len;
+        self.blocks.len() - 1
+    }
+}
+
+fn addr(block: usize, offset: usize) -> ArenaAddr {
+    ((block as u64) << 32) | offset as u64
+}
+
+fn split(at: ArenaAddr) -> (usize, usize) {
+    ((at >> 32) as usize, (at & u32::MAX as u64) as usize)
+}
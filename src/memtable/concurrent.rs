@@ -0,0 +1,448 @@
+use std::alloc::{self, Layout};
+use std::cmp::Ordering as KeyOrdering;
+use std::io;
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+use crate::wal::{RecordType, WalRecord};
+
+use super::MemtableEntry;
+use super::arena::ARENA_BLOCK_SIZE;
+use super::skiplist::MAX_HEIGHT;
+
+const BRANCHING: u64 = 4;
+const ALIGN: usize = 8;
+
+struct Block {
+    data: *mut u8,
+    len: usize,
+    /// Bump pointer, may go past `len` when allocations race at the end of the block
+    used: AtomicUsize,
+}
+
+impl Block {
+    fn new(len: usize) -> Box<Block> {
+        let layout = Layout::from_size_align(len, ALIGN).unwrap();
+        // SAFETY: `len` is never zero, the smallest allocation is a node header
+        let data = unsafe { alloc::alloc_zeroed(layout) };
+        if data.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        Box::new(Block {
+            data,
+            len,
+            used: AtomicUsize::new(0),
+        })
+    }
+}
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        // SAFETY: allocated in `Block::new` with the same layout
+        unsafe { alloc::dealloc(self.data, Layout::from_size_align(self.len, ALIGN).unwrap()) };
+    }
+}
+
+/// Bump allocator shared by concurrent writers
+///
+/// Allocating is a `fetch_add` on the bump pointer of the current block, only the writer that
+/// finds the block full takes a lock to install the next one. Allocations are 8-byte aligned.
+pub struct ConcurrentArena {
+    current: AtomicPtr<Block>,
+    /// Owns every block, the memory is released when the arena is dropped. Boxed because `current`
+    /// points to a block
+    #[allow(clippy::vec_box)]
+    blocks: Mutex<Vec<Box<Block>>>,
+    memory_usage: AtomicUsize,
+}
+
+// SAFETY: blocks are only written through the disjoint ranges handed out by `allocate`
+unsafe impl Send for ConcurrentArena {}
+unsafe impl Sync for ConcurrentArena {}
+
+impl Default for ConcurrentArena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConcurrentArena {
+    pub fn new() -> Self {
+        ConcurrentArena {
+            current: AtomicPtr::new(ptr::null_mut()),
+            blocks: Mutex::new(Vec::new()),
+            memory_usage: AtomicUsize::new(0),
+        }
+    }
+
+    /// Allocates `len` zeroed bytes, valid as long as the arena
+    pub fn allocate(&self, len: usize) -> *mut u8 {
+        let len = len.next_multiple_of(ALIGN);
+        if len > ARENA_BLOCK_SIZE / 4 {
+            let block = Block::new(len);
+            let data = block.data;
+            self.install(block);
+            return data;
+        }
+        loop {
+            let current = self.current.load(Ordering::Acquire);
+            if !current.is_null() {
+                // SAFETY: blocks live as long as the arena
+                let block = unsafe { &*current };
+                let offset = block.used.fetch_add(len, Ordering::Relaxed);
+                if offset + len <= block.len {
+                    // SAFETY: the range is inside the block and was handed out to this call only
+                    return unsafe { block.data.add(offset) };
+                }
+            }
+            let mut blocks = self.blocks.lock().unwrap();
+            // another writer may have installed a fresh block while we waited for the lock
+            if self.current.load(Ordering::Acquire) == current {
+                let block = Block::new(ARENA_BLOCK_SIZE);
+                self.memory_usage.fetch_add(ARENA_BLOCK_SIZE, Ordering::Relaxed);
+                self.current.store(&*block as *const Block as *mut Block, Ordering::Release);
+                blocks.push(block);
+            }
+        }
+    }
+
+    /// Bytes reserved from the allocator, allocated or not
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+
+    fn install(&self, block: Box<Block>) {
+        self.memory_usage.fetch_add(block.len, Ordering::Relaxed);
+        self.blocks.lock().unwrap().push(block);
+    }
+}
+
+/// Fixed part of a node, followed in the arena by `height` next pointers, the key and the value
+#[repr(C)]
+struct Node {
+    sequence: u64,
+    key_len: u32,
+    value_len: u32,
+    record_type: RecordType,
+    height: u8,
+}
+
+impl Node {
+    fn tower(&self) -> &[AtomicPtr<Node>] {
+        // SAFETY: `allocate_node` placed `height` pointers right after the node
+        unsafe {
+            let first = (self as *const Node).add(1) as *const AtomicPtr<Node>;
+            std::slice::from_raw_parts(first, self.height as usize)
+        }
+    }
+
+    fn next(&self, level: usize) -> *mut Node {
+        self.tower()[level].load(Ordering::Acquire)
+    }
+
+    fn entry(&self) -> MemtableEntry<'_> {
+        let tower = self.tower();
+        // SAFETY: the key and value were copied right after the tower before the node was published
+        let data = unsafe {
+            let start = tower.as_ptr().add(tower.len()) as *const u8;
+            std::slice::from_raw_parts(start, self.key_len as usize + self.value_len as usize)
+        };
+        let (key, value) = data.split_at(self.key_len as usize);
+        MemtableEntry {
+            key,
+            sequence: self.sequence,
+            record_type: self.record_type,
+            value,
+        }
+    }
+
+    /// Orders this node against `(key, sequence)`, by key then newest first
+    fn compare(&self, key: &[u8], sequence: u64) -> KeyOrdering {
+        self.entry()
+            .key
+            .cmp(key)
+            .then_with(|| sequence.cmp(&self.sequence))
+    }
+}
+
+/// Skiplist that many writers insert into without a lock
+///
+/// Same order as `SkipList`. A node is linked level by level with a CAS on the next pointer of its
+/// predecessor, from the bottom up: once the level 0 CAS succeeds the entry is in the list, the
+/// upper levels only make it faster to find. Nodes are never unlinked, so a reader following next
+/// pointers never sees freed memory and never waits for a writer.
+pub struct ConcurrentSkipList {
+    arena: ConcurrentArena,
+    head: *mut Node,
+    height: AtomicUsize,
+    len: AtomicUsize,
+    rng: AtomicU64,
+}
+
+// SAFETY: nodes are immutable once published except for their next pointers, which are atomics
+unsafe impl Send for ConcurrentSkipList {}
+unsafe impl Sync for ConcurrentSkipList {}
+
+impl Default for ConcurrentSkipList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConcurrentSkipList {
+    pub fn new() -> Self {
+        let arena = ConcurrentArena::new();
+        let head = allocate_node(&arena, MAX_HEIGHT, b"", 0, RecordType::Put, b"");
+        ConcurrentSkipList {
+            arena,
+            head,
+            height: AtomicUsize::new(1),
+            len: AtomicUsize::new(0),
+            rng: AtomicU64::new(0),
+        }
+    }
+
+    /// Inserts an entry, returns false when the same key and sequence is already present
+    pub fn insert(&self, key: &[u8], sequence: u64, record_type: RecordType, value: &[u8]) -> bool {
+        let height = self.random_height();
+        self.height.fetch_max(height, Ordering::Relaxed);
+
+        let mut prev = [self.head; MAX_HEIGHT];
+        let mut next = [ptr::null_mut(); MAX_HEIGHT];
+        let mut node = self.head;
+        for level in (0..self.height.load(Ordering::Relaxed)).rev() {
+            (prev[level], next[level]) = self.find_splice(key, sequence, node, level);
+            node = prev[level];
+        }
+        if self.is_equal(next[0], key, sequence) {
+            return false;
+        }
+
+        let new = allocate_node(&self.arena, height, key, sequence, record_type, value);
+        // SAFETY: `new` was just allocated and is not shared yet
+        let tower = unsafe { (*new).tower() };
+        for level in 0..height {
+            loop {
+                tower[level].store(next[level], Ordering::Relaxed);
+                // SAFETY: nodes live as long as the arena
+                let prev_tower = unsafe { (*prev[level]).tower() };
+                match prev_tower[level].compare_exchange(next[level], new, Ordering::Release, Ordering::Acquire) {
+                    Ok(_) => break,
+                    Err(_) => {
+                        // another writer linked a node here, search again from our predecessor
+                        (prev[level], next[level]) = self.find_splice(key, sequence, prev[level], level);
+                        if level == 0 && self.is_equal(next[0], key, sequence) {
+                            // the node stays allocated but unreachable, like any arena garbage
+                            return false;
+                        }
+                    }
+                }
+            }
+        }
+        self.len.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// First entry at or after `(key, sequence)`
+    pub fn seek(&self, key: &[u8], sequence: u64) -> ConcurrentIter<'_> {
+        let mut node = self.head;
+        let mut next = ptr::null_mut();
+        for level in (0..self.height.load(Ordering::Relaxed)).rev() {
+            (node, next) = self.find_splice(key, sequence, node, level);
+        }
+        ConcurrentIter {
+            node: next,
+            list: PhantomData,
+        }
+    }
+
+    pub fn iter(&self) -> ConcurrentIter<'_> {
+        // SAFETY: the head lives as long as the list
+        let first = unsafe { (*self.head).next(0) };
+        ConcurrentIter {
+            node: first,
+            list: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.arena.memory_usage()
+    }
+
+    /// Nodes `(prev, next)` at `level` such that `prev < (key, sequence) <= next`, starting from `start`
+    fn find_splice(&self, key: &[u8], sequence: u64, start: *mut Node, level: usize) -> (*mut Node, *mut Node) {
+        let mut prev = start;
+        loop {
+            // SAFETY: nodes live as long as the arena
+            let next = unsafe { (*prev).next(level) };
+            if next.is_null() || unsafe { (*next).compare(key, sequence) } != KeyOrdering::Less {
+                return (prev, next);
+            }
+            prev = next;
+        }
+    }
+
+    fn is_equal(&self, node: *mut Node, key: &[u8], sequence: u64) -> bool {
+        // SAFETY: nodes live as long as the arena
+        !node.is_null() && unsafe { (*node).compare(key, sequence) } == KeyOrdering::Equal
+    }
+
+    fn random_height(&self) -> usize {
+        // splitmix64 over a shared counter, each call gets its own value without a lock
+        let mut z = self
+            .rng
+            .fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed)
+            .wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        let mut height = 1;
+        while height < MAX_HEIGHT && z.is_multiple_of(BRANCHING) {
+            height += 1;
+            z /= BRANCHING;
+        }
+        height
+    }
+}
+
+fn allocate_node(
+    arena: &ConcurrentArena,
+    height: usize,
+    key: &[u8],
+    sequence: u64,
+    record_type: RecordType,
+    value: &[u8],
+) -> *mut Node {
+    assert!(
+        key.len() <= u32::MAX as usize && value.len() <= u32::MAX as usize,
+        "key or value larger than 4GB"
+    );
+    let tower_len = height * size_of::<AtomicPtr<Node>>();
+    let len = size_of::<Node>() + tower_len + key.len() + value.len();
+    let node = arena.allocate(len) as *mut Node;
+    // SAFETY: the allocation is large enough and 8-byte aligned, the zeroed tower is a tower of
+    // null pointers
+    unsafe {
+        node.write(Node {
+            sequence,
+            key_len: key.len() as u32,
+            value_len: value.len() as u32,
+            record_type,
+            height: height as u8,
+        });
+        let data = (node as *mut u8).add(size_of::<Node>() + tower_len);
+        ptr::copy_nonoverlapping(key.as_ptr(), data, key.len());
+        ptr::copy_nonoverlapping(value.as_ptr(), data.add(key.len()), value.len());
+    }
+    node
+}
+
+/// Entries in order, it sees the entries inserted after its creation that land ahead of it
+#[derive(Clone)]
+pub struct ConcurrentIter<'a> {
+    node: *mut Node,
+    list: PhantomData<&'a ConcurrentSkipList>,
+}
+
+impl<'a> Iterator for ConcurrentIter<'a> {
+    type Item = MemtableEntry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.node.is_null() {
+            return None;
+        }
+        // SAFETY: nodes live as long as the arena, which `list` borrows
+        let node: &'a Node = unsafe { &*self.node };
+        self.node = node.next(0);
+        Some(node.entry())
+    }
+}
+
+/// `Memtable` that many threads write to at the same time
+///
+/// Writers insert in parallel without a global lock, readers never block; this is the memtable of
+/// the multi-core ingest path fed by `ConcurrentWal`.
+#[derive(Default)]
+pub struct ConcurrentMemtable {
+    list: ConcurrentSkipList,
+    /// Highest sequence plus one, 0 while empty
+    max_sequence: AtomicU64,
+}
+
+impl ConcurrentMemtable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a version of `key`, returns false when this key already has a version with `sequence`
+    pub fn add(&self, sequence: u64, record_type: RecordType, key: &[u8], value: &[u8]) -> bool {
+        let value = match record_type {
+            RecordType::Put => value,
+            RecordType::Delete => &[],
+        };
+        let inserted = self.list.insert(key, sequence, record_type, value);
+        if inserted {
+            self.max_sequence.fetch_max(sequence.saturating_add(1), Ordering::Relaxed);
+        }
+        inserted
+    }
+
+    pub fn put(&self, sequence: u64, key: &[u8], value: &[u8]) -> bool {
+        self.add(sequence, RecordType::Put, key, value)
+    }
+
+    pub fn delete(&self, sequence: u64, key: &[u8]) -> bool {
+        self.add(sequence, RecordType::Delete, key, &[])
+    }
+
+    /// Newest version of `key` visible at `sequence`, a `Delete` entry means the key is deleted
+    pub fn get(&self, key: &[u8], sequence: u64) -> Option<MemtableEntry<'_>> {
+        self.list.seek(key, sequence).next().filter(|entry| entry.key == key)
+    }
+
+    pub fn iter(&self) -> ConcurrentIter<'_> {
+        self.list.iter()
+    }
+
+    pub fn iter_from(&self, key: &[u8]) -> ConcurrentIter<'_> {
+        self.list.seek(key, u64::MAX)
+    }
+
+    /// Adds the records read from a WAL segment, returns how many were added
+    pub fn replay(&self, records: impl IntoIterator<Item = io::Result<WalRecord>>) -> io::Result<u64> {
+        let mut added = 0;
+        for record in records {
+            let record = record?;
+            if self.add(record.sequence, record.record_type, &record.key, &record.value) {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn max_sequence(&self) -> Option<u64> {
+        self.max_sequence.load(Ordering::Relaxed).checked_sub(1)
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.list.memory_usage()
+    }
+}
@@ -0,0 +1,149 @@
+use std::cmp::Ordering;
+
+use crate::wal::RecordType;
+
+use super::MemtableEntry;
+use super::arena::{Arena, ArenaAddr};
+use super::rep::{MemtableIter, MemtableRep, compare_entries};
+
+const NULL: ArenaAddr = u64::MAX;
+
+/// `[Next(8B) | Sequence(8B) | Type(1B) | Key_Size(4B) | Value_Size(4B)]`, followed by the key
+/// and the value
+const NODE_HEADER_SIZE: usize = 25;
+
+/// Memtable representation hashing keys by prefix into buckets of sorted linked lists
+///
+/// Keys sharing their first `prefix_len` bytes (the whole key when shorter) land in the same
+/// bucket, so a point lookup only walks the versions of keys with that prefix. Buckets are
+/// ordered but there is no order across them: an iteration collects and sorts every entry, which
+/// is done once for the flush. Fits workloads of point lookups on keys grouped by prefix, with
+/// few keys per prefix.
+pub struct HashLinkListRep {
+    arena: Arena,
+    /// Head node of every bucket
+    buckets: Vec<ArenaAddr>,
+    prefix_len: usize,
+    len: usize,
+}
+
+impl HashLinkListRep {
+    pub fn new(bucket_count: usize, prefix_len: usize) -> Self {
+        HashLinkListRep {
+            arena: Arena::new(),
+            buckets: vec![NULL; bucket_count.max(1)],
+            prefix_len,
+            len: 0,
+        }
+    }
+
+    fn bucket(&self, key: &[u8]) -> usize {
+        let prefix = &key[..key.len().min(self.prefix_len)];
+        // FNV-1a
+        let hash = prefix
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325u64, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3));
+        (hash % self.buckets.len() as u64) as usize
+    }
+
+    /// First node of the bucket of `key` at or after `(key, sequence)`, with its predecessor
+    fn find(&self, key: &[u8], sequence: u64) -> (Option<ArenaAddr>, ArenaAddr) {
+        let mut prev = None;
+        let mut node = self.buckets[self.bucket(key)];
+        while node != NULL {
+            let entry = self.entry(node);
+            if entry.key.cmp(key).then_with(|| sequence.cmp(&entry.sequence)) != Ordering::Less {
+                break;
+            }
+            prev = Some(node);
+            node = self.next(node);
+        }
+        (prev, node)
+    }
+
+    fn entry(&self, node: ArenaAddr) -> MemtableEntry<'_> {
+        let header = self.arena.bytes(node, NODE_HEADER_SIZE);
+        let sequence = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        let record_type = RecordType::from_byte(header[16]).expect("memtable node with an unknown type");
+        let key_len = u32::from_le_bytes(header[17..21].try_into().unwrap()) as usize;
+        let value_len = u32::from_le_bytes(header[21..25].try_into().unwrap()) as usize;
+        let data = self.arena.bytes(node + NODE_HEADER_SIZE as u64, key_len + value_len);
+        let (key, value) = data.split_at(key_len);
+        MemtableEntry {
+            key,
+            sequence,
+            record_type,
+            value,
+        }
+    }
+
+    fn next(&self, node: ArenaAddr) -> ArenaAddr {
+        u64::from_le_bytes(self.arena.bytes(node, 8).try_into().unwrap())
+    }
+
+    fn set_next(&mut self, node: ArenaAddr, next: ArenaAddr) {
+        self.arena.bytes_mut(node, 8).copy_from_slice(&next.to_le_bytes());
+    }
+}
+
+impl MemtableRep for HashLinkListRep {
+    fn insert(&mut self, key: &[u8], sequence: u64, record_type: RecordType, value: &[u8]) -> bool {
+        let (prev, found) = self.find(key, sequence);
+        if found != NULL {
+            let entry = self.entry(found);
+            if entry.key == key && entry.sequence == sequence {
+                return false;
+            }
+        }
+
+        let len = NODE_HEADER_SIZE + key.len() + value.len();
+        let node = self.arena.allocate(len);
+        let bytes = self.arena.bytes_mut(node, len);
+        bytes[0..8].copy_from_slice(&found.to_le_bytes());
+        bytes[8..16].copy_from_slice(&sequence.to_le_bytes());
+        bytes[16] = record_type as u8;
+        bytes[17..21].copy_from_slice(&(key.len() as u32).to_le_bytes());
+        bytes[21..25].copy_from_slice(&(value.len() as u32).to_le_bytes());
+        bytes[NODE_HEADER_SIZE..NODE_HEADER_SIZE + key.len()].copy_from_slice(key);
+        bytes[NODE_HEADER_SIZE + key.len()..].copy_from_slice(value);
+        match prev {
+            Some(prev) => self.set_next(prev, node),
+            None => {
+                let bucket = self.bucket(key);
+                self.buckets[bucket] = node;
+            }
+        }
+        self.len += 1;
+        true
+    }
+
+    fn seek(&self, key: &[u8], sequence: u64) -> MemtableIter<'_> {
+        let mut entries = Vec::with_capacity(self.len);
+        for &head in &self.buckets {
+            let mut node = head;
+            while node != NULL {
+                entries.push(self.entry(node));
+                node = self.next(node);
+            }
+        }
+        entries.sort_unstable_by(compare_entries);
+        let start = entries
+            .partition_point(|entry| entry.key.cmp(key).then_with(|| sequence.cmp(&entry.sequence)) == Ordering::Less);
+        Box::new(entries.into_iter().skip(start))
+    }
+
+    fn get(&self, key: &[u8], sequence: u64) -> Option<MemtableEntry<'_>> {
+        let (_, node) = self.find(key, sequence);
+        (node != NULL)
+            .then(|| self.entry(node))
+            .filter(|entry| entry.key == key)
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.arena.memory_usage() + self.buckets.capacity() * size_of::<ArenaAddr>()
+    }
+}
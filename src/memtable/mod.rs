@@ -0,0 +1,137 @@
+pub mod arena;
+pub mod concurrent;
+pub mod hash_linklist;
+pub mod rep;
+pub mod skiplist;
+pub mod vector;
+
+use std::io;
+
+use crate::wal::{RecordType, WalRecord};
+
+pub use arena::Arena;
+pub use concurrent::{ConcurrentArena, ConcurrentMemtable, ConcurrentSkipList};
+pub use hash_linklist::HashLinkListRep;
+pub use rep::{
+    HashLinkListRepFactory, MemtableIter, MemtableRep, MemtableRepFactory, SkipListRepFactory, VectorRepFactory,
+};
+pub use skiplist::SkipList;
+pub use vector::VectorRep;
+
+/// A version of a key, borrowed from the memtable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemtableEntry<'a> {
+    pub key: &'a [u8],
+    pub sequence: u64,
+    pub record_type: RecordType,
+    /// Empty for a delete
+    pub value: &'a [u8],
+}
+
+/// In-memory buffer of the latest writes, the first place a read looks into
+///
+/// Every version of a key is kept with its sequence number, so that a read at an older sequence
+/// (a snapshot) still sees the value it expects. A delete is kept as a tombstone entry that hides
+/// the older versions of its key. The entries are held by a `MemtableRep`, a skiplist by default.
+pub struct Memtable {
+    rep: Box<dyn MemtableRep>,
+    max_sequence: Option<u64>,
+}
+
+impl Default for Memtable {
+    fn default() -> Self {
+        Self::with_rep(Box::new(SkipList::new()))
+    }
+}
+
+impl Memtable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rep(rep: Box<dyn MemtableRep>) -> Self {
+        Memtable {
+            rep,
+            max_sequence: None,
+        }
+    }
+
+    /// Adds a version of `key`, returns false when this key already has a version with `sequence`
+    ///
+    /// `VectorRep` does not look for duplicates on insert and always returns true, they are dropped
+    /// when it is sorted.
+    pub fn add(&mut self, sequence: u64, record_type: RecordType, key: &[u8], value: &[u8]) -> bool {
+        let value = match record_type {
+            RecordType::Put => value,
+            RecordType::Delete => &[],
+        };
+        let inserted = self.rep.insert(key, sequence, record_type, value);
+        if inserted {
+            self.max_sequence = self.max_sequence.max(Some(sequence));
+        }
+        inserted
+    }
+
+    pub fn put(&mut self, sequence: u64, key: &[u8], value: &[u8]) -> bool {
+        self.add(sequence, RecordType::Put, key, value)
+    }
+
+    pub fn delete(&mut self, sequence: u64, key: &[u8]) -> bool {
+        self.add(sequence, RecordType::Delete, key, &[])
+    }
+
+    /// Newest version of `key` visible at `sequence`, a `Delete` entry means the key is deleted
+    pub fn get(&self, key: &[u8], sequence: u64) -> Option<MemtableEntry<'_>> {
+        self.rep.get(key, sequence)
+    }
+
+    /// Every version of every key, by key then newest version first
+    pub fn iter(&self) -> MemtableIter<'_> {
+        self.rep.iter()
+    }
+
+    /// Like `iter`, starting at the first key not less than `key`
+    pub fn iter_from(&self, key: &[u8]) -> MemtableIter<'_> {
+        self.rep.seek(key, u64::MAX)
+    }
+
+    /// Adds the records read from a WAL segment, returns how many were added
+    ///
+    /// A record already present (same key and sequence) is skipped, so replaying a segment twice
+    /// is harmless. Stops at the first read error.
+    pub fn replay(&mut self, records: impl IntoIterator<Item = io::Result<WalRecord>>) -> io::Result<u64> {
+        let mut added = 0;
+        for record in records {
+            let record = record?;
+            if self.add(record.sequence, record.record_type, &record.key, &record.value) {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    /// Number of entries, every version counts
+    pub fn len(&self) -> usize {
+        self.rep.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rep.is_empty()
+    }
+
+    /// Highest sequence added so far
+    pub fn max_sequence(&self) -> Option<u64> {
+        self.max_sequence
+    }
+
+    /// Bytes held by the memtable's arena, compared with the write buffer size to decide a flush
+    pub fn memory_usage(&self) -> usize {
+        self.rep.memory_usage()
+    }
+
+    /// Called when the memtable is frozen, before it is flushed: lets the representation prepare
+    /// for the ordered iteration of the flush (a vector sorts its entries)
+    pub fn mark_read_only(&mut self) {
+        self.rep.mark_read_only();
+    }
+}
@@ -0,0 +1,118 @@
+use crate::wal::RecordType;
+
+use super::MemtableEntry;
+use super::hash_linklist::HashLinkListRep;
+use super::skiplist::SkipList;
+use super::vector::VectorRep;
+
+/// Entries of a representation in order: key ascending, then sequence descending
+pub type MemtableIter<'a> = Box<dyn Iterator<Item = MemtableEntry<'a>> + 'a>;
+
+/// Data structure holding the entries of a `Memtable`
+///
+/// Every representation returns the same entries in the same order, they differ in what they make
+/// cheap: the skiplist keeps the order on every insert, the vector only sorts once the memtable is
+/// read-only, and the hash buckets answer point lookups without a total order.
+pub trait MemtableRep: Send + Sync {
+    /// Inserts an entry, returns false when the same key and sequence is known to be present
+    fn insert(&mut self, key: &[u8], sequence: u64, record_type: RecordType, value: &[u8]) -> bool;
+
+    /// Entries from the first one at or after `(key, sequence)`
+    fn seek(&self, key: &[u8], sequence: u64) -> MemtableIter<'_>;
+
+    fn iter(&self) -> MemtableIter<'_> {
+        self.seek(b"", u64::MAX)
+    }
+
+    /// Newest version of `key` not newer than `sequence`
+    fn get(&self, key: &[u8], sequence: u64) -> Option<MemtableEntry<'_>> {
+        self.seek(key, sequence).next().filter(|entry| entry.key == key)
+    }
+
+    /// Number of entries, every version counts
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn memory_usage(&self) -> usize;
+
+    /// No entry is inserted after this call, the memtable is about to be flushed
+    fn mark_read_only(&mut self) {}
+}
+
+/// Creates the representation of every new memtable of a store
+pub trait MemtableRepFactory: Send + Sync {
+    fn create(&self) -> Box<dyn MemtableRep>;
+}
+
+impl MemtableRep for SkipList {
+    fn insert(&mut self, key: &[u8], sequence: u64, record_type: RecordType, value: &[u8]) -> bool {
+        SkipList::insert(self, key, sequence, record_type, value)
+    }
+
+    fn seek(&self, key: &[u8], sequence: u64) -> MemtableIter<'_> {
+        Box::new(SkipList::seek(self, key, sequence))
+    }
+
+    fn iter(&self) -> MemtableIter<'_> {
+        Box::new(SkipList::iter(self))
+    }
+
+    fn len(&self) -> usize {
+        SkipList::len(self)
+    }
+
+    fn memory_usage(&self) -> usize {
+        SkipList::memory_usage(self)
+    }
+}
+
+/// The default: ordered at all times, fit for any mix of reads and writes
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SkipListRepFactory;
+
+impl MemtableRepFactory for SkipListRepFactory {
+    fn create(&self) -> Box<dyn MemtableRep> {
+        Box::new(SkipList::new())
+    }
+}
+
+/// Append-only memtables for bulk loads, see `VectorRep`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VectorRepFactory;
+
+impl MemtableRepFactory for VectorRepFactory {
+    fn create(&self) -> Box<dyn MemtableRep> {
+        Box::new(VectorRep::new())
+    }
+}
+
+/// Hash-bucketed memtables for point lookups, see `HashLinkListRep`
+#[derive(Debug, Clone, Copy)]
+pub struct HashLinkListRepFactory {
+    pub bucket_count: usize,
+    /// Keys sharing their first `prefix_len` bytes land in the same bucket
+    pub prefix_len: usize,
+}
+
+impl Default for HashLinkListRepFactory {
+    fn default() -> Self {
+        HashLinkListRepFactory {
+            bucket_count: 50_000,
+            prefix_len: 8,
+        }
+    }
+}
+
+impl MemtableRepFactory for HashLinkListRepFactory {
+    fn create(&self) -> Box<dyn MemtableRep> {
+        Box::new(HashLinkListRep::new(self.bucket_count, self.prefix_len))
+    }
+}
+
+/// Orders `a` against `b`: key ascending, then sequence descending
+pub(super) fn compare_entries(a: &MemtableEntry<'_>, b: &MemtableEntry<'_>) -> std::cmp::Ordering {
+    a.key.cmp(b.key).then_with(|| b.sequence.cmp(&a.sequence))
+}
@@ -0,0 +1,216 @@
+use std::cmp::Ordering;
+
+use crate::wal::RecordType;
+
+use super::MemtableEntry;
+use super::arena::{Arena, ArenaAddr};
+
+/// Towers never get taller than this, enough for ~16M entries with a branching factor of 4
+pub const MAX_HEIGHT: usize = 12;
+const BRANCHING: u64 = 4;
+
+const NULL: ArenaAddr = u64::MAX;
+
+/// `[Sequence(8B) | Type(1B) | Height(1B) | Key_Size(4B) | Value_Size(4B)]`, followed by
+/// `Height` next pointers of 8 bytes, the key and the value
+const NODE_HEADER_SIZE: usize = 18;
+const NEXT_OFFSET: usize = NODE_HEADER_SIZE;
+
+/// Skiplist whose nodes live in an `Arena`
+///
+/// Entries are ordered by key ascending, then sequence descending, so that the first entry found
+/// for a key is its newest version. Entries are never removed, a delete is an entry of type `Delete`.
+pub struct SkipList {
+    arena: Arena,
+    head: ArenaAddr,
+    height: usize,
+    len: usize,
+    rng: u64,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SkipList {
+    pub fn new() -> Self {
+        let mut arena = Arena::new();
+        let head = allocate_node(&mut arena, MAX_HEIGHT, b"", 0, RecordType::Put, b"");
+        SkipList {
+            arena,
+            head,
+            height: 1,
+            len: 0,
+            rng: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    /// Inserts an entry, returns false when the same key and sequence is already present
+    pub fn insert(&mut self, key: &[u8], sequence: u64, record_type: RecordType, value: &[u8]) -> bool {
+        let mut prev = [self.head; MAX_HEIGHT];
+        let found = self.find_greater_or_equal(key, sequence, Some(&mut prev));
+        if found != NULL && self.compare(found, key, sequence) == Ordering::Equal {
+            return false;
+        }
+
+        let height = self.random_height();
+        // levels above the current height start from the head, `prev` already holds it
+        self.height = self.height.max(height);
+        let node = allocate_node(&mut self.arena, height, key, sequence, record_type, value);
+        for (level, &prev) in prev.iter().enumerate().take(height) {
+            let next = self.next(prev, level);
+            self.set_next(node, level, next);
+            self.set_next(prev, level, node);
+        }
+        self.len += 1;
+        true
+    }
+
+    /// First entry at or after `(key, sequence)`, i.e. the newest version of `key` not newer than
+    /// `sequence`, or the first entry of a greater key
+    pub fn seek(&self, key: &[u8], sequence: u64) -> Iter<'_> {
+        Iter {
+            list: self,
+            node: self.find_greater_or_equal(key, sequence, None),
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            list: self,
+            node: self.next(self.head, 0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.arena.memory_usage()
+    }
+
+    fn find_greater_or_equal(
+        &self,
+        key: &[u8],
+        sequence: u64,
+        mut prev: Option<&mut [ArenaAddr; MAX_HEIGHT]>,
+    ) -> ArenaAddr {
+        let mut node = self.head;
+        let mut level = self.height - 1;
+        loop {
+            let next = self.next(node, level);
+            if next != NULL && self.compare(next, key, sequence) == Ordering::Less {
+                node = next;
+                continue;
+            }
+            if let Some(prev) = prev.as_deref_mut() {
+                prev[level] = node;
+            }
+            if level == 0 {
+                return next;
+            }
+            level -= 1;
+        }
+    }
+
+    /// Orders the entry at `node` against `(key, sequence)`
+    fn compare(&self, node: ArenaAddr, key: &[u8], sequence: u64) -> Ordering {
+        let entry = self.entry(node);
+        entry.key.cmp(key).then_with(|| sequence.cmp(&entry.sequence))
+    }
+
+    fn entry(&self, node: ArenaAddr) -> MemtableEntry<'_> {
+        let header = self.arena.bytes(node, NODE_HEADER_SIZE);
+        let sequence = u64::from_le_bytes(header[0..8].try_into().unwrap());
+        let record_type = RecordType::from_byte(header[8]).expect("memtable node with an unknown type");
+        let height = header[9] as usize;
+        let key_len = u32::from_le_bytes(header[10..14].try_into().unwrap()) as usize;
+        let value_len = u32::from_le_bytes(header[14..18].try_into().unwrap()) as usize;
+        let data = self
+            .arena
+            .bytes(node + (NEXT_OFFSET + height * 8) as u64, key_len + value_len);
+        let (key, value) = data.split_at(key_len);
+        MemtableEntry {
+            key,
+            sequence,
+            record_type,
+            value,
+        }
+    }
+
+    fn next(&self, node: ArenaAddr, level: usize) -> ArenaAddr {
+        let at = node + (NEXT_OFFSET + level * 8) as u64;
+        u64::from_le_bytes(self.arena.bytes(at, 8).try_into().unwrap())
+    }
+
+    fn set_next(&mut self, node: ArenaAddr, level: usize, next: ArenaAddr) {
+        let at = node + (NEXT_OFFSET + level * 8) as u64;
+        self.arena.bytes_mut(at, 8).copy_from_slice(&next.to_le_bytes());
+    }
+
+    fn random_height(&mut self) -> usize {
+        let mut height = 1;
+        while height < MAX_HEIGHT && self.next_random().is_multiple_of(BRANCHING) {
+            height += 1;
+        }
+        height
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+}
+
+fn allocate_node(
+    arena: &mut Arena,
+    height: usize,
+    key: &[u8],
+    sequence: u64,
+    record_type: RecordType,
+    value: &[u8],
+) -> ArenaAddr {
+    let links = height * 8;
+    let len = NODE_HEADER_SIZE + links + key.len() + value.len();
+    let node = arena.allocate(len);
+    let bytes = arena.bytes_mut(node, len);
+    bytes[0..8].copy_from_slice(&sequence.to_le_bytes());
+    bytes[8] = record_type as u8;
+    bytes[9] = height as u8;
+    bytes[10..14].copy_from_slice(&(key.len() as u32).to_le_bytes());
+    bytes[14..18].copy_from_slice(&(value.len() as u32).to_le_bytes());
+    bytes[NEXT_OFFSET..NEXT_OFFSET + links].fill(0xff);
+    let data = &mut bytes[NEXT_OFFSET + links..];
+    data[..key.len()].copy_from_slice(key);
+    data[key.len()..].copy_from_slice(value);
+    node
+}
+
+/// Entries in order, starting from where the iterator was positioned
+#[derive(Clone)]
+pub struct Iter<'a> {
+    list: &'a SkipList,
+    node: ArenaAddr,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = MemtableEntry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.node == NULL {
+            return None;
+        }
+        let entry = self.list.entry(self.node);
+        self.node = self.list.next(self.node, 0);
+        Some(entry)
+    }
+}
@@ -0,0 +1,132 @@
+use std::cmp::Ordering;
+
+use crate::wal::RecordType;
+
+use super::MemtableEntry;
+use super::arena::{Arena, ArenaAddr};
+use super::rep::{MemtableIter, MemtableRep, compare_entries};
+
+/// Location of an entry whose key and value are stored in the arena
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    data: ArenaAddr,
+    sequence: u64,
+    key_len: u32,
+    value_len: u32,
+    record_type: RecordType,
+}
+
+/// Append-only memtable representation, sorted once when it becomes read-only
+///
+/// An insert is a copy to the arena and a push, with no ordering work, which suits bulk loads
+/// that never read before the flush. Until it is sorted a lookup scans every entry and an
+/// iteration sorts a copy of the slots, so reads are expensive. Duplicates (same key and sequence)
+/// are not detected by `insert`, the sort drops all but the first one inserted.
+#[derive(Default)]
+pub struct VectorRep {
+    arena: Arena,
+    slots: Vec<Slot>,
+    sorted: bool,
+}
+
+impl VectorRep {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entry(&self, slot: &Slot) -> MemtableEntry<'_> {
+        let key_len = slot.key_len as usize;
+        let data = self.arena.bytes(slot.data, key_len + slot.value_len as usize);
+        let (key, value) = data.split_at(key_len);
+        MemtableEntry {
+            key,
+            sequence: slot.sequence,
+            record_type: slot.record_type,
+            value,
+        }
+    }
+
+    fn compare(&self, a: &Slot, b: &Slot) -> Ordering {
+        compare_entries(&self.entry(a), &self.entry(b))
+    }
+
+    /// Index of the first slot at or after `(key, sequence)` in sorted `slots`
+    fn lower_bound(&self, slots: &[Slot], key: &[u8], sequence: u64) -> usize {
+        slots.partition_point(|slot| {
+            let entry = self.entry(slot);
+            entry.key.cmp(key).then_with(|| sequence.cmp(&entry.sequence)) == Ordering::Less
+        })
+    }
+
+    fn sorted_slots(&self) -> Vec<Slot> {
+        let mut slots = self.slots.clone();
+        self.sort(&mut slots);
+        slots
+    }
+
+    fn sort(&self, slots: &mut Vec<Slot>) {
+        // stable, so that the first copy of a duplicate is the one kept
+        slots.sort_by(|a, b| self.compare(a, b));
+        slots.dedup_by(|b, a| self.compare(a, b) == Ordering::Equal);
+    }
+}
+
+impl MemtableRep for VectorRep {
+    fn insert(&mut self, key: &[u8], sequence: u64, record_type: RecordType, value: &[u8]) -> bool {
+        let data = self.arena.allocate(key.len() + value.len());
+        let bytes = self.arena.bytes_mut(data, key.len() + value.len());
+        bytes[..key.len()].copy_from_slice(key);
+        bytes[key.len()..].copy_from_slice(value);
+        self.slots.push(Slot {
+            data,
+            sequence,
+            key_len: key.len() as u32,
+            value_len: value.len() as u32,
+            record_type,
+        });
+        self.sorted = false;
+        true
+    }
+
+    fn seek(&self, key: &[u8], sequence: u64) -> MemtableIter<'_> {
+        if self.sorted {
+            let start = self.lower_bound(&self.slots, key, sequence);
+            return Box::new(self.slots[start..].iter().map(|slot| self.entry(slot)));
+        }
+        let slots = self.sorted_slots();
+        let start = self.lower_bound(&slots, key, sequence);
+        Box::new(slots.into_iter().skip(start).map(|slot| self.entry(&slot)))
+    }
+
+    fn get(&self, key: &[u8], sequence: u64) -> Option<MemtableEntry<'_>> {
+        if self.sorted {
+            return self.seek(key, sequence).next().filter(|entry| entry.key == key);
+        }
+        self.slots
+            .iter()
+            .map(|slot| self.entry(slot))
+            .filter(|entry| entry.key == key && entry.sequence <= sequence)
+            // the first copy of a duplicate wins, as after the sort
+            .fold(None, |newest, entry| match newest {
+                Some(newest) if newest.sequence >= entry.sequence => Some(newest),
+                _ => Some(entry),
+            })
+    }
+
+    fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.arena.memory_usage() + self.slots.capacity() * size_of::<Slot>()
+    }
+
+    fn mark_read_only(&mut self) {
+        if !self.sorted {
+            let mut slots = std::mem::take(&mut self.slots);
+            self.sort(&mut slots);
+            self.slots = slots;
+            self.sorted = true;
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use std::cmp::Ordering;
+use std::io;
+use std::sync::Arc;
+
+use super::filter::hash64;
+use super::format::{corruption, get_varint32, put_varint64, user_key};
+
+/// Set in the restart count of a block followed by a hash index
+const HASH_INDEX_FLAG: u32 = 1 << 31;
+
+/// Most restart points a block may have to get a hash index, a bucket holds a restart index in a
+/// byte and the two values above it are markers
+pub const MAX_HASH_INDEX_RESTARTS: usize = 253;
+
+/// Bucket of the hash index holding no key
+const EMPTY_BUCKET: u8 = 255;
+
+/// Bucket of the hash index whose keys are in more than one restart interval
+const COLLISION_BUCKET: u8 = 254;
+
+/// Builds a block of sorted entries with prefix-compressed keys
+///
+/// An entry is `[Shared(varint) | Non_Shared(varint) | Value_Size(varint) | Key_Delta | Value]`,
+/// where `Shared` bytes of the key are taken from the previous key. Every `restart_interval`
+/// entries the key is stored whole (a restart point) so that a reader can binary search the
+/// restarts. The block ends with the restart offsets (`u32` each) and their count (`u32`).
+///
+/// With a hash index (`with_hash_index`) the restart offsets are followed by
+/// `[Buckets(1B each) | Num_Buckets(u16)]` and the high bit of the count is set. A bucket holds
+/// the restart interval of the user keys hashing to it, so that a point lookup goes straight to
+/// that interval instead of binary searching the restarts.
+pub struct BlockBuilder {
+    buf: Vec<u8>,
+    restarts: Vec<u32>,
+    restart_interval: usize,
+    /// Entries since the last restart point
+    counter: usize,
+    last_key: Vec<u8>,
+    entries: usize,
+    /// Keys per bucket of the hash index, none without one
+    hash_util_ratio: Option<f64>,
+    /// Hash of each user key with the index of its restart interval
+    hashes: Vec<(u64, u8)>,
+}
+
+impl BlockBuilder {
+    pub fn new(restart_interval: usize) -> Self {
+        BlockBuilder {
+            buf: Vec::new(),
+            restarts: vec![0],
+            restart_interval: restart_interval.max(1),
+            counter: 0,
+            last_key: Vec::new(),
+            entries: 0,
+            hash_util_ratio: None,
+            hashes: Vec::new(),
+        }
+    }
+
+    /// A builder of blocks with a hash index of about `util_ratio` user keys per bucket, the keys
+    /// must be internal keys. A block of more than `MAX_HASH_INDEX_RESTARTS` restart points is
+    /// written without the index.
+    pub fn with_hash_index(restart_interval: usize, util_ratio: f64) -> Self {
+        BlockBuilder {
+            hash_util_ratio: Some(util_ratio.clamp(0.05, 1.0)),
+            ..Self::new(restart_interval)
+        }
+    }
+
+    /// Adds an entry, keys must be added in increasing order
+    pub fn add(&mut self, key: &[u8], value: &[u8]) {
+        let restart = self.counter >= self.restart_interval;
+        if self.hash_util_ratio.is_some() {
+            // each version of a user key after the first one is a duplicate unless it starts an interval
+            let new_key = self.entries == 0 || user_key(key) != user_key(&self.last_key);
+            if new_key || restart {
+                let index = self.restarts.len() - 1 + restart as usize;
+                self.hashes.push((hash64(user_key(key)), index.min(u8::MAX as usize) as u8));
+            }
+        }
+        let shared = if !restart {
+            key.iter()
+                .zip(&self.last_key)
+                .take_while(|(a, b)| a == b)
+                .count()
+        } else {
+            self.restarts.push(self.buf.len() as u32);
+            self.counter = 0;
+            0
+        };
+        put_varint64(&mut self.buf, shared as u64);
+        put_varint64(&mut self.buf, (key.len() - shared) as u64);
+        put_varint64(&mut self.buf, value.len() as u64);
+        self.buf.extend_from_slice(&key[shared..]);
+        self.buf.extend_from_slice(value);
+
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
+        self.counter += 1;
+        self.entries += 1;
+    }
+
+    /// Size of the block if it was finished now
+    pub fn size_estimate(&self) -> usize {
+        let hash_index = match self.hash_util_ratio {
+            Some(ratio) => (self.hashes.len() as f64 / ratio) as usize + 3,
+            None => 0,
+        };
+        self.buf.len() + (self.restarts.len() + 1) * 4 + hash_index
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries
+    }
+
+    pub fn last_key(&self) -> &[u8] {
+        &self.last_key
+    }
+
+    /// Appends the restarts, and the hash index if any, and returns the contents of the block,
+    /// `reset` starts the next one
+    pub fn finish(&mut self) -> &[u8] {
+        for restart in &self.restarts {
+            self.buf.extend_from_slice(&restart.to_le_bytes());
+        }
+        let mut num_restarts = self.restarts.len() as u32;
+        if let Some(ratio) = self.hash_util_ratio
+            && self.restarts.len() <= MAX_HASH_INDEX_RESTARTS
+        {
+            let num_buckets = ((self.hashes.len() as f64 / ratio) as usize).clamp(1, u16::MAX as usize);
+            let mut buckets = vec![EMPTY_BUCKET; num_buckets];
+            for &(hash, index) in &self.hashes {
+                let bucket = &mut buckets[(hash % num_buckets as u64) as usize];
+                if *bucket == EMPTY_BUCKET {
+                    *bucket = index;
+                } else if *bucket != index {
+                    *bucket = COLLISION_BUCKET;
+                }
+            }
+            self.buf.extend_from_slice(&buckets);
+            self.buf.extend_from_slice(&(num_buckets as u16).to_le_bytes());
+            num_restarts |= HASH_INDEX_FLAG;
+        }
+        self.buf.extend_from_slice(&num_restarts.to_le_bytes());
+        &self.buf
+    }
+
+    pub fn reset(&mut self) {
+        self.buf.clear();
+        self.restarts.clear();
+        self.restarts.push(0);
+        self.counter = 0;
+        self.last_key.clear();
+        self.entries = 0;
+        self.hashes.clear();
+    }
+}
+
+/// The contents of a block read from a table, checksum already verified
+pub struct Block {
+    data: Vec<u8>,
+    /// Where the restart array starts, i.e. the end of the entries
+    restarts_offset: usize,
+    num_restarts: usize,
+    /// Where the buckets of the hash index start and their number, 0 without an index
+    buckets_offset: usize,
+    num_buckets: usize,
+}
+
+impl Block {
+    pub fn new(data: Vec<u8>) -> io::Result<Self> {
+        if data.len() < 4 {
+            return Err(corruption("block too short"));
+        }
+        let packed = u32::from_le_bytes(data[data.len() - 4..].try_into().unwrap());
+        let num_restarts = (packed & !HASH_INDEX_FLAG) as usize;
+        let (buckets_offset, num_buckets) = match packed & HASH_INDEX_FLAG {
+            0 => (data.len() - 4, 0),
+            _ => {
+                let end = (data.len() - 4)
+                    .checked_sub(2)
+                    .ok_or_else(|| corruption("bad block hash index"))?;
+                let num_buckets = u16::from_le_bytes(data[end..end + 2].try_into().unwrap()) as usize;
+                let offset = end
+                    .checked_sub(num_buckets)
+                    .filter(|_| num_buckets > 0)
+                    .ok_or_else(|| corruption("bad block hash index"))?;
+                (offset, num_buckets)
+            }
+        };
+        let restarts_offset = buckets_offset
+            .checked_sub(num_restarts.saturating_mul(4))
+            .filter(|_| num_restarts > 0)
+            .ok_or_else(|| corruption("bad block restart array"))?;
+        Ok(Block {
+            data,
+            restarts_offset,
+            num_restarts,
+            buckets_offset,
+            num_buckets,
+        })
+    }
+
+    /// Whether the block has a hash index for point lookups
+    pub fn has_hash_index(&self) -> bool {
+        self.num_buckets > 0
+    }
+
+    /// Bytes held by the block
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn iter(self: &Arc<Self>) -> BlockIter {
+        BlockIter {
+            block: Arc::clone(self),
+            offset: self.restarts_offset,
+            next_offset: self.restarts_offset,
+            key: Vec::new(),
+            value: (0, 0),
+            corrupted: false,
+        }
+    }
+
+    fn restart_point(&self, index: usize) -> usize {
+        let at = self.restarts_offset + index * 4;
+        u32::from_le_bytes(self.data[at..at + 4].try_into().unwrap()) as usize
+    }
+}
+
+/// Cursor over the entries of a block
+///
+/// Unpositioned when created, `seek_to_first` or `seek` positions it. Keeps the block alive, so it
+/// can outlive the reader that loaded it.
+pub struct BlockIter {
+    block: Arc<Block>,
+    /// Start of the current entry, `restarts_offset` when not valid
+    offset: usize,
+    next_offset: usize,
+    key: Vec<u8>,
+    /// Offset and length of the current value
+    value: (usize, usize),
+    corrupted: bool,
+}
+
+impl BlockIter {
+    pub fn valid(&self) -> bool {
+        self.offset < self.block.restarts_offset
+    }
+
+    /// `InvalidData` once a malformed entry has been found, the cursor is then not valid
+    pub fn status(&self) -> io::Result<()> {
+        match self.corrupted {
+            true => Err(corruption("bad entry in block")),
+            false => Ok(()),
+        }
+    }
+
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    pub fn value(&self) -> &[u8] {
+        &self.block.data[self.value.0..self.value.0 + self.value.1]
+    }
+
+    pub fn seek_to_first(&mut self) {
+        self.seek_to_restart(0);
+        self.advance();
+    }
+
+    /// Positions at the first entry whose key is not less than `target` under `compare`
+    pub fn seek(&mut self, target: &[u8], compare: fn(&[u8], &[u8]) -> Ordering) {
+        // the last restart point whose key is less than the target
+        let (mut left, mut right) = (0, self.block.num_restarts - 1);
+        while left < right {
+            let mid = (left + right).div_ceil(2);
+            let Some(key) = self.restart_key(mid) else {
+                return self.set_corrupted();
+            };
+            if compare(key, target) == Ordering::Less {
+                left = mid;
+            } else {
+                right = mid - 1;
+            }
+        }
+        self.seek_to_restart(left);
+        while self.advance() {
+            if compare(&self.key, target) != Ordering::Less {
+                return;
+            }
+        }
+    }
+
+    /// Positions like `seek` for a lookup of the user key of the internal key `target`, through the
+    /// hash index of the block when it has one: the cursor goes to the restart interval of the key
+    /// and scans it, or is left not valid when the index rules the key out. Falls back to `seek`
+    /// when the keys hashing to the bucket are in more than one interval.
+    pub fn seek_for_get(&mut self, target: &[u8], compare: fn(&[u8], &[u8]) -> Ordering) {
+        let block = &self.block;
+        if block.num_buckets == 0 {
+            return self.seek(target, compare);
+        }
+        let bucket = (hash64(user_key(target)) % block.num_buckets as u64) as usize;
+        match block.data[block.buckets_offset + bucket] {
+            EMPTY_BUCKET => {
+                self.key.clear();
+                self.offset = block.restarts_offset;
+                self.next_offset = block.restarts_offset;
+            }
+            COLLISION_BUCKET => self.seek(target, compare),
+            index if index as usize >= block.num_restarts => self.set_corrupted(),
+            index => {
+                self.seek_to_restart(index as usize);
+                while self.advance() {
+                    if compare(&self.key, target) != Ordering::Less {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Moves to the next entry, returns whether the cursor is still valid
+    pub fn advance(&mut self) -> bool {
+        self.offset = self.next_offset;
+        if self.offset >= self.block.restarts_offset {
+            self.offset = self.block.restarts_offset;
+            return false;
+        }
+        let data = &self.block.data[self.offset..self.block.restarts_offset];
+        let mut input = data;
+        let decoded = (|| {
+            let shared = get_varint32(&mut input)? as usize;
+            let non_shared = get_varint32(&mut input)? as usize;
+            let value_len = get_varint32(&mut input)? as usize;
+            (shared <= self.key.len() && input.len() >= non_shared + value_len).then_some((
+                shared,
+                non_shared,
+                value_len,
+            ))
+        })();
+        let Some((shared, non_shared, value_len)) = decoded else {
+            self.set_corrupted();
+            return false;
+        };
+        let header = data.len() - input.len();
+        self.key.truncate(shared);
+        self.key.extend_from_slice(&input[..non_shared]);
+        let value_start = self.offset + header + non_shared;
+        self.value = (value_start, value_len);
+        self.next_offset = value_start + value_len;
+        true
+    }
+
+    fn seek_to_restart(&mut self, index: usize) {
+        self.key.clear();
+        let offset = self.block.restart_point(index);
+        if offset > self.block.restarts_offset {
+            return self.set_corrupted();
+        }
+        self.next_offset = offset;
+        self.offset = self.block.restarts_offset;
+    }
+
+    /// Key stored whole at a restart point
+    fn restart_key(&self, index: usize) -> Option<&[u8]> {
+        let offset = self.block.restart_point(index);
+        let mut input = self.block.data.get(offset..self.block.restarts_offset)?;
+        let shared = get_varint32(&mut input)?;
+        let non_shared = get_varint32(&mut input)? as usize;
+        get_varint32(&mut input)?;
+        (shared == 0).then(|| input.get(..non_shared)).flatten()
+    }
+
+    fn set_corrupted(&mut self) {
+        self.corrupted = true;
+        self.offset = self.block.restarts_offset;
+        self.next_offset = self.block.restarts_offset;
+    }
+}
@@ -0,0 +1,423 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cache::BlockCache;
+use crate::env::WritableFile;
+use crate::memtable::MemtableEntry;
+use crate::wal::RecordType;
+
+use super::block::BlockBuilder;
+use super::dictionary::{self, COMPRESSION_DICTIONARY_BLOCK};
+use super::filter::{FILTER_BLOCK, FilterBuilder, FilterPolicy, FilterStats};
+use super::format::{
+    BLOCK_TRAILER_SIZE, BlockHandle, CompressionType, Footer, MAX_SEQUENCE, TABLE_FORMAT_VERSION, block_trailer, compare_internal_keys,
+    encode_internal_key,
+};
+use super::prefix::{PREFIX_EXTRACTOR_BLOCK, PrefixExtractor};
+use super::properties::{PROPERTIES_BLOCK, TableProperties};
+use super::TableSink;
+
+/// How the index of a table is stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IndexType {
+    /// One index block, loaded whole when the table is opened
+    #[default]
+    Single,
+    /// Index partitions of about `index_partition_size` bytes, found through a small top-level
+    /// index: only the top level stays in memory, partitions are read on demand through the block
+    /// cache
+    Partitioned,
+}
+
+/// How a point lookup finds its key in a data block
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DataBlockIndexType {
+    /// Binary search over the restart points
+    #[default]
+    BinarySearch,
+    /// A hash index after the restart points maps the user keys to their restart interval, a
+    /// point lookup skips the binary search; seeks and scans still use the restart points
+    BinaryAndHash,
+}
+
+/// Layout of the block-based tables
+#[derive(Debug, Clone)]
+pub struct TableOptions {
+    /// Uncompressed size at which a data block is cut. A value up to 16KB (the sizes the store is
+    /// tuned for) fits in a single block.
+    pub block_size: usize,
+    /// Entries between two restart points of a data block: fewer is a faster seek inside the block,
+    /// more is a better prefix compression
+    pub block_restart_interval: usize,
+    pub data_block_index_type: DataBlockIndexType,
+    /// User keys per bucket of the hash index of a data block, with
+    /// `DataBlockIndexType::BinaryAndHash`: lower is fewer collisions for more space
+    pub data_block_hash_table_util_ratio: f64,
+    pub index_type: IndexType,
+    /// Size at which an index partition is cut, with `IndexType::Partitioned`
+    pub index_partition_size: usize,
+    /// Compression of the data blocks, for the tables of every level not in
+    /// `compression_per_level`
+    pub compression: CompressionType,
+    /// Compression of the data blocks of the tables of each level, the last one for the levels
+    /// past the end; empty to use `compression` everywhere
+    pub compression_per_level: Vec<CompressionType>,
+    /// Smallest ratio of the uncompressed size of a block to its compressed size for the block
+    /// to be stored compressed, a block compressing less is stored raw
+    pub compression_ratio_threshold: f64,
+    /// Size of the dictionary trained from the first data blocks of each table and used to
+    /// compress all of them, up to 64KB; 0 (the default) compresses each block on its own. For
+    /// small similar values, which a block alone holds too few of to compress well.
+    pub compression_dictionary_size: usize,
+    /// Data blocks held in memory to train the dictionary from, the blocks past it are compressed
+    /// with the dictionary trained from the first ones
+    pub compression_dictionary_buffer_size: usize,
+    /// Cache of the data blocks and index partitions shared by the readers, without it every
+    /// access reads the file
+    pub block_cache: Option<Arc<BlockCache>>,
+    /// Filter written in every table and checked by point lookups before reading a data block,
+    /// none by default
+    pub filter_policy: Option<Arc<dyn FilterPolicy>>,
+    /// Filter counters of the readers opened with these options
+    pub filter_stats: Arc<FilterStats>,
+    /// With a filter policy, the prefixes of the keys are added to the filter so that a prefix
+    /// scan skips the tables not holding its prefix
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    /// Adds the whole keys to the filter, for point lookups. Without it a point lookup only checks
+    /// the prefix of its key, for a smaller filter when the store mostly scans prefixes.
+    pub whole_key_filtering: bool,
+}
+
+impl Default for TableOptions {
+    fn default() -> Self {
+        TableOptions {
+            block_size: 16 * 1024,
+            block_restart_interval: 16,
+            data_block_index_type: DataBlockIndexType::BinarySearch,
+            data_block_hash_table_util_ratio: 0.75,
+            index_type: IndexType::Single,
+            index_partition_size: 4 * 1024,
+            compression: CompressionType::None,
+            compression_per_level: Vec::new(),
+            compression_ratio_threshold: 1.125,
+            compression_dictionary_size: 0,
+            compression_dictionary_buffer_size: 1024 * 1024,
+            block_cache: None,
+            filter_policy: None,
+            filter_stats: Arc::new(FilterStats::new()),
+            prefix_extractor: None,
+            whole_key_filtering: true,
+        }
+    }
+}
+
+impl TableOptions {
+    /// Compression of the tables of `level`
+    pub fn compression_for_level(&self, level: usize) -> CompressionType {
+        match self.compression_per_level.last() {
+            Some(&last) => self.compression_per_level.get(level).copied().unwrap_or(last),
+            None => self.compression,
+        }
+    }
+}
+
+/// Writes a block-based table
+///
+/// ```text
+/// [Data block 1] ... [Data block N]
+/// [Index partition 1] ... [Index partition M]   with `IndexType::Partitioned` only
+/// [Filter block]                                 with a filter policy only
+/// [Prefix extractor block]                       with a filter policy and a prefix extractor
+/// [Compression dictionary block]                 with a compression dictionary only
+/// [Properties block]
+/// [Metaindex block]   meta block name -> handle
+/// [Index block]       last internal key of each data block (or index partition) -> handle
+/// [Footer]            fixed size: metaindex and index handles, version, magic
+/// ```
+///
+/// Every block is followed by a `[Type(1B) | CRC32C(4B)]` trailer, the CRC covering the contents
+/// as stored and the type byte. Data blocks are compressed with `compression`, the type byte
+/// telling how a block is stored; the other blocks are always stored raw. Keys are internal keys
+/// (the user key followed by `Sequence << 8 | Type`) and must be added in the internal order: user
+/// key ascending, then sequence descending.
+///
+/// With a compression dictionary the data blocks are held in memory, up to
+/// `compression_dictionary_buffer_size`, until the dictionary is trained from them; they are then
+/// written compressed with it, as are the following ones.
+pub struct TableBuilder {
+    file: Box<dyn WritableFile>,
+    options: TableOptions,
+    offset: u64,
+    data_block: BlockBuilder,
+    /// The index, or its current partition
+    index_block: BlockBuilder,
+    /// Finished index partitions with their last key, written after the data blocks
+    partitions: Vec<(Vec<u8>, Vec<u8>)>,
+    filter: Option<Box<dyn FilterBuilder>>,
+    /// Last prefix added to the filter, each prefix is added once
+    last_prefix: Option<Vec<u8>>,
+    last_key: Vec<u8>,
+    properties: TableProperties,
+    /// Dictionary the data blocks are compressed with, empty without one, none until trained
+    dictionary: Option<Vec<u8>>,
+    /// Data blocks waiting for the dictionary, with their last key
+    buffered: Vec<(Vec<u8>, Vec<u8>)>,
+    buffered_size: usize,
+}
+
+impl TableBuilder {
+    pub fn new(file: Box<dyn WritableFile>, options: TableOptions) -> Self {
+        let properties = TableProperties {
+            compression: options.compression as u64,
+            creation_time: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs()),
+            ..TableProperties::default()
+        };
+        let dictionary = match options.compression {
+            CompressionType::None => Some(Vec::new()),
+            _ if options.compression_dictionary_size == 0 => Some(Vec::new()),
+            _ => None,
+        };
+        TableBuilder {
+            file,
+            data_block: match options.data_block_index_type {
+                DataBlockIndexType::BinarySearch => BlockBuilder::new(options.block_restart_interval),
+                DataBlockIndexType::BinaryAndHash => BlockBuilder::with_hash_index(
+                    options.block_restart_interval,
+                    options.data_block_hash_table_util_ratio,
+                ),
+            },
+            index_block: BlockBuilder::new(1),
+            partitions: Vec::new(),
+            filter: options.filter_policy.as_ref().map(|policy| policy.new_builder()),
+            options,
+            offset: 0,
+            last_prefix: None,
+            last_key: Vec::new(),
+            properties,
+            dictionary,
+            buffered: Vec::new(),
+            buffered_size: 0,
+        }
+    }
+
+    /// Adds a version of `key`, fails with `InvalidInput` when it is not after the previous one
+    pub fn add(&mut self, key: &[u8], sequence: u64, record_type: RecordType, value: &[u8]) -> io::Result<()> {
+        if sequence > MAX_SEQUENCE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("sequence {sequence} does not fit in a table"),
+            ));
+        }
+        let mut internal_key = Vec::with_capacity(key.len() + 8);
+        encode_internal_key(&mut internal_key, key, sequence, record_type);
+        if self.properties.num_entries > 0 && compare_internal_keys(&self.last_key, &internal_key).is_ge() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "table entries must be added in order",
+            ));
+        }
+
+        self.data_block.add(&internal_key, value);
+        if let Some(filter) = &mut self.filter {
+            if let Some(prefix) = self.options.prefix_extractor.as_ref().and_then(|e| e.prefix(key))
+                && self.last_prefix.as_deref() != Some(prefix)
+            {
+                filter.add_key(prefix);
+                self.last_prefix = Some(prefix.to_vec());
+            }
+            if self.options.whole_key_filtering {
+                filter.add_key(key);
+            }
+        }
+        self.last_key = internal_key;
+        let properties = &mut self.properties;
+        if properties.num_entries == 0 {
+            properties.min_sequence = sequence;
+        }
+        properties.num_entries += 1;
+        properties.num_deletions += (record_type == RecordType::Delete) as u64;
+        properties.raw_key_size += key.len() as u64;
+        properties.raw_value_size += value.len() as u64;
+        properties.min_sequence = properties.min_sequence.min(sequence);
+        properties.max_sequence = properties.max_sequence.max(sequence);
+
+        if self.data_block.size_estimate() >= self.options.block_size {
+            self.flush_data_block()?;
+        }
+        Ok(())
+    }
+
+    pub fn num_entries(&self) -> u64 {
+        self.properties.num_entries
+    }
+
+    /// Bytes written so far, the size of the file once finished
+    pub fn file_size(&self) -> u64 {
+        self.offset
+    }
+
+    /// Bytes written so far and held for the next blocks, about the size of the file if finished now
+    pub fn estimated_size(&self) -> u64 {
+        self.offset + (self.buffered_size + self.data_block.size_estimate()) as u64
+    }
+
+    /// Writes the index, meta blocks and footer, then syncs the file, returns the size of the table
+    pub fn finish(mut self) -> io::Result<u64> {
+        self.flush_data_block()?;
+        if self.dictionary.is_none() {
+            self.train_dictionary()?;
+        }
+        self.properties.data_size = self.offset;
+
+        let mut index = std::mem::replace(&mut self.index_block, BlockBuilder::new(1));
+        let mut index_size = 0;
+        if self.options.index_type == IndexType::Partitioned {
+            if !index.is_empty() {
+                self.partitions.push((index.last_key().to_vec(), index.finish().to_vec()));
+            }
+            index.reset();
+            let mut handle = Vec::new();
+            for (last_key, partition) in std::mem::take(&mut self.partitions) {
+                let partition = self.write_block(&partition)?;
+                handle.clear();
+                partition.encode_to(&mut handle);
+                index.add(&last_key, &handle);
+                index_size += partition.size + BLOCK_TRAILER_SIZE as u64;
+                self.properties.index_partitions += 1;
+            }
+        }
+        let mut meta_blocks = BTreeMap::new();
+        if let Some(mut filter) = self.filter.take() {
+            let filter = filter.finish();
+            self.properties.filter_size = (filter.len() + BLOCK_TRAILER_SIZE) as u64;
+            meta_blocks.insert(FILTER_BLOCK, self.write_block(&filter)?);
+            if let Some(extractor) = self.options.prefix_extractor.clone() {
+                let mut block = vec![self.options.whole_key_filtering as u8];
+                block.extend_from_slice(extractor.name().as_bytes());
+                meta_blocks.insert(PREFIX_EXTRACTOR_BLOCK, self.write_block(&block)?);
+            }
+        }
+        let dictionary = self.dictionary.take().unwrap_or_default();
+        if !dictionary.is_empty() {
+            self.properties.compression_dictionary_size = dictionary.len() as u64;
+            meta_blocks.insert(COMPRESSION_DICTIONARY_BLOCK, self.write_block(&dictionary)?);
+        }
+        // written before the index, the properties need the size of the index: it is known from
+        // the index contents plus the trailer
+        self.properties.index_size = index_size + (index.size_estimate() + BLOCK_TRAILER_SIZE) as u64;
+        meta_blocks.insert(PROPERTIES_BLOCK, self.write_block(&self.properties.encode())?);
+
+        let mut metaindex = BlockBuilder::new(1);
+        let mut handle = Vec::new();
+        for (name, block) in meta_blocks {
+            handle.clear();
+            block.encode_to(&mut handle);
+            metaindex.add(name.as_bytes(), &handle);
+        }
+        let metaindex = self.write_block(metaindex.finish())?;
+        let index = self.write_block(index.finish())?;
+
+        let footer = Footer {
+            metaindex,
+            index,
+            version: TABLE_FORMAT_VERSION,
+        }
+        .encode();
+        self.file.append(&footer)?;
+        self.offset += footer.len() as u64;
+        self.file.sync()?;
+        Ok(self.offset)
+    }
+
+    fn flush_data_block(&mut self) -> io::Result<()> {
+        if self.data_block.is_empty() {
+            return Ok(());
+        }
+        let contents = self.data_block.finish().to_vec();
+        let last_key = self.data_block.last_key().to_vec();
+        self.data_block.reset();
+        if self.dictionary.is_some() {
+            return self.write_data_block(&last_key, &contents);
+        }
+        self.buffered_size += contents.len();
+        self.buffered.push((last_key, contents));
+        if self.buffered_size >= self.options.compression_dictionary_buffer_size {
+            self.train_dictionary()?;
+        }
+        Ok(())
+    }
+
+    /// Trains the dictionary from the buffered data blocks, then writes them
+    fn train_dictionary(&mut self) -> io::Result<()> {
+        let buffered = std::mem::take(&mut self.buffered);
+        let samples: Vec<&[u8]> = buffered.iter().map(|(_, contents)| contents.as_slice()).collect();
+        self.dictionary = Some(dictionary::train(&samples, self.options.compression_dictionary_size));
+        for (last_key, contents) in buffered {
+            self.write_data_block(&last_key, &contents)?;
+        }
+        self.buffered_size = 0;
+        Ok(())
+    }
+
+    /// Writes a data block and adds it to the index
+    fn write_data_block(&mut self, last_key: &[u8], contents: &[u8]) -> io::Result<()> {
+        let handle = self.write_compressed_block(contents)?;
+        let mut encoded = Vec::new();
+        handle.encode_to(&mut encoded);
+        self.index_block.add(last_key, &encoded);
+        if self.options.index_type == IndexType::Partitioned
+            && self.index_block.size_estimate() >= self.options.index_partition_size
+        {
+            let last_key = self.index_block.last_key().to_vec();
+            self.partitions.push((last_key, self.index_block.finish().to_vec()));
+            self.index_block.reset();
+        }
+        self.properties.num_data_blocks += 1;
+        Ok(())
+    }
+
+    /// Writes a data block compressed when it compresses to the threshold of the options
+    fn write_compressed_block(&mut self, contents: &[u8]) -> io::Result<BlockHandle> {
+        let compression = self.options.compression;
+        if compression != CompressionType::None {
+            let dictionary = self.dictionary.as_deref().unwrap_or_default();
+            let compressed = compression.compress_with_dict(contents, dictionary);
+            if contents.len() as f64 >= compressed.len() as f64 * self.options.compression_ratio_threshold {
+                self.properties.num_compressed_blocks += 1;
+                return self.write_stored_block(&compressed, compression);
+            }
+        }
+        self.write_stored_block(contents, CompressionType::None)
+    }
+
+    fn write_block(&mut self, contents: &[u8]) -> io::Result<BlockHandle> {
+        self.write_stored_block(contents, CompressionType::None)
+    }
+
+    fn write_stored_block(&mut self, contents: &[u8], compression: CompressionType) -> io::Result<BlockHandle> {
+        let handle = BlockHandle {
+            offset: self.offset,
+            size: contents.len() as u64,
+        };
+        self.file.append(contents)?;
+        self.file.append(&block_trailer(contents, compression))?;
+        self.offset += (contents.len() + BLOCK_TRAILER_SIZE) as u64;
+        Ok(handle)
+    }
+}
+
+impl TableSink for TableBuilder {
+    fn add(&mut self, entry: MemtableEntry<'_>) -> io::Result<()> {
+        TableBuilder::add(self, entry.key, entry.sequence, entry.record_type, entry.value)
+    }
+
+    fn estimated_size(&self) -> u64 {
+        TableBuilder::estimated_size(self)
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<u64> {
+        TableBuilder::finish(*self)
+    }
+}
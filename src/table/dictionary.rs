@@ -0,0 +1,83 @@
+use std::collections::{BinaryHeap, HashMap};
+
+/// Name of the compression dictionary block in the metaindex
+pub const COMPRESSION_DICTIONARY_BLOCK: &str = "stone.compression_dictionary";
+
+/// Largest dictionary the codecs use, their matches reach 64KB back
+pub const MAX_DICTIONARY_SIZE: usize = u16::MAX as usize;
+
+/// Length of the substrings counted across the samples
+const GRAM: usize = 8;
+
+/// Length of the pieces of samples the dictionary is made of
+const SEGMENT: usize = 32;
+
+/// Trains a dictionary of up to `max_size` bytes from `samples` (the uncompressed data blocks of a
+/// table)
+///
+/// The samples are cut in segments of 32 bytes, each scored by how often its 8-byte substrings
+/// occur across all the samples. The best segments are taken greedily, a substring in a segment
+/// already taken no longer adding to the score of the others, and the ends of a segment already in
+/// the dictionary left out, so that the dictionary mostly holds the field names, keys and values
+/// repeated across blocks once each. The best segments go last, nearest to the data compressed
+/// with the dictionary. Empty when nothing repeats.
+pub fn train(samples: &[&[u8]], max_size: usize) -> Vec<u8> {
+    let max_size = max_size.min(MAX_DICTIONARY_SIZE);
+    let mut counts: HashMap<u64, u32> = HashMap::new();
+    for sample in samples {
+        for gram in sample.windows(GRAM) {
+            *counts.entry(gram_key(gram)).or_default() += 1;
+        }
+    }
+
+    // lazy greedy: scores only drop as segments are taken, a popped segment whose score is still
+    // the best once recomputed is the best
+    let segments = || samples.iter().flat_map(|sample| sample.chunks_exact(SEGMENT));
+    let mut heap: BinaryHeap<(u64, usize)> = segments()
+        .enumerate()
+        .map(|(index, segment)| (score(segment, &counts), index))
+        .filter(|&(score, _)| score > 0)
+        .collect();
+    let all: Vec<&[u8]> = segments().collect();
+    let mut taken: Vec<&[u8]> = Vec::new();
+    let mut size = 0;
+    while size + SEGMENT <= max_size {
+        let Some((stale, index)) = heap.pop() else {
+            break;
+        };
+        let current = score(all[index], &counts);
+        if current == 0 {
+            continue;
+        }
+        if current < stale && heap.peek().is_some_and(|&(next, _)| next > current) {
+            heap.push((current, index));
+            continue;
+        }
+        // the ends already in the dictionary are left out
+        let repeated = |gram: &[u8]| counts.get(&gram_key(gram)).is_some_and(|&count| count > 1);
+        let grams: Vec<&[u8]> = all[index].windows(GRAM).collect();
+        let first = grams.iter().position(|gram| repeated(gram)).unwrap();
+        let last = grams.iter().rposition(|gram| repeated(gram)).unwrap();
+        for gram in grams {
+            counts.insert(gram_key(gram), 0);
+        }
+        let segment = &all[index][first..last + GRAM];
+        size += segment.len();
+        taken.push(segment);
+    }
+    taken.iter().rev().flat_map(|segment| segment.iter()).copied().collect()
+}
+
+/// Occurrences across the samples of the substrings of a segment, those seen once not counting
+fn score(segment: &[u8], counts: &HashMap<u64, u32>) -> u64 {
+    segment
+        .windows(GRAM)
+        .map(|gram| counts.get(&gram_key(gram)).copied().unwrap_or(0))
+        .filter(|&count| count > 1)
+        .map(u64::from)
+        .sum()
+}
+
+fn gram_key(gram: &[u8]) -> u64 {
+    u64::from_le_bytes(gram.try_into().unwrap())
+}
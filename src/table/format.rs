@@ -0,0 +1,230 @@
+use std::cmp::Ordering;
+use std::io;
+
+use crate::wal::RecordType;
+use crate::wal::crc32c::crc32c_hw_append;
+
+use super::{lz4, snappy};
+
+/// Magic bytes at the very end of every block-based table
+pub const TABLE_MAGIC: [u8; 8] = *b"SKVTABLE";
+
+/// Version of the block-based table format, stored in the footer
+pub const TABLE_FORMAT_VERSION: u32 = 1;
+
+/// `[Type(1B) | CRC32C(4B)]` after the contents of every block
+pub const BLOCK_TRAILER_SIZE: usize = 5;
+
+/// A `BlockHandle` is two varint64s
+pub const MAX_BLOCK_HANDLE_SIZE: usize = 20;
+
+/// `[Metaindex handle | Index handle | Padding (up to 40B)] [Version(4B) | Magic(8B)]`
+pub const FOOTER_SIZE: usize = 2 * MAX_BLOCK_HANDLE_SIZE + 4 + TABLE_MAGIC.len();
+
+/// Size of the `Sequence << 8 | Type` trailer of an internal key
+pub const INTERNAL_KEY_TRAILER_SIZE: usize = 8;
+
+/// Sequences share 8 bytes with the record type in an internal key
+pub const MAX_SEQUENCE: u64 = (1 << 56) - 1;
+
+/// How the contents of a block are stored, the type byte of the block trailer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum CompressionType {
+    #[default]
+    None = 0x00,
+    Snappy = 0x01,
+    Lz4 = 0x04,
+}
+
+impl CompressionType {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0x00 => Some(CompressionType::None),
+            0x01 => Some(CompressionType::Snappy),
+            0x04 => Some(CompressionType::Lz4),
+            _ => None,
+        }
+    }
+
+    /// The contents of a block as stored with this type
+    pub fn compress(self, contents: &[u8]) -> Vec<u8> {
+        self.compress_with_dict(contents, &[])
+    }
+
+    /// The contents of a block as stored with this type and the compression dictionary `dict`
+    pub fn compress_with_dict(self, contents: &[u8], dict: &[u8]) -> Vec<u8> {
+        match self {
+            CompressionType::None => contents.to_vec(),
+            CompressionType::Snappy => snappy::compress_with_dict(contents, dict),
+            CompressionType::Lz4 => lz4::compress_with_dict(contents, dict),
+        }
+    }
+
+    /// The contents of a block stored with this type, `InvalidData` when they are malformed
+    pub fn uncompress(self, stored: Vec<u8>) -> io::Result<Vec<u8>> {
+        self.uncompress_with_dict(stored, &[])
+    }
+
+    /// The contents of a block stored with this type and the compression dictionary `dict`
+    pub fn uncompress_with_dict(self, stored: Vec<u8>, dict: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            CompressionType::None => Ok(stored),
+            CompressionType::Snappy => snappy::uncompress_with_dict(&stored, dict),
+            CompressionType::Lz4 => lz4::uncompress_with_dict(&stored, dict),
+        }
+    }
+}
+
+pub fn put_varint64(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Decodes a varint64 at the start of `input` and advances past it, `None` when malformed
+pub fn get_varint64(input: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for (i, &byte) in input.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            *input = &input[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Decodes a varint that must fit in 32 bits
+pub fn get_varint32(input: &mut &[u8]) -> Option<u32> {
+    get_varint64(input).and_then(|value| u32::try_from(value).ok())
+}
+
+pub(crate) fn corruption(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Location of a block in the table file, `size` excludes the trailer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BlockHandle {
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl BlockHandle {
+    pub fn encode_to(&self, buf: &mut Vec<u8>) {
+        put_varint64(buf, self.offset);
+        put_varint64(buf, self.size);
+    }
+
+    pub fn decode_from(input: &mut &[u8]) -> io::Result<Self> {
+        let offset = get_varint64(input).ok_or_else(|| corruption("bad block handle"))?;
+        let size = get_varint64(input).ok_or_else(|| corruption("bad block handle"))?;
+        Ok(BlockHandle { offset, size })
+    }
+}
+
+/// The fixed-size end of a table, the entry point of a reader
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Footer {
+    pub metaindex: BlockHandle,
+    pub index: BlockHandle,
+    pub version: u32,
+}
+
+impl Footer {
+    pub fn encode(&self) -> [u8; FOOTER_SIZE] {
+        let mut handles = Vec::with_capacity(2 * MAX_BLOCK_HANDLE_SIZE);
+        self.metaindex.encode_to(&mut handles);
+        self.index.encode_to(&mut handles);
+        let mut out = [0u8; FOOTER_SIZE];
+        out[..handles.len()].copy_from_slice(&handles);
+        out[2 * MAX_BLOCK_HANDLE_SIZE..2 * MAX_BLOCK_HANDLE_SIZE + 4].copy_from_slice(&self.version.to_le_bytes());
+        out[FOOTER_SIZE - TABLE_MAGIC.len()..].copy_from_slice(&TABLE_MAGIC);
+        out
+    }
+
+    /// Fails with `InvalidData` when the magic does not match and `Unsupported` for an unknown version
+    pub fn decode(bytes: &[u8; FOOTER_SIZE]) -> io::Result<Self> {
+        if bytes[FOOTER_SIZE - TABLE_MAGIC.len()..] != TABLE_MAGIC {
+            return Err(corruption("not a table: bad magic"));
+        }
+        let version = u32::from_le_bytes(
+            bytes[2 * MAX_BLOCK_HANDLE_SIZE..2 * MAX_BLOCK_HANDLE_SIZE + 4]
+                .try_into()
+                .unwrap(),
+        );
+        if version != TABLE_FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("unsupported table format version {version}"),
+            ));
+        }
+        let mut input = &bytes[..2 * MAX_BLOCK_HANDLE_SIZE];
+        let metaindex = BlockHandle::decode_from(&mut input)?;
+        let index = BlockHandle::decode_from(&mut input)?;
+        Ok(Footer {
+            metaindex,
+            index,
+            version,
+        })
+    }
+}
+
+/// Checksum of a block: its contents as stored (compressed) followed by the type byte
+pub fn block_checksum(contents: &[u8], compression: CompressionType) -> u32 {
+    crc32c_hw_append(crc32c_hw_append(0, contents), &[compression as u8])
+}
+
+pub fn block_trailer(contents: &[u8], compression: CompressionType) -> [u8; BLOCK_TRAILER_SIZE] {
+    let mut trailer = [0u8; BLOCK_TRAILER_SIZE];
+    trailer[0] = compression as u8;
+    trailer[1..].copy_from_slice(&block_checksum(contents, compression).to_le_bytes());
+    trailer
+}
+
+/// Appends the internal key of a version: the user key followed by `Sequence << 8 | Type` in
+/// little endian
+pub fn encode_internal_key(buf: &mut Vec<u8>, key: &[u8], sequence: u64, record_type: RecordType) {
+    buf.extend_from_slice(key);
+    buf.extend_from_slice(&(sequence << 8 | record_type as u64).to_le_bytes());
+}
+
+pub fn internal_key(key: &[u8], sequence: u64, record_type: RecordType) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(key.len() + INTERNAL_KEY_TRAILER_SIZE);
+    encode_internal_key(&mut buf, key, sequence, record_type);
+    buf
+}
+
+/// Splits an internal key into user key, sequence and type
+pub fn parse_internal_key(internal_key: &[u8]) -> io::Result<(&[u8], u64, RecordType)> {
+    let Some(split) = internal_key.len().checked_sub(INTERNAL_KEY_TRAILER_SIZE) else {
+        return Err(corruption("internal key too short"));
+    };
+    let (key, trailer) = internal_key.split_at(split);
+    let trailer = u64::from_le_bytes(trailer.try_into().unwrap());
+    let record_type =
+        RecordType::from_byte(trailer as u8).ok_or_else(|| corruption("internal key with an unknown type"))?;
+    Ok((key, trailer >> 8, record_type))
+}
+
+/// User key of an internal key, the whole input when it is too short to have a trailer
+pub fn user_key(internal_key: &[u8]) -> &[u8] {
+    &internal_key[..internal_key.len().saturating_sub(INTERNAL_KEY_TRAILER_SIZE)]
+}
+
+fn sequence_of(internal_key: &[u8]) -> u64 {
+    let trailer = &internal_key[internal_key.len().saturating_sub(INTERNAL_KEY_TRAILER_SIZE)..];
+    let mut bytes = [0u8; 8];
+    bytes[..trailer.len()].copy_from_slice(trailer);
+    u64::from_le_bytes(bytes) >> 8
+}
+
+/// Internal order: user key ascending, then sequence descending (newest version first)
+pub fn compare_internal_keys(a: &[u8], b: &[u8]) -> Ordering {
+    user_key(a)
+        .cmp(user_key(b))
+        .then_with(|| sequence_of(b).cmp(&sequence_of(a)))
+}
@@ -0,0 +1,161 @@
+use std::borrow::Cow;
+use std::io;
+
+use super::format::{corruption, get_varint32, put_varint64};
+
+const HASH_BITS: u32 = 12;
+
+const MIN_MATCH: usize = 4;
+
+/// The last bytes of a block are always literals
+const LAST_LITERALS: usize = 5;
+
+/// A match starts at least this far from the end of a block
+const MF_LIMIT: usize = 12;
+
+const MAX_OFFSET: usize = u16::MAX as usize;
+
+/// Compresses `input` to an LZ4 block preceded by its uncompressed length
+///
+/// The output is `[Uncompressed_Length(varint) | Sequences]`, the LZ4 block format not storing
+/// the length itself. A sequence is `[Token(1B) | Literal_Length+ | Literals | Offset(u16) |
+/// Match_Length+]`: the token holds both lengths in 4 bits each, a length of 15 goes on in the
+/// following bytes, 255 at a time; the last sequence is literals only.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    compress_with_dict(input, &[])
+}
+
+/// Like `compress`, with matches reaching into `dict` as if it preceded the input, the way LZ4
+/// uses a dictionary: the last 64KB of it can be matched
+pub fn compress_with_dict(input: &[u8], dict: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2 + 16);
+    put_varint64(&mut out, input.len() as u64);
+    let dict = &dict[dict.len().saturating_sub(MAX_OFFSET)..];
+    let data = match dict.is_empty() {
+        true => Cow::Borrowed(input),
+        false => Cow::Owned([dict, input].concat()),
+    };
+    let mut table = vec![0u32; 1 << HASH_BITS];
+    for pos in 0..dict.len().saturating_sub(3) {
+        table[hash(load32(&data, pos))] = pos as u32;
+    }
+
+    let mut literal_start = dict.len();
+    let mut pos = dict.len();
+    let match_limit = data.len().saturating_sub(MF_LIMIT);
+    while pos < match_limit {
+        let slot = hash(load32(&data, pos));
+        let candidate = table[slot] as usize;
+        table[slot] = pos as u32;
+        if candidate >= pos || pos - candidate > MAX_OFFSET || load32(&data, candidate) != load32(&data, pos) {
+            pos += 1;
+            continue;
+        }
+        let end = data.len() - LAST_LITERALS;
+        let len = MIN_MATCH + common_len(&data[candidate + MIN_MATCH..end], &data[pos + MIN_MATCH..end]);
+        emit_sequence(&data[literal_start..pos], Some((pos - candidate, len)), &mut out);
+        pos += len;
+        literal_start = pos;
+    }
+    emit_sequence(&data[literal_start..], None, &mut out);
+    out
+}
+
+fn load32(input: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(input[pos..pos + 4].try_into().unwrap())
+}
+
+fn hash(bytes: u32) -> usize {
+    (bytes.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+fn common_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+fn emit_sequence(literals: &[u8], copy: Option<(usize, usize)>, out: &mut Vec<u8>) {
+    let match_len = copy.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push((literals.len().min(15) as u8) << 4 | match_len.min(15) as u8);
+    if literals.len() >= 15 {
+        put_length(literals.len() - 15, out);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = copy {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_len >= 15 {
+            put_length(match_len - 15, out);
+        }
+    }
+}
+
+fn put_length(mut len: usize, out: &mut Vec<u8>) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+fn get_length(input: &mut &[u8]) -> io::Result<usize> {
+    let mut len = 0usize;
+    loop {
+        let (&byte, rest) = input.split_first().ok_or_else(|| corruption("truncated lz4 length"))?;
+        *input = rest;
+        len = len.checked_add(byte as usize).ok_or_else(|| corruption("bad lz4 length"))?;
+        if byte != 255 {
+            return Ok(len);
+        }
+    }
+}
+
+/// Uncompresses what `compress` wrote, any malformed input is `InvalidData`
+pub fn uncompress(input: &[u8]) -> io::Result<Vec<u8>> {
+    uncompress_with_dict(input, &[])
+}
+
+/// Uncompresses what `compress_with_dict` wrote with `dict`
+pub fn uncompress_with_dict(input: &[u8], dict: &[u8]) -> io::Result<Vec<u8>> {
+    let mut input = input;
+    let dict = &dict[dict.len().saturating_sub(MAX_OFFSET)..];
+    let len = get_varint32(&mut input).ok_or_else(|| corruption("bad lz4 length"))? as usize + dict.len();
+    let mut out = Vec::with_capacity(len);
+    out.extend_from_slice(dict);
+    while let Some((&token, rest)) = input.split_first() {
+        input = rest;
+        let mut literal_len = (token >> 4) as usize;
+        if literal_len == 15 {
+            literal_len += get_length(&mut input)?;
+        }
+        let literals = input
+            .get(..literal_len)
+            .filter(|literals| out.len() + literals.len() <= len)
+            .ok_or_else(|| corruption("bad lz4 literals"))?;
+        out.extend_from_slice(literals);
+        input = &input[literal_len..];
+        if input.is_empty() {
+            // the last sequence has no match
+            break;
+        }
+        let bytes = input.get(..2).ok_or_else(|| corruption("truncated lz4 offset"))?;
+        let offset = u16::from_le_bytes(bytes.try_into().unwrap()) as usize;
+        input = &input[2..];
+        let mut match_len = (token & 0x0f) as usize;
+        if match_len == 15 {
+            match_len += get_length(&mut input)?;
+        }
+        match_len += MIN_MATCH;
+        if offset == 0 || offset > out.len() || out.len() + match_len > len {
+            return Err(corruption("bad lz4 match"));
+        }
+        // the source may overlap the bytes being copied, which repeats them
+        let start = out.len() - offset;
+        for i in 0..match_len {
+            out.push(out[start + i]);
+        }
+    }
+    if out.len() != len {
+        return Err(corruption("lz4 output shorter than its length"));
+    }
+    out.drain(..dict.len());
+    Ok(out)
+}
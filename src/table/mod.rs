@@ -0,0 +1,160 @@
+pub mod block;
+pub mod builder;
+pub mod dictionary;
+pub mod filter;
+pub mod format;
+pub mod lz4;
+pub mod plain;
+pub mod prefix;
+pub mod properties;
+pub mod reader;
+pub mod ribbon;
+pub mod snappy;
+
+use std::cmp::Reverse;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::env::FileSystem;
+use crate::memtable::MemtableEntry;
+use crate::wal::RecordType;
+
+pub use builder::{DataBlockIndexType, IndexType, TableBuilder, TableOptions};
+pub use filter::{BloomFilterPolicy, FilterCounters, FilterPolicy, FilterStats};
+pub use format::CompressionType;
+pub use plain::{PlainTable, PlainTableFactory};
+pub use prefix::{CustomPrefix, DelimiterPrefix, FixedPrefix, PrefixExtractor};
+pub use properties::TableProperties;
+pub use reader::{BlockBasedTableFactory, TableReader, TwoLevelIter};
+pub use ribbon::RibbonFilterPolicy;
+
+/// Extension of table files, a table is named after its file number: `000042.sst`
+pub const TABLE_EXTENSION: &str = "sst";
+
+pub fn table_file_name(number: u64) -> String {
+    format!("{number:06}.{TABLE_EXTENSION}")
+}
+
+/// Returns the table number encoded in a file name, `None` for any other file
+pub fn parse_table_file_name(name: &str) -> Option<u64> {
+    let stem = name.strip_suffix(TABLE_EXTENSION)?.strip_suffix('.')?;
+    if stem.is_empty() || !stem.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    stem.parse().ok()
+}
+
+/// A version of a key read from a table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableEntry {
+    pub key: Vec<u8>,
+    pub sequence: u64,
+    pub record_type: RecordType,
+    pub value: Vec<u8>,
+}
+
+impl TableEntry {
+    pub fn from_memtable(entry: &MemtableEntry<'_>) -> Self {
+        TableEntry {
+            key: entry.key.to_vec(),
+            sequence: entry.sequence,
+            record_type: entry.record_type,
+            value: entry.value.to_vec(),
+        }
+    }
+
+    /// Sort key of the internal order: key ascending, then newest version first
+    pub fn order(&self) -> (&[u8], Reverse<u64>) {
+        (&self.key, Reverse(self.sequence))
+    }
+}
+
+/// Entries of a table in order, reading can fail on I/O errors and corrupted data
+pub type TableIter<'a> = Box<dyn Iterator<Item = io::Result<TableEntry>> + 'a>;
+
+/// An immutable sorted file of key versions, in the same order as the memtable
+pub trait Table: Send + Sync {
+    /// Newest version of `key` visible at `sequence`, a `Delete` entry means the key is deleted
+    fn get(&self, key: &[u8], sequence: u64) -> io::Result<Option<TableEntry>>;
+
+    /// Highest sequence stored in the table, the WAL records up to it are not needed anymore
+    fn max_sequence(&self) -> Option<u64>;
+
+    /// Seconds since the Unix epoch when the table was written, `None` when the format does not
+    /// record it
+    fn creation_time(&self) -> Option<u64> {
+        None
+    }
+
+    /// Every version of every key, by key then newest version first
+    fn iter(&self) -> TableIter<'_>;
+
+    /// Like `iter`, starting at the first key not less than `key`
+    fn iter_from(&self, key: &[u8]) -> TableIter<'_>;
+
+    /// False when the table certainly holds no key starting with `prefix`, e.g. ruled out by a
+    /// prefix filter
+    fn may_contain_prefix(&self, _prefix: &[u8]) -> bool {
+        true
+    }
+
+    /// Every version of the keys starting with `prefix`, nothing is read when the table does not
+    /// hold the prefix
+    fn prefix_iter<'a>(&'a self, prefix: &'a [u8]) -> TableIter<'a> {
+        if !self.may_contain_prefix(prefix) {
+            return Box::new(std::iter::empty());
+        }
+        Box::new(
+            self.iter_from(prefix)
+                .take_while(move |entry| entry.as_ref().map_or(true, |entry| entry.key.starts_with(prefix))),
+        )
+    }
+}
+
+/// A table being written one entry at a time, the entries come in the order of `TableFactory::build`
+pub trait TableSink {
+    fn add(&mut self, entry: MemtableEntry<'_>) -> io::Result<()>;
+
+    /// About the size of the file if it were finished now
+    fn estimated_size(&self) -> u64;
+
+    /// Writes what is left and syncs the file, returns its size
+    fn finish(self: Box<Self>) -> io::Result<u64>;
+}
+
+/// Writes and opens the tables of one format
+pub trait TableFactory: Send + Sync {
+    /// Writes `entries`, sorted by key then newest first, to a new file at `path` and syncs it
+    /// Returns the size of the file.
+    fn build(
+        &self,
+        fs: &dyn FileSystem,
+        path: &Path,
+        entries: &mut dyn Iterator<Item = MemtableEntry<'_>>,
+    ) -> io::Result<u64>;
+
+    /// Like `build`, for a table of level `level` of the store: a format with per-level settings
+    /// (e.g. the compression) applies those of the level
+    fn build_for_level(
+        &self,
+        fs: &dyn FileSystem,
+        path: &Path,
+        _level: usize,
+        entries: &mut dyn Iterator<Item = MemtableEntry<'_>>,
+    ) -> io::Result<u64> {
+        self.build(fs, path, entries)
+    }
+
+    /// A new table of level `level` at `path`, filled by the caller: unlike `build` the entries are
+    /// not needed at once, a compaction adds them as it merges and finishes the table at its size
+    fn new_builder(&self, fs: &dyn FileSystem, path: &Path, level: usize) -> io::Result<Box<dyn TableSink>>;
+
+    fn open(&self, fs: &dyn FileSystem, path: &Path) -> io::Result<Arc<dyn Table>>;
+
+    /// Like `open`, for a table known by `cache_id` whenever it is opened: a format with a block
+    /// cache keys the blocks of the table by it, so that a reopened table finds them
+    fn open_with_id(&self, fs: &dyn FileSystem, path: &Path, _cache_id: u64) -> io::Result<Arc<dyn Table>> {
+        self.open(fs, path)
+    }
+}
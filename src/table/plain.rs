@@ -0,0 +1,142 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::env::{FileSystem, WritableFile};
+use crate::memtable::MemtableEntry;
+use crate::wal::WalReader;
+use crate::wal::record::{FileHeader, encode_record};
+use crate::wal::version::{CURRENT_VERSION, DecoderRegistry};
+
+use super::{Table, TableEntry, TableFactory, TableIter, TableSink};
+
+/// Simplest table format: the entries in order, framed as WAL records
+///
+/// The whole table is loaded in memory when opened and searched with a binary search. It needs no
+/// index and is easy to inspect with `stone-wal dump`, but is only meant for small stores and tests.
+pub struct PlainTable {
+    entries: Vec<TableEntry>,
+    max_sequence: Option<u64>,
+}
+
+impl PlainTable {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[TableEntry] {
+        &self.entries
+    }
+}
+
+impl Table for PlainTable {
+    fn get(&self, key: &[u8], sequence: u64) -> io::Result<Option<TableEntry>> {
+        let at = self
+            .entries
+            .partition_point(|entry| entry.order() < (key, std::cmp::Reverse(sequence)));
+        Ok(self.entries.get(at).filter(|entry| entry.key == key).cloned())
+    }
+
+    fn max_sequence(&self) -> Option<u64> {
+        self.max_sequence
+    }
+
+    fn iter(&self) -> TableIter<'_> {
+        Box::new(self.entries.iter().cloned().map(Ok))
+    }
+
+    fn iter_from(&self, key: &[u8]) -> TableIter<'_> {
+        let at = self.entries.partition_point(|entry| entry.key.as_slice() < key);
+        Box::new(self.entries[at..].iter().cloned().map(Ok))
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PlainTableFactory;
+
+impl TableFactory for PlainTableFactory {
+    fn build(
+        &self,
+        fs: &dyn FileSystem,
+        path: &Path,
+        entries: &mut dyn Iterator<Item = MemtableEntry<'_>>,
+    ) -> io::Result<u64> {
+        let mut builder = self.new_builder(fs, path, 0)?;
+        for entry in entries {
+            builder.add(entry)?;
+        }
+        builder.finish()
+    }
+
+    fn new_builder(&self, fs: &dyn FileSystem, path: &Path, _level: usize) -> io::Result<Box<dyn TableSink>> {
+        Ok(Box::new(PlainTableBuilder {
+            file: fs.create_new(path)?,
+            buf: FileHeader { version: CURRENT_VERSION }.encode().to_vec(),
+            len: 0,
+        }))
+    }
+
+    fn open(&self, fs: &dyn FileSystem, path: &Path) -> io::Result<Arc<dyn Table>> {
+        let mut reader = WalReader::open_in(fs, path, &DecoderRegistry::default())?;
+        let mut entries = Vec::new();
+        while let Some(record) = reader.next_record()? {
+            entries.push(TableEntry {
+                key: record.key,
+                sequence: record.sequence,
+                record_type: record.record_type,
+                value: record.value,
+            });
+        }
+        let stats = reader.stats();
+        // a table is written whole before it is used, any damage is corruption
+        if stats.corrupted_records > 0 || stats.truncated_tail_bytes > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("table {} is corrupted", path.display()),
+            ));
+        }
+        if entries.windows(2).any(|pair| pair[0].order() >= pair[1].order()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("table {} is not sorted", path.display()),
+            ));
+        }
+        let max_sequence = entries.iter().map(|entry| entry.sequence).max();
+        Ok(Arc::new(PlainTable { entries, max_sequence }))
+    }
+}
+
+/// Appends the records in chunks of 64KB
+struct PlainTableBuilder {
+    file: Box<dyn WritableFile>,
+    buf: Vec<u8>,
+    /// Bytes appended to the file
+    len: u64,
+}
+
+impl TableSink for PlainTableBuilder {
+    fn add(&mut self, entry: MemtableEntry<'_>) -> io::Result<()> {
+        encode_record(&mut self.buf, entry.record_type, entry.sequence, entry.key, entry.value);
+        if self.buf.len() >= 64 * 1024 {
+            self.file.append(&self.buf)?;
+            self.len += self.buf.len() as u64;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+
+    fn estimated_size(&self) -> u64 {
+        self.len + self.buf.len() as u64
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<u64> {
+        self.file.append(&self.buf)?;
+        self.len += self.buf.len() as u64;
+        self.file.sync()?;
+        Ok(self.len)
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt;
+
+/// Name of the meta block recording the prefix extractor the filter of a table was built with
+pub const PREFIX_EXTRACTOR_BLOCK: &str = "stone.prefix_extractor";
+
+/// Maps a key to the prefix its range scans share, e.g. the tenant of a `tenant/entity/id` key
+///
+/// The table builder adds the prefix of every key to the filter, so that a prefix scan skips the
+/// tables whose filter rules its prefix out. An extractor must be consistent: when `prefix(key)`
+/// is `Some(p)`, every key starting with `key` has the prefix `p` too. `None` means the key is out
+/// of the domain of the extractor, it adds no prefix to the filter and a scan of it reads every
+/// table.
+///
+/// The name is recorded in the tables: the prefixes of a table are only used by an extractor of
+/// the same name, changing what an extractor does requires a new name.
+pub trait PrefixExtractor: Send + Sync + fmt::Debug {
+    fn name(&self) -> &str;
+
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]>;
+}
+
+/// The first `len` bytes of a key, keys shorter than that are out of the domain
+#[derive(Debug, Clone, Copy)]
+pub struct FixedPrefix {
+    len: usize,
+    name: &'static str,
+}
+
+impl FixedPrefix {
+    pub fn new(len: usize) -> Self {
+        FixedPrefix {
+            len,
+            name: "stone.fixed",
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl PrefixExtractor for FixedPrefix {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        key.get(..self.len)
+    }
+}
+
+/// A key up to its `count`-th `delimiter`, included: `DelimiterPrefix::new(b'/', 1)` maps
+/// `tenant/entity/id` to `tenant/`. Keys with fewer delimiters are out of the domain.
+#[derive(Debug, Clone, Copy)]
+pub struct DelimiterPrefix {
+    delimiter: u8,
+    count: usize,
+}
+
+impl DelimiterPrefix {
+    pub fn new(delimiter: u8, count: usize) -> Self {
+        DelimiterPrefix {
+            delimiter,
+            count: count.max(1),
+        }
+    }
+}
+
+impl PrefixExtractor for DelimiterPrefix {
+    fn name(&self) -> &str {
+        "stone.delimiter"
+    }
+
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        let (end, _) = key
+            .iter()
+            .enumerate()
+            .filter(|&(_, &b)| b == self.delimiter)
+            .nth(self.count - 1)?;
+        Some(&key[..=end])
+    }
+}
+
+/// An extractor given as a function
+pub struct CustomPrefix<F> {
+    name: String,
+    extract: F,
+}
+
+impl<F> CustomPrefix<F>
+where
+    F: for<'a> Fn(&'a [u8]) -> Option<&'a [u8]> + Send + Sync,
+{
+    /// `name` identifies the function in the tables, see `PrefixExtractor`
+    pub fn new(name: impl Into<String>, extract: F) -> Self {
+        CustomPrefix {
+            name: name.into(),
+            extract,
+        }
+    }
+}
+
+impl<F> fmt::Debug for CustomPrefix<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomPrefix")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl<F> PrefixExtractor for CustomPrefix<F>
+where
+    F: for<'a> Fn(&'a [u8]) -> Option<&'a [u8]> + Send + Sync,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        (self.extract)(key)
+    }
+}
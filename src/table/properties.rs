@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Arc;
+
+use super::block::{Block, BlockBuilder};
+use super::format::{corruption, get_varint64, put_varint64};
+
+/// Name of the properties block in the metaindex
+pub const PROPERTIES_BLOCK: &str = "stone.properties";
+
+/// Statistics of a table, stored in a meta block and read when the table is opened
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableProperties {
+    pub num_entries: u64,
+    pub num_deletions: u64,
+    pub num_data_blocks: u64,
+    /// Data blocks stored compressed, the others did not compress enough
+    pub num_compressed_blocks: u64,
+    /// Type byte of the compression of the data blocks (`CompressionType`)
+    pub compression: u64,
+    /// Bytes of the dictionary the data blocks are compressed with, 0 without one
+    pub compression_dictionary_size: u64,
+    /// User keys and values as given to the builder, before any encoding
+    pub raw_key_size: u64,
+    pub raw_value_size: u64,
+    /// Bytes of the data blocks and of the index, trailers included
+    pub data_size: u64,
+    pub index_size: u64,
+    /// Number of index partitions, 0 when the index is a single block
+    pub index_partitions: u64,
+    /// Bytes of the filter block, trailer included, 0 without a filter
+    pub filter_size: u64,
+    /// Lowest and highest sequence of the entries, 0 for an empty table
+    pub min_sequence: u64,
+    pub max_sequence: u64,
+    /// Seconds since the Unix epoch when the builder was created, 0 for a table written before
+    /// the property existed
+    pub creation_time: u64,
+}
+
+impl TableProperties {
+    /// Encodes the properties as a block of `name -> varint64` entries, so that a reader skips
+    /// the names it does not know
+    pub fn encode(&self) -> Vec<u8> {
+        let mut block = BlockBuilder::new(1);
+        let mut value = Vec::new();
+        for (name, property) in self.named() {
+            value.clear();
+            put_varint64(&mut value, property);
+            block.add(name.as_bytes(), &value);
+        }
+        block.finish().to_vec()
+    }
+
+    pub fn decode(block: Block) -> io::Result<Self> {
+        let mut named = BTreeMap::new();
+        let mut iter = Arc::new(block).iter();
+        iter.seek_to_first();
+        while iter.valid() {
+            let mut value = iter.value();
+            let property = get_varint64(&mut value).ok_or_else(|| corruption("bad table property"))?;
+            named.insert(String::from_utf8_lossy(iter.key()).into_owned(), property);
+            iter.advance();
+        }
+        iter.status()?;
+
+        let mut properties = TableProperties::default();
+        for (name, property) in properties.named_mut() {
+            if let Some(&value) = named.get(name) {
+                *property = value;
+            }
+        }
+        Ok(properties)
+    }
+
+    /// The properties by name, in the order of the names
+    fn named(&self) -> BTreeMap<&'static str, u64> {
+        let mut copy = self.clone();
+        copy.named_mut().into_iter().map(|(name, value)| (name, *value)).collect()
+    }
+
+    fn named_mut(&mut self) -> BTreeMap<&'static str, &mut u64> {
+        BTreeMap::from([
+            ("stone.compression", &mut self.compression),
+            ("stone.compression.dictionary.size", &mut self.compression_dictionary_size),
+            ("stone.creation.time", &mut self.creation_time),
+            ("stone.data.size", &mut self.data_size),
+            ("stone.filter.size", &mut self.filter_size),
+            ("stone.index.partitions", &mut self.index_partitions),
+            ("stone.index.size", &mut self.index_size),
+            ("stone.num.compressed.blocks", &mut self.num_compressed_blocks),
+            ("stone.num.data.blocks", &mut self.num_data_blocks),
+            ("stone.num.deletions", &mut self.num_deletions),
+            ("stone.num.entries", &mut self.num_entries),
+            ("stone.raw.key.size", &mut self.raw_key_size),
+            ("stone.raw.value.size", &mut self.raw_value_size),
+            ("stone.sequence.max", &mut self.max_sequence),
+            ("stone.sequence.min", &mut self.min_sequence),
+        ])
+    }
+}
@@ -0,0 +1,637 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::cache::{BlockCache, Priority};
+use crate::env::{FileSystem, RandomAccessFile};
+use crate::memtable::MemtableEntry;
+use crate::wal::RecordType;
+
+use super::block::{Block, BlockIter};
+use super::builder::{TableBuilder, TableOptions};
+use super::dictionary::COMPRESSION_DICTIONARY_BLOCK;
+use super::filter::{FILTER_BLOCK, Filter, FilterStats};
+use super::format::{
+    BLOCK_TRAILER_SIZE, BlockHandle, CompressionType, FOOTER_SIZE, Footer, MAX_SEQUENCE, block_checksum,
+    compare_internal_keys, corruption, internal_key, parse_internal_key, user_key,
+};
+use super::prefix::{PREFIX_EXTRACTOR_BLOCK, PrefixExtractor};
+use super::properties::{PROPERTIES_BLOCK, TableProperties};
+use super::{Table, TableEntry, TableFactory, TableIter, TableSink};
+
+/// Reads a table written by `TableBuilder`
+///
+/// The index block and the properties are loaded when the table is opened, a lookup reads the one
+/// data block the index points to. With a partitioned index only its top level is loaded, a lookup
+/// also reads the index partition it points to. Data blocks and partitions go through the block
+/// cache of the options when there is one. Every block read is checked against its CRC32C, a mismatch or
+/// any malformed content is reported as `InvalidData`.
+pub struct TableReader {
+    file: Arc<dyn RandomAccessFile>,
+    file_size: u64,
+    path: PathBuf,
+    /// The index, or the top level of a partitioned index
+    index: Arc<Block>,
+    partitioned: bool,
+    block_cache: Option<Arc<BlockCache>>,
+    /// First part of the cache keys of the blocks of this table
+    cache_id: u64,
+    meta_blocks: BTreeMap<String, BlockHandle>,
+    properties: TableProperties,
+    /// Loaded when the table is opened and kept with the reader
+    filter: Option<Filter>,
+    filter_stats: Arc<FilterStats>,
+    /// The extractor of the options, when the filter holds the prefixes it extracts
+    prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    /// False when the filter only holds prefixes
+    whole_key_filtered: bool,
+    /// Dictionary the data blocks were compressed with, empty without one
+    compression_dictionary: Vec<u8>,
+}
+
+impl TableReader {
+    /// Opens a table, `options` only provides the block cache: the layout is read from the file
+    ///
+    /// The blocks are cached under a new id of the cache, see `open_with_id` to reach the blocks
+    /// cached by an earlier reader of the same table.
+    pub fn open(fs: &dyn FileSystem, path: &Path, options: &TableOptions) -> io::Result<Self> {
+        let cache_id = options.block_cache.as_ref().map_or(0, |cache| cache.new_id());
+        Self::open_with_id(fs, path, options, cache_id)
+    }
+
+    /// Like `open`, caching the blocks under `cache_id`: the id of the table in its store, the
+    /// same on every reopen
+    pub fn open_with_id(fs: &dyn FileSystem, path: &Path, options: &TableOptions, cache_id: u64) -> io::Result<Self> {
+        let file = fs.open_random_access(path)?;
+        let file_size = file.size()?;
+        if file_size < FOOTER_SIZE as u64 {
+            return Err(corruption(format!("{} is too short to be a table", path.display())));
+        }
+        let mut footer = [0u8; FOOTER_SIZE];
+        file.read_exact_at(file_size - FOOTER_SIZE as u64, &mut footer)?;
+        let footer = Footer::decode(&footer)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+
+        let index = read_block(file.as_ref(), file_size, path, footer.index)?;
+        let mut metaindex = Arc::new(read_block(file.as_ref(), file_size, path, footer.metaindex)?).iter();
+        let mut meta_blocks = BTreeMap::new();
+        metaindex.seek_to_first();
+        while metaindex.valid() {
+            let handle = BlockHandle::decode_from(&mut metaindex.value())?;
+            meta_blocks.insert(String::from_utf8_lossy(metaindex.key()).into_owned(), handle);
+            metaindex.advance();
+        }
+        metaindex.status()?;
+
+        let properties = match meta_blocks.get(PROPERTIES_BLOCK) {
+            Some(&handle) => TableProperties::decode(read_block(file.as_ref(), file_size, path, handle)?)?,
+            None => TableProperties::default(),
+        };
+        let filter = match meta_blocks.get(FILTER_BLOCK) {
+            Some(&handle) => Some(Filter::new(read_block_contents(file.as_ref(), file_size, path, handle, &[])?)),
+            None => None,
+        };
+        // the prefixes in the filter are only usable by the extractor that added them
+        let (prefix_extractor, whole_key_filtered) = match meta_blocks.get(PREFIX_EXTRACTOR_BLOCK) {
+            Some(&handle) => {
+                let block = read_block_contents(file.as_ref(), file_size, path, handle, &[])?;
+                let (&whole_keys, name) = block
+                    .split_first()
+                    .ok_or_else(|| corruption(format!("empty prefix extractor block in {}", path.display())))?;
+                let extractor = options
+                    .prefix_extractor
+                    .clone()
+                    .filter(|extractor| extractor.name().as_bytes() == name);
+                (extractor, whole_keys != 0)
+            }
+            None => (None, true),
+        };
+        let compression_dictionary = match meta_blocks.get(COMPRESSION_DICTIONARY_BLOCK) {
+            Some(&handle) => read_block_contents(file.as_ref(), file_size, path, handle, &[])?,
+            None => Vec::new(),
+        };
+        let block_cache = options.block_cache.clone();
+        Ok(TableReader {
+            file,
+            file_size,
+            path: path.to_path_buf(),
+            index: Arc::new(index),
+            partitioned: properties.index_partitions > 0,
+            cache_id,
+            block_cache,
+            meta_blocks,
+            properties,
+            filter,
+            filter_stats: Arc::clone(&options.filter_stats),
+            prefix_extractor,
+            whole_key_filtered,
+            compression_dictionary,
+        })
+    }
+
+    pub fn properties(&self) -> &TableProperties {
+        &self.properties
+    }
+
+    /// Location of the meta block `name`, as recorded in the metaindex
+    pub fn meta_block(&self, name: &str) -> Option<BlockHandle> {
+        self.meta_blocks.get(name).copied()
+    }
+
+    /// Bytes of index held by the reader: the whole index, or the top level of a partitioned one
+    pub fn index_memory_usage(&self) -> usize {
+        self.index.size()
+    }
+
+    /// Reads a block and checks its trailer
+    pub fn read_block(&self, handle: BlockHandle) -> io::Result<Block> {
+        let dictionary = &self.compression_dictionary;
+        Block::new(read_block_contents(self.file.as_ref(), self.file_size, &self.path, handle, dictionary)?)
+    }
+
+    /// A data block or index partition, from the block cache when it holds it. Index partitions
+    /// go to the high-priority pool of the cache.
+    fn block(&self, handle: BlockHandle, priority: Priority) -> io::Result<Arc<Block>> {
+        let Some(cache) = &self.block_cache else {
+            return Ok(Arc::new(self.read_block(handle)?));
+        };
+        let key = (self.cache_id, handle.offset);
+        if let Some(block) = cache.get(key) {
+            return Ok(block);
+        }
+        let block = Arc::new(self.read_block(handle)?);
+        cache.insert_with(key, Arc::clone(&block), block.size(), priority);
+        Ok(block)
+    }
+
+    fn index_iter(&self) -> IndexIter {
+        match self.partitioned {
+            true => IndexIter::Partitioned {
+                top: self.index.iter(),
+                partition: None,
+            },
+            false => IndexIter::Single(self.index.iter()),
+        }
+    }
+
+    /// Newest version of `key` visible at `sequence`
+    ///
+    /// With a filter, a key the filter rules out is answered without reading a data block. When
+    /// the filter only holds prefixes the prefix of the key is checked instead.
+    pub fn get(&self, key: &[u8], sequence: u64) -> io::Result<Option<TableEntry>> {
+        let checked = match (&self.filter, self.point_filter_key(key)) {
+            (Some(filter), Some(filter_key)) => {
+                let may_match = filter.may_match(filter_key);
+                self.filter_stats.record_check(may_match);
+                if !may_match {
+                    return Ok(None);
+                }
+                true
+            }
+            _ => false,
+        };
+        let (found, present) = self.lookup(key, sequence)?;
+        // a key only newer than `sequence` was rightly let through by the filter
+        if !present && checked {
+            self.filter_stats.record_false_positive();
+        }
+        Ok(found)
+    }
+
+    /// False when the filter rules out every key starting with `prefix`
+    ///
+    /// Only known when the table was built with the prefix extractor of the options and `prefix`
+    /// is in its domain, otherwise the table may hold such keys.
+    pub fn may_contain_prefix(&self, prefix: &[u8]) -> bool {
+        let (Some(filter), Some(extractor)) = (&self.filter, &self.prefix_extractor) else {
+            return true;
+        };
+        let Some(prefix) = extractor.prefix(prefix) else {
+            return true;
+        };
+        let may_match = filter.may_match(prefix);
+        self.filter_stats.record_prefix_check(may_match);
+        may_match
+    }
+
+    /// What a point lookup of `key` checks in the filter
+    fn point_filter_key<'k>(&self, key: &'k [u8]) -> Option<&'k [u8]> {
+        match self.whole_key_filtered {
+            true => Some(key),
+            false => self.prefix_extractor.as_ref()?.prefix(key),
+        }
+    }
+
+    /// Bytes of filter held by the reader
+    pub fn filter_memory_usage(&self) -> usize {
+        self.filter.as_ref().map_or(0, Filter::size)
+    }
+
+    /// The newest version of `key` not newer than `sequence`, and whether the table holds any
+    /// version of `key`
+    ///
+    /// The lookup goes to the newest version first, which tells whether the key is there, then on to
+    /// the version visible at `sequence`: in the same block unless the versions span blocks.
+    fn lookup(&self, key: &[u8], sequence: u64) -> io::Result<(Option<TableEntry>, bool)> {
+        let newest = seek_key(key, MAX_SEQUENCE);
+        let target = seek_key(key, sequence);
+        let mut index = self.index_iter();
+        index.seek(self, &newest)?;
+        if !index.valid() {
+            index.status()?;
+            return Ok((None, false));
+        }
+        let mut data = self.data_block(&index)?;
+        data.seek_for_get(&newest, compare_internal_keys);
+        if !data.valid() {
+            data.status()?;
+            return Ok((None, false));
+        }
+        if user_key(data.key()) != key {
+            return Ok((None, false));
+        }
+        if compare_internal_keys(data.key(), &target) == Ordering::Less {
+            data.seek(&target, compare_internal_keys);
+            while !data.valid() {
+                data.status()?;
+                index.advance(self)?;
+                if !index.valid() {
+                    index.status()?;
+                    return Ok((None, true));
+                }
+                data = self.data_block(&index)?;
+                data.seek(&target, compare_internal_keys);
+            }
+        }
+        let entry = decode_entry(data.key(), data.value())?;
+        Ok(((entry.key == key).then_some(entry), true))
+    }
+
+    /// Cursor over the data block the index is positioned on
+    fn data_block(&self, index: &IndexIter) -> io::Result<BlockIter> {
+        let handle = BlockHandle::decode_from(&mut index.value())?;
+        Ok(self.block(handle, Priority::Low)?.iter())
+    }
+
+    /// Every version of every key, in the internal order
+    pub fn iter(&self) -> TwoLevelIter<'_> {
+        let mut iter = TwoLevelIter::new(self);
+        if let Err(e) = iter.index.seek_to_first(self) {
+            iter.error = Some(e);
+        }
+        iter
+    }
+
+    /// Like `iter`, starting at the first key not less than `key`
+    pub fn iter_from(&self, key: &[u8]) -> TwoLevelIter<'_> {
+        let mut iter = TwoLevelIter::new(self);
+        iter.seek(key, u64::MAX);
+        iter
+    }
+}
+
+impl Table for TableReader {
+    fn get(&self, key: &[u8], sequence: u64) -> io::Result<Option<TableEntry>> {
+        TableReader::get(self, key, sequence)
+    }
+
+    fn max_sequence(&self) -> Option<u64> {
+        (self.properties.num_entries > 0).then_some(self.properties.max_sequence)
+    }
+
+    fn creation_time(&self) -> Option<u64> {
+        (self.properties.creation_time > 0).then_some(self.properties.creation_time)
+    }
+
+    fn iter(&self) -> TableIter<'_> {
+        Box::new(TableReader::iter(self))
+    }
+
+    fn iter_from(&self, key: &[u8]) -> TableIter<'_> {
+        Box::new(TableReader::iter_from(self, key))
+    }
+
+    fn may_contain_prefix(&self, prefix: &[u8]) -> bool {
+        TableReader::may_contain_prefix(self, prefix)
+    }
+}
+
+/// Cursor over the data block handles of a table, going through the partitions of a partitioned
+/// index
+enum IndexIter {
+    Single(BlockIter),
+    Partitioned {
+        top: BlockIter,
+        /// Cursor over the partition `top` is positioned on
+        partition: Option<BlockIter>,
+    },
+}
+
+impl IndexIter {
+    fn valid(&self) -> bool {
+        match self {
+            IndexIter::Single(index) => index.valid(),
+            IndexIter::Partitioned { partition, .. } => partition.as_ref().is_some_and(BlockIter::valid),
+        }
+    }
+
+    /// The encoded handle of the current data block
+    fn value(&self) -> &[u8] {
+        match self {
+            IndexIter::Single(index) => index.value(),
+            IndexIter::Partitioned { partition, .. } => partition.as_ref().expect("index not valid").value(),
+        }
+    }
+
+    fn status(&self) -> io::Result<()> {
+        match self {
+            IndexIter::Single(index) => index.status(),
+            IndexIter::Partitioned { top, partition } => {
+                top.status()?;
+                partition.as_ref().map_or(Ok(()), BlockIter::status)
+            }
+        }
+    }
+
+    fn seek_to_first(&mut self, table: &TableReader) -> io::Result<()> {
+        match self {
+            IndexIter::Single(index) => index.seek_to_first(),
+            IndexIter::Partitioned { top, partition } => {
+                top.seek_to_first();
+                *partition = load_partition(table, top)?;
+                if let Some(partition) = partition {
+                    partition.seek_to_first();
+                }
+                self.skip_empty_partitions(table)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn seek(&mut self, table: &TableReader, target: &[u8]) -> io::Result<()> {
+        match self {
+            IndexIter::Single(index) => index.seek(target, compare_internal_keys),
+            IndexIter::Partitioned { top, partition } => {
+                top.seek(target, compare_internal_keys);
+                *partition = load_partition(table, top)?;
+                if let Some(partition) = partition {
+                    partition.seek(target, compare_internal_keys);
+                }
+                self.skip_empty_partitions(table)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn advance(&mut self, table: &TableReader) -> io::Result<()> {
+        match self {
+            IndexIter::Single(index) => {
+                index.advance();
+            }
+            IndexIter::Partitioned { partition, .. } => {
+                if let Some(partition) = partition {
+                    partition.advance();
+                }
+                self.skip_empty_partitions(table)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves to the first entry of the next partitions while the current one is exhausted
+    fn skip_empty_partitions(&mut self, table: &TableReader) -> io::Result<()> {
+        let IndexIter::Partitioned { top, partition } = self else {
+            return Ok(());
+        };
+        while let Some(current) = partition {
+            if current.valid() {
+                break;
+            }
+            current.status()?;
+            top.advance();
+            *partition = load_partition(table, top)?;
+            if let Some(partition) = partition {
+                partition.seek_to_first();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Cursor over the partition the top-level index is positioned on, `None` past the last one
+fn load_partition(table: &TableReader, top: &BlockIter) -> io::Result<Option<BlockIter>> {
+    if !top.valid() {
+        top.status()?;
+        return Ok(None);
+    }
+    let handle = BlockHandle::decode_from(&mut top.value())?;
+    Ok(Some(table.block(handle, Priority::High)?.iter()))
+}
+
+/// Iterates over a table: a cursor over the index, and one over the data block it points to
+pub struct TwoLevelIter<'a> {
+    table: &'a TableReader,
+    index: IndexIter,
+    data: Option<BlockIter>,
+    /// Set by a seek that failed, returned by the next call to `next`
+    error: Option<io::Error>,
+    done: bool,
+}
+
+impl<'a> TwoLevelIter<'a> {
+    fn new(table: &'a TableReader) -> Self {
+        TwoLevelIter {
+            table,
+            index: table.index_iter(),
+            data: None,
+            error: None,
+            done: false,
+        }
+    }
+
+    /// Positions at the newest version of `key` not newer than `sequence`, or the first entry after
+    pub fn seek(&mut self, key: &[u8], sequence: u64) {
+        let target = seek_key(key, sequence);
+        self.data = None;
+        self.error = None;
+        self.done = false;
+        let loaded = self.index.seek(self.table, &target).and_then(|()| match self.index.valid() {
+            true => self.load_block().map(|()| true),
+            false => Ok(false),
+        });
+        match loaded {
+            Ok(true) => self.data.as_mut().unwrap().seek(&target, compare_internal_keys),
+            Ok(false) => {}
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    /// Reads the data block of the current index entry and moves the index to the next one
+    fn load_block(&mut self) -> io::Result<()> {
+        let handle = BlockHandle::decode_from(&mut self.index.value())?;
+        let block = self.table.block(handle, Priority::Low)?;
+        self.index.advance(self.table)?;
+        let mut data = block.iter();
+        data.seek_to_first();
+        self.data = Some(data);
+        Ok(())
+    }
+
+    fn fail(&mut self, error: io::Error) -> Option<io::Result<TableEntry>> {
+        self.done = true;
+        Some(Err(error))
+    }
+}
+
+impl Iterator for TwoLevelIter<'_> {
+    type Item = io::Result<TableEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Some(error) = self.error.take() {
+            return self.fail(error);
+        }
+        loop {
+            if let Some(data) = &mut self.data {
+                if data.valid() {
+                    let entry = decode_entry(data.key(), data.value());
+                    data.advance();
+                    return match entry {
+                        Ok(entry) => Some(Ok(entry)),
+                        Err(e) => self.fail(e),
+                    };
+                }
+                if let Err(e) = data.status() {
+                    return self.fail(e);
+                }
+            }
+            if !self.index.valid() {
+                self.done = true;
+                return self.index.status().err().map(Err);
+            }
+            if let Err(e) = self.load_block() {
+                return self.fail(e);
+            }
+        }
+    }
+}
+
+/// Writes and opens block-based tables
+#[derive(Debug, Clone, Default)]
+pub struct BlockBasedTableFactory {
+    pub options: TableOptions,
+}
+
+impl TableFactory for BlockBasedTableFactory {
+    fn build(
+        &self,
+        fs: &dyn FileSystem,
+        path: &Path,
+        entries: &mut dyn Iterator<Item = MemtableEntry<'_>>,
+    ) -> io::Result<u64> {
+        self.build_for_level(fs, path, 0, entries)
+    }
+
+    fn build_for_level(
+        &self,
+        fs: &dyn FileSystem,
+        path: &Path,
+        level: usize,
+        entries: &mut dyn Iterator<Item = MemtableEntry<'_>>,
+    ) -> io::Result<u64> {
+        let mut builder = self.new_builder(fs, path, level)?;
+        for entry in entries {
+            builder.add(entry)?;
+        }
+        builder.finish()
+    }
+
+    fn new_builder(&self, fs: &dyn FileSystem, path: &Path, level: usize) -> io::Result<Box<dyn TableSink>> {
+        let options = TableOptions {
+            compression: self.options.compression_for_level(level),
+            ..self.options.clone()
+        };
+        Ok(Box::new(TableBuilder::new(fs.create_new(path)?, options)))
+    }
+
+    fn open(&self, fs: &dyn FileSystem, path: &Path) -> io::Result<Arc<dyn Table>> {
+        Ok(Arc::new(TableReader::open(fs, path, &self.options)?))
+    }
+
+    fn open_with_id(&self, fs: &dyn FileSystem, path: &Path, cache_id: u64) -> io::Result<Arc<dyn Table>> {
+        Ok(Arc::new(TableReader::open_with_id(fs, path, &self.options, cache_id)?))
+    }
+}
+
+/// The internal key a seek to `(key, sequence)` looks for, the type does not take part in the order
+fn seek_key(key: &[u8], sequence: u64) -> Vec<u8> {
+    internal_key(key, sequence.min(MAX_SEQUENCE), RecordType::Put)
+}
+
+fn decode_entry(internal_key: &[u8], value: &[u8]) -> io::Result<TableEntry> {
+    let (key, sequence, record_type) = parse_internal_key(internal_key)?;
+    Ok(TableEntry {
+        key: key.to_vec(),
+        sequence,
+        record_type,
+        value: value.to_vec(),
+    })
+}
+
+/// Reads a block compressed without a dictionary (the index and meta blocks) and checks its trailer
+fn read_block(file: &dyn RandomAccessFile, file_size: u64, path: &Path, handle: BlockHandle) -> io::Result<Block> {
+    Block::new(read_block_contents(file, file_size, path, handle, &[])?)
+}
+
+/// Reads the contents of a block, without its trailer once checked, uncompressed with `dictionary`
+/// when it is compressed
+fn read_block_contents(
+    file: &dyn RandomAccessFile,
+    file_size: u64,
+    path: &Path,
+    handle: BlockHandle,
+    dictionary: &[u8],
+) -> io::Result<Vec<u8>> {
+    let end = handle
+        .offset
+        .checked_add(handle.size)
+        .and_then(|end| end.checked_add(BLOCK_TRAILER_SIZE as u64))
+        .filter(|&end| end <= file_size);
+    if end.is_none() {
+        return Err(corruption(format!(
+            "block at offset {} of {} is past the end of the file",
+            handle.offset,
+            path.display()
+        )));
+    }
+    let mut data = vec![0u8; handle.size as usize + BLOCK_TRAILER_SIZE];
+    file.read_exact_at(handle.offset, &mut data)?;
+    let trailer = data.split_off(handle.size as usize);
+    let compression = CompressionType::from_byte(trailer[0]).ok_or_else(|| {
+        corruption(format!(
+            "unknown block type {} at offset {} of {}",
+            trailer[0],
+            handle.offset,
+            path.display()
+        ))
+    })?;
+    let stored = u32::from_le_bytes(trailer[1..].try_into().unwrap());
+    if block_checksum(&data, compression) != stored {
+        return Err(corruption(format!(
+            "checksum mismatch in block at offset {} of {}",
+            handle.offset,
+            path.display()
+        )));
+    }
+    compression.uncompress_with_dict(data, dictionary).map_err(|e| {
+        corruption(format!(
+            "{e} in {compression:?} block at offset {} of {}",
+            handle.offset,
+            path.display()
+        ))
+    })
+}
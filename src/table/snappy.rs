@@ -0,0 +1,183 @@
+use std::borrow::Cow;
+use std::io;
+
+use super::format::{corruption, get_varint32, put_varint64};
+
+const HASH_BITS: u32 = 14;
+
+/// Longest match a copy element holds
+const MAX_COPY: usize = 64;
+
+/// Farthest offset a copy reaches, the one of a copy with a 2-byte offset
+const MAX_OFFSET: usize = u16::MAX as usize;
+
+/// Compresses `input` to the raw Snappy format (no framing)
+///
+/// The output is `[Uncompressed_Length(varint) | Elements]`; an element is a literal or a copy of
+/// earlier output, told apart by the low 2 bits of its tag byte. Matches are found with a table of
+/// the last position of each hashed 4 bytes, up to 64KB back.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    compress_with_dict(input, &[])
+}
+
+/// Like `compress`, with copies reaching into `dict` as if it preceded the input. Only
+/// `uncompress_with_dict` with the same dictionary reads the output, the format has no place
+/// for it.
+pub fn compress_with_dict(input: &[u8], dict: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2 + 16);
+    put_varint64(&mut out, input.len() as u64);
+    let dict = &dict[dict.len().saturating_sub(MAX_OFFSET)..];
+    let data = match dict.is_empty() {
+        true => Cow::Borrowed(input),
+        false => Cow::Owned([dict, input].concat()),
+    };
+    let mut table = vec![0u32; 1 << HASH_BITS];
+    for pos in 0..dict.len().saturating_sub(3) {
+        table[hash(load32(&data, pos))] = pos as u32;
+    }
+    compress_from(&data, dict.len(), &mut table, &mut out);
+    out
+}
+
+/// Compresses `data[start..]`, the bytes before `start` are the history copies may reach into
+fn compress_from(data: &[u8], start: usize, table: &mut [u32], out: &mut Vec<u8>) {
+    // a match needs 4 bytes at both ends
+    let limit = data.len().saturating_sub(3);
+    let mut literal_start = start;
+    let mut pos = start;
+    while pos < limit {
+        let slot = hash(load32(data, pos));
+        let candidate = table[slot] as usize;
+        table[slot] = pos as u32;
+        if candidate >= pos || pos - candidate > MAX_OFFSET || load32(data, candidate) != load32(data, pos) {
+            pos += 1;
+            continue;
+        }
+        emit_literal(&data[literal_start..pos], out);
+        let len = 4 + common_len(&data[candidate + 4..], &data[pos + 4..]);
+        emit_copy(pos - candidate, len, out);
+        pos += len;
+        literal_start = pos;
+        if pos < limit {
+            table[hash(load32(data, pos - 1))] = (pos - 1) as u32;
+        }
+    }
+    emit_literal(&data[literal_start..], out);
+}
+
+fn load32(input: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(input[pos..pos + 4].try_into().unwrap())
+}
+
+fn hash(bytes: u32) -> usize {
+    (bytes.wrapping_mul(0x1e35_a7bd) >> (32 - HASH_BITS)) as usize
+}
+
+fn common_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+fn emit_literal(literal: &[u8], out: &mut Vec<u8>) {
+    if literal.is_empty() {
+        return;
+    }
+    let n = literal.len() - 1;
+    if n < 60 {
+        out.push((n as u8) << 2);
+    } else {
+        let bytes = (n.ilog2() / 8 + 1) as usize;
+        out.push(((59 + bytes) as u8) << 2);
+        out.extend_from_slice(&n.to_le_bytes()[..bytes]);
+    }
+    out.extend_from_slice(literal);
+}
+
+fn emit_copy(offset: usize, mut len: usize, out: &mut Vec<u8>) {
+    // leaves at least 4 bytes for the last copy
+    while len >= MAX_COPY + 4 {
+        emit_copy_upto64(offset, MAX_COPY, out);
+        len -= MAX_COPY;
+    }
+    if len > MAX_COPY {
+        emit_copy_upto64(offset, 60, out);
+        len -= 60;
+    }
+    emit_copy_upto64(offset, len, out);
+}
+
+fn emit_copy_upto64(offset: usize, len: usize, out: &mut Vec<u8>) {
+    if (4..12).contains(&len) && offset < 2048 {
+        out.push(0b01 | ((len - 4) as u8) << 2 | ((offset >> 8) as u8) << 5);
+        out.push(offset as u8);
+    } else {
+        out.push(0b10 | ((len - 1) as u8) << 2);
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+    }
+}
+
+/// Uncompresses the raw Snappy format, any malformed input is `InvalidData`
+pub fn uncompress(input: &[u8]) -> io::Result<Vec<u8>> {
+    uncompress_with_dict(input, &[])
+}
+
+/// Uncompresses what `compress_with_dict` wrote with `dict`
+pub fn uncompress_with_dict(input: &[u8], dict: &[u8]) -> io::Result<Vec<u8>> {
+    let mut input = input;
+    let dict = &dict[dict.len().saturating_sub(MAX_OFFSET)..];
+    let len = get_varint32(&mut input).ok_or_else(|| corruption("bad snappy length"))? as usize + dict.len();
+    let mut out = Vec::with_capacity(len);
+    out.extend_from_slice(dict);
+    while let Some((&tag, rest)) = input.split_first() {
+        input = rest;
+        let (offset, copy_len) = match tag & 0b11 {
+            0b00 => {
+                let mut n = (tag >> 2) as usize;
+                if n >= 60 {
+                    let bytes = n - 59;
+                    let extra = input.get(..bytes).ok_or_else(|| corruption("truncated snappy literal"))?;
+                    let mut le = [0u8; 8];
+                    le[..bytes].copy_from_slice(extra);
+                    n = u64::from_le_bytes(le) as usize;
+                    input = &input[bytes..];
+                }
+                let literal = input
+                    .get(..n + 1)
+                    .filter(|literal| out.len() + literal.len() <= len)
+                    .ok_or_else(|| corruption("bad snappy literal"))?;
+                out.extend_from_slice(literal);
+                input = &input[n + 1..];
+                continue;
+            }
+            0b01 => {
+                let &low = input.first().ok_or_else(|| corruption("truncated snappy copy"))?;
+                input = &input[1..];
+                (((tag as usize) >> 5) << 8 | low as usize, 4 + ((tag >> 2) & 0b111) as usize)
+            }
+            0b10 => {
+                let bytes = input.get(..2).ok_or_else(|| corruption("truncated snappy copy"))?;
+                let offset = u16::from_le_bytes(bytes.try_into().unwrap()) as usize;
+                input = &input[2..];
+                (offset, 1 + (tag >> 2) as usize)
+            }
+            _ => {
+                let bytes = input.get(..4).ok_or_else(|| corruption("truncated snappy copy"))?;
+                let offset = u32::from_le_bytes(bytes.try_into().unwrap()) as usize;
+                input = &input[4..];
+                (offset, 1 + (tag >> 2) as usize)
+            }
+        };
+        if offset == 0 || offset > out.len() || out.len() + copy_len > len {
+            return Err(corruption("bad snappy copy"));
+        }
+        // the source may overlap the bytes being copied, which repeats them
+        let start = out.len() - offset;
+        for i in 0..copy_len {
+            out.push(out[start + i]);
+        }
+    }
+    if out.len() != len {
+        return Err(corruption("snappy output shorter than its length"));
+    }
+    out.drain(..dict.len());
+    Ok(out)
+}
@@ -0,0 +1,397 @@
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::env::{FileSystem, PosixFileSystem, WritableFile};
+
+use super::record::{FileHeader, RECORD_HEADER_SIZE, RecordType, encode_record};
+use super::version::CURRENT_VERSION;
+use super::writer::DurableSequence;
+
+// Layout of the reservation word: [closed(1b) | record count(25b) | byte offset(38b)]
+// a single CAS hands out both the sequence number and the byte range so that the two orders always agree
+const OFFSET_BITS: u32 = 38;
+const COUNT_BITS: u32 = 25;
+const OFFSET_MASK: u64 = (1 << OFFSET_BITS) - 1;
+const COUNT_MASK: u64 = (1 << COUNT_BITS) - 1;
+const CLOSED_BIT: u64 = 1 << 63;
+
+const FLUSHER_IDLE_WAIT: Duration = Duration::from_millis(1);
+
+#[derive(Debug, Clone, Copy)]
+pub struct ConcurrentWalOptions {
+    /// Size of the shared buffer records are filled into, a record cannot be larger than this
+    pub buffer_size: usize,
+    /// When true the flusher calls `sync_data` after every write, records are durable once flushed
+    pub sync: bool,
+}
+
+impl Default for ConcurrentWalOptions {
+    fn default() -> Self {
+        ConcurrentWalOptions {
+            buffer_size: 4 * 1024 * 1024,
+            sync: true,
+        }
+    }
+}
+
+/// The shared buffer, used as a ring indexed by segment byte offset modulo its size
+///
+/// Writers copy their records into disjoint reserved ranges without synchronization, the only
+/// atomic access is the type byte which is stored last (Release) to publish a completed record.
+/// A zero type byte means "not completed yet", the flusher zeroes the ranges it has written.
+struct Ring {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// SAFETY: concurrent accesses to the ring are coordinated through the reservation word, the type
+// byte of each record and the `flushed` watermark, see the comments on each access
+unsafe impl Send for Ring {}
+unsafe impl Sync for Ring {}
+
+impl Ring {
+    fn new(len: usize) -> Self {
+        let ptr = Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8;
+        Ring { ptr, len }
+    }
+
+    fn type_byte(&self, offset: u64) -> &AtomicU8 {
+        // SAFETY: the index is in bounds and the byte lives as long as the ring
+        unsafe { AtomicU8::from_ptr(self.ptr.add((offset % self.len as u64) as usize)) }
+    }
+
+    /// Copies `bytes` to the ring starting at `offset`, wrapping at the end
+    ///
+    /// SAFETY: the caller owns the range (reserved and not yet flushed)
+    unsafe fn write(&self, offset: u64, bytes: &[u8]) {
+        let start = (offset % self.len as u64) as usize;
+        let first = bytes.len().min(self.len - start);
+        unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), self.ptr.add(start), first);
+            std::ptr::copy_nonoverlapping(bytes[first..].as_ptr(), self.ptr, bytes.len() - first);
+        }
+    }
+
+    /// Copies `out.len()` bytes starting at `offset`
+    ///
+    /// SAFETY: the range holds completed records (their type byte was observed with Acquire)
+    unsafe fn read(&self, offset: u64, out: &mut [u8]) {
+        let start = (offset % self.len as u64) as usize;
+        let first = out.len().min(self.len - start);
+        unsafe {
+            std::ptr::copy_nonoverlapping(self.ptr.add(start), out.as_mut_ptr(), first);
+            std::ptr::copy_nonoverlapping(self.ptr, out[first..].as_mut_ptr(), out.len() - first);
+        }
+    }
+
+    /// SAFETY: only the flusher calls it, on a range no writer can reserve until `flushed` moves past it
+    unsafe fn zero(&self, offset: u64, len: usize) {
+        let start = (offset % self.len as u64) as usize;
+        let first = len.min(self.len - start);
+        unsafe {
+            std::ptr::write_bytes(self.ptr.add(start), 0, first);
+            std::ptr::write_bytes(self.ptr, 0, len - first);
+        }
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        // SAFETY: ptr and len come from the boxed slice created in `new`
+        unsafe { drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(self.ptr, self.len))) }
+    }
+}
+
+struct Durability {
+    /// Highest sequence written (and synced when `sync` is on), every lower one is too
+    durable_sequence: Option<u64>,
+    error: Option<(io::ErrorKind, String)>,
+    /// Watermark of the synced records, only with `sync` on
+    published: Option<DurableSequence>,
+}
+
+struct Shared {
+    ring: Ring,
+    reservation: AtomicU64,
+    /// Bytes after the file header written to the segment, only the flusher stores it
+    flushed: AtomicU64,
+    first_sequence: u64,
+    failed: AtomicBool,
+    durability: Mutex<Durability>,
+    durable_changed: Condvar,
+}
+
+impl Shared {
+    fn error(&self) -> io::Error {
+        let durability = self.durability.lock().unwrap();
+        match &durability.error {
+            Some((kind, message)) => io::Error::new(*kind, message.clone()),
+            None => io::Error::other("concurrent WAL is closed"),
+        }
+    }
+}
+
+/// WAL front-end for many writer threads
+///
+/// `append` reserves a byte range and a sequence number with a single CAS on an atomic counter,
+/// encodes the record (CRC32C included) into the reserved range of a shared buffer, and returns.
+/// A single flusher thread writes the longest prefix of completed records to the segment, in order,
+/// so the file is identical to the one `WalWriter` would produce and is read with `WalReader`.
+/// Writers only block when the buffer is full or when they wait for durability.
+pub struct ConcurrentWal {
+    shared: Arc<Shared>,
+    flusher: Option<JoinHandle<()>>,
+    sync: bool,
+}
+
+impl ConcurrentWal {
+    /// Creates a new segment whose first record will get `first_sequence`
+    pub fn create(path: impl AsRef<Path>, first_sequence: u64, options: ConcurrentWalOptions) -> io::Result<Self> {
+        Self::create_in(&PosixFileSystem, path, first_sequence, options)
+    }
+
+    pub fn create_in(
+        fs: &dyn FileSystem,
+        path: impl AsRef<Path>,
+        first_sequence: u64,
+        options: ConcurrentWalOptions,
+    ) -> io::Result<Self> {
+        if options.buffer_size < RECORD_HEADER_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer smaller than a record header"));
+        }
+        let mut file = fs.create_new(path.as_ref())?;
+        file.append(&FileHeader { version: CURRENT_VERSION }.encode())?;
+        if options.sync {
+            file.sync()?;
+        } else {
+            file.flush()?;
+        }
+
+        let shared = Arc::new(Shared {
+            ring: Ring::new(options.buffer_size),
+            reservation: AtomicU64::new(0),
+            flushed: AtomicU64::new(0),
+            first_sequence,
+            failed: AtomicBool::new(false),
+            durability: Mutex::new(Durability {
+                durable_sequence: None,
+                error: None,
+                published: None,
+            }),
+            durable_changed: Condvar::new(),
+        });
+        let flusher_shared = Arc::clone(&shared);
+        let flusher = thread::Builder::new()
+            .name("stone-wal-flusher".to_string())
+            .spawn(move || run_flusher(flusher_shared, file, options.sync))?;
+        Ok(ConcurrentWal {
+            shared,
+            flusher: Some(flusher),
+            sync: options.sync,
+        })
+    }
+
+    /// Appends a record and returns its sequence, the record is durable after `wait_durable`
+    pub fn append(&self, record_type: RecordType, key: &[u8], value: &[u8]) -> io::Result<u64> {
+        let len = RECORD_HEADER_SIZE + key.len() + value.len();
+        if len > self.shared.ring.len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("record of {len} bytes does not fit the {} bytes buffer", self.shared.ring.len),
+            ));
+        }
+        let (count, offset) = self.reserve(len as u64)?;
+        let sequence = self.shared.first_sequence + count;
+
+        // wait until the flusher has released the bytes we are about to overwrite
+        let mut spins = 0u32;
+        while offset + len as u64 - self.shared.flushed.load(Ordering::Acquire) > self.shared.ring.len as u64 {
+            if self.shared.failed.load(Ordering::Acquire) {
+                return Err(self.shared.error());
+            }
+            backoff(&mut spins);
+        }
+
+        let mut record = Vec::with_capacity(len);
+        encode_record(&mut record, record_type, sequence, key, value);
+        // SAFETY: the range [offset, offset + len) was handed out to this call only and the flusher
+        // does not touch it before the type byte is published
+        unsafe { self.shared.ring.write(offset + 1, &record[1..]) };
+        self.shared.ring.type_byte(offset).store(record[0], Ordering::Release);
+        if let Some(flusher) = &self.flusher {
+            flusher.thread().unpark();
+        }
+        Ok(sequence)
+    }
+
+    pub fn put(&self, key: &[u8], value: &[u8]) -> io::Result<u64> {
+        self.append(RecordType::Put, key, value)
+    }
+
+    pub fn delete(&self, key: &[u8]) -> io::Result<u64> {
+        self.append(RecordType::Delete, key, &[])
+    }
+
+    /// Blocks until `sequence` (and every sequence before it) has been written by the flusher
+    pub fn wait_durable(&self, sequence: u64) -> io::Result<()> {
+        let mut durability = self.shared.durability.lock().unwrap();
+        loop {
+            if durability.durable_sequence.is_some_and(|durable| durable >= sequence) {
+                return Ok(());
+            }
+            if let Some((kind, message)) = &durability.error {
+                return Err(io::Error::new(*kind, message.clone()));
+            }
+            durability = self.shared.durable_changed.wait(durability).unwrap();
+        }
+    }
+
+    pub fn durable_sequence(&self) -> Option<u64> {
+        self.shared.durability.lock().unwrap().durable_sequence
+    }
+
+    /// Publishes the synced records to `durable`; with `sync` off nothing is ever synced and the
+    /// watermark does not move
+    pub fn set_durable_sequence(&self, durable: DurableSequence) {
+        let mut durability = self.shared.durability.lock().unwrap();
+        if self.sync
+            && let Some(sequence) = durability.durable_sequence
+        {
+            durable.advance(sequence);
+        }
+        durability.published = Some(durable);
+    }
+
+    /// The sequence the next `append` will get
+    pub fn next_sequence(&self) -> u64 {
+        let state = self.shared.reservation.load(Ordering::Relaxed);
+        self.shared.first_sequence + ((state >> OFFSET_BITS) & COUNT_MASK)
+    }
+
+    /// Rejects new appends, waits for the flusher to write every reserved record and stops it
+    pub fn close(mut self) -> io::Result<()> {
+        self.shutdown()
+    }
+
+    fn reserve(&self, len: u64) -> io::Result<(u64, u64)> {
+        let mut state = self.shared.reservation.load(Ordering::Relaxed);
+        loop {
+            if state & CLOSED_BIT != 0 {
+                return Err(io::Error::other("concurrent WAL is closed"));
+            }
+            let count = (state >> OFFSET_BITS) & COUNT_MASK;
+            let offset = state & OFFSET_MASK;
+            if count == COUNT_MASK || offset + len > OFFSET_MASK {
+                return Err(io::Error::new(
+                    io::ErrorKind::StorageFull,
+                    "WAL segment is full, roll to a new segment",
+                ));
+            }
+            let next = ((count + 1) << OFFSET_BITS) | (offset + len);
+            match self
+                .shared
+                .reservation
+                .compare_exchange_weak(state, next, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => return Ok((count, offset)),
+                Err(current) => state = current,
+            }
+        }
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        let Some(flusher) = self.flusher.take() else {
+            return Ok(());
+        };
+        self.shared.reservation.fetch_or(CLOSED_BIT, Ordering::AcqRel);
+        flusher.thread().unpark();
+        flusher
+            .join()
+            .map_err(|_| io::Error::other("WAL flusher thread panicked"))?;
+        match self.shared.failed.load(Ordering::Acquire) {
+            true => Err(self.shared.error()),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Drop for ConcurrentWal {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+fn backoff(spins: &mut u32) {
+    *spins += 1;
+    if *spins < 64 {
+        std::hint::spin_loop();
+    } else if *spins < 128 {
+        thread::yield_now();
+    } else {
+        thread::sleep(Duration::from_micros(50));
+    }
+}
+
+fn run_flusher(shared: Arc<Shared>, mut file: Box<dyn WritableFile>, sync: bool) {
+    let mut batch = Vec::new();
+    loop {
+        let start = shared.flushed.load(Ordering::Relaxed);
+        let (end, last_sequence) = completed_prefix(&shared, start);
+
+        if end == start {
+            let state = shared.reservation.load(Ordering::Acquire);
+            if state & CLOSED_BIT != 0 && state & OFFSET_MASK == start {
+                return;
+            }
+            thread::park_timeout(FLUSHER_IDLE_WAIT);
+            continue;
+        }
+
+        batch.resize((end - start) as usize, 0);
+        // SAFETY: every record in [start, end) has been published
+        unsafe { shared.ring.read(start, &mut batch) };
+        let result = file
+            .append(&batch)
+            .and_then(|_| if sync { file.sync() } else { file.flush() });
+        if let Err(e) = result {
+            let mut durability = shared.durability.lock().unwrap();
+            durability.error = Some((e.kind(), format!("WAL flush failed: {e}")));
+            shared.failed.store(true, Ordering::Release);
+            shared.durable_changed.notify_all();
+            return;
+        }
+
+        // SAFETY: writers cannot reuse [start, end) until `flushed` moves past it
+        unsafe { shared.ring.zero(start, batch.len()) };
+        shared.flushed.store(end, Ordering::Release);
+        let mut durability = shared.durability.lock().unwrap();
+        durability.durable_sequence = last_sequence;
+        if sync
+            && let (Some(published), Some(sequence)) = (&durability.published, last_sequence)
+        {
+            published.advance(sequence);
+        }
+        shared.durable_changed.notify_all();
+    }
+}
+
+/// End of the run of published records starting at `start`, and the sequence of the last one
+fn completed_prefix(shared: &Shared, start: u64) -> (u64, Option<u64>) {
+    let ring = &shared.ring;
+    let mut pos = start;
+    let mut last_sequence = None;
+    while pos - start < ring.len as u64 && ring.type_byte(pos).load(Ordering::Acquire) != 0 {
+        let mut header = [0u8; RECORD_HEADER_SIZE];
+        // SAFETY: the record at pos is published, its header was written before the type byte
+        unsafe { ring.read(pos, &mut header) };
+        let key_size = u32::from_le_bytes(header[13..17].try_into().unwrap()) as u64;
+        let value_size = u32::from_le_bytes(header[17..21].try_into().unwrap()) as u64;
+        last_sequence = Some(u64::from_le_bytes(header[1..9].try_into().unwrap()));
+        pos += RECORD_HEADER_SIZE as u64 + key_size + value_size;
+    }
+    (pos, last_sequence)
+}
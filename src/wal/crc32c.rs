@@ -77,7 +77,7 @@ const fn generate_crc32c_tables_32() -> [[u32; 256]; 32] {
     tables
 }
 
-const CRC32C_TABLES_32: [[u32; 256]; 32] = generate_crc32c_tables_32();
+static CRC32C_TABLES_32: [[u32; 256]; 32] = generate_crc32c_tables_32();
 
 /// Generate 16 CRC32C lookup tables for slicing-by-16 implementation
 const fn generate_crc32c_tables_16() -> [[u32; 256]; 16] {
@@ -151,7 +151,13 @@ pub fn crc32c(data: &[u8]) -> u32 {
 /// CRC(VAL2) is simply VAL2>>8 since the last 8 bits are zero and the
 /// algorithm in this case simply requires a shift
 pub fn crc32c_table(data: &[u8]) -> u32 {
-    let mut crc = 0xffffffff;
+    crc32c_table_append(0, data)
+}
+
+/// Continues a table-based CRC32C over `data`, starting from the checksum of the bytes before it
+/// `crc32c_table_append(crc32c_table(a), b) == crc32c_table(a ++ b)`, starting from 0 is the plain checksum
+pub fn crc32c_table_append(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
     for &byte in data {
         crc = (crc >> 8) ^ CRC32C_TABLE[((crc as u8) ^ byte) as usize];
     }
@@ -196,30 +202,36 @@ pub fn crc32c_slice8(data: &[u8]) -> u32 {
 /// Hardware-accelerated CRC32C implementation using CPU intrinsics
 /// Falls back to table-based implementation if hardware support is not available
 pub fn crc32c_hw(data: &[u8]) -> u32 {
+    crc32c_hw_append(0, data)
+}
+
+/// Hardware-accelerated continuation of a CRC32C, see `crc32c_table_append`
+/// Used when the checksummed bytes are not contiguous (e.g. WAL records, where the CRC field sits in the middle)
+pub fn crc32c_hw_append(crc: u32, data: &[u8]) -> u32 {
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     {
         if std::arch::is_x86_feature_detected!("sse4.2") {
-            return crc32c_hw_x86(data);
+            return crc32c_hw_x86(crc, data);
         }
     }
     
     #[cfg(target_arch = "aarch64")]
     {
         if std::arch::is_aarch64_feature_detected!("crc") {
-            return crc32c_hw_arm(data);
+            return crc32c_hw_arm(crc, data);
         }
     }
     
     // Fallback to table-based implementation
-    crc32c_table(data)
+    crc32c_table_append(crc, data)
 }
 
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
-fn crc32c_hw_x86(data: &[u8]) -> u32 {
+fn crc32c_hw_x86(crc: u32, data: &[u8]) -> u32 {
     use std::arch::x86_64::*;
     
     unsafe {
-        let mut crc = 0xffffffffu32;
+        let mut crc = !crc;
         
         let (prefix, u64s, suffix) = data.align_to::<u64>();
         
@@ -243,11 +255,11 @@ fn crc32c_hw_x86(data: &[u8]) -> u32 {
 }
 
 #[cfg(target_arch = "aarch64")]
-fn crc32c_hw_arm(data: &[u8]) -> u32 {
+fn crc32c_hw_arm(crc: u32, data: &[u8]) -> u32 {
     use std::arch::aarch64::*;
     
     unsafe {
-        let mut crc = 0xffffffffu32;
+        let mut crc = !crc;
         
         let (prefix, u64s, suffix) = data.align_to::<u64>();
         
@@ -0,0 +1,222 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use super::reader::{ReadStats, read_header};
+use super::record::{FILE_HEADER_SIZE, RecordType};
+use super::version::{Decoded, DecoderRegistry, RecordDecoder, RecordView};
+
+/// A read-only, private memory mapping of a whole file
+///
+/// The mapped file must not be truncated while mapped (the process would get SIGBUS), which holds
+/// for WAL segments being replayed: they are closed and only deleted once flushed.
+pub struct Mmap {
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    ptr: *mut u8,
+    #[cfg(not(all(unix, target_pointer_width = "64")))]
+    data: Vec<u8>,
+    len: usize,
+}
+
+// SAFETY: the mapping is read-only and owned by this struct until it is dropped
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+/// `off_t` is 64 bits on every 64-bit unix, 32-bit targets may use a 32-bit one and read the file instead
+#[cfg(all(unix, target_pointer_width = "64"))]
+mod sys {
+    use std::ffi::c_void;
+
+    pub const PROT_READ: i32 = 1;
+    pub const MAP_PRIVATE: i32 = 2;
+    pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+    unsafe extern "C" {
+        pub fn mmap(addr: *mut c_void, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> i32;
+    }
+}
+
+impl Mmap {
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    pub fn map(file: &File) -> io::Result<Self> {
+        use std::os::fd::AsRawFd;
+
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file too large to map"))?;
+        if len == 0 {
+            // mmap rejects empty mappings
+            return Ok(Mmap {
+                ptr: std::ptr::NonNull::dangling().as_ptr(),
+                len,
+            });
+        }
+        // SAFETY: a fresh read-only private mapping of a valid file descriptor
+        let ptr = unsafe {
+            sys::mmap(
+                std::ptr::null_mut(),
+                len,
+                sys::PROT_READ,
+                sys::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == sys::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap { ptr: ptr as *mut u8, len })
+    }
+
+    /// Without mmap (not unix, or 32-bit) the file is read into memory, the API stays zero-copy from
+    /// that buffer on
+    #[cfg(not(all(unix, target_pointer_width = "64")))]
+    pub fn map(file: &File) -> io::Result<Self> {
+        use std::io::Read;
+
+        let mut data = Vec::new();
+        (&*file).read_to_end(&mut data)?;
+        let len = data.len();
+        Ok(Mmap { data, len })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl AsRef<[u8]> for Mmap {
+    fn as_ref(&self) -> &[u8] {
+        #[cfg(all(unix, target_pointer_width = "64"))]
+        {
+            // SAFETY: ptr is valid for len bytes for the lifetime of self
+            unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+        }
+        #[cfg(not(all(unix, target_pointer_width = "64")))]
+        {
+            &self.data
+        }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        #[cfg(all(unix, target_pointer_width = "64"))]
+        if self.len > 0 {
+            // SAFETY: ptr/len describe the mapping created in `map`
+            unsafe { sys::munmap(self.ptr as *mut std::ffi::c_void, self.len) };
+        }
+    }
+}
+
+/// A record whose key and value are views into the mapped segment, no bytes are copied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MappedRecord {
+    pub record_type: RecordType,
+    pub sequence: u64,
+    pub key: Bytes,
+    pub value: Bytes,
+}
+
+/// Memory-mapped WAL reader for recovery
+///
+/// Same recovery rules as `WalReader` (version dispatch, corrupted records skipped, torn tail
+/// ignored), but records are returned as borrowed `RecordView`s or as `Bytes` views that keep
+/// the mapping alive, instead of copying every key and value.
+pub struct MmapWalReader {
+    data: Bytes,
+    decoder: Arc<dyn RecordDecoder>,
+    version: u32,
+    pos: usize,
+    stats: ReadStats,
+}
+
+impl MmapWalReader {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_with_registry(path, &DecoderRegistry::default())
+    }
+
+    pub fn open_with_registry(path: impl AsRef<Path>, registry: &DecoderRegistry) -> io::Result<Self> {
+        let mmap = Mmap::map(&File::open(path)?)?;
+        Self::from_bytes(Bytes::from_owner(mmap), registry)
+    }
+
+    /// Reads a segment already in memory, `data` must start with the file header
+    pub fn from_bytes(data: Bytes, registry: &DecoderRegistry) -> io::Result<Self> {
+        let version = read_header(&mut data.as_ref())?;
+        let decoder = registry.get(version).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("unsupported WAL version {version}, known versions: {:?}", registry.versions()),
+            )
+        })?;
+        Ok(MmapWalReader {
+            data,
+            decoder,
+            version,
+            pos: FILE_HEADER_SIZE,
+            stats: ReadStats::default(),
+        })
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn stats(&self) -> ReadStats {
+        self.stats
+    }
+
+    /// Next valid record borrowed from the mapping, the cheapest way to replay into a memtable
+    pub fn next_view(&mut self) -> Option<RecordView<'_>> {
+        loop {
+            match self.decoder.decode(&self.data[self.pos..]) {
+                Decoded::Record { record, len } => {
+                    self.pos += len;
+                    self.stats.records += 1;
+                    return Some(record);
+                }
+                Decoded::Corrupted { len } => {
+                    self.pos += len;
+                    self.stats.corrupted_records += 1;
+                }
+                Decoded::Incomplete => {
+                    self.stats.truncated_tail_bytes = (self.data.len() - self.pos) as u64;
+                    self.pos = self.data.len();
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Next valid record as `Bytes` views sharing the mapping, for callers that keep them around
+    pub fn next_record(&mut self) -> Option<MappedRecord> {
+        let base = self.data.as_ptr() as usize;
+        let view = self.next_view()?;
+        let key_start = view.key.as_ptr() as usize - base;
+        let value_start = view.value.as_ptr() as usize - base;
+        let (record_type, sequence) = (view.record_type, view.sequence);
+        let (key_len, value_len) = (view.key.len(), view.value.len());
+        Some(MappedRecord {
+            record_type,
+            sequence,
+            key: self.data.slice(key_start..key_start + key_len),
+            value: self.data.slice(value_start..value_start + value_len),
+        })
+    }
+}
+
+impl Iterator for MmapWalReader {
+    type Item = MappedRecord;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record()
+    }
+}
@@ -1 +1,19 @@
+pub mod concurrent;
 pub mod crc32c;
+pub mod mmap;
+pub mod reader;
+pub mod record;
+pub mod segment;
+pub mod tailer;
+pub mod upgrade;
+pub mod version;
+pub mod writer;
+
+pub use concurrent::{ConcurrentWal, ConcurrentWalOptions};
+pub use mmap::{MappedRecord, MmapWalReader};
+pub use reader::{ReadStats, ScanEntry, WalReader};
+pub use record::{RecordType, WalRecord};
+pub use tailer::{TailStats, WalTailer};
+pub use upgrade::{UpgradeReport, upgrade_segment, upgrade_segment_in};
+pub use version::{CURRENT_VERSION, DecoderRegistry, RecordDecoder};
+pub use writer::{DurableSequence, WalWriter};
@@ -0,0 +1,193 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::env::{FileSystem, SequentialFile};
+
+use super::record::{FILE_HEADER_SIZE, FileHeader, WalRecord};
+use super::version::{Decoded, DecoderRegistry, RecordDecoder};
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// What the reader found while scanning a segment
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadStats {
+    /// Valid records returned to the caller
+    pub records: u64,
+    /// Records skipped because of a CRC32C mismatch
+    pub corrupted_records: u64,
+    /// Bytes at the end of the segment that do not form a whole record (torn write)
+    pub truncated_tail_bytes: u64,
+}
+
+/// One step of a segment scan, including what `next_record` skips
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanEntry {
+    /// A record whose CRC32C matches, `offset` is its position in the file
+    Record { offset: u64, len: usize, record: WalRecord },
+    /// A record whose CRC32C does not match, `raw` holds its bytes as found on disk
+    Corrupted { offset: u64, raw: Vec<u8> },
+    /// Trailing bytes that do not form a whole record
+    TornTail { offset: u64, len: usize },
+}
+
+/// Sequential reader of a WAL segment
+///
+/// The decoder is chosen from the `Version` field of the file header, corrupted records are skipped
+/// and counted as described in the recovery strategy of `wal.md`
+pub struct WalReader<R: Read> {
+    inner: R,
+    decoder: Arc<dyn RecordDecoder>,
+    version: u32,
+    buf: Vec<u8>,
+    pos: usize,
+    /// File offset of `buf[pos]`
+    offset: u64,
+    eof: bool,
+    stats: ReadStats,
+}
+
+impl WalReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_with_registry(path, &DecoderRegistry::default())
+    }
+
+    pub fn open_with_registry(path: impl AsRef<Path>, registry: &DecoderRegistry) -> io::Result<Self> {
+        let file = File::open(path)?;
+        WalReader::with_registry(BufReader::new(file), registry)
+    }
+}
+
+impl WalReader<Box<dyn SequentialFile>> {
+    pub fn open_in(fs: &dyn FileSystem, path: impl AsRef<Path>, registry: &DecoderRegistry) -> io::Result<Self> {
+        WalReader::with_registry(fs.open_sequential(path.as_ref())?, registry)
+    }
+}
+
+impl<R: Read> WalReader<R> {
+    pub fn new(inner: R) -> io::Result<Self> {
+        Self::with_registry(inner, &DecoderRegistry::default())
+    }
+
+    /// Reads the file header and selects the decoder registered for its version
+    pub fn with_registry(mut inner: R, registry: &DecoderRegistry) -> io::Result<Self> {
+        let version = read_header(&mut inner)?;
+        let decoder = registry.get(version).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("unsupported WAL version {version}, known versions: {:?}", registry.versions()),
+            )
+        })?;
+        Ok(WalReader {
+            inner,
+            decoder,
+            version,
+            buf: Vec::new(),
+            pos: 0,
+            offset: FILE_HEADER_SIZE as u64,
+            eof: false,
+            stats: ReadStats::default(),
+        })
+    }
+
+    /// Format version found in the segment header
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn stats(&self) -> ReadStats {
+        self.stats
+    }
+
+    /// Returns the next valid record, `None` at the end of the segment
+    pub fn next_record(&mut self) -> io::Result<Option<WalRecord>> {
+        loop {
+            match self.next_entry()? {
+                Some(ScanEntry::Record { record, .. }) => return Ok(Some(record)),
+                Some(ScanEntry::Corrupted { .. }) => continue,
+                Some(ScanEntry::TornTail { .. }) | None => return Ok(None),
+            }
+        }
+    }
+
+    /// Returns the next entry of the segment, corrupted records included, for inspection tools
+    pub fn next_entry(&mut self) -> io::Result<Option<ScanEntry>> {
+        loop {
+            let offset = self.offset;
+            match self.decoder.decode(&self.buf[self.pos..]) {
+                Decoded::Record { record, len } => {
+                    let record = record.to_record();
+                    self.advance(len);
+                    self.stats.records += 1;
+                    return Ok(Some(ScanEntry::Record { offset, len, record }));
+                }
+                Decoded::Corrupted { len } => {
+                    let raw = self.buf[self.pos..self.pos + len].to_vec();
+                    self.advance(len);
+                    self.stats.corrupted_records += 1;
+                    return Ok(Some(ScanEntry::Corrupted { offset, raw }));
+                }
+                Decoded::Incomplete => {
+                    if !self.eof {
+                        self.fill()?;
+                        continue;
+                    }
+                    let len = self.buf.len() - self.pos;
+                    if len == 0 {
+                        return Ok(None);
+                    }
+                    self.advance(len);
+                    self.stats.truncated_tail_bytes = len as u64;
+                    return Ok(Some(ScanEntry::TornTail { offset, len }));
+                }
+            }
+        }
+    }
+
+    fn advance(&mut self, len: usize) {
+        self.pos += len;
+        self.offset += len as u64;
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        if self.pos > 0 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        let start = self.buf.len();
+        self.buf.resize(start + READ_CHUNK_SIZE, 0);
+        let read = loop {
+            match self.inner.read(&mut self.buf[start..]) {
+                Ok(read) => break read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.buf.truncate(start);
+                    return Err(e);
+                }
+            }
+        };
+        self.buf.truncate(start + read);
+        if read == 0 {
+            self.eof = true;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for WalReader<R> {
+    type Item = io::Result<WalRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+/// Reads and validates the segment header, returning its version
+pub fn read_header(inner: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; FILE_HEADER_SIZE];
+    inner.read_exact(&mut bytes)?;
+    FileHeader::decode(&bytes)
+        .map(|header| header.version)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a WAL segment: bad magic"))
+}
@@ -0,0 +1,112 @@
+use super::crc32c::crc32c_hw_append;
+
+/// Magic bytes at the start of every WAL segment
+pub const WAL_MAGIC: [u8; 4] = *b"SKVW";
+
+/// `[Magic(4B) | Version(4B) | Reserved(8B)]`
+pub const FILE_HEADER_SIZE: usize = 16;
+
+/// `[Type(1B) | Sequence(8B) | CRC32C(4B) | Key_Size(4B) | Value_Size(4B)]`
+pub const RECORD_HEADER_SIZE: usize = 21;
+
+/// Offset of the CRC32C field inside a record, the checksum covers every byte except these 4
+pub const CRC_OFFSET: usize = 9;
+
+/// Operation stored in a WAL record
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum RecordType {
+    Put = 0x01,
+    Delete = 0x02,
+}
+
+impl RecordType {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0x01 => Some(RecordType::Put),
+            0x02 => Some(RecordType::Delete),
+            _ => None,
+        }
+    }
+}
+
+/// The fixed header at the beginning of a WAL segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileHeader {
+    pub version: u32,
+}
+
+impl FileHeader {
+    pub fn encode(&self) -> [u8; FILE_HEADER_SIZE] {
+        let mut out = [0u8; FILE_HEADER_SIZE];
+        out[..4].copy_from_slice(&WAL_MAGIC);
+        out[4..8].copy_from_slice(&self.version.to_le_bytes());
+        out
+    }
+
+    /// Returns `None` when the magic does not match, i.e. the file is not a WAL segment
+    pub fn decode(bytes: &[u8; FILE_HEADER_SIZE]) -> Option<Self> {
+        if bytes[..4] != WAL_MAGIC {
+            return None;
+        }
+        let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        Some(FileHeader { version })
+    }
+}
+
+/// A decoded WAL record, the in-memory counterpart of the on-disk format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalRecord {
+    pub record_type: RecordType,
+    pub sequence: u64,
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+impl WalRecord {
+    pub fn put(sequence: u64, key: &[u8], value: &[u8]) -> Self {
+        WalRecord {
+            record_type: RecordType::Put,
+            sequence,
+            key: key.to_vec(),
+            value: value.to_vec(),
+        }
+    }
+
+    pub fn delete(sequence: u64, key: &[u8]) -> Self {
+        WalRecord {
+            record_type: RecordType::Delete,
+            sequence,
+            key: key.to_vec(),
+            value: Vec::new(),
+        }
+    }
+
+    /// Size of the record once encoded, `Total_Length` in `wal.md`
+    pub fn encoded_len(&self) -> usize {
+        RECORD_HEADER_SIZE + self.key.len() + self.value.len()
+    }
+}
+
+/// Appends a record in the current (v1) format to `buf`
+pub fn encode_record(buf: &mut Vec<u8>, record_type: RecordType, sequence: u64, key: &[u8], value: &[u8]) {
+    let start = buf.len();
+    buf.reserve(RECORD_HEADER_SIZE + key.len() + value.len());
+    buf.push(record_type as u8);
+    buf.extend_from_slice(&sequence.to_le_bytes());
+    buf.extend_from_slice(&[0u8; 4]);
+    buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    buf.extend_from_slice(key);
+    buf.extend_from_slice(value);
+
+    let crc = record_checksum(&buf[start..]);
+    buf[start + CRC_OFFSET..start + CRC_OFFSET + 4].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// CRC32C of an encoded record, skipping the CRC field itself
+/// `record` must start at the type byte and contain the whole record
+pub fn record_checksum(record: &[u8]) -> u32 {
+    let crc = crc32c_hw_append(0, &record[..CRC_OFFSET]);
+    crc32c_hw_append(crc, &record[CRC_OFFSET + 4..])
+}
@@ -0,0 +1,39 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::env::{FileSystem, PosixFileSystem};
+
+/// Extension of WAL segment files, a segment is named after its number: `000042.log`
+pub const SEGMENT_EXTENSION: &str = "log";
+
+pub fn segment_file_name(number: u64) -> String {
+    format!("{number:06}.{SEGMENT_EXTENSION}")
+}
+
+pub fn segment_path(dir: impl AsRef<Path>, number: u64) -> PathBuf {
+    dir.as_ref().join(segment_file_name(number))
+}
+
+/// Returns the segment number encoded in a file name, `None` for any other file
+pub fn parse_segment_file_name(name: &str) -> Option<u64> {
+    let stem = name.strip_suffix(SEGMENT_EXTENSION)?.strip_suffix('.')?;
+    if stem.is_empty() || !stem.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    stem.parse().ok()
+}
+
+/// Numbers of the segments in `dir`, oldest first
+pub fn list_segments(dir: impl AsRef<Path>) -> io::Result<Vec<u64>> {
+    list_segments_in(&PosixFileSystem, dir)
+}
+
+pub fn list_segments_in(fs: &dyn FileSystem, dir: impl AsRef<Path>) -> io::Result<Vec<u64>> {
+    let mut numbers: Vec<u64> = fs
+        .list_dir(dir.as_ref())?
+        .iter()
+        .filter_map(|name| parse_segment_file_name(name))
+        .collect();
+    numbers.sort_unstable();
+    Ok(numbers)
+}
@@ -0,0 +1,275 @@
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::env::{FileSystem, PosixFileSystem, SequentialFile};
+
+use super::reader::{WalReader, read_header};
+use super::record::{FILE_HEADER_SIZE, WalRecord};
+use super::segment::{list_segments_in, segment_path};
+use super::version::{Decoded, DecoderRegistry, RecordDecoder};
+use super::writer::DurableSequence;
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What the tailer skipped while following the log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TailStats {
+    pub records: u64,
+    pub corrupted_records: u64,
+    /// Bytes left at the end of a segment the writer has rolled away from
+    pub truncated_tail_bytes: u64,
+    pub segments_completed: u64,
+}
+
+/// What `decode_available` found in the bytes read so far
+enum Available {
+    Record(WalRecord),
+    /// A complete record past the durable watermark, delivered once it is synced
+    NotDurable,
+    /// Nothing complete left to decode
+    CaughtUp,
+}
+
+struct TailedSegment {
+    number: u64,
+    file: Box<dyn SequentialFile>,
+    decoder: Option<Arc<dyn RecordDecoder>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl TailedSegment {
+    fn open(fs: &dyn FileSystem, dir: &Path, number: u64) -> io::Result<Self> {
+        Ok(TailedSegment {
+            number,
+            file: fs.open_sequential(&segment_path(dir, number))?,
+            decoder: None,
+            buf: Vec::new(),
+            pos: 0,
+        })
+    }
+
+    /// Appends whatever the writer has handed to the OS since the last call
+    fn read_available(&mut self) -> io::Result<()> {
+        if self.pos > 0 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        match self.file.read_to_end(&mut self.buf) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn resolve_decoder(&mut self, registry: &DecoderRegistry) -> io::Result<bool> {
+        if self.decoder.is_some() {
+            return Ok(true);
+        }
+        if self.buf.len() < FILE_HEADER_SIZE {
+            return Ok(false);
+        }
+        let version = read_header(&mut &self.buf[..FILE_HEADER_SIZE])?;
+        let decoder = registry.get(version).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("segment {} has unsupported WAL version {version}", self.number),
+            )
+        })?;
+        self.decoder = Some(decoder);
+        self.pos = FILE_HEADER_SIZE;
+        Ok(true)
+    }
+}
+
+/// Follows the WAL of a directory as it is written, for change data capture
+///
+/// Records are delivered in sequence order, and the tailer moves to the next segment when the
+/// writer rolls over. By default a record is delivered once the writer has handed it to the OS
+/// (after `flush`): a power loss may still take it back. With `set_durable_sequence` only the
+/// records the writer has synced are, so that a consumer never sees a write the store loses.
+/// After a restart the consumer passes the first sequence it has not processed yet.
+pub struct WalTailer {
+    fs: Arc<dyn FileSystem>,
+    dir: PathBuf,
+    registry: DecoderRegistry,
+    next_sequence: u64,
+    durable: Option<DurableSequence>,
+    current: Option<TailedSegment>,
+    poll_interval: Duration,
+    stats: TailStats,
+}
+
+impl WalTailer {
+    /// Tails `dir` delivering every record with a sequence greater or equal to `from_sequence`
+    pub fn open(dir: impl AsRef<Path>, from_sequence: u64) -> io::Result<Self> {
+        Self::open_in(Arc::new(PosixFileSystem), dir, from_sequence, DecoderRegistry::default())
+    }
+
+    pub fn open_in(
+        fs: Arc<dyn FileSystem>,
+        dir: impl AsRef<Path>,
+        from_sequence: u64,
+        registry: DecoderRegistry,
+    ) -> io::Result<Self> {
+        let mut tailer = WalTailer {
+            fs,
+            dir: dir.as_ref().to_path_buf(),
+            registry,
+            next_sequence: from_sequence,
+            durable: None,
+            current: None,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            stats: TailStats::default(),
+        };
+        tailer.current = tailer.starting_segment()?;
+        Ok(tailer)
+    }
+
+    /// How long `wait_next` sleeps between two polls
+    pub fn set_poll_interval(&mut self, poll_interval: Duration) {
+        self.poll_interval = poll_interval;
+    }
+
+    /// Delivers only the records up to the sequence `durable` publishes, the watermark a writer
+    /// (`WalWriter` or `ConcurrentWal` with `sync` on) advances on each sync
+    pub fn set_durable_sequence(&mut self, durable: DurableSequence) {
+        self.durable = Some(durable);
+    }
+
+    /// The sequence the next delivered record will have at least, persist it to resume after a restart
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    /// Number of the segment being followed
+    pub fn current_segment(&self) -> Option<u64> {
+        self.current.as_ref().map(|segment| segment.number)
+    }
+
+    pub fn stats(&self) -> TailStats {
+        self.stats
+    }
+
+    /// Returns the next available record without blocking, `None` when the tailer has caught up
+    pub fn poll(&mut self) -> io::Result<Option<WalRecord>> {
+        loop {
+            if self.current.is_none() {
+                self.current = self.starting_segment()?;
+            }
+            let Some(segment) = self.current.as_mut() else {
+                return Ok(None);
+            };
+            segment.read_available()?;
+
+            match self.decode_available()? {
+                Available::Record(record) => return Ok(Some(record)),
+                Available::NotDurable => return Ok(None),
+                Available::CaughtUp => {}
+            }
+
+            // caught up with the segment, it is complete only if the writer has moved to a newer one
+            let number = self.current.as_ref().map(|segment| segment.number).unwrap();
+            let Some(next) = list_segments_in(self.fs.as_ref(), &self.dir)?.into_iter().find(|&n| n > number) else {
+                return Ok(None);
+            };
+            // the writer rolled between our read and the listing, drain what it wrote in the meantime
+            self.current.as_mut().unwrap().read_available()?;
+            match self.decode_available()? {
+                Available::Record(record) => return Ok(Some(record)),
+                Available::NotDurable => return Ok(None),
+                Available::CaughtUp => {}
+            }
+            let segment = self.current.take().unwrap();
+            self.stats.truncated_tail_bytes += (segment.buf.len() - segment.pos) as u64;
+            self.stats.segments_completed += 1;
+            self.current = Some(TailedSegment::open(self.fs.as_ref(), &self.dir, next)?);
+        }
+    }
+
+    /// Waits up to `timeout` for the next record
+    pub fn wait_next(&mut self, timeout: Duration) -> io::Result<Option<WalRecord>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(record) = self.poll()? {
+                return Ok(Some(record));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            std::thread::sleep(self.poll_interval.min(deadline - now));
+        }
+    }
+
+    fn decode_available(&mut self) -> io::Result<Available> {
+        let segment = self.current.as_mut().unwrap();
+        if !segment.resolve_decoder(&self.registry)? {
+            return Ok(Available::CaughtUp);
+        }
+        let decoder = segment.decoder.clone().unwrap();
+        loop {
+            match decoder.decode(&segment.buf[segment.pos..]) {
+                Decoded::Record { record, len } => {
+                    if record.sequence < self.next_sequence {
+                        segment.pos += len;
+                        continue;
+                    }
+                    let durable = |durable: &DurableSequence| durable.get().is_some_and(|d| d >= record.sequence);
+                    if !self.durable.as_ref().is_none_or(durable) {
+                        // read again once synced
+                        return Ok(Available::NotDurable);
+                    }
+                    segment.pos += len;
+                    self.next_sequence = record.sequence + 1;
+                    self.stats.records += 1;
+                    return Ok(Available::Record(record.to_record()));
+                }
+                Decoded::Corrupted { len } => {
+                    segment.pos += len;
+                    self.stats.corrupted_records += 1;
+                }
+                Decoded::Incomplete => return Ok(Available::CaughtUp),
+            }
+        }
+    }
+
+    /// The newest segment whose first record is not after `next_sequence`, older ones hold only
+    /// records the consumer has already processed
+    fn starting_segment(&self) -> io::Result<Option<TailedSegment>> {
+        let segments = list_segments_in(self.fs.as_ref(), &self.dir)?;
+        let mut start = None;
+        for &number in &segments {
+            match first_sequence(self.fs.as_ref(), &self.dir, number, &self.registry)? {
+                Some(first) if first > self.next_sequence => break,
+                Some(_) => start = Some(number),
+                // a segment without records yet is where the writer is appending
+                None => {
+                    start.get_or_insert(number);
+                    break;
+                }
+            }
+        }
+        let start = start.or_else(|| segments.first().copied());
+        start
+            .map(|number| TailedSegment::open(self.fs.as_ref(), &self.dir, number))
+            .transpose()
+    }
+}
+
+fn first_sequence(
+    fs: &dyn FileSystem,
+    dir: &Path,
+    number: u64,
+    registry: &DecoderRegistry,
+) -> io::Result<Option<u64>> {
+    let mut reader = match WalReader::open_in(fs, segment_path(dir, number), registry) {
+        Ok(reader) => reader,
+        // the writer has not written the header yet
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(reader.next_record()?.map(|record| record.sequence))
+}
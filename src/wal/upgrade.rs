@@ -0,0 +1,137 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::env::{FileSystem, PosixFileSystem, SequentialFile};
+
+use super::reader::WalReader;
+use super::version::{CURRENT_VERSION, DecoderRegistry};
+use super::writer::WalWriter;
+
+/// Summary of a segment upgrade
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpgradeReport {
+    pub from_version: u32,
+    pub to_version: u32,
+    pub records: u64,
+    /// False when the segment was already in the current version and has been left untouched
+    pub rewritten: bool,
+}
+
+/// Rewrites a segment of an older format version into `CURRENT_VERSION`, in place
+///
+/// Every record must pass its CRC32C check, a segment with corrupted records or a torn tail is
+/// refused instead of silently losing data. The new segment is written next to the old one,
+/// read back and compared, then renamed over it so that a crash leaves either the old or the new file.
+/// A segment of a newer version than `CURRENT_VERSION` is refused with `Unsupported`, it is never downgraded.
+pub fn upgrade_segment(path: impl AsRef<Path>, registry: &DecoderRegistry) -> io::Result<UpgradeReport> {
+    upgrade_segment_in(&PosixFileSystem, path, registry)
+}
+
+pub fn upgrade_segment_in(
+    fs: &dyn FileSystem,
+    path: impl AsRef<Path>,
+    registry: &DecoderRegistry,
+) -> io::Result<UpgradeReport> {
+    let path = path.as_ref();
+    let mut reader = WalReader::open_in(fs, path, registry)?;
+    let from_version = reader.version();
+    if from_version > CURRENT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("WAL version {from_version} is newer than the current version {CURRENT_VERSION}"),
+        ));
+    }
+
+    if from_version == CURRENT_VERSION {
+        let mut records = 0;
+        while reader.next_record()?.is_some() {
+            records += 1;
+        }
+        check_clean(path, &reader)?;
+        return Ok(UpgradeReport {
+            from_version,
+            to_version: CURRENT_VERSION,
+            records,
+            rewritten: false,
+        });
+    }
+
+    let tmp_path = upgrade_tmp_path(path);
+    let result = rewrite(fs, path, &tmp_path, &mut reader, registry);
+    if result.is_err() {
+        let _ = fs.remove_file(&tmp_path);
+    }
+    let records = result?;
+
+    fs.rename(&tmp_path, path)?;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs.sync_dir(dir)?;
+    }
+
+    Ok(UpgradeReport {
+        from_version,
+        to_version: CURRENT_VERSION,
+        records,
+        rewritten: true,
+    })
+}
+
+fn rewrite(
+    fs: &dyn FileSystem,
+    path: &Path,
+    tmp_path: &Path,
+    reader: &mut WalReader<Box<dyn SequentialFile>>,
+    registry: &DecoderRegistry,
+) -> io::Result<u64> {
+    let mut writer = WalWriter::create_in(fs, tmp_path)?;
+    let mut sequences = Vec::new();
+    while let Some(record) = reader.next_record()? {
+        writer.append(record.record_type, record.sequence, &record.key, &record.value)?;
+        sequences.push(record.sequence);
+    }
+    check_clean(path, reader)?;
+    writer.sync()?;
+    drop(writer);
+
+    // read the new segment back, every record must decode with a valid CRC and keep its sequence
+    let mut verify = WalReader::open_in(fs, tmp_path, registry)?;
+    let mut expected = sequences.iter();
+    while let Some(record) = verify.next_record()? {
+        if expected.next() != Some(&record.sequence) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("upgraded segment {} lost sequence order at {}", path.display(), record.sequence),
+            ));
+        }
+    }
+    check_clean(tmp_path, &verify)?;
+    if expected.next().is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("upgraded segment {} is missing records", path.display()),
+        ));
+    }
+    Ok(sequences.len() as u64)
+}
+
+fn check_clean(path: &Path, reader: &WalReader<impl io::Read>) -> io::Result<()> {
+    let stats = reader.stats();
+    if stats.corrupted_records > 0 || stats.truncated_tail_bytes > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "segment {} has {} corrupted records and {} torn tail bytes",
+                path.display(),
+                stats.corrupted_records,
+                stats.truncated_tail_bytes
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn upgrade_tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".upgrade");
+    path.with_file_name(name)
+}
@@ -0,0 +1,148 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::env::{FileSystem, PosixFileSystem, WritableFile};
+
+use super::record::{FILE_HEADER_SIZE, FileHeader, RecordType, encode_record};
+use super::version::CURRENT_VERSION;
+
+/// Highest sequence a writer has made durable, shared with the readers that must not get ahead
+/// of it, see `WalTailer::set_durable_sequence`
+///
+/// One watermark can be handed to the writers of successive segments, it only moves forward.
+#[derive(Debug, Clone, Default)]
+pub struct DurableSequence(Arc<AtomicU64>);
+
+impl DurableSequence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `None` until a writer has synced a record
+    pub fn get(&self) -> Option<u64> {
+        self.0.load(Ordering::Acquire).checked_sub(1)
+    }
+
+    /// Raises the watermark to `sequence`, a lower one is ignored
+    pub fn advance(&self, sequence: u64) {
+        self.0.fetch_max(sequence.saturating_add(1), Ordering::AcqRel);
+    }
+}
+
+/// Sequential writer of a WAL segment in the current format version
+///
+/// Records are buffered, they are durable only after `sync` returns.
+/// After an I/O error the writer refuses any further call: the tail of the segment is unknown
+/// (a record may be half written) and appending after it would hide the records behind garbage.
+pub struct WalWriter {
+    out: Box<dyn WritableFile>,
+    buf: Vec<u8>,
+    last_sequence: Option<u64>,
+    len: u64,
+    failed: bool,
+    durable: Option<DurableSequence>,
+}
+
+impl WalWriter {
+    /// Creates a new segment, failing if the file already exists
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::create_in(&PosixFileSystem, path)
+    }
+
+    pub fn create_in(fs: &dyn FileSystem, path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(fs.create_new(path.as_ref())?)
+    }
+
+    /// Writes the file header to a freshly created file
+    pub fn new(mut out: Box<dyn WritableFile>) -> io::Result<Self> {
+        out.append(&FileHeader { version: CURRENT_VERSION }.encode())?;
+        Ok(WalWriter {
+            out,
+            buf: Vec::new(),
+            last_sequence: None,
+            len: FILE_HEADER_SIZE as u64,
+            failed: false,
+            durable: None,
+        })
+    }
+
+    /// Appends a record, sequences must be strictly increasing inside a segment
+    pub fn append(&mut self, record_type: RecordType, sequence: u64, key: &[u8], value: &[u8]) -> io::Result<()> {
+        if self.last_sequence.is_some_and(|last| sequence <= last) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("sequence {sequence} is not greater than the last written {:?}", self.last_sequence),
+            ));
+        }
+        if key.len() > u32::MAX as usize || value.len() > u32::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "key or value larger than 4GB"));
+        }
+        self.check_failed()?;
+        self.buf.clear();
+        encode_record(&mut self.buf, record_type, sequence, key, value);
+        let result = self.out.append(&self.buf);
+        self.track(result)?;
+        self.len += self.buf.len() as u64;
+        self.last_sequence = Some(sequence);
+        Ok(())
+    }
+
+    pub fn put(&mut self, sequence: u64, key: &[u8], value: &[u8]) -> io::Result<()> {
+        self.append(RecordType::Put, sequence, key, value)
+    }
+
+    pub fn delete(&mut self, sequence: u64, key: &[u8]) -> io::Result<()> {
+        self.append(RecordType::Delete, sequence, key, &[])
+    }
+
+    /// Hands the buffered records to the OS, they survive a process crash but not a power loss
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.check_failed()?;
+        let result = self.out.flush();
+        self.track(result)
+    }
+
+    /// Makes every appended record durable
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.check_failed()?;
+        let result = self.out.sync();
+        self.track(result)?;
+        if let (Some(durable), Some(sequence)) = (&self.durable, self.last_sequence) {
+            durable.advance(sequence);
+        }
+        Ok(())
+    }
+
+    /// Publishes the last sequence of every successful `sync` to `durable`
+    pub fn set_durable_sequence(&mut self, durable: DurableSequence) {
+        self.durable = Some(durable);
+    }
+
+    pub fn last_sequence(&self) -> Option<u64> {
+        self.last_sequence
+    }
+
+    /// Size of the segment including the buffered bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// True when only the file header has been written
+    pub fn is_empty(&self) -> bool {
+        self.last_sequence.is_none()
+    }
+
+    fn check_failed(&self) -> io::Result<()> {
+        match self.failed {
+            true => Err(io::Error::other("WAL writer failed on a previous I/O error")),
+            false => Ok(()),
+        }
+    }
+
+    fn track(&mut self, result: io::Result<()>) -> io::Result<()> {
+        self.failed |= result.is_err();
+        result
+    }
+}
@@ -0,0 +1,237 @@
+use std::path::Path;
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use stone_kvs::cache::{BlockCache, BlockCacheOptions, EvictionPolicy, Priority};
+use stone_kvs::env::{FileSystem, MemFileSystem};
+use stone_kvs::table::block::{Block, BlockBuilder};
+use stone_kvs::table::{IndexType, TableBuilder, TableOptions, TableReader};
+use stone_kvs::wal::RecordType;
+
+/// A block of about `size` bytes
+fn block(size: usize) -> Arc<Block> {
+    let mut builder = BlockBuilder::new(16);
+    builder.add(b"key", &vec![7u8; size]);
+    Arc::new(Block::new(builder.finish().to_vec()).unwrap())
+}
+
+fn single_shard(capacity: usize, eviction: EvictionPolicy) -> BlockCache {
+    BlockCache::with_options(BlockCacheOptions {
+        capacity,
+        num_shard_bits: Some(0),
+        eviction,
+        ..BlockCacheOptions::default()
+    })
+}
+
+fn cached(cache: &BlockCache, keys: impl Iterator<Item = u64>) -> Vec<u64> {
+    keys.filter(|&offset| cache.get((1, offset)).is_some()).collect()
+}
+
+#[test]
+fn lru_evicts_least_recently_used() {
+    let cache = single_shard(1000, EvictionPolicy::Lru);
+    for offset in 0..4 {
+        cache.insert_with((1, offset), block(200), 250, Priority::Low);
+    }
+    assert!(cache.get((1, 0)).is_some());
+    cache.insert_with((1, 4), block(200), 250, Priority::Low);
+
+    assert_eq!(cached(&cache, 0..5), vec![0, 2, 3, 4]);
+    assert_eq!(cache.usage(), 1000);
+    let stats = cache.stats();
+    assert_eq!((stats.inserts, stats.evictions), (5, 1));
+    // larger than the capacity: not kept
+    assert!(!cache.insert_with((1, 9), block(10), 2000, Priority::Low));
+    assert_eq!(cache.len(), 4);
+}
+
+#[test]
+fn high_priority_pool_survives_scans() {
+    let cache = BlockCache::with_options(BlockCacheOptions {
+        capacity: 10_000,
+        num_shard_bits: Some(0),
+        high_priority_ratio: 0.3,
+        ..BlockCacheOptions::default()
+    });
+    for offset in 0..3 {
+        cache.insert_with((1, offset), block(900), 1000, Priority::High);
+    }
+    // a scan through many more low-priority blocks than the cache holds
+    for offset in 100..200 {
+        cache.insert_with((1, offset), block(900), 1000, Priority::Low);
+    }
+
+    assert_eq!(cached(&cache, 0..3), vec![0, 1, 2]);
+    assert_eq!(cache.high_priority_usage(), 3000);
+    assert_eq!(cache.usage(), 10_000);
+    // a high-priority block past the pool is demoted, then evicted like the others
+    cache.insert_with((1, 3), block(900), 1000, Priority::High);
+    assert_eq!(cache.high_priority_usage(), 3000);
+    for offset in 200..210 {
+        cache.insert_with((1, offset), block(900), 1000, Priority::Low);
+    }
+    assert_eq!(cached(&cache, 0..4).len(), 3);
+}
+
+#[test]
+fn clock_keeps_blocks_hit_since_the_last_sweep() {
+    let cache = single_shard(4000, EvictionPolicy::Clock);
+    for offset in 0..4 {
+        cache.insert_with((1, offset), block(900), 1000, Priority::Low);
+    }
+    for _ in 0..2 {
+        assert!(cache.get((1, 1)).is_some());
+        assert!(cache.get((1, 3)).is_some());
+    }
+    for offset in 10..12 {
+        cache.insert_with((1, offset), block(900), 1000, Priority::Low);
+    }
+
+    assert_eq!(cached(&cache, 0..4), vec![1, 3]);
+    assert_eq!(cache.usage(), 4000);
+    // high-priority blocks start with the highest count
+    cache.insert_with((1, 20), block(900), 1000, Priority::High);
+    for offset in 30..33 {
+        cache.insert_with((1, offset), block(900), 1000, Priority::Low);
+    }
+    assert_eq!(cached(&cache, 20..21), vec![20]);
+    assert_eq!(cache.stats().evictions, 6);
+}
+
+#[test]
+fn pinned_blocks_are_not_evicted() {
+    for eviction in [EvictionPolicy::Lru, EvictionPolicy::Clock] {
+        let cache = single_shard(1000, eviction);
+        cache.insert_with((1, 0), block(400), 500, Priority::Low);
+        let pinned = cache.get((1, 0)).unwrap();
+        cache.insert_with((1, 1), block(400), 500, Priority::Low);
+        let also_pinned = cache.get((1, 1)).unwrap();
+        // held by the caller while inserted, like a block read by a table
+        let inserted = block(400);
+        cache.insert_with((1, 2), Arc::clone(&inserted), 500, Priority::Low);
+
+        // over capacity while the blocks are held
+        assert_eq!(cached(&cache, 0..3), vec![0, 1, 2], "{eviction:?}");
+        assert_eq!(cache.pinned_usage(), 1500);
+        assert_eq!(cache.usage(), 1500);
+        drop((pinned, also_pinned, inserted));
+        assert_eq!(cache.pinned_usage(), 0);
+        assert!(cache.usage() <= 1000);
+
+        let strict = BlockCache::with_options(BlockCacheOptions {
+            capacity: 1000,
+            num_shard_bits: Some(0),
+            eviction,
+            strict_capacity_limit: true,
+            ..BlockCacheOptions::default()
+        });
+        strict.insert_with((1, 0), block(400), 600, Priority::Low);
+        let pinned = strict.get((1, 0)).unwrap();
+        assert!(!strict.insert_with((1, 1), block(400), 600, Priority::Low));
+        assert_eq!(strict.len(), 1);
+        drop(pinned);
+        assert!(strict.insert_with((1, 1), block(400), 600, Priority::Low));
+        assert_eq!(cached(&strict, 0..2), vec![1]);
+    }
+}
+
+#[test]
+fn cache_is_sharded_by_capacity() {
+    assert_eq!(BlockCache::new(64 * 1024).num_shards(), 1);
+    assert_eq!(BlockCache::new(8 * 1024 * 1024).num_shards(), 16);
+    assert_eq!(BlockCache::new(1 << 30).num_shards(), 64);
+    let explicit = BlockCache::with_options(BlockCacheOptions {
+        num_shard_bits: Some(3),
+        ..BlockCacheOptions::default()
+    });
+    assert_eq!(explicit.num_shards(), 8);
+}
+
+#[test]
+fn concurrent_readers_share_the_cache() {
+    for eviction in [EvictionPolicy::Lru, EvictionPolicy::Clock] {
+        let cache = Arc::new(BlockCache::with_options(BlockCacheOptions {
+            capacity: 64 * 1024,
+            num_shard_bits: Some(4),
+            eviction,
+            ..BlockCacheOptions::default()
+        }));
+        let barrier = Arc::new(Barrier::new(8));
+        let threads: Vec<_> = (0..8u64)
+            .map(|thread| {
+                let cache = Arc::clone(&cache);
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    // 16 blocks shared by the threads, they fit in the cache
+                    for i in 0..2000u64 {
+                        let key = (thread % 2, (i * 7 + thread) % 8);
+                        match cache.get(key) {
+                            Some(block) => assert!(block.size() > 500),
+                            None => {
+                                cache.insert(key, block(600));
+                            }
+                        }
+                    }
+                    // then a scan of blocks read once pushes them out, whatever the interleaving
+                    barrier.wait();
+                    for offset in 0..100 {
+                        cache.insert((10 + thread, offset), block(600));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits + stats.misses, 16_000);
+        // a block is missed at most once per thread before it is cached
+        assert!(stats.misses <= 16 * 8, "{eviction:?} {stats:?}");
+        assert!(stats.evictions > 0, "{eviction:?} {stats:?}");
+        // each of the 16 shards holds up to a sixteenth of the capacity
+        assert!(cache.usage() <= 64 * 1024);
+    }
+}
+
+#[test]
+fn tables_read_through_a_clock_cache() {
+    let fs = MemFileSystem::new();
+    fs.create_dir_all(Path::new("/table")).unwrap();
+    let cache = Arc::new(BlockCache::with_options(BlockCacheOptions {
+        capacity: 256 * 1024,
+        eviction: EvictionPolicy::Clock,
+        ..BlockCacheOptions::default()
+    }));
+    let options = TableOptions {
+        block_size: 1024,
+        index_type: IndexType::Partitioned,
+        index_partition_size: 256,
+        block_cache: Some(Arc::clone(&cache)),
+        ..TableOptions::default()
+    };
+    let path = Path::new("/table/000001.sst");
+    let mut builder = TableBuilder::new(fs.create_new(path).unwrap(), options.clone());
+    for n in 0..5000u32 {
+        builder.add(format!("key{n:06}").as_bytes(), 1, RecordType::Put, &[1u8; 40]).unwrap();
+    }
+    builder.finish().unwrap();
+    let table = TableReader::open(&fs, path, &options).unwrap();
+
+    let lookup_all = || {
+        for n in (0..5000u32).step_by(100) {
+            assert!(table.get(format!("key{n:06}").as_bytes(), u64::MAX).unwrap().is_some());
+        }
+    };
+    lookup_all();
+    let first_pass = cache.stats();
+    lookup_all();
+    let second_pass = cache.stats();
+    // a partition and a data block per key, read once
+    assert_eq!(second_pass.misses, first_pass.misses);
+    assert_eq!(second_pass.hits - first_pass.hits, 100);
+    assert_eq!(table.iter().count(), 5000);
+    assert_eq!(cache.pinned_usage(), 0);
+    assert!(cache.usage() <= 256 * 1024);
+}
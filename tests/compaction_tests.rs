@@ -0,0 +1,251 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use stone_kvs::db::{Db, DbOptions, NUM_LEVELS, compaction_scores, max_bytes_for_level};
+use stone_kvs::env::{FaultInjectionFileSystem, FileOp, FileSystem, MemFileSystem};
+use stone_kvs::table::{TableEntry, parse_table_file_name};
+
+/// Small levels, the tests flush their memtable themselves
+fn small_levels() -> DbOptions {
+    DbOptions {
+        level0_file_num_compaction_trigger: 4,
+        max_bytes_for_level_base: 256 * 1024,
+        max_bytes_for_level_multiplier: 4.0,
+        target_file_size_base: 64 * 1024,
+        ..DbOptions::default()
+    }
+}
+
+fn key(n: u64) -> Vec<u8> {
+    format!("key{n:06}").into_bytes()
+}
+
+/// Every entry of every table of the store
+fn table_entries(db: &Db) -> Vec<TableEntry> {
+    let mut entries = Vec::new();
+    for number in db.table_numbers() {
+        let table = db.table_cache().get(number).unwrap();
+        entries.extend(table.iter().collect::<io::Result<Vec<_>>>().unwrap());
+    }
+    entries
+}
+
+fn table_files(fs: &dyn FileSystem) -> Vec<u64> {
+    let names = fs.list_dir(Path::new("/db")).unwrap();
+    let mut numbers: Vec<u64> = names.iter().filter_map(|name| parse_table_file_name(name)).collect();
+    numbers.sort_unstable();
+    numbers
+}
+
+#[test]
+fn level_targets_grow_by_the_multiplier() {
+    let options = small_levels();
+    assert_eq!(max_bytes_for_level(&options, 1), 256.0 * 1024.0);
+    assert_eq!(max_bytes_for_level(&options, 3), 16.0 * 256.0 * 1024.0);
+    let db = Db::open_in(Arc::new(MemFileSystem::new()), "/db", options.clone()).unwrap();
+    assert_eq!(compaction_scores(&db.current_version(), &options), vec![0.0; NUM_LEVELS - 1]);
+}
+
+#[test]
+fn leveled_compaction_keeps_the_levels_in_shape() {
+    let fs: Arc<dyn FileSystem> = Arc::new(MemFileSystem::new());
+    let options = DbOptions {
+        write_buffer_size: 256 * 1024,
+        max_bytes_for_level_base: 128 * 1024,
+        ..small_levels()
+    };
+    let db = Db::open_in(Arc::clone(&fs), "/db", options.clone()).unwrap();
+    let mut expected = BTreeMap::new();
+    let mut seed = 7u64;
+    for i in 0..20_000u64 {
+        seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+        let n = (seed >> 33) % 5000;
+        if i % 10 == 9 {
+            db.delete(&key(n)).unwrap();
+            expected.remove(&key(n));
+        } else {
+            let value = format!("value{i}-").repeat(8).into_bytes();
+            db.put(&key(n), &value).unwrap();
+            expected.insert(key(n), value);
+        }
+    }
+    db.flush().unwrap();
+    db.wait_for_compactions().unwrap();
+
+    let version = db.current_version();
+    assert!(version.files(0).len() < options.level0_file_num_compaction_trigger);
+    assert!(version.files(2).len() + version.files(3).len() > 0, "{version:?}");
+    for score in compaction_scores(&version, &options) {
+        assert!(score < 1.0);
+    }
+    // the tables of a level are sorted and never share a key
+    for level in 1..NUM_LEVELS {
+        for pair in version.files(level).windows(2) {
+            assert!(pair[0].largest < pair[1].smallest, "level {level}");
+        }
+    }
+    // the inputs of the compactions are deleted
+    assert_eq!(table_files(fs.as_ref()), {
+        let mut numbers = version.table_numbers();
+        numbers.sort_unstable();
+        numbers
+    });
+
+    for n in 0..5000 {
+        assert_eq!(db.get(&key(n)).unwrap(), expected.get(&key(n)).cloned(), "{n}");
+    }
+    let scanned: Vec<(Vec<u8>, Vec<u8>)> = db.prefix_iter(b"key").collect::<io::Result<_>>().unwrap();
+    assert_eq!(scanned, expected.clone().into_iter().collect::<Vec<_>>());
+
+    drop(db);
+    let db = Db::open_in(fs, "/db", options).unwrap();
+    assert_eq!(db.current_version().table_numbers(), version.table_numbers());
+    assert_eq!(db.get(&key(1234)).unwrap(), expected.get(&key(1234)).cloned());
+}
+
+#[test]
+fn compaction_drops_shadowed_versions_and_tombstones() {
+    let db = Db::open_in(Arc::new(MemFileSystem::new()), "/db", small_levels()).unwrap();
+    for round in 0..4u64 {
+        for n in 0..200 {
+            db.put(&key(n), format!("round{round}").as_bytes()).unwrap();
+        }
+        for n in (0..200).filter(|n| n % 4 == round) {
+            db.delete(&key(n)).unwrap();
+        }
+        db.flush().unwrap();
+    }
+    db.wait_for_compactions().unwrap();
+
+    // one version per key left, the keys deleted last round are gone: nothing lies below
+    let version = db.current_version();
+    assert!(version.files(0).is_empty());
+    let entries = table_entries(&db);
+    assert_eq!(entries.len(), 150);
+    assert!(entries.iter().all(|entry| entry.value == b"round3"));
+    assert_eq!(db.get(&key(3)).unwrap(), None);
+    assert_eq!(db.get(&key(4)).unwrap(), Some(b"round3".to_vec()));
+}
+
+#[test]
+fn compaction_keeps_what_a_snapshot_sees() {
+    let db = Db::open_in(Arc::new(MemFileSystem::new()), "/db", small_levels()).unwrap();
+    for n in 0..100 {
+        db.put(&key(n), b"old").unwrap();
+    }
+    let snapshot = db.snapshot();
+    db.flush().unwrap();
+    for round in 0..3 {
+        for n in 0..100 {
+            match n % 2 {
+                0 => db.put(&key(n), format!("new{round}").as_bytes()).unwrap(),
+                _ => db.delete(&key(n)).unwrap(),
+            };
+        }
+        db.flush().unwrap();
+    }
+    db.wait_for_compactions().unwrap();
+    assert!(db.current_version().files(0).is_empty(), "{:?}", db.current_version());
+
+    for n in 0..100 {
+        assert_eq!(db.get_at(&key(n), &snapshot).unwrap(), Some(b"old".to_vec()));
+        let latest = (n % 2 == 0).then(|| b"new2".to_vec());
+        assert_eq!(db.get(&key(n)).unwrap(), latest);
+    }
+    // every version newer than the snapshot is kept while it lives
+    assert_eq!(table_entries(&db).len(), 400);
+
+    drop(snapshot);
+    for n in 0..4 {
+        db.put(&key(n * 2), b"more").unwrap();
+        db.flush().unwrap();
+    }
+    db.wait_for_compactions().unwrap();
+    // one version of each key left, no tombstone
+    assert_eq!(table_entries(&db).len(), 50);
+}
+
+#[test]
+fn prefix_iter_reads_the_tables_a_compaction_removed() {
+    let fs: Arc<dyn FileSystem> = Arc::new(MemFileSystem::new());
+    let db = Db::open_in(Arc::clone(&fs), "/db", small_levels()).unwrap();
+    for table in 0..3u64 {
+        for n in 0..300 {
+            db.put(&key(n * 3 + table), &[table as u8; 100]).unwrap();
+        }
+        db.flush().unwrap();
+    }
+    let scan = db.prefix_iter(b"key");
+    let version = db.current_version().table_numbers();
+
+    for n in 0..300 {
+        db.put(&key(n * 3), &[9; 100]).unwrap();
+    }
+    db.flush().unwrap();
+    db.wait_for_compactions().unwrap();
+    assert!(db.current_version().files(0).is_empty());
+    // the old tables are still on disk for the iterator
+    assert!(version.iter().all(|number| table_files(fs.as_ref()).contains(number)));
+
+    let scanned: Vec<(Vec<u8>, Vec<u8>)> = scan.collect::<io::Result<_>>().unwrap();
+    assert_eq!(scanned.len(), 900);
+    assert_eq!(scanned[0], (key(0), vec![0; 100]));
+    assert_eq!(scanned[4], (key(4), vec![1; 100]));
+
+    // deleted with the next change of the tables
+    db.put(b"zzz", b"v").unwrap();
+    db.flush().unwrap();
+    db.wait_for_compactions().unwrap();
+    let mut live = db.current_version().table_numbers();
+    live.sort_unstable();
+    assert_eq!(table_files(fs.as_ref()), live);
+}
+
+#[test]
+fn compaction_output_is_cut_at_the_target_size_and_removed_on_failure() {
+    let fs = Arc::new(FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new())));
+    let options = DbOptions {
+        disable_auto_compactions: true,
+        ..small_levels()
+    };
+    let db = Db::open_in(fs.clone(), "/db", options.clone()).unwrap();
+    for table in 0..4u64 {
+        for n in 0..1000 {
+            db.put(&key(n * 4 + table), &[table as u8; 100]).unwrap();
+        }
+        db.flush().unwrap();
+    }
+    drop(db);
+
+    // the second output table fails while written
+    fs.fail_nth_on_path(FileOp::Sync, ".sst", 2);
+    let auto = DbOptions {
+        disable_auto_compactions: false,
+        ..options
+    };
+    let db = Db::open_in(fs.clone(), "/db", auto.clone()).unwrap();
+    assert!(db.wait_for_compactions().is_err());
+    drop(db);
+    let names = fs.list_dir(Path::new("/db")).unwrap();
+    assert!(!names.iter().any(|name| name.ends_with(".tmp")), "{names:?}");
+
+    fs.clear_faults();
+    let db = Db::open_in(fs.clone(), "/db", auto).unwrap();
+    db.wait_for_compactions().unwrap();
+    let version = db.current_version();
+    assert!(version.files(0).is_empty());
+    // about 480KB in tables of 64KB, each a little over the target
+    let files: Vec<_> = version.tables().collect();
+    assert!(files.len() >= 6, "{version:?}");
+    for file in &files {
+        assert!(file.file_size < 64 * 1024 + 8 * 1024, "{file:?}");
+    }
+    assert_eq!(table_files(fs.as_ref()), {
+        let mut numbers = version.table_numbers();
+        numbers.sort_unstable();
+        numbers
+    });
+    assert_eq!(db.get(&key(2001)).unwrap(), Some(vec![1; 100]));
+}
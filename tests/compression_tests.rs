@@ -0,0 +1,282 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use stone_kvs::db::{Db, DbOptions};
+use stone_kvs::env::{FileSystem, MemFileSystem};
+use stone_kvs::table::{
+    BlockBasedTableFactory, CompressionType, TableBuilder, TableEntry, TableOptions, TableReader, dictionary, lz4,
+    snappy,
+};
+use stone_kvs::wal::RecordType;
+
+const CODECS: [CompressionType; 2] = [CompressionType::Snappy, CompressionType::Lz4];
+
+fn random_bytes(len: usize, mut seed: u64) -> Vec<u8> {
+    (0..len)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as u8
+        })
+        .collect()
+}
+
+/// Records with repeated field names and small numbers, like the values of a store
+fn text_like(len: usize) -> Vec<u8> {
+    let mut text = Vec::new();
+    let mut n = 0u64;
+    while text.len() < len {
+        text.extend_from_slice(format!("{{\"id\":{n},\"name\":\"user-{}\",\"active\":true}}", n % 97).as_bytes());
+        n += 1;
+    }
+    text.truncate(len);
+    text
+}
+
+#[test]
+fn codecs_round_trip() {
+    let inputs = [
+        Vec::new(),
+        b"a".to_vec(),
+        b"abcdabcdabcdabcdabcd".to_vec(),
+        vec![0u8; 100_000],
+        text_like(16 * 1024),
+        // over the 64KB fragments of Snappy
+        text_like(200_000),
+        random_bytes(20_000, 7),
+        [random_bytes(500, 1), random_bytes(500, 1), vec![9; 300]].concat(),
+    ];
+    for codec in CODECS {
+        for input in &inputs {
+            let compressed = codec.compress(input);
+            assert_eq!(&codec.uncompress(compressed).unwrap(), input, "{codec:?} {}", input.len());
+        }
+        for input in [&inputs[3], &inputs[4], &inputs[5]] {
+            assert!(codec.compress(input).len() * 2 < input.len(), "{codec:?} {}", input.len());
+        }
+        // incompressible input grows a little, by the length and the element headers
+        let random = random_bytes(16 * 1024, 3);
+        assert!(codec.compress(&random).len() < random.len() + 100);
+    }
+}
+
+#[test]
+fn codecs_read_reference_streams() {
+    // a literal "abcd" then a copy of 6 bytes at offset 4, with a 1-byte offset
+    let stream = [10, 0x0c, b'a', b'b', b'c', b'd', 0x09, 0x04];
+    assert_eq!(snappy::uncompress(&stream).unwrap(), b"abcdabcdab");
+    // the same copy with a 2-byte offset
+    let stream = [10, 0x0c, b'a', b'b', b'c', b'd', 0x16, 0x04, 0x00];
+    assert_eq!(snappy::uncompress(&stream).unwrap(), b"abcdabcdab");
+    // 4 literals with a match of 6, then 5 last literals
+    let stream = [15, 0x42, b'a', b'b', b'c', b'd', 0x04, 0x00, 0x50, b'v', b'w', b'x', b'y', b'z'];
+    assert_eq!(lz4::uncompress(&stream).unwrap(), b"abcdabcdabvwxyz");
+}
+
+#[test]
+fn codecs_reject_malformed_input() {
+    let input = text_like(4096);
+    for codec in CODECS {
+        let compressed = codec.compress(&input);
+        for len in 0..compressed.len() {
+            let err = codec.uncompress(compressed[..len].to_vec()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        // a flipped byte never panics, mostly fails
+        for at in 0..compressed.len() {
+            let mut flipped = compressed.clone();
+            flipped[at] ^= 0x5a;
+            if let Err(e) = codec.uncompress(flipped) {
+                assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            }
+        }
+    }
+    // a copy reaching before the start of the output
+    assert!(snappy::uncompress(&[8, 0x00, b'a', 0x09, 0x04]).is_err());
+    assert!(lz4::uncompress(&[8, 0x12, b'a', 0x04, 0x00]).is_err());
+}
+
+fn build_table(fs: &MemFileSystem, name: &str, options: &TableOptions, value: impl Fn(u32) -> Vec<u8>) -> TableReader {
+    let path = Path::new("/table").join(name);
+    let mut builder = TableBuilder::new(fs.create_new(&path).unwrap(), options.clone());
+    for n in 0..3000u32 {
+        builder.add(format!("key{n:06}").as_bytes(), n as u64 + 1, RecordType::Put, &value(n)).unwrap();
+    }
+    builder.finish().unwrap();
+    TableReader::open(fs, &path, options).unwrap()
+}
+
+#[test]
+fn tables_store_compressed_data_blocks() {
+    let fs = MemFileSystem::new();
+    fs.create_dir_all(Path::new("/table")).unwrap();
+    let value = |n: u32| text_like(100 + (n % 200) as usize);
+    let raw = build_table(&fs, "raw.sst", &TableOptions::default(), value);
+    let entries: Vec<TableEntry> = raw.iter().collect::<io::Result<_>>().unwrap();
+
+    for codec in CODECS {
+        let options = TableOptions {
+            compression: codec,
+            ..TableOptions::default()
+        };
+        let table = build_table(&fs, &format!("{codec:?}.sst"), &options, value);
+        let properties = table.properties();
+        assert_eq!(properties.compression, codec as u64);
+        assert_eq!(properties.num_compressed_blocks, properties.num_data_blocks);
+        assert!(properties.data_size * 2 < raw.properties().data_size, "{codec:?}");
+
+        assert_eq!(table.iter().collect::<io::Result<Vec<_>>>().unwrap(), entries);
+        for n in (0..3000).step_by(37) {
+            let key = format!("key{n:06}").into_bytes();
+            assert_eq!(table.get(&key, u64::MAX).unwrap(), raw.get(&key, u64::MAX).unwrap());
+        }
+    }
+}
+
+#[test]
+fn incompressible_blocks_are_stored_raw() {
+    let fs = MemFileSystem::new();
+    fs.create_dir_all(Path::new("/table")).unwrap();
+    let value = |n: u32| random_bytes(300, n as u64 + 1);
+    let raw = build_table(&fs, "raw.sst", &TableOptions::default(), value);
+    for codec in CODECS {
+        let options = TableOptions {
+            compression: codec,
+            ..TableOptions::default()
+        };
+        let table = build_table(&fs, &format!("{codec:?}.sst"), &options, value);
+        assert_eq!(table.properties().num_compressed_blocks, 0);
+        assert_eq!(table.properties().data_size, raw.properties().data_size);
+        assert_eq!(table.get(b"key000042", u64::MAX).unwrap().unwrap().value, value(42));
+
+        // the keys alone save a bit, stored compressed without a threshold
+        let options = TableOptions {
+            compression_ratio_threshold: 1.0,
+            ..options
+        };
+        let table = build_table(&fs, &format!("{codec:?}-any.sst"), &options, value);
+        assert!(table.properties().num_compressed_blocks > 0);
+    }
+}
+
+#[test]
+fn compression_is_chosen_per_level() {
+    let options = TableOptions {
+        compression: CompressionType::Snappy,
+        compression_per_level: vec![CompressionType::None, CompressionType::Lz4, CompressionType::Snappy],
+        ..TableOptions::default()
+    };
+    let levels: Vec<_> = (0..5).map(|level| options.compression_for_level(level)).collect();
+    assert_eq!(levels[..3], options.compression_per_level[..]);
+    assert_eq!(levels[3..], [CompressionType::Snappy; 2]);
+    let everywhere = TableOptions {
+        compression: CompressionType::Lz4,
+        ..TableOptions::default()
+    };
+    assert_eq!(everywhere.compression_for_level(6), CompressionType::Lz4);
+
+    // flushes write level 0
+    let fs = Arc::new(MemFileSystem::new());
+    let factory = Arc::new(BlockBasedTableFactory {
+        options: TableOptions {
+            compression_per_level: vec![CompressionType::Lz4, CompressionType::Snappy],
+            ..TableOptions::default()
+        },
+    });
+    let db_options = DbOptions {
+        table_factory: factory.clone(),
+        ..DbOptions::default()
+    };
+    let db = Db::open_in(fs.clone(), "/db", db_options.clone()).unwrap();
+    for n in 0..1000 {
+        db.put(format!("key{n:06}").as_bytes(), &text_like(200)).unwrap();
+    }
+    db.flush().unwrap();
+    let number = db.table_numbers()[0];
+    drop(db);
+    let path = Path::new("/db").join(stone_kvs::table::table_file_name(number));
+    let table = TableReader::open(fs.as_ref(), &path, &factory.options).unwrap();
+    assert_eq!(table.properties().compression, CompressionType::Lz4 as u64);
+    assert!(table.properties().num_compressed_blocks > 0);
+
+    let db = Db::open_in(fs, "/db", db_options).unwrap();
+    assert_eq!(db.get(b"key000500").unwrap(), Some(text_like(200)));
+}
+
+/// A small JSON document, similar to the others but for its numbers and names
+fn document(n: u32) -> Vec<u8> {
+    let city = ["Lyon", "Porto", "Gdansk", "Tampere", "Bilbao"][n as usize % 5];
+    format!(
+        "{{\"id\":{n},\"type\":\"customer\",\"name\":\"customer-{}\",\"address\":{{\"city\":\"{city}\",\
+         \"zip\":\"{:05}\"}},\"tags\":[\"retail\",\"newsletter\"],\"created_at\":\"2024-03-{:02}T10:{:02}:00Z\"}}",
+        n * 7919 % 10007,
+        n * 31 % 100_000,
+        1 + n % 28,
+        n % 60
+    )
+    .into_bytes()
+}
+
+#[test]
+fn dictionary_compresses_small_documents_better() {
+    let fs = MemFileSystem::new();
+    fs.create_dir_all(Path::new("/table")).unwrap();
+    let raw = build_table(&fs, "raw.sst", &TableOptions::default(), document);
+    let entries: Vec<TableEntry> = raw.iter().collect::<io::Result<_>>().unwrap();
+
+    for codec in CODECS {
+        let options = TableOptions {
+            block_size: 1024,
+            compression: codec,
+            ..TableOptions::default()
+        };
+        let plain = build_table(&fs, &format!("{codec:?}.sst"), &options, document);
+        let options = TableOptions {
+            compression_dictionary_size: 16 * 1024,
+            // the dictionary is trained from the first blocks, the others use it too
+            compression_dictionary_buffer_size: 64 * 1024,
+            ..options
+        };
+        let table = build_table(&fs, &format!("{codec:?}-dict.sst"), &options, document);
+
+        let properties = table.properties();
+        assert!(properties.compression_dictionary_size > 0);
+        assert!(properties.compression_dictionary_size <= 16 * 1024);
+        assert_eq!(properties.num_compressed_blocks, properties.num_data_blocks);
+        assert!(
+            properties.data_size * 5 < plain.properties().data_size * 4,
+            "{codec:?}: {} with a dictionary, {} without",
+            properties.data_size,
+            plain.properties().data_size
+        );
+        assert_eq!(table.iter().collect::<io::Result<Vec<_>>>().unwrap(), entries);
+        for n in (0..3000).step_by(41) {
+            let key = format!("key{n:06}").into_bytes();
+            assert_eq!(table.get(&key, u64::MAX).unwrap().unwrap().value, document(n));
+        }
+    }
+}
+
+#[test]
+fn dictionary_holds_what_repeats_across_samples() {
+    let documents: Vec<Vec<u8>> = (0..200).map(document).collect();
+    let samples: Vec<&[u8]> = documents.iter().map(Vec::as_slice).collect();
+    let dict = dictionary::train(&samples, 4096);
+    assert!(!dict.is_empty() && dict.len() <= 4096);
+    assert!(dict.windows(8).any(|w| w == b"customer"));
+    assert!(dict.windows(10).any(|w| w == b"newsletter"));
+
+    let random: Vec<Vec<u8>> = (0..20).map(|seed| random_bytes(1000, seed + 1)).collect();
+    let samples: Vec<&[u8]> = random.iter().map(Vec::as_slice).collect();
+    assert!(dictionary::train(&samples, 4096).is_empty());
+
+    // a block only reads back with its dictionary
+    for codec in CODECS {
+        let compressed = codec.compress_with_dict(&document(5000), &dict);
+        assert!(compressed.len() * 2 < codec.compress(&document(5000)).len());
+        assert_eq!(codec.uncompress_with_dict(compressed.clone(), &dict).unwrap(), document(5000));
+        assert!(codec.uncompress(compressed).is_err());
+    }
+}
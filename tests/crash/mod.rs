@@ -0,0 +1,396 @@
+#![allow(dead_code)]
+
+//! Power-loss simulation shared by the crash-consistency tests
+//!
+//! A workload runs on a `FaultInjectionFileSystem` over a `MemFileSystem` while a `Model` records
+//! every write attempted and the sequence acknowledged as durable. `CrashFs::crash` cuts the power
+//! and returns the disk image, `check_wal` replays the image and compares it with the model:
+//! every acknowledged record must be there and nothing that was never written may appear.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use stone_kvs::env::{FaultInjectionFileSystem, FileSystem, MemFileSystem, read_file};
+use stone_kvs::wal::crc32c::{
+    crc32c, crc32c_hw, crc32c_hw_append, crc32c_slice8, crc32c_slice16, crc32c_slice16_bt, crc32c_slice32,
+    crc32c_table, crc32c_table_append,
+};
+use stone_kvs::wal::record::CRC_OFFSET;
+use stone_kvs::wal::segment::{list_segments_in, segment_path};
+use stone_kvs::wal::{ScanEntry, WalReader, WalRecord, WalWriter};
+
+type Crc32cFn = fn(&[u8]) -> u32;
+
+/// Every CRC32C implementation, each must agree with the checksum the writer stored
+pub const CRC32C_BACKENDS: [(&str, Crc32cFn); 9] = [
+    ("crc32c", crc32c),
+    ("crc32c_table", crc32c_table),
+    ("crc32c_slice8", crc32c_slice8),
+    ("crc32c_slice16", crc32c_slice16),
+    ("crc32c_slice16_bt", crc32c_slice16_bt),
+    ("crc32c_slice32", crc32c_slice32),
+    ("crc32c_hw", crc32c_hw),
+    ("crc32c_table_append", |data| {
+        let (head, tail) = data.split_at(data.len() / 2);
+        crc32c_table_append(crc32c_table_append(0, head), tail)
+    }),
+    ("crc32c_hw_append", |data| {
+        let (head, tail) = data.split_at(data.len() / 3);
+        crc32c_hw_append(crc32c_hw_append(0, head), tail)
+    }),
+];
+
+/// What reaches the disk of the bytes appended since the last sync of each file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerLoss {
+    /// Only synced bytes survive
+    DropUnsynced,
+    /// Everything handed to the OS survives
+    KeepUnsynced,
+    /// The first `n` unsynced bytes of every file survive
+    KeepPrefix(u64),
+    /// The unsynced bytes survive with one byte flipped, at this position modulo their length
+    FlipByte(u64),
+    /// The unsynced bytes are replaced by pseudo-random bytes from this seed
+    Garbage(u64),
+}
+
+impl PowerLoss {
+    /// One of each kind, with a few prefix lengths cutting a record header, a key and a value
+    pub fn all() -> Vec<PowerLoss> {
+        vec![
+            PowerLoss::DropUnsynced,
+            PowerLoss::KeepUnsynced,
+            PowerLoss::KeepPrefix(1),
+            PowerLoss::KeepPrefix(12),
+            PowerLoss::KeepPrefix(23),
+            PowerLoss::KeepPrefix(40),
+            PowerLoss::FlipByte(3),
+            PowerLoss::FlipByte(30),
+            PowerLoss::Garbage(17),
+        ]
+    }
+}
+
+/// Deterministic xorshift generator, the tests must replay the same workload for every crash point
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next_u64() as u8).collect()
+    }
+}
+
+/// A file system whose power can be cut
+pub struct CrashFs {
+    mem: MemFileSystem,
+    fs: FaultInjectionFileSystem,
+}
+
+impl CrashFs {
+    pub fn new(dir: &Path) -> Self {
+        let mem = MemFileSystem::new();
+        let fs = FaultInjectionFileSystem::new(Arc::new(mem.clone()));
+        fs.create_dir_all(dir).unwrap();
+        CrashFs { mem, fs }
+    }
+
+    pub fn fs(&self) -> &FaultInjectionFileSystem {
+        &self.fs
+    }
+
+    /// Simulates a power loss and returns what the disk holds afterwards
+    ///
+    /// Handles opened before the call fail from now on, the image is independent from `self`.
+    pub fn crash(&self, loss: PowerLoss) -> MemFileSystem {
+        let mut unsynced = Vec::new();
+        self.fs
+            .drop_unsynced_data_with(|path, synced, written| {
+                unsynced.push((path.to_path_buf(), synced, written));
+                match loss {
+                    PowerLoss::DropUnsynced => synced,
+                    PowerLoss::KeepPrefix(n) => synced + n,
+                    PowerLoss::KeepUnsynced | PowerLoss::FlipByte(_) | PowerLoss::Garbage(_) => written,
+                }
+            })
+            .unwrap();
+        let image = self.mem.snapshot();
+        if !matches!(loss, PowerLoss::FlipByte(_) | PowerLoss::Garbage(_)) {
+            return image;
+        }
+
+        for (path, synced, written) in unsynced {
+            let mut data = read_file(&image, &path).unwrap();
+            let tail = &mut data[synced as usize..written as usize];
+            match loss {
+                PowerLoss::FlipByte(at) => tail[(at % tail.len() as u64) as usize] ^= 0x10,
+                PowerLoss::Garbage(seed) => tail.copy_from_slice(&Rng::new(seed).bytes(tail.len())),
+                _ => unreachable!(),
+            }
+            image.set_contents(&path, data).unwrap();
+        }
+        image
+    }
+}
+
+/// What the workload wrote, and up to where the writes were acknowledged durable
+#[derive(Debug, Default)]
+pub struct Model {
+    written: BTreeMap<u64, WalRecord>,
+    durable_sequence: Option<u64>,
+}
+
+impl Model {
+    /// Records an attempted write, whether or not it succeeded
+    pub fn write(&mut self, record: WalRecord) {
+        self.written.insert(record.sequence, record);
+    }
+
+    /// Every write up to `sequence` has been acknowledged durable
+    pub fn acknowledge(&mut self, sequence: u64) {
+        self.durable_sequence = self.durable_sequence.max(Some(sequence));
+    }
+
+    pub fn durable_sequence(&self) -> Option<u64> {
+        self.durable_sequence
+    }
+
+    pub fn written(&self) -> usize {
+        self.written.len()
+    }
+}
+
+/// What replaying a crash image found, summed over many crashes by the tests
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Recovery {
+    pub crashes: u64,
+    pub records: u64,
+    pub corrupted_records: u64,
+    pub torn_tail_bytes: u64,
+    /// Segments whose header never made it to the disk
+    pub headerless_segments: u64,
+    /// Records whose checksum has been recomputed with every backend
+    pub checksums_verified: u64,
+}
+
+impl Recovery {
+    pub fn add(&mut self, other: Recovery) {
+        self.crashes += other.crashes;
+        self.records += other.records;
+        self.corrupted_records += other.corrupted_records;
+        self.torn_tail_bytes += other.torn_tail_bytes;
+        self.headerless_segments += other.headerless_segments;
+        self.checksums_verified += other.checksums_verified;
+    }
+}
+
+/// Replays every segment of `dir` in the image and checks it against the model
+///
+/// Panics with `context` when an acknowledged record is missing, when a record that was never
+/// written appears, or when the CRC32C backends disagree on a record.
+pub fn check_wal(image: &MemFileSystem, dir: &Path, model: &Model, context: &str) -> Recovery {
+    let mut recovery = Recovery {
+        crashes: 1,
+        ..Recovery::default()
+    };
+    let mut recovered: Vec<WalRecord> = Vec::new();
+
+    for number in list_segments_in(image, dir).unwrap() {
+        let path = segment_path(dir, number);
+        let data = read_file(image, &path).unwrap();
+        let mut reader = match WalReader::new(data.as_slice()) {
+            Ok(reader) => reader,
+            Err(e) => {
+                assert!(
+                    matches!(
+                        e.kind(),
+                        io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData | io::ErrorKind::Unsupported
+                    ),
+                    "{context}: {} cannot be opened: {e}",
+                    path.display()
+                );
+                recovery.headerless_segments += 1;
+                continue;
+            }
+        };
+        while let Some(entry) = reader.next_entry().unwrap() {
+            match entry {
+                ScanEntry::Record { offset, len, record } => {
+                    let raw = &data[offset as usize..offset as usize + len];
+                    check_checksum_with_every_backend(raw, true, context);
+                    recovery.checksums_verified += 1;
+                    recovered.push(record);
+                }
+                ScanEntry::Corrupted { raw, .. } => check_checksum_with_every_backend(&raw, false, context),
+                ScanEntry::TornTail { .. } => {}
+            }
+        }
+        let stats = reader.stats();
+        recovery.records += stats.records;
+        recovery.corrupted_records += stats.corrupted_records;
+        recovery.torn_tail_bytes += stats.truncated_tail_bytes;
+    }
+
+    for pair in recovered.windows(2) {
+        assert!(
+            pair[0].sequence < pair[1].sequence,
+            "{context}: sequence {} replayed after {}",
+            pair[1].sequence,
+            pair[0].sequence
+        );
+    }
+    for record in &recovered {
+        assert_eq!(
+            model.written.get(&record.sequence),
+            Some(record),
+            "{context}: replayed a record that was never written"
+        );
+    }
+    if let Some(durable) = model.durable_sequence {
+        let mut replayed = recovered.iter().map(|record| record.sequence).peekable();
+        for &sequence in model.written.range(..=durable).map(|(sequence, _)| sequence) {
+            while replayed.next_if(|&replayed| replayed < sequence).is_some() {}
+            assert_eq!(
+                replayed.peek(),
+                Some(&sequence),
+                "{context}: acknowledged sequence {sequence} (durable up to {durable}) is lost"
+            );
+        }
+    }
+    recovery
+}
+
+fn check_checksum_with_every_backend(raw: &[u8], expect_match: bool, context: &str) {
+    let stored = u32::from_le_bytes(raw[CRC_OFFSET..CRC_OFFSET + 4].try_into().unwrap());
+    let mut covered = raw[..CRC_OFFSET].to_vec();
+    covered.extend_from_slice(&raw[CRC_OFFSET + 4..]);
+    for (name, backend) in CRC32C_BACKENDS {
+        assert_eq!(
+            backend(&covered) == stored,
+            expect_match,
+            "{context}: {name} disagrees with the reader on a {} bytes record",
+            raw.len()
+        );
+    }
+}
+
+/// One operation of a single-writer WAL workload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Put(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+    Flush,
+    Sync,
+    /// Syncs the current segment and starts the next one
+    Roll,
+}
+
+/// A random mix of writes, flushes, syncs and segment rolls
+pub fn generate_steps(seed: u64, len: usize) -> Vec<Step> {
+    let mut rng = Rng::new(seed);
+    (0..len)
+        .map(|_| match rng.below(20) {
+            0..=9 => {
+                let key = format!("key{:03}", rng.below(50)).into_bytes();
+                let value_len = rng.below(48) as usize;
+                Step::Put(key, rng.bytes(value_len))
+            }
+            10..=12 => Step::Delete(format!("key{:03}", rng.below(50)).into_bytes()),
+            13..=15 => Step::Flush,
+            16..=18 => Step::Sync,
+            _ => Step::Roll,
+        })
+        .collect()
+}
+
+/// Runs `Step`s with a `WalWriter`, one segment at a time, keeping the model up to date
+pub struct WalWorkload {
+    dir: PathBuf,
+    segment: u64,
+    next_sequence: u64,
+    writer: Option<WalWriter>,
+    model: Model,
+}
+
+impl WalWorkload {
+    pub fn new(dir: &Path) -> Self {
+        WalWorkload {
+            dir: dir.to_path_buf(),
+            segment: 0,
+            next_sequence: 1,
+            writer: None,
+            model: Model::default(),
+        }
+    }
+
+    /// Applies a step, an error leaves the writer unusable as after a real I/O error
+    pub fn apply(&mut self, fs: &dyn FileSystem, step: &Step) -> io::Result<()> {
+        if self.writer.is_none() || *step == Step::Roll {
+            if let Some(mut writer) = self.writer.take() {
+                self.sync(&mut writer)?;
+            }
+            self.segment += 1;
+            self.writer = Some(WalWriter::create_in(fs, segment_path(&self.dir, self.segment))?);
+            if *step == Step::Roll {
+                return Ok(());
+            }
+        }
+        let mut writer = self.writer.take().unwrap();
+        let result = match step {
+            Step::Put(key, value) => {
+                let sequence = self.take_sequence();
+                self.model.write(WalRecord::put(sequence, key, value));
+                writer.put(sequence, key, value)
+            }
+            Step::Delete(key) => {
+                let sequence = self.take_sequence();
+                self.model.write(WalRecord::delete(sequence, key));
+                writer.delete(sequence, key)
+            }
+            Step::Flush => writer.flush(),
+            Step::Sync => self.sync(&mut writer),
+            Step::Roll => unreachable!(),
+        };
+        self.writer = Some(writer);
+        result
+    }
+
+    /// Applies the steps until the first error
+    pub fn run(&mut self, fs: &dyn FileSystem, steps: &[Step]) -> io::Result<()> {
+        steps.iter().try_for_each(|step| self.apply(fs, step))
+    }
+
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+
+    fn take_sequence(&mut self) -> u64 {
+        self.next_sequence += 1;
+        self.next_sequence - 1
+    }
+
+    fn sync(&mut self, writer: &mut WalWriter) -> io::Result<()> {
+        writer.sync()?;
+        if let Some(sequence) = writer.last_sequence() {
+            self.model.acknowledge(sequence);
+        }
+        Ok(())
+    }
+}
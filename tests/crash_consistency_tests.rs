@@ -0,0 +1,205 @@
+mod crash;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crash::{CrashFs, Model, PowerLoss, Recovery, Step, WalWorkload, check_wal, generate_steps};
+use stone_kvs::db::{Db, DbOptions};
+use stone_kvs::env::FileOp;
+use stone_kvs::wal::segment::segment_path;
+use stone_kvs::wal::{ConcurrentWal, ConcurrentWalOptions, WalRecord};
+
+const DIR: &str = "/db/wal";
+
+fn count_steps(steps: &[Step], step: &Step) -> usize {
+    steps.iter().filter(|s| *s == step).count()
+}
+
+#[test]
+fn wal_power_loss_after_every_step_keeps_acknowledged_records() {
+    let dir = Path::new(DIR);
+    let steps = generate_steps(7, 80);
+    let mut total = Recovery::default();
+
+    for crash_after in 0..=steps.len() {
+        for loss in PowerLoss::all() {
+            let crash_fs = CrashFs::new(dir);
+            let mut workload = WalWorkload::new(dir);
+            workload.run(crash_fs.fs(), &steps[..crash_after]).unwrap();
+
+            let image = crash_fs.crash(loss);
+            let context = format!("crash after step {crash_after} with {loss:?}");
+            total.add(check_wal(&image, dir, workload.model(), &context));
+        }
+    }
+
+    // every recovery path of the reader has been taken
+    assert!(total.records > 0);
+    assert!(total.corrupted_records > 0);
+    assert!(total.torn_tail_bytes > 0);
+    assert!(total.headerless_segments > 0);
+    assert_eq!(total.checksums_verified, total.records);
+}
+
+#[test]
+fn wal_power_loss_after_torn_append_keeps_acknowledged_records() {
+    let dir = Path::new(DIR);
+    let steps = generate_steps(11, 60);
+    let appends = steps.len() + count_steps(&steps, &Step::Roll) + 1;
+    let mut total = Recovery::default();
+
+    for append in 1..=appends as u64 {
+        for keep in [0, 5, 20, 30] {
+            for loss in [PowerLoss::DropUnsynced, PowerLoss::KeepUnsynced] {
+                let crash_fs = CrashFs::new(dir);
+                crash_fs.fs().tear_nth_append(append, keep);
+                let mut workload = WalWorkload::new(dir);
+                let _ = workload.run(crash_fs.fs(), &steps);
+
+                let image = crash_fs.crash(loss);
+                let context = format!("append {append} torn after {keep} bytes, {loss:?}");
+                total.add(check_wal(&image, dir, workload.model(), &context));
+            }
+        }
+    }
+
+    assert!(total.torn_tail_bytes > 0);
+    assert!(total.headerless_segments > 0);
+}
+
+#[test]
+fn wal_failed_sync_is_not_acknowledged() {
+    let dir = Path::new(DIR);
+    let steps = generate_steps(23, 60);
+    let syncs = count_steps(&steps, &Step::Sync) + count_steps(&steps, &Step::Roll);
+    assert!(syncs > 0);
+
+    for sync in 1..=syncs as u64 {
+        for loss in [PowerLoss::DropUnsynced, PowerLoss::KeepPrefix(9)] {
+            let crash_fs = CrashFs::new(dir);
+            crash_fs.fs().fail_nth(FileOp::Sync, sync);
+            let mut workload = WalWorkload::new(dir);
+
+            assert!(workload.run(crash_fs.fs(), &steps).is_err());
+
+            let image = crash_fs.crash(loss);
+            check_wal(&image, dir, workload.model(), &format!("sync {sync} failed, {loss:?}"));
+        }
+    }
+}
+
+#[test]
+fn wal_power_loss_without_any_sync_acknowledges_nothing() {
+    let dir = Path::new(DIR);
+    let steps: Vec<Step> = generate_steps(5, 40)
+        .into_iter()
+        .filter(|step| !matches!(step, Step::Sync | Step::Roll))
+        .collect();
+    let crash_fs = CrashFs::new(dir);
+    let mut workload = WalWorkload::new(dir);
+    workload.run(crash_fs.fs(), &steps).unwrap();
+    assert_eq!(workload.model().durable_sequence(), None);
+
+    let image = crash_fs.crash(PowerLoss::DropUnsynced);
+
+    let recovery = check_wal(&image, dir, workload.model(), "no sync");
+    assert_eq!(recovery.records, 0);
+    assert_eq!(recovery.headerless_segments, 1);
+}
+
+#[test]
+fn concurrent_wal_power_loss_keeps_every_durable_sequence() {
+    let dir = Path::new(DIR);
+    let mut total = Recovery::default();
+
+    for (round, loss) in PowerLoss::all().into_iter().enumerate() {
+        let crash_fs = CrashFs::new(dir);
+        let model = Mutex::new(Model::default());
+        let options = ConcurrentWalOptions {
+            buffer_size: 4096,
+            sync: true,
+        };
+        let wal = ConcurrentWal::create_in(crash_fs.fs(), segment_path(dir, 1), 1, options).unwrap();
+
+        let image = thread::scope(|scope| {
+            for writer in 0..4 {
+                let (wal, model) = (&wal, &model);
+                scope.spawn(move || {
+                    // runs until the writes fail on the dead segment
+                    for i in 0.. {
+                        let key = format!("w{writer}-{i}").into_bytes();
+                        let value = vec![writer as u8; i % 40];
+                        let Ok(sequence) = wal.put(&key, &value) else { break };
+                        model.lock().unwrap().write(WalRecord::put(sequence, &key, &value));
+                        if i % 3 == 0 {
+                            if wal.wait_durable(sequence).is_err() {
+                                break;
+                            }
+                            model.lock().unwrap().acknowledge(sequence);
+                        }
+                    }
+                });
+            }
+
+            while wal.durable_sequence().unwrap_or(0) < 200 + round as u64 * 37 {
+                thread::yield_now();
+            }
+            crash_fs.crash(loss)
+        });
+
+        let model = model.lock().unwrap();
+        assert!(model.durable_sequence().is_some());
+        total.add(check_wal(&image, dir, &model, &format!("concurrent WAL, {loss:?}")));
+        drop(wal);
+    }
+
+    assert!(total.records > 0);
+}
+
+#[test]
+fn db_power_loss_keeps_acknowledged_writes() {
+    let dir = Path::new("/db");
+    let options = DbOptions {
+        write_buffer_size: 96 * 1024,
+        sync: true,
+        ..DbOptions::default()
+    };
+
+    for (round, loss) in PowerLoss::all().into_iter().enumerate() {
+        for writes in [0, 1, 90, 250, 400 + round * 23] {
+            let crash_fs = CrashFs::new(dir);
+            let db = Db::open_in(Arc::new(crash_fs.fs().clone()), dir, options.clone()).unwrap();
+            // latest acknowledged value of each key, `None` once deleted
+            let mut acknowledged = BTreeMap::new();
+            for i in 0..writes {
+                let key = format!("key{:02}", i % 60).into_bytes();
+                if i % 7 == 6 {
+                    db.delete(&key).unwrap();
+                    acknowledged.insert(key, None);
+                } else {
+                    let value = format!("value{i}").repeat(100).into_bytes();
+                    db.put(&key, &value).unwrap();
+                    acknowledged.insert(key, Some(value));
+                }
+            }
+
+            // the flush thread may be in the middle of writing a table
+            let image = crash_fs.crash(loss);
+            drop(db);
+            let db = Db::open_in(Arc::new(image), dir, options.clone()).unwrap();
+
+            for (key, value) in &acknowledged {
+                assert_eq!(
+                    &db.get(key).unwrap(),
+                    value,
+                    "{} after {writes} writes and {loss:?}",
+                    String::from_utf8_lossy(key)
+                );
+            }
+            assert_eq!(db.last_sequence(), writes as u64);
+            db.flush().unwrap();
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use stone_kvs::wal::crc32c::{crc32c, crc32c_table, crc32c_slice8, crc32c_hw, crc32c_slice32, crc32c_slice16, crc32c_slice16_bt};
+use stone_kvs::wal::crc32c::{crc32c, crc32c_table, crc32c_slice8, crc32c_hw, crc32c_slice32, crc32c_slice16, crc32c_slice16_bt, crc32c_table_append, crc32c_hw_append};
 
 #[test]
 fn crc32c_single_byte_returns_known_value() {
@@ -201,3 +201,19 @@ fn crc32c_hw_hello_world_returns_known_value() {
         0xc99465aa
     );
 }
+
+// Tests for the append variants, continuing a checksum must give the checksum of the concatenation
+#[test]
+fn crc32c_table_append_matches_whole_input() {
+    let data = b"hello world";
+    assert_eq!(crc32c_table_append(crc32c_table(&data[..5]), &data[5..]), 0xc99465aa);
+    assert_eq!(crc32c_table_append(0, data), 0xc99465aa);
+}
+
+#[test]
+fn crc32c_hw_append_matches_whole_input() {
+    let data: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+    for split in [0, 1, 7, 8, 9, 500, 999, 1000] {
+        assert_eq!(crc32c_hw_append(crc32c_hw(&data[..split]), &data[split..]), crc32c_table(&data));
+    }
+}
@@ -0,0 +1,395 @@
+mod common;
+
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::Duration;
+
+use common::TempDir;
+use stone_kvs::db::{Db, DbOptions};
+use stone_kvs::env::{
+    FaultInjectionFileSystem, FileOp, FileSystem, MemFileSystem, RandomAccessFile, SequentialFile, WritableFile,
+};
+use stone_kvs::table::parse_table_file_name;
+use stone_kvs::wal::segment::{list_segments_in, parse_segment_file_name, segment_path};
+
+/// A flush every ~120 writes, the tables stay in level 0
+fn small_buffer() -> DbOptions {
+    DbOptions {
+        write_buffer_size: 128 * 1024,
+        disable_auto_compactions: true,
+        ..DbOptions::default()
+    }
+}
+
+fn mem_fs() -> Arc<dyn FileSystem> {
+    Arc::new(MemFileSystem::new())
+}
+
+fn fill(db: &Db, from: u32, to: u32) {
+    for i in from..to {
+        db.put(format!("key{i:05}").as_bytes(), &[i as u8; 1000]).unwrap();
+    }
+}
+
+#[test]
+fn db_put_get_delete() {
+    let db = Db::open_in(mem_fs(), "/db", DbOptions::default()).unwrap();
+
+    db.put(b"a", b"1").unwrap();
+    db.put(b"b", b"2").unwrap();
+    db.delete(b"a").unwrap();
+
+    assert_eq!(db.get(b"a").unwrap(), None);
+    assert_eq!(db.get(b"b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(db.get(b"c").unwrap(), None);
+    assert_eq!(db.last_sequence(), 3);
+}
+
+#[test]
+fn db_full_memtable_is_flushed_and_its_wal_segment_released() {
+    let fs = mem_fs();
+    let db = Db::open_in(Arc::clone(&fs), "/db", small_buffer()).unwrap();
+
+    fill(&db, 0, 400);
+    db.flush().unwrap();
+
+    assert!(db.table_numbers().len() >= 3);
+    assert_eq!(db.immutable_memtables(), 0);
+    // only the active segment is left
+    assert_eq!(list_segments_in(fs.as_ref(), "/db").unwrap(), db.wal_segment_numbers());
+    for i in 0..400u32 {
+        assert_eq!(db.get(format!("key{i:05}").as_bytes()).unwrap(), Some(vec![i as u8; 1000]));
+    }
+}
+
+#[test]
+fn db_newer_versions_shadow_flushed_ones() {
+    let db = Db::open_in(mem_fs(), "/db", small_buffer()).unwrap();
+    db.put(b"k", b"old").unwrap();
+    db.put(b"gone", b"x").unwrap();
+    db.flush().unwrap();
+
+    db.put(b"k", b"new").unwrap();
+    db.delete(b"gone").unwrap();
+    assert_eq!(db.get(b"k").unwrap(), Some(b"new".to_vec()));
+    assert_eq!(db.get(b"gone").unwrap(), None);
+
+    db.flush().unwrap();
+    assert_eq!(db.table_numbers().len(), 2);
+    assert_eq!(db.get(b"k").unwrap(), Some(b"new".to_vec()));
+    assert_eq!(db.get(b"gone").unwrap(), None);
+}
+
+#[test]
+fn db_reopen_replays_the_wal() {
+    let dir = TempDir::new("db-reopen");
+    let db = Db::open(dir.path(), DbOptions::default()).unwrap();
+    db.put(b"a", b"1").unwrap();
+    db.delete(b"a").unwrap();
+    db.put(b"b", b"2").unwrap();
+    db.close().unwrap();
+
+    let db = Db::open(dir.path(), DbOptions::default()).unwrap();
+
+    assert_eq!(db.get(b"a").unwrap(), None);
+    assert_eq!(db.get(b"b").unwrap(), Some(b"2".to_vec()));
+    assert_eq!(db.last_sequence(), 3);
+    // the next write continues the sequence
+    assert_eq!(db.put(b"c", b"3").unwrap(), 4);
+}
+
+#[test]
+fn db_reopen_replays_mapped_segments() {
+    let dir = TempDir::new("db-mapped");
+    let db = Db::open(dir.path(), small_buffer()).unwrap();
+    fill(&db, 0, 50);
+    db.delete(b"key00003").unwrap();
+    let segment = segment_path(dir.path(), *db.wal_segment_numbers().last().unwrap());
+    db.close().unwrap();
+    // a torn record at the end of the segment, and a later segment whose header never reached the disk
+    let mut file = std::fs::OpenOptions::new().append(true).open(&segment).unwrap();
+    std::io::Write::write_all(&mut file, &[7; 5]).unwrap();
+    std::fs::File::create(segment_path(dir.path(), 1000)).unwrap();
+
+    let db = Db::open(dir.path(), small_buffer()).unwrap();
+    assert_eq!(db.last_sequence(), 51);
+    assert_eq!(db.get(b"key00003").unwrap(), None);
+    assert_eq!(db.get(b"key00049").unwrap(), Some(vec![49; 1000]));
+    assert_eq!(db.put(b"a", b"1").unwrap(), 52);
+}
+
+#[test]
+fn db_reopen_reads_tables_and_replays_only_newer_records() {
+    let fs = mem_fs();
+    let db = Db::open_in(Arc::clone(&fs), "/db", small_buffer()).unwrap();
+    fill(&db, 0, 300);
+    db.flush().unwrap();
+    db.put(b"key00007", b"after flush").unwrap();
+    let last_sequence = db.last_sequence();
+    drop(db);
+
+    let db = Db::open_in(Arc::clone(&fs), "/db", small_buffer()).unwrap();
+
+    assert_eq!(db.last_sequence(), last_sequence);
+    assert_eq!(db.get(b"key00007").unwrap(), Some(b"after flush".to_vec()));
+    assert_eq!(db.get(b"key00299").unwrap(), Some(vec![299u32 as u8; 1000]));
+    db.flush().unwrap();
+    let segments = list_segments_in(fs.as_ref(), "/db").unwrap();
+    assert_eq!(segments, db.wal_segment_numbers());
+}
+
+#[test]
+fn db_interrupted_flush_leaves_no_table_behind() {
+    let mem = MemFileSystem::new();
+    let fs = Arc::new(FaultInjectionFileSystem::new(Arc::new(mem.clone())));
+    let db = Db::open_in(fs.clone(), "/db", small_buffer()).unwrap();
+    fill(&db, 0, 20);
+    fs.fail_nth_on_path(FileOp::Sync, ".sst", 1);
+
+    assert!(db.flush().is_err());
+    // writes fail once the background flush has failed
+    assert!(db.put(b"k", b"v").is_err());
+    drop(db);
+
+    let names = fs.list_dir(Path::new("/db")).unwrap();
+    assert!(!names.iter().any(|name| parse_table_file_name(name).is_some()));
+    assert!(names.iter().any(|name| parse_segment_file_name(name).is_some()));
+
+    fs.clear_faults();
+    let db = Db::open_in(fs, "/db", small_buffer()).unwrap();
+    assert_eq!(db.get(b"key00019").unwrap(), Some(vec![19u8; 1000]));
+}
+
+#[test]
+fn db_failed_memtable_switch_fails_the_next_writes() {
+    let mem = MemFileSystem::new();
+    let fs = Arc::new(FaultInjectionFileSystem::new(Arc::new(mem.clone())));
+    let db = Db::open_in(fs.clone(), "/db", small_buffer()).unwrap();
+    fs.fail_nth_on_path(FileOp::Create, ".log", 1);
+
+    // the write that fills the memtable is applied, the switch after it fails
+    let mut written = 0;
+    let err = loop {
+        match db.put(format!("key{written:05}").as_bytes(), &[1; 1000]) {
+            Ok(_) => written += 1,
+            Err(e) => break e,
+        }
+    };
+    assert!(err.to_string().contains("memtable switch failed"), "{err}");
+    assert_eq!(db.last_sequence(), written);
+    assert!(db.put(b"k", b"v").is_err());
+    drop(db);
+
+    fs.clear_faults();
+    let db = Db::open_in(fs, "/db", small_buffer()).unwrap();
+    assert_eq!(db.last_sequence(), written);
+    assert_eq!(db.get(format!("key{:05}", written - 1).as_bytes()).unwrap(), Some(vec![1; 1000]));
+}
+
+#[test]
+fn db_prefix_iter_reads_the_memtables_as_of_its_creation() {
+    let db = Db::open_in(mem_fs(), "/db", small_buffer()).unwrap();
+    // several versions of each key, spread over the active memtable, frozen ones and tables
+    for round in 0..3u8 {
+        for i in 0..300u32 {
+            db.put(format!("key{i:05}").as_bytes(), &[round; 1000]).unwrap();
+        }
+    }
+    for i in (0..300u32).step_by(3) {
+        db.delete(format!("key{i:05}").as_bytes()).unwrap();
+    }
+    let scan = db.prefix_iter(b"key");
+
+    for i in 0..300u32 {
+        db.put(format!("key{i:05}").as_bytes(), &[9; 1000]).unwrap();
+    }
+    db.put(b"key99999", b"after").unwrap();
+    db.flush().unwrap();
+    let scanned: Vec<(Vec<u8>, Vec<u8>)> = scan.collect::<std::io::Result<_>>().unwrap();
+    let expected: Vec<(Vec<u8>, Vec<u8>)> = (0..300u32)
+        .filter(|i| i % 3 != 0)
+        .map(|i| (format!("key{i:05}").into_bytes(), vec![2; 1000]))
+        .collect();
+    assert_eq!(scanned, expected);
+    assert_eq!(db.prefix_iter(b"key").count(), 301);
+}
+
+#[test]
+fn db_failed_wal_write_fails_the_next_writes() {
+    for sync in [false, true] {
+        let fs = Arc::new(FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new())));
+        let options = DbOptions {
+            sync,
+            ..small_buffer()
+        };
+        let db = Db::open_in(fs.clone(), "/db", options.clone()).unwrap();
+        db.put(b"a", b"1").unwrap();
+        match sync {
+            true => fs.fail_nth_on_path(FileOp::Sync, ".log", 1),
+            false => fs.fail_nth_on_path(FileOp::Append, ".log", 1),
+        }
+
+        assert!(db.put(b"b", b"2").is_err());
+        // the store fails the same way from then on, without appending behind the failed record
+        let err = db.put(b"c", b"3").unwrap_err();
+        assert!(err.to_string().contains("WAL write failed"), "{err}");
+        assert!(db.delete(b"a").is_err());
+        assert!(db.flush().is_err());
+        assert_eq!(db.last_sequence(), 1);
+        drop(db);
+
+        fs.clear_faults();
+        let db = Db::open_in(fs, "/db", options).unwrap();
+        assert_eq!(db.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.get(b"c").unwrap(), None);
+        db.put(b"d", b"4").unwrap();
+    }
+}
+
+/// Holds the syncs of the WAL segments while the test holds `gate`
+struct GatedSyncFs {
+    inner: MemFileSystem,
+    gate: Arc<Mutex<()>>,
+    syncing: Arc<AtomicUsize>,
+}
+
+struct GatedSyncFile {
+    inner: Box<dyn WritableFile>,
+    gate: Arc<Mutex<()>>,
+    syncing: Arc<AtomicUsize>,
+}
+
+impl WritableFile for GatedSyncFile {
+    fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        self.inner.append(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.syncing.fetch_add(1, Ordering::SeqCst);
+        let _gate = self.gate.lock().unwrap();
+        self.inner.sync()
+    }
+}
+
+impl GatedSyncFs {
+    fn gated(&self, path: &Path, inner: Box<dyn WritableFile>) -> Box<dyn WritableFile> {
+        match path.extension().is_some_and(|extension| extension == "log") {
+            true => Box::new(GatedSyncFile {
+                inner,
+                gate: Arc::clone(&self.gate),
+                syncing: Arc::clone(&self.syncing),
+            }),
+            false => inner,
+        }
+    }
+}
+
+impl FileSystem for GatedSyncFs {
+    fn create_new(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        Ok(self.gated(path, self.inner.create_new(path)?))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        Ok(self.gated(path, self.inner.create(path)?))
+    }
+
+    fn open_sequential(&self, path: &Path) -> io::Result<Box<dyn SequentialFile>> {
+        self.inner.open_sequential(path)
+    }
+
+    fn open_random_access(&self, path: &Path) -> io::Result<Arc<dyn RandomAccessFile>> {
+        self.inner.open_random_access(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner.exists(path)
+    }
+
+    fn file_size(&self, path: &Path) -> io::Result<u64> {
+        self.inner.file_size(path)
+    }
+
+    fn list_dir(&self, dir: &Path) -> io::Result<Vec<String>> {
+        self.inner.list_dir(dir)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_file(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner.rename(from, to)
+    }
+
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()> {
+        self.inner.create_dir_all(dir)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        self.inner.sync_dir(dir)
+    }
+}
+
+#[test]
+fn db_reads_do_not_wait_for_the_wal_sync() {
+    let gate = Arc::new(Mutex::new(()));
+    let syncing = Arc::new(AtomicUsize::new(0));
+    let fs = Arc::new(GatedSyncFs {
+        inner: MemFileSystem::new(),
+        gate: Arc::clone(&gate),
+        syncing: Arc::clone(&syncing),
+    });
+    let options = DbOptions {
+        sync: true,
+        ..small_buffer()
+    };
+    let db = Db::open_in(fs, "/db", options).unwrap();
+    db.put(b"a", b"1").unwrap();
+
+    let db = &db;
+    thread::scope(|scope| {
+        let held = gate.lock().unwrap();
+        let synced = syncing.load(Ordering::SeqCst);
+        let writer = scope.spawn(|| db.put(b"b", b"2").unwrap());
+        while syncing.load(Ordering::SeqCst) == synced {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // the write is stuck in its sync, the reads go on and do not see it yet
+        let (tx, rx) = mpsc::channel();
+        scope.spawn(move || {
+            let snapshot = db.snapshot();
+            let read = (db.get(b"a").unwrap(), db.get_at(b"b", &snapshot).unwrap(), db.last_sequence());
+            tx.send(read).unwrap();
+        });
+        let read = rx.recv_timeout(Duration::from_secs(10)).expect("reads waited for the WAL sync");
+        assert_eq!(read, (Some(b"1".to_vec()), None, 1));
+
+        drop(held);
+        assert_eq!(writer.join().unwrap(), 2);
+    });
+    assert_eq!(db.get(b"b").unwrap(), Some(b"2".to_vec()));
+}
+
+#[test]
+fn db_writes_stall_until_flush_catches_up() {
+    let options = DbOptions {
+        write_buffer_size: 100 * 1024,
+        max_immutable_memtables: 1,
+        ..DbOptions::default()
+    };
+    let db = Db::open_in(mem_fs(), "/db", options).unwrap();
+
+    fill(&db, 0, 1000);
+
+    assert!(db.immutable_memtables() <= 1);
+    db.flush().unwrap();
+    assert_eq!(db.get(b"key00500").unwrap(), Some(vec![500u32 as u8; 1000]));
+}
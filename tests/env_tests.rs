@@ -0,0 +1,254 @@
+mod common;
+
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+use common::TempDir;
+use stone_kvs::env::{
+    FaultInjectionFileSystem, FileOp, FileSystem, MemFileSystem, PosixFileSystem, read_file, write_file,
+};
+use stone_kvs::wal::{DecoderRegistry, WalReader, WalWriter};
+
+fn exercise_basic_operations(fs: &dyn FileSystem, dir: &Path) {
+    fs.create_dir_all(dir).unwrap();
+    let path = dir.join("a");
+
+    let mut file = fs.create_new(&path).unwrap();
+    file.append(b"hello ").unwrap();
+    file.append(b"world").unwrap();
+    file.sync().unwrap();
+    drop(file);
+
+    assert!(fs.exists(&path));
+    assert_eq!(fs.file_size(&path).unwrap(), 11);
+    assert_eq!(read_file(fs, &path).unwrap(), b"hello world");
+    assert_eq!(fs.create_new(&path).err().unwrap().kind(), ErrorKind::AlreadyExists);
+
+    let random = fs.open_random_access(&path).unwrap();
+    let mut buf = [0u8; 5];
+    random.read_exact_at(6, &mut buf).unwrap();
+    assert_eq!(&buf, b"world");
+    assert_eq!(random.read_exact_at(8, &mut buf).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+
+    fs.rename(&path, &dir.join("b")).unwrap();
+    assert!(!fs.exists(&path));
+    write_file(fs, &dir.join("c"), b"c").unwrap();
+    let mut names = fs.list_dir(dir).unwrap();
+    names.sort();
+    assert_eq!(names, vec!["b", "c"]);
+
+    fs.remove_file(&dir.join("b")).unwrap();
+    assert_eq!(fs.remove_file(&dir.join("b")).unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(fs.open_sequential(&path).err().unwrap().kind(), ErrorKind::NotFound);
+    fs.sync_dir(dir).unwrap();
+}
+
+#[test]
+fn posix_file_system_basic_operations() {
+    let dir = TempDir::new("env-posix");
+    exercise_basic_operations(&PosixFileSystem, &dir.join("db"));
+}
+
+#[test]
+fn mem_file_system_basic_operations() {
+    exercise_basic_operations(&MemFileSystem::new(), Path::new("/db"));
+}
+
+#[test]
+fn only_posix_file_system_maps_files() {
+    let dir = TempDir::new("env-map");
+    let path = dir.join("file");
+    write_file(&PosixFileSystem, &path, b"mapped").unwrap();
+    assert_eq!(PosixFileSystem.map(&path).unwrap().unwrap().as_ref(), b"mapped");
+    assert_eq!(PosixFileSystem.map(&dir.join("absent")).unwrap_err().kind(), ErrorKind::NotFound);
+
+    let fs = MemFileSystem::new();
+    fs.create_dir_all(Path::new("/db")).unwrap();
+    write_file(&fs, Path::new("/db/file"), b"read").unwrap();
+    assert!(fs.map(Path::new("/db/file")).unwrap().is_none());
+}
+
+#[test]
+fn fault_file_system_without_faults_behaves_like_its_base() {
+    let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+    exercise_basic_operations(&fs, Path::new("/db"));
+}
+
+#[test]
+fn mem_file_system_requires_parent_directory() {
+    let fs = MemFileSystem::new();
+
+    assert_eq!(fs.create(Path::new("/missing/file")).err().unwrap().kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn mem_sequential_file_sees_data_appended_after_eof() {
+    let fs = MemFileSystem::new();
+    fs.create_dir_all(Path::new("/db")).unwrap();
+    let mut writer = fs.create(Path::new("/db/log")).unwrap();
+    let mut reader = fs.open_sequential(Path::new("/db/log")).unwrap();
+    let mut out = Vec::new();
+
+    writer.append(b"ab").unwrap();
+    reader.read_to_end(&mut out).unwrap();
+    writer.append(b"cd").unwrap();
+    reader.read_to_end(&mut out).unwrap();
+
+    assert_eq!(out, b"abcd");
+}
+
+#[test]
+fn mem_snapshot_is_independent() {
+    let fs = MemFileSystem::new();
+    fs.create_dir_all(Path::new("/db")).unwrap();
+    write_file(&fs, Path::new("/db/a"), b"before").unwrap();
+
+    let snapshot = fs.snapshot();
+    write_file(&fs, Path::new("/db/a"), b"after").unwrap();
+
+    assert_eq!(read_file(&snapshot, Path::new("/db/a")).unwrap(), b"before");
+    assert_eq!(snapshot.files(), vec![Path::new("/db/a").to_path_buf()]);
+}
+
+#[test]
+fn fault_nth_call_fails_once() {
+    let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+    fs.create_dir_all(Path::new("/db")).unwrap();
+    let mut file = fs.create(Path::new("/db/a")).unwrap();
+    fs.fail_nth(FileOp::Sync, 2);
+
+    file.append(b"x").unwrap();
+    assert!(file.sync().is_ok());
+    assert!(file.sync().is_err());
+    assert!(file.sync().is_ok());
+    assert_eq!(fs.op_count(FileOp::Sync), 3);
+}
+
+#[test]
+fn fault_on_path_only_hits_matching_files() {
+    let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+    fs.create_dir_all(Path::new("/db")).unwrap();
+    fs.fail_nth_on_path(FileOp::Create, "MANIFEST", 1);
+
+    assert!(fs.create(Path::new("/db/000001.log")).is_ok());
+    assert!(fs.create(Path::new("/db/MANIFEST-000001")).is_err());
+}
+
+#[test]
+fn fault_always_fails_until_cleared() {
+    let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+    fs.create_dir_all(Path::new("/db")).unwrap();
+    write_file(&fs, Path::new("/db/a"), b"data").unwrap();
+    fs.fail_always(FileOp::Read);
+
+    assert!(read_file(&fs, Path::new("/db/a")).is_err());
+    assert!(read_file(&fs, Path::new("/db/a")).is_err());
+    fs.clear_faults();
+    assert_eq!(read_file(&fs, Path::new("/db/a")).unwrap(), b"data");
+}
+
+#[test]
+fn drop_unsynced_data_keeps_only_synced_bytes() {
+    let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+    fs.create_dir_all(Path::new("/db")).unwrap();
+    let path = Path::new("/db/a");
+    let mut file = fs.create(path).unwrap();
+    file.append(b"durable").unwrap();
+    file.sync().unwrap();
+    file.append(b" lost").unwrap();
+    assert_eq!(fs.unsynced_bytes(path), 5);
+    let reader = fs.open_random_access(path).unwrap();
+
+    fs.drop_unsynced_data().unwrap();
+
+    assert_eq!(read_file(&fs, path).unwrap(), b"durable");
+    // the handles belonged to the process that lost power
+    assert!(file.append(b"more").is_err());
+    assert!(reader.read_at(0, &mut [0; 12]).is_err());
+    assert!(reader.size().is_err());
+}
+
+#[test]
+fn drop_unsynced_data_with_keeps_a_partial_prefix() {
+    let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+    fs.create_dir_all(Path::new("/db")).unwrap();
+    let path = Path::new("/db/a");
+    let mut file = fs.create(path).unwrap();
+    file.append(b"0123").unwrap();
+    file.sync().unwrap();
+    file.append(b"456789").unwrap();
+
+    fs.drop_unsynced_data_with(|_, synced, _| synced + 2).unwrap();
+
+    assert_eq!(read_file(&fs, path).unwrap(), b"012345");
+}
+
+#[test]
+fn renamed_file_keeps_its_sync_state() {
+    let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+    fs.create_dir_all(Path::new("/db")).unwrap();
+    let mut file = fs.create(Path::new("/db/tmp")).unwrap();
+    file.append(b"synced").unwrap();
+    file.sync().unwrap();
+    fs.rename(Path::new("/db/tmp"), Path::new("/db/CURRENT")).unwrap();
+
+    fs.drop_unsynced_data().unwrap();
+
+    assert_eq!(read_file(&fs, Path::new("/db/CURRENT")).unwrap(), b"synced");
+}
+
+#[test]
+fn wal_recovers_exactly_the_synced_records_after_power_loss() {
+    let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+    fs.create_dir_all(Path::new("/db")).unwrap();
+    let path = Path::new("/db/000001.log");
+    let mut writer = WalWriter::create_in(&fs, path).unwrap();
+    for seq in 1..=10 {
+        writer.put(seq, b"key", b"value").unwrap();
+    }
+    writer.sync().unwrap();
+    for seq in 11..=15 {
+        writer.put(seq, b"key", b"value").unwrap();
+    }
+    writer.flush().unwrap();
+
+    fs.drop_unsynced_data().unwrap();
+
+    let mut reader = WalReader::open_in(&fs, path, &DecoderRegistry::default()).unwrap();
+    let sequences: Vec<u64> = reader.by_ref().map(|r| r.unwrap().sequence).collect();
+    assert_eq!(sequences, (1..=10).collect::<Vec<_>>());
+    assert_eq!(reader.stats().truncated_tail_bytes, 0);
+}
+
+#[test]
+fn wal_ignores_record_torn_mid_write() {
+    let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+    fs.create_dir_all(Path::new("/db")).unwrap();
+    let path = Path::new("/db/000001.log");
+    let mut writer = WalWriter::create_in(&fs, path).unwrap();
+    writer.put(1, b"a", b"1").unwrap();
+    fs.tear_nth_append(1, 10);
+
+    assert!(writer.put(2, b"b", b"2").is_err());
+    // the writer does not append behind a torn record
+    assert!(writer.put(3, b"c", b"3").is_err());
+    assert!(writer.sync().is_err());
+
+    let mut reader = WalReader::open_in(&fs, path, &DecoderRegistry::default()).unwrap();
+    let sequences: Vec<u64> = reader.by_ref().map(|r| r.unwrap().sequence).collect();
+    assert_eq!(sequences, vec![1]);
+    assert_eq!(reader.stats().truncated_tail_bytes, 10);
+}
+
+#[test]
+fn wal_sync_failure_is_reported() {
+    let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+    fs.create_dir_all(Path::new("/db")).unwrap();
+    let mut writer = WalWriter::create_in(&fs, Path::new("/db/000001.log")).unwrap();
+    writer.put(1, b"a", b"1").unwrap();
+    fs.fail_nth(FileOp::Sync, 1);
+
+    assert!(writer.sync().is_err());
+    assert!(writer.put(2, b"b", b"2").is_err());
+}
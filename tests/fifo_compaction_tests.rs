@@ -0,0 +1,120 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use stone_kvs::db::{CompactionStyle, Db, DbOptions, FifoCompactionOptions};
+use stone_kvs::env::{FileSystem, MemFileSystem};
+use stone_kvs::table::{PlainTableFactory, parse_table_file_name};
+
+fn fifo(options: FifoCompactionOptions) -> DbOptions {
+    DbOptions {
+        compaction_style: CompactionStyle::Fifo,
+        compaction_options_fifo: options,
+        ..DbOptions::default()
+    }
+}
+
+fn key(n: u64) -> Vec<u8> {
+    format!("key{n:06}").into_bytes()
+}
+
+/// Writes `keys` to a table of their own
+fn fill(db: &Db, keys: impl Iterator<Item = u64>) {
+    for n in keys {
+        db.put(&key(n), &[n as u8; 100]).unwrap();
+    }
+    db.flush().unwrap();
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[test]
+fn tables_record_their_creation_time() {
+    let before = now();
+    let fs: Arc<dyn FileSystem> = Arc::new(MemFileSystem::new());
+    let db = Db::open_in(Arc::clone(&fs), "/db", DbOptions::default()).unwrap();
+    fill(&db, 0..10);
+    let table = db.table_cache().get(db.table_numbers()[0]).unwrap();
+    let created = table.creation_time().unwrap();
+    assert!(before <= created && created <= now());
+    // the MANIFEST holds it too, FIFO reads it from there
+    let logged = db.current_version().files(0)[0].creation_time;
+    assert!(before <= logged && logged <= now());
+    drop(db);
+    let db = Db::open_in(fs, "/db", DbOptions::default()).unwrap();
+    assert_eq!(db.current_version().files(0)[0].creation_time, logged);
+
+    // the plain format has no properties
+    let options = DbOptions {
+        table_factory: Arc::new(PlainTableFactory),
+        ..DbOptions::default()
+    };
+    let db = Db::open_in(Arc::new(MemFileSystem::new()), "/db", options).unwrap();
+    fill(&db, 0..10);
+    assert_eq!(db.table_cache().get(db.table_numbers()[0]).unwrap().creation_time(), None);
+    assert!(db.current_version().files(0)[0].creation_time > 0);
+}
+
+#[test]
+fn fifo_deletes_the_oldest_tables_past_the_size_limit() {
+    let fs: Arc<dyn FileSystem> = Arc::new(MemFileSystem::new());
+    let options = fifo(FifoCompactionOptions {
+        max_table_files_size: 40 * 1024,
+        ..FifoCompactionOptions::default()
+    });
+    let db = Db::open_in(Arc::clone(&fs), "/db", options.clone()).unwrap();
+    for round in 0..10 {
+        fill(&db, round * 100..round * 100 + 100);
+    }
+    db.wait_for_compactions().unwrap();
+
+    // the newest tables under the limit are left, nothing was rewritten
+    let version = db.current_version();
+    let size: u64 = version.tables().map(|file| file.file_size).sum();
+    assert!(size <= 40 * 1024, "{version:?}");
+    assert!(size + version.files(0).last().unwrap().file_size > 40 * 1024);
+    let stats = db.compaction_stats();
+    assert_eq!((stats.bytes_read, stats.bytes_written), (0, 0));
+    assert_eq!(stats.tables_dropped, 10 - version.num_files() as u64);
+    assert_eq!(db.get(&key(950)).unwrap(), Some(vec![950u64 as u8; 100]));
+    assert_eq!(db.get(&key(50)).unwrap(), None);
+
+    let names = fs.list_dir(Path::new("/db")).unwrap();
+    let mut files: Vec<u64> = names.iter().filter_map(|name| parse_table_file_name(name)).collect();
+    files.sort_unstable();
+    let mut live = version.table_numbers();
+    live.sort_unstable();
+    assert_eq!(files, live);
+}
+
+#[test]
+fn fifo_ttl_expires_old_tables_without_new_writes() {
+    let db = Db::open_in(
+        Arc::new(MemFileSystem::new()),
+        "/db",
+        fifo(FifoCompactionOptions {
+            ttl: 1,
+            ..FifoCompactionOptions::default()
+        }),
+    )
+    .unwrap();
+    fill(&db, 0..100);
+    fill(&db, 100..200);
+    db.wait_for_compactions().unwrap();
+    assert_eq!(db.table_numbers().len(), 2);
+
+    // older than one second once the clock has moved on twice
+    thread::sleep(Duration::from_millis(2100));
+    db.wait_for_compactions().unwrap();
+    assert!(db.table_numbers().is_empty());
+    assert_eq!(db.get(&key(10)).unwrap(), None);
+    assert_eq!(db.compaction_stats().tables_dropped, 2);
+
+    fill(&db, 200..300);
+    db.wait_for_compactions().unwrap();
+    assert_eq!(db.table_numbers().len(), 1);
+    assert_eq!(db.get(&key(250)).unwrap(), Some(vec![250; 100]));
+}
@@ -0,0 +1,233 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use stone_kvs::cache::BlockCache;
+use stone_kvs::db::{Db, DbOptions};
+use stone_kvs::env::{FileSystem, MemFileSystem};
+use stone_kvs::table::filter::{Filter, FilterPolicy};
+use stone_kvs::table::{
+    BlockBasedTableFactory, BloomFilterPolicy, RibbonFilterPolicy, TableBuilder, TableOptions, TableReader,
+};
+use stone_kvs::wal::RecordType;
+
+const PATH: &str = "/table/000001.sst";
+
+fn key(n: u64) -> Vec<u8> {
+    format!("user:{n:08}").into_bytes()
+}
+
+fn bloom(bits_per_key: f64, keys: impl Iterator<Item = Vec<u8>>) -> Filter {
+    build_filter(&BloomFilterPolicy::new(bits_per_key), keys)
+}
+
+fn build_filter(policy: &dyn FilterPolicy, keys: impl Iterator<Item = Vec<u8>>) -> Filter {
+    let mut builder = policy.new_builder();
+    for key in keys {
+        builder.add_key(&key);
+    }
+    Filter::new(builder.finish())
+}
+
+fn false_positive_rate(filter: &Filter) -> f64 {
+    let absent = (1_000_000..1_020_000).filter(|&n| filter.may_match(&key(n))).count();
+    absent as f64 / 20_000.0
+}
+
+fn build_table(fs: &MemFileSystem, options: &TableOptions, keys: impl Iterator<Item = u64>) {
+    fs.create_dir_all(Path::new("/table")).unwrap();
+    let mut builder = TableBuilder::new(fs.create_new(Path::new(PATH)).unwrap(), options.clone());
+    for (sequence, n) in keys.enumerate() {
+        builder
+            .add(&key(n), sequence as u64 + 1, RecordType::Put, &[7u8; 100])
+            .unwrap();
+    }
+    builder.finish().unwrap();
+}
+
+#[test]
+fn bloom_filter_has_no_false_negatives() {
+    let filter = bloom(10.0, (0..10_000).map(key));
+
+    assert!((0..10_000).all(|n| filter.may_match(&key(n))));
+    // 10 bits for each of the 10000 keys, in 64-byte lines, and a 6-byte trailer
+    assert_eq!(filter.size(), 100_000usize.div_ceil(512) * 64 + 6);
+}
+
+#[test]
+fn bloom_false_positive_rate_follows_bits_per_key() {
+    let rates: Vec<f64> = [5.0, 10.0, 15.0]
+        .iter()
+        .map(|&bits| false_positive_rate(&bloom(bits, (0..10_000).map(key))))
+        .collect();
+
+    assert!(rates[1] < 0.02, "{rates:?}");
+    assert!(rates[0] > rates[1] && rates[1] > rates[2], "{rates:?}");
+}
+
+#[test]
+fn empty_and_unknown_filters() {
+    let empty = bloom(10.0, std::iter::empty());
+    assert!(!empty.may_match(b"anything"));
+
+    // a kind this version does not know lets every key through
+    let unknown = Filter::new(vec![0, 0, 0, 0x7f]);
+    assert!(unknown.may_match(b"anything"));
+    assert!(Filter::new(Vec::new()).may_match(b"anything"));
+}
+
+#[test]
+fn ribbon_filter_matches_bloom_false_positives_in_less_memory() {
+    let policy = RibbonFilterPolicy::new(10.0);
+    let ribbon = build_filter(&policy, (0..20_000).map(key));
+    let bloom = bloom(10.0, (0..20_000).map(key));
+
+    assert_eq!(policy.result_bits(), 7);
+    assert!((0..20_000).all(|n| ribbon.may_match(&key(n))));
+    let (ribbon_rate, bloom_rate) = (false_positive_rate(&ribbon), false_positive_rate(&bloom));
+    assert!(ribbon_rate <= bloom_rate * 1.1, "ribbon {ribbon_rate} bloom {bloom_rate}");
+    assert!(ribbon_rate > 0.003, "{ribbon_rate}");
+    assert!(ribbon.size() * 10 < bloom.size() * 8, "ribbon {} bloom {}", ribbon.size(), bloom.size());
+}
+
+#[test]
+fn ribbon_filter_of_no_keys_falls_back_to_bloom() {
+    let filter = build_filter(&RibbonFilterPolicy::new(10.0), std::iter::empty());
+
+    assert!(!filter.may_match(b"anything"));
+    let one = build_filter(&RibbonFilterPolicy::new(10.0), std::iter::once(key(1)));
+    assert!(one.may_match(&key(1)));
+}
+
+#[test]
+fn table_filter_skips_data_blocks_for_absent_keys() {
+    let fs = MemFileSystem::new();
+    let cache = Arc::new(BlockCache::new(1 << 20));
+    let options = TableOptions {
+        block_cache: Some(Arc::clone(&cache)),
+        filter_policy: Some(Arc::new(BloomFilterPolicy::new(10.0))),
+        ..TableOptions::default()
+    };
+    // even keys only, odd keys are absent but inside the key range
+    build_table(&fs, &options, (0..5000).map(|n| n * 2));
+    let table = TableReader::open(&fs, Path::new(PATH), &options).unwrap();
+    assert!(table.properties().filter_size > 0);
+    assert!(table.filter_memory_usage() > 0);
+
+    for n in 0..5000 {
+        assert!(table.get(&key(n * 2 + 1), u64::MAX).unwrap().is_none());
+    }
+    let counters = options.filter_stats.snapshot();
+    assert_eq!(counters.checked, 5000);
+    assert_eq!(counters.useful + counters.false_positives, 5000);
+    assert!(counters.false_positive_rate() < 0.03, "{counters:?}");
+    // only the false positives read a data block
+    assert_eq!(
+        cache.stats().misses + cache.stats().hits,
+        counters.false_positives
+    );
+
+    for n in 0..5000 {
+        assert!(table.get(&key(n * 2), u64::MAX).unwrap().is_some());
+    }
+    let after = options.filter_stats.snapshot();
+    assert_eq!(after.checked, 10_000);
+    assert_eq!(
+        (after.useful, after.false_positives),
+        (counters.useful, counters.false_positives)
+    );
+}
+
+#[test]
+fn table_filter_does_not_count_keys_newer_than_the_read_as_false_positives() {
+    let fs = MemFileSystem::new();
+    let options = TableOptions {
+        filter_policy: Some(Arc::new(BloomFilterPolicy::new(10.0))),
+        ..TableOptions::default()
+    };
+    // key 2n is written at sequence n + 1
+    build_table(&fs, &options, (0..5000).map(|n| n * 2));
+    let table = TableReader::open(&fs, Path::new(PATH), &options).unwrap();
+
+    for n in 0..5000 {
+        assert!(table.get(&key(n * 2), n).unwrap().is_none());
+    }
+    let counters = options.filter_stats.snapshot();
+    assert_eq!(counters.checked, 5000);
+    assert_eq!((counters.useful, counters.false_positives), (0, 0));
+
+    for n in 0..5000 {
+        assert!(table.get(&key(n * 2 + 1), n).unwrap().is_none());
+    }
+    let after = options.filter_stats.snapshot();
+    assert_eq!(after.useful + after.false_positives, 5000);
+    assert!(after.false_positives > 0, "{after:?}");
+}
+
+#[test]
+fn table_ribbon_filter_is_smaller_than_bloom() {
+    let mut sizes = Vec::new();
+    for policy in [
+        Arc::new(BloomFilterPolicy::new(10.0)) as Arc<dyn FilterPolicy>,
+        Arc::new(RibbonFilterPolicy::new(10.0)),
+    ] {
+        let fs = MemFileSystem::new();
+        let options = TableOptions {
+            filter_policy: Some(policy),
+            ..TableOptions::default()
+        };
+        build_table(&fs, &options, (0..5000).map(|n| n * 2));
+        let table = TableReader::open(&fs, Path::new(PATH), &options).unwrap();
+        for n in 0..5000 {
+            assert!(table.get(&key(n * 2), u64::MAX).unwrap().is_some());
+            assert!(table.get(&key(n * 2 + 1), u64::MAX).unwrap().is_none());
+        }
+        let counters = options.filter_stats.snapshot();
+        assert!(counters.false_positive_rate() < 0.03, "{counters:?}");
+        sizes.push(table.properties().filter_size);
+    }
+    assert!(sizes[1] * 10 < sizes[0] * 8, "{sizes:?}");
+}
+
+#[test]
+fn table_without_filter_policy_has_no_filter() {
+    let fs = MemFileSystem::new();
+    let options = TableOptions::default();
+    build_table(&fs, &options, 0..100);
+    let table = TableReader::open(&fs, Path::new(PATH), &options).unwrap();
+
+    assert_eq!(table.properties().filter_size, 0);
+    assert!(table.get(&key(1000), u64::MAX).unwrap().is_none());
+    assert_eq!(options.filter_stats.snapshot().checked, 0);
+}
+
+#[test]
+fn db_lookups_check_the_filters_of_every_table() {
+    let table_options = TableOptions {
+        filter_policy: Some(Arc::new(BloomFilterPolicy::new(10.0))),
+        ..TableOptions::default()
+    };
+    let stats = Arc::clone(&table_options.filter_stats);
+    let options = DbOptions {
+        table_factory: Arc::new(BlockBasedTableFactory {
+            options: table_options,
+        }),
+        ..DbOptions::default()
+    };
+    let db = Db::open_in(Arc::new(MemFileSystem::new()), "/db", options).unwrap();
+    // interleaved keys: the key ranges of the tables overlap, only the filters tell them apart
+    for table in 0..3u64 {
+        for n in 0..200 {
+            db.put(&key(n * 3 + table), b"v").unwrap();
+        }
+        db.flush().unwrap();
+    }
+
+    // in the newest table only: the two older tables are not read
+    assert_eq!(db.get(&key(5)).unwrap(), Some(b"v".to_vec()));
+    assert_eq!(stats.snapshot().checked, 1);
+    // in the oldest table: the two newer tables are checked first
+    assert_eq!(db.get(&key(3)).unwrap(), Some(b"v".to_vec()));
+    let counters = stats.snapshot();
+    assert_eq!(counters.checked, 4);
+    assert_eq!(counters.useful + counters.false_positives, 2);
+}
@@ -0,0 +1,261 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use stone_kvs::db::{
+    CURRENT_FILE, Db, DbOptions, FileMetaData, VersionEdit, VersionSet, manifest_file_name, parse_manifest_file_name,
+};
+use stone_kvs::env::{FileSystem, MemFileSystem, read_file, write_file};
+use stone_kvs::table::table_file_name;
+use stone_kvs::wal::{DecoderRegistry, ScanEntry, WalReader};
+
+fn fill(db: &Db, from: u32, to: u32) {
+    for i in from..to {
+        db.put(format!("key{i:05}").as_bytes(), &[i as u8; 100]).unwrap();
+    }
+}
+
+fn file(number: u64, smallest: &str, largest: &str) -> FileMetaData {
+    FileMetaData {
+        number,
+        file_size: 1000 + number,
+        smallest: smallest.as_bytes().to_vec(),
+        largest: largest.as_bytes().to_vec(),
+        smallest_sequence: number * 10,
+        largest_sequence: number * 10 + 9,
+        creation_time: 0,
+    }
+}
+
+fn manifests(fs: &dyn FileSystem) -> Vec<u64> {
+    let names = fs.list_dir(Path::new("/db")).unwrap();
+    names.iter().filter_map(|name| parse_manifest_file_name(name)).collect()
+}
+
+fn current_manifest(fs: &dyn FileSystem) -> String {
+    let current = read_file(fs, &Path::new("/db").join(CURRENT_FILE)).unwrap();
+    String::from_utf8(current).unwrap().trim_end().to_string()
+}
+
+/// Offsets of the records of the MANIFEST in use
+fn edit_offsets(fs: &dyn FileSystem) -> Vec<u64> {
+    let path = Path::new("/db").join(current_manifest(fs));
+    let mut reader = WalReader::open_in(fs, &path, &DecoderRegistry::default()).unwrap();
+    let mut offsets = Vec::new();
+    while let Some(entry) = reader.next_entry().unwrap() {
+        if let ScanEntry::Record { offset, .. } = entry {
+            offsets.push(offset);
+        }
+    }
+    offsets
+}
+
+#[test]
+fn version_edit_round_trips() {
+    let edit = VersionEdit {
+        log_number: Some(12),
+        next_file_number: Some(40),
+        last_sequence: Some(1 << 40),
+        deleted_files: vec![(0, 3), (2, 17)],
+        new_files: vec![
+            (0, file(20, "a", "m")),
+            (
+                1,
+                FileMetaData {
+                    creation_time: 1_700_000_000,
+                    ..file(21, "", "\u{ff}")
+                },
+            ),
+        ],
+    };
+    assert_eq!(VersionEdit::decode(&edit.encode()).unwrap(), edit);
+    assert_eq!(VersionEdit::decode(&[]).unwrap(), VersionEdit::default());
+
+    let encoded = edit.encode();
+    for len in 1..encoded.len() {
+        if let Err(e) = VersionEdit::decode(&encoded[..len]) {
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+    // a tag from a newer release
+    let err = VersionEdit::decode(&[99, 1]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn version_set_replays_its_edits() {
+    let fs: Arc<dyn FileSystem> = Arc::new(MemFileSystem::new());
+    fs.create_dir_all(Path::new("/db")).unwrap();
+    let mut versions = VersionSet::recover(Arc::clone(&fs), "/db", 1, u64::MAX).unwrap();
+    versions
+        .log_and_apply(VersionEdit {
+            new_files: vec![(0, file(5, "a", "z")), (0, file(6, "c", "d"))],
+            ..VersionEdit::default()
+        })
+        .unwrap();
+    let number = versions.new_file_number();
+    versions
+        .log_and_apply(VersionEdit {
+            log_number: Some(9),
+            last_sequence: Some(99),
+            deleted_files: vec![(0, 5)],
+            new_files: vec![(1, file(8, "n", "z")), (1, file(7, "a", "m"))],
+            ..VersionEdit::default()
+        })
+        .unwrap();
+    let version = versions.current();
+    drop(versions);
+
+    let mut versions = VersionSet::recover(fs, "/db", 1, u64::MAX).unwrap();
+    let recovered = versions.current();
+    assert_eq!(recovered.files(0).iter().map(|f| f.number).collect::<Vec<_>>(), [6]);
+    // deeper levels are sorted by key
+    assert_eq!(recovered.files(1).iter().map(|f| f.number).collect::<Vec<_>>(), [7, 8]);
+    assert_eq!(*recovered.files(1)[0], file(7, "a", "m"));
+    assert_eq!(recovered.table_numbers(), version.table_numbers());
+    assert_eq!(versions.log_number(), 9);
+    assert_eq!(versions.last_sequence(), 99);
+    assert_eq!(recovered.num_files(), 3);
+    // numbers handed out before the last edit are never reused
+    assert!(versions.new_file_number() > number);
+}
+
+#[test]
+fn db_reopen_replays_the_manifest() {
+    let fs: Arc<dyn FileSystem> = Arc::new(MemFileSystem::new());
+    let db = Db::open_in(Arc::clone(&fs), "/db", DbOptions::default()).unwrap();
+    for round in 0..3 {
+        fill(&db, round * 100, round * 100 + 150);
+        db.flush().unwrap();
+    }
+    let version = db.current_version();
+    assert_eq!(version.files(0).len(), 3);
+    let newest = &version.files(0)[0];
+    assert_eq!((newest.smallest.as_slice(), newest.largest.as_slice()), (&b"key00200"[..], &b"key00349"[..]));
+    assert_eq!(newest.largest_sequence, 450);
+    drop(db);
+
+    let db = Db::open_in(Arc::clone(&fs), "/db", DbOptions::default()).unwrap();
+    let reopened = db.current_version();
+    assert_eq!(reopened.files(0), version.files(0));
+    assert_eq!(db.last_sequence(), 450);
+    assert_eq!(db.get(b"key00120").unwrap(), Some(vec![120; 100]));
+    // a new MANIFEST for every open, the old one removed
+    assert_eq!(manifests(fs.as_ref()), [parse_manifest_file_name(&current_manifest(fs.as_ref())).unwrap()]);
+}
+
+#[test]
+fn manifest_torn_tail_is_ignored() {
+    let fs: Arc<dyn FileSystem> = Arc::new(MemFileSystem::new());
+    let db = Db::open_in(Arc::clone(&fs), "/db", DbOptions::default()).unwrap();
+    fill(&db, 0, 100);
+    db.flush().unwrap();
+    drop(db);
+
+    // an edit cut by a crash: never acknowledged, its table is not part of the store
+    let path = Path::new("/db").join(current_manifest(fs.as_ref()));
+    let mut contents = read_file(fs.as_ref(), &path).unwrap();
+    let edit = contents[*edit_offsets(fs.as_ref()).last().unwrap() as usize..].to_vec();
+    contents.extend_from_slice(&edit[..edit.len() / 2]);
+    write_file(fs.as_ref(), &path, &contents).unwrap();
+
+    let db = Db::open_in(Arc::clone(&fs), "/db", DbOptions::default()).unwrap();
+    assert_eq!(db.table_numbers().len(), 1);
+    assert_eq!(db.get(b"key00050").unwrap(), Some(vec![50; 100]));
+}
+
+#[test]
+fn manifest_corrupted_edit_is_invalid_data() {
+    let fs: Arc<dyn FileSystem> = Arc::new(MemFileSystem::new());
+    let db = Db::open_in(Arc::clone(&fs), "/db", DbOptions::default()).unwrap();
+    for round in 0..2 {
+        fill(&db, round * 100, round * 100 + 100);
+        db.flush().unwrap();
+    }
+    drop(db);
+
+    // the snapshot and one edit per flush, the first flush edit is corrupted
+    let offsets = edit_offsets(fs.as_ref());
+    assert_eq!(offsets.len(), 3);
+    let path = Path::new("/db").join(current_manifest(fs.as_ref()));
+    let mut contents = read_file(fs.as_ref(), &path).unwrap();
+    contents[offsets[2] as usize - 1] ^= 0x40;
+    write_file(fs.as_ref(), &path, &contents).unwrap();
+
+    let err = Db::open_in(fs, "/db", DbOptions::default()).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn db_open_removes_files_the_manifest_does_not_know() {
+    let fs: Arc<dyn FileSystem> = Arc::new(MemFileSystem::new());
+    let db = Db::open_in(Arc::clone(&fs), "/db", DbOptions::default()).unwrap();
+    fill(&db, 0, 100);
+    db.flush().unwrap();
+    let tables = db.table_numbers();
+    drop(db);
+
+    // a flush whose edit never made it to the MANIFEST, and a MANIFEST never named by CURRENT
+    let orphan = Path::new("/db").join(table_file_name(90));
+    write_file(fs.as_ref(), &orphan, b"not logged").unwrap();
+    write_file(fs.as_ref(), &Path::new("/db").join(manifest_file_name(91)), b"").unwrap();
+
+    let db = Db::open_in(Arc::clone(&fs), "/db", DbOptions::default()).unwrap();
+    assert!(!fs.exists(&orphan));
+    assert_eq!(manifests(fs.as_ref()).len(), 1);
+    assert_eq!(db.table_numbers(), tables);
+    // the file numbers go on past every file found
+    fill(&db, 100, 200);
+    db.flush().unwrap();
+    assert!(db.table_numbers()[0] > 91);
+}
+
+#[test]
+fn db_without_manifest_adopts_its_tables() {
+    let fs: Arc<dyn FileSystem> = Arc::new(MemFileSystem::new());
+    let db = Db::open_in(Arc::clone(&fs), "/db", DbOptions::default()).unwrap();
+    fill(&db, 0, 100);
+    db.flush().unwrap();
+    fill(&db, 50, 120);
+    db.flush().unwrap();
+    let version = db.current_version();
+    drop(db);
+
+    // a store written before the MANIFEST existed
+    fs.remove_file(&Path::new("/db").join(CURRENT_FILE)).unwrap();
+    for number in manifests(fs.as_ref()) {
+        fs.remove_file(&Path::new("/db").join(manifest_file_name(number))).unwrap();
+    }
+
+    let db = Db::open_in(Arc::clone(&fs), "/db", DbOptions::default()).unwrap();
+    let adopted = db.current_version();
+    assert_eq!(adopted.files(0), version.files(0));
+    assert_eq!(db.get(b"key00060").unwrap(), Some(vec![60; 100]));
+    assert_eq!(db.last_sequence(), 170);
+    assert!(fs.exists(&Path::new("/db").join(CURRENT_FILE)));
+}
+
+#[test]
+fn manifest_is_replaced_past_its_size_limit() {
+    let fs: Arc<dyn FileSystem> = Arc::new(MemFileSystem::new());
+    let options = DbOptions {
+        max_manifest_file_size: 200,
+        ..DbOptions::default()
+    };
+    let db = Db::open_in(Arc::clone(&fs), "/db", options.clone()).unwrap();
+    let first = manifests(fs.as_ref());
+    for round in 0..6 {
+        fill(&db, round * 10, round * 10 + 10);
+        db.flush().unwrap();
+    }
+    let rolled = manifests(fs.as_ref());
+    assert_eq!(rolled.len(), 1);
+    assert!(rolled[0] > first[0]);
+    assert!(edit_offsets(fs.as_ref()).len() < 7);
+    let tables = db.table_numbers();
+    drop(db);
+
+    let db = Db::open_in(fs, "/db", options).unwrap();
+    assert_eq!(db.table_numbers(), tables);
+    assert_eq!(db.get(b"key00005").unwrap(), Some(vec![5; 100]));
+}
@@ -0,0 +1,295 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+use stone_kvs::memtable::{ConcurrentArena, ConcurrentMemtable};
+use stone_kvs::wal::RecordType;
+
+/// Logical clock shared by the threads of a history, every operation takes a tick before and after
+static CLOCK: AtomicU64 = AtomicU64::new(0);
+
+fn tick() -> u64 {
+    CLOCK.fetch_add(1, Ordering::SeqCst)
+}
+
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// An insert as seen by the thread that made it
+#[derive(Debug, Clone, Copy)]
+struct Insert {
+    key: u64,
+    sequence: u64,
+    invoked: u64,
+    returned: u64,
+}
+
+/// A `get(key, MAX)` as seen by a reader
+#[derive(Debug, Clone, Copy)]
+struct Read {
+    key: u64,
+    result: Option<u64>,
+    invoked: u64,
+    returned: u64,
+}
+
+/// A full iteration as seen by a reader
+#[derive(Debug, Clone)]
+struct Scan {
+    entries: Vec<(u64, u64)>,
+    invoked: u64,
+    returned: u64,
+}
+
+fn key_name(n: u64) -> Vec<u8> {
+    format!("key{n:03}").into_bytes()
+}
+
+fn parse_key(key: &[u8]) -> u64 {
+    std::str::from_utf8(&key[3..]).unwrap().parse().unwrap()
+}
+
+/// Checks a read against the inserts, as the memtable's specification (newest version of a key)
+/// would answer at some instant between the invocation and the return of the read
+fn check_read(read: &Read, inserts: &[Insert]) {
+    let of_key = inserts.iter().filter(|insert| insert.key == read.key);
+    // any version completed before the read started must be visible, so the result is at least as new
+    let newest_completed = of_key
+        .clone()
+        .filter(|insert| insert.returned < read.invoked)
+        .map(|insert| insert.sequence)
+        .max();
+    match read.result {
+        None => assert_eq!(newest_completed, None, "{read:?} missed a completed insert"),
+        Some(sequence) => {
+            let insert = of_key
+                .clone()
+                .find(|insert| insert.sequence == sequence)
+                .unwrap_or_else(|| panic!("{read:?} returned a version never inserted"));
+            assert!(insert.invoked < read.returned, "{read:?} saw an insert from the future");
+            assert!(
+                newest_completed.is_none_or(|newest| newest <= sequence),
+                "{read:?} returned a version older than a completed insert"
+            );
+        }
+    }
+}
+
+fn check_scan(scan: &Scan, inserts: &[Insert]) {
+    for pair in scan.entries.windows(2) {
+        assert!(
+            pair[0].0 < pair[1].0 || (pair[0].0 == pair[1].0 && pair[0].1 > pair[1].1),
+            "scan out of order: {pair:?}"
+        );
+    }
+    let seen: BTreeSet<(u64, u64)> = scan.entries.iter().copied().collect();
+    for insert in inserts {
+        let entry = (insert.key, insert.sequence);
+        if insert.returned < scan.invoked {
+            assert!(seen.contains(&entry), "scan missed completed insert {insert:?}");
+        }
+        if seen.contains(&entry) {
+            assert!(insert.invoked < scan.returned, "scan saw an insert from the future {insert:?}");
+        }
+    }
+    assert_eq!(seen.len(), scan.entries.len(), "scan returned an entry twice");
+}
+
+#[test]
+fn concurrent_memtable_histories_are_linearizable() {
+    for round in 0..8u64 {
+        let memtable = ConcurrentMemtable::new();
+        let next_sequence = AtomicU64::new(1);
+
+        let (inserts, reads, scans) = thread::scope(|scope| {
+            let writers: Vec<_> = (0..4u64)
+                .map(|writer| {
+                    let (memtable, next_sequence) = (&memtable, &next_sequence);
+                    scope.spawn(move || {
+                        let mut rng = Rng(round * 31 + writer + 1);
+                        let mut history = Vec::new();
+                        for _ in 0..1500 {
+                            let key = rng.next() % 64;
+                            let sequence = next_sequence.fetch_add(1, Ordering::Relaxed);
+                            let invoked = tick();
+                            assert!(memtable.put(sequence, &key_name(key), &sequence.to_le_bytes()));
+                            history.push(Insert {
+                                key,
+                                sequence,
+                                invoked,
+                                returned: tick(),
+                            });
+                        }
+                        history
+                    })
+                })
+                .collect();
+            let readers: Vec<_> = (0..2u64)
+                .map(|reader| {
+                    let memtable = &memtable;
+                    scope.spawn(move || {
+                        let mut rng = Rng(round * 17 + reader + 100);
+                        let (mut reads, mut scans) = (Vec::new(), Vec::new());
+                        for i in 0..600 {
+                            let invoked = tick();
+                            if i % 50 == 0 {
+                                let entries = memtable
+                                    .iter()
+                                    .map(|entry| (parse_key(entry.key), entry.sequence))
+                                    .collect();
+                                scans.push(Scan {
+                                    entries,
+                                    invoked,
+                                    returned: tick(),
+                                });
+                                continue;
+                            }
+                            let key = rng.next() % 64;
+                            let entry = memtable.get(&key_name(key), u64::MAX);
+                            if let Some(entry) = entry {
+                                assert_eq!(entry.value, entry.sequence.to_le_bytes());
+                            }
+                            reads.push(Read {
+                                key,
+                                result: entry.map(|entry| entry.sequence),
+                                invoked,
+                                returned: tick(),
+                            });
+                        }
+                        (reads, scans)
+                    })
+                })
+                .collect();
+
+            let inserts: Vec<Insert> = writers.into_iter().flat_map(|w| w.join().unwrap()).collect();
+            let (mut reads, mut scans) = (Vec::new(), Vec::new());
+            for reader in readers {
+                let (r, s) = reader.join().unwrap();
+                reads.extend(r);
+                scans.extend(s);
+            }
+            (inserts, reads, scans)
+        });
+
+        for read in &reads {
+            check_read(read, &inserts);
+        }
+        for scan in &scans {
+            check_scan(scan, &inserts);
+        }
+        let final_scan = Scan {
+            entries: memtable
+                .iter()
+                .map(|entry| (parse_key(entry.key), entry.sequence))
+                .collect(),
+            invoked: tick(),
+            returned: tick(),
+        };
+        check_scan(&final_scan, &inserts);
+        assert_eq!(final_scan.entries.len(), inserts.len());
+        assert_eq!(memtable.len(), inserts.len());
+    }
+}
+
+#[test]
+fn concurrent_memtable_same_entry_inserted_once() {
+    let memtable = ConcurrentMemtable::new();
+
+    let inserted: usize = thread::scope(|scope| {
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let memtable = &memtable;
+                scope.spawn(move || (0..500u64).filter(|&sequence| memtable.put(sequence, b"same", b"v")).count())
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).sum()
+    });
+
+    assert_eq!(inserted, 500);
+    assert_eq!(memtable.len(), 500);
+    assert_eq!(memtable.iter().count(), 500);
+}
+
+#[test]
+fn concurrent_memtable_get_respects_sequence_and_tombstones() {
+    let memtable = ConcurrentMemtable::new();
+    memtable.put(1, b"k", b"v1");
+    memtable.put(3, b"k", b"v3");
+    memtable.delete(5, b"k");
+
+    assert_eq!(memtable.get(b"k", 2).unwrap().value, b"v1");
+    assert_eq!(memtable.get(b"k", 4).unwrap().value, b"v3");
+    assert_eq!(memtable.get(b"k", 5).unwrap().record_type, RecordType::Delete);
+    assert_eq!(memtable.get(b"k", 0), None);
+    assert_eq!(memtable.get(b"j", u64::MAX), None);
+    assert_eq!(memtable.max_sequence(), Some(5));
+}
+
+#[test]
+fn concurrent_memtable_matches_sorted_map_after_parallel_inserts() {
+    let memtable = ConcurrentMemtable::new();
+
+    thread::scope(|scope| {
+        for writer in 0..4u64 {
+            let memtable = &memtable;
+            scope.spawn(move || {
+                for i in 0..2000u64 {
+                    let sequence = i * 4 + writer;
+                    memtable.put(sequence, &key_name(sequence % 97), &vec![writer as u8; (i % 30) as usize]);
+                }
+            });
+        }
+    });
+
+    let mut model = BTreeMap::new();
+    for sequence in 0..8000u64 {
+        model.insert((key_name(sequence % 97), u64::MAX - sequence), ());
+    }
+    let expected: Vec<(Vec<u8>, u64)> = model.into_keys().map(|(k, inv)| (k, u64::MAX - inv)).collect();
+    let actual: Vec<(Vec<u8>, u64)> = memtable.iter().map(|e| (e.key.to_vec(), e.sequence)).collect();
+    assert_eq!(actual, expected);
+    let from: Vec<u64> = memtable.iter_from(&key_name(96)).map(|e| parse_key(e.key)).collect();
+    assert!(from.iter().all(|&k| k == 96));
+}
+
+#[test]
+fn concurrent_arena_allocations_do_not_overlap() {
+    let arena = ConcurrentArena::new();
+
+    let ranges: Vec<(usize, usize)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..4usize)
+            .map(|t| {
+                let arena = &arena;
+                scope.spawn(move || {
+                    (0..3000usize)
+                        .map(|i| {
+                            let len = 1 + (i * 7 + t) % 200;
+                            let at = arena.allocate(len);
+                            assert_eq!(at as usize % 8, 0);
+                            // SAFETY: the range was just allocated for this thread
+                            unsafe { std::ptr::write_bytes(at, t as u8, len) };
+                            (at as usize, len)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    });
+
+    let mut sorted = ranges.clone();
+    sorted.sort();
+    for pair in sorted.windows(2) {
+        assert!(pair[0].0 + pair[0].1 <= pair[1].0, "overlapping allocations {pair:?}");
+    }
+    let total: usize = ranges.iter().map(|(_, len)| len).sum();
+    assert!(arena.memory_usage() >= total);
+}
@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use stone_kvs::db::{Db, DbOptions};
+use stone_kvs::env::MemFileSystem;
+use stone_kvs::memtable::{
+    HashLinkListRep, HashLinkListRepFactory, Memtable, MemtableRep, MemtableRepFactory, SkipList,
+    SkipListRepFactory, VectorRep, VectorRepFactory,
+};
+use stone_kvs::wal::RecordType;
+
+fn reps() -> Vec<(&'static str, Box<dyn MemtableRep>)> {
+    vec![
+        ("skiplist", Box::new(SkipList::new())),
+        ("vector", Box::new(VectorRep::new())),
+        // few buckets and short prefixes, so that buckets hold many keys
+        ("hash_linklist", Box::new(HashLinkListRep::new(7, 4))),
+    ]
+}
+
+fn factories() -> Vec<(&'static str, Arc<dyn MemtableRepFactory>)> {
+    vec![
+        ("skiplist", Arc::new(SkipListRepFactory)),
+        ("vector", Arc::new(VectorRepFactory)),
+        (
+            "hash_linklist",
+            Arc::new(HashLinkListRepFactory {
+                bucket_count: 64,
+                prefix_len: 5,
+            }),
+        ),
+    ]
+}
+
+fn entries(memtable: &Memtable) -> Vec<(Vec<u8>, u64, RecordType, Vec<u8>)> {
+    memtable
+        .iter()
+        .map(|entry| (entry.key.to_vec(), entry.sequence, entry.record_type, entry.value.to_vec()))
+        .collect()
+}
+
+#[test]
+fn memtable_reps_match_sorted_map() {
+    for (name, rep) in reps() {
+        let mut memtable = Memtable::with_rep(rep);
+        // (key, Reverse(sequence)) -> (type, value)
+        let mut model = BTreeMap::new();
+        let mut rng = 0x9e37_79b9_7f4a_7c15u64;
+        for sequence in 1..=3000u64 {
+            rng ^= rng << 13;
+            rng ^= rng >> 7;
+            rng ^= rng << 17;
+            let key = format!("user{:03}", rng % 150).into_bytes();
+            let (record_type, value) = match rng % 5 {
+                0 => (RecordType::Delete, Vec::new()),
+                _ => (RecordType::Put, sequence.to_le_bytes().to_vec()),
+            };
+            memtable.add(sequence, record_type, &key, &value);
+            model.insert((key, u64::MAX - sequence), (record_type, value));
+        }
+
+        for read in [0, 500, 1777, 3000] {
+            for key in 0..150 {
+                let key = format!("user{key:03}").into_bytes();
+                let expected = model
+                    .range((key.clone(), u64::MAX - read)..)
+                    .next()
+                    .filter(|((k, _), _)| *k == key)
+                    .map(|((_, inverted), (record_type, value))| (u64::MAX - inverted, *record_type, value.clone()));
+                let actual = memtable
+                    .get(&key, read)
+                    .map(|entry| (entry.sequence, entry.record_type, entry.value.to_vec()));
+                assert_eq!(actual, expected, "{name}: get {key:?} at {read}");
+            }
+        }
+
+        memtable.mark_read_only();
+        let expected: Vec<_> = model
+            .iter()
+            .map(|((key, inverted), (record_type, value))| (key.clone(), u64::MAX - inverted, *record_type, value.clone()))
+            .collect();
+        assert_eq!(entries(&memtable), expected, "{name}");
+        let from = memtable.iter_from(b"user100").next().unwrap();
+        assert_eq!(from.key, b"user100", "{name}");
+        assert_eq!(memtable.len(), 3000, "{name}");
+        assert_eq!(memtable.max_sequence(), Some(3000), "{name}");
+    }
+}
+
+#[test]
+fn memtable_reps_read_before_being_read_only() {
+    for (name, rep) in reps() {
+        let mut memtable = Memtable::with_rep(rep);
+        memtable.put(2, b"b", b"b2");
+        memtable.put(1, b"a", b"a1");
+        memtable.put(3, b"a", b"a3");
+        memtable.delete(4, b"b");
+
+        assert_eq!(memtable.get(b"a", u64::MAX).unwrap().value, b"a3", "{name}");
+        assert_eq!(memtable.get(b"a", 2).unwrap().value, b"a1", "{name}");
+        assert_eq!(memtable.get(b"b", u64::MAX).unwrap().record_type, RecordType::Delete, "{name}");
+        let keys: Vec<(&[u8], u64)> = memtable.iter().map(|entry| (entry.key, entry.sequence)).collect();
+        assert_eq!(keys, [(&b"a"[..], 3), (b"a", 1), (b"b", 4), (b"b", 2)], "{name}");
+
+        // still writable after a read
+        memtable.put(5, b"0", b"first");
+        assert_eq!(memtable.iter().next().unwrap().key, b"0", "{name}");
+    }
+}
+
+#[test]
+fn vector_rep_drops_duplicates_when_sorted() {
+    let mut memtable = Memtable::with_rep(Box::new(VectorRep::new()));
+    assert!(memtable.put(1, b"k", b"first"));
+    // not detected by the insert
+    assert!(memtable.put(1, b"k", b"second"));
+    memtable.put(2, b"k", b"newer");
+
+    assert_eq!(memtable.get(b"k", 1).unwrap().value, b"first");
+    memtable.mark_read_only();
+
+    assert_eq!(memtable.len(), 2);
+    assert_eq!(memtable.get(b"k", 1).unwrap().value, b"first");
+    assert_eq!(entries(&memtable).len(), 2);
+}
+
+#[test]
+fn hash_linklist_rep_detects_duplicates_and_reports_buckets() {
+    let empty = HashLinkListRep::new(1000, 8);
+    assert!(empty.memory_usage() >= 1000 * 8);
+
+    let mut memtable = Memtable::with_rep(Box::new(HashLinkListRep::new(1, 8)));
+    assert!(memtable.put(1, b"k", b"v"));
+    assert!(!memtable.put(1, b"k", b"again"));
+    assert!(memtable.put(1, b"j", b"other key"));
+    assert_eq!(memtable.len(), 2);
+    assert_eq!(memtable.get(b"k", 1).unwrap().value, b"v");
+}
+
+#[test]
+fn db_flushes_and_reopens_with_every_memtable_rep() {
+    for (name, factory) in factories() {
+        let fs = Arc::new(MemFileSystem::new());
+        let options = DbOptions {
+            write_buffer_size: 64 * 1024,
+            memtable_factory: factory,
+            // the flushed tables stay in level 0
+            disable_auto_compactions: true,
+            ..DbOptions::default()
+        };
+        let db = Db::open_in(fs.clone(), "/db", options.clone()).unwrap();
+        for i in 0..500u32 {
+            db.put(format!("key{:05}", (i * 7) % 500).as_bytes(), &[i as u8; 300]).unwrap();
+        }
+        db.delete(b"key00003").unwrap();
+        db.flush().unwrap();
+        assert!(db.table_numbers().len() >= 2, "{name}");
+        db.put(b"key00004", b"in the wal").unwrap();
+        drop(db);
+
+        let db = Db::open_in(fs, "/db", options).unwrap();
+        assert_eq!(db.get(b"key00003").unwrap(), None, "{name}");
+        assert_eq!(db.get(b"key00004").unwrap(), Some(b"in the wal".to_vec()), "{name}");
+        // (i * 7) % 500 == 10 for i == 430
+        assert_eq!(db.get(b"key00010").unwrap(), Some(vec![430u32 as u8; 300]), "{name}");
+    }
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use common::TempDir;
+use stone_kvs::memtable::arena::ARENA_BLOCK_SIZE;
+use stone_kvs::memtable::{Arena, Memtable};
+use stone_kvs::wal::{RecordType, WalReader, WalWriter};
+
+fn entries(memtable: &Memtable) -> Vec<(Vec<u8>, u64, RecordType)> {
+    memtable
+        .iter()
+        .map(|entry| (entry.key.to_vec(), entry.sequence, entry.record_type))
+        .collect()
+}
+
+#[test]
+fn memtable_get_returns_newest_version() {
+    let mut memtable = Memtable::new();
+    memtable.put(1, b"k", b"v1");
+    memtable.put(2, b"k", b"v2");
+
+    let entry = memtable.get(b"k", u64::MAX).unwrap();
+
+    assert_eq!(entry.value, b"v2");
+    assert_eq!(entry.sequence, 2);
+}
+
+#[test]
+fn memtable_get_at_older_sequence_sees_older_version() {
+    let mut memtable = Memtable::new();
+    memtable.put(10, b"k", b"v10");
+    memtable.put(20, b"k", b"v20");
+
+    assert_eq!(memtable.get(b"k", 15).unwrap().value, b"v10");
+    assert_eq!(memtable.get(b"k", 20).unwrap().value, b"v20");
+    assert_eq!(memtable.get(b"k", 9), None);
+}
+
+#[test]
+fn memtable_delete_hides_older_versions() {
+    let mut memtable = Memtable::new();
+    memtable.put(1, b"k", b"v");
+    memtable.delete(2, b"k");
+
+    assert_eq!(memtable.get(b"k", 2).unwrap().record_type, RecordType::Delete);
+    assert_eq!(memtable.get(b"k", 1).unwrap().record_type, RecordType::Put);
+}
+
+#[test]
+fn memtable_get_missing_key_returns_none() {
+    let mut memtable = Memtable::new();
+    memtable.put(1, b"a", b"1");
+    memtable.put(2, b"c", b"3");
+
+    assert_eq!(memtable.get(b"b", u64::MAX), None);
+    assert_eq!(memtable.get(b"", u64::MAX), None);
+    assert_eq!(memtable.get(b"d", u64::MAX), None);
+}
+
+#[test]
+fn memtable_duplicate_sequence_is_ignored() {
+    let mut memtable = Memtable::new();
+
+    assert!(memtable.put(1, b"k", b"first"));
+    assert!(!memtable.put(1, b"k", b"second"));
+    assert_eq!(memtable.len(), 1);
+    assert_eq!(memtable.get(b"k", 1).unwrap().value, b"first");
+}
+
+#[test]
+fn memtable_iterates_by_key_then_newest_first() {
+    let mut memtable = Memtable::new();
+    memtable.put(3, b"b", b"");
+    memtable.put(1, b"a", b"");
+    memtable.delete(4, b"a");
+    memtable.put(2, b"c", b"");
+
+    assert_eq!(
+        entries(&memtable),
+        vec![
+            (b"a".to_vec(), 4, RecordType::Delete),
+            (b"a".to_vec(), 1, RecordType::Put),
+            (b"b".to_vec(), 3, RecordType::Put),
+            (b"c".to_vec(), 2, RecordType::Put),
+        ]
+    );
+    let from_b: Vec<&[u8]> = memtable.iter_from(b"aa").map(|entry| entry.key).collect();
+    assert_eq!(from_b, vec![b"b".as_slice(), b"c"]);
+}
+
+#[test]
+fn memtable_matches_a_sorted_map_on_random_inserts() {
+    let mut memtable = Memtable::new();
+    let mut model = BTreeMap::new();
+    let mut state = 0x1234_5678u64;
+    for sequence in 1..=5000u64 {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let key = format!("key{:04}", state % 700).into_bytes();
+        let value = sequence.to_le_bytes().repeat((state % 5) as usize);
+        memtable.put(sequence, &key, &value);
+        model.insert((key, u64::MAX - sequence), value);
+    }
+
+    let expected: Vec<(Vec<u8>, u64, Vec<u8>)> = model
+        .into_iter()
+        .map(|((key, inverted), value)| (key, u64::MAX - inverted, value))
+        .collect();
+    let actual: Vec<(Vec<u8>, u64, Vec<u8>)> = memtable
+        .iter()
+        .map(|entry| (entry.key.to_vec(), entry.sequence, entry.value.to_vec()))
+        .collect();
+    assert_eq!(actual, expected);
+    assert_eq!(memtable.max_sequence(), Some(5000));
+}
+
+#[test]
+fn memtable_memory_usage_grows_with_data() {
+    let mut memtable = Memtable::new();
+    let empty = memtable.memory_usage();
+    let value = vec![7u8; 1000];
+
+    for sequence in 0..200 {
+        memtable.put(sequence, format!("key{sequence}").as_bytes(), &value);
+    }
+
+    assert!(memtable.memory_usage() >= 200 * 1000);
+    assert!(memtable.memory_usage() < empty + 200 * 1000 + 2 * ARENA_BLOCK_SIZE);
+}
+
+#[test]
+fn arena_large_allocation_gets_its_own_block() {
+    let mut arena = Arena::new();
+    let small = arena.allocate_copy(b"small");
+    let usage = arena.memory_usage();
+
+    let large = arena.allocate_copy(&vec![1u8; ARENA_BLOCK_SIZE]);
+    let after = arena.allocate_copy(b"after");
+
+    assert!(arena.memory_usage() - usage >= ARENA_BLOCK_SIZE);
+    assert_eq!(arena.bytes(small, 5), b"small");
+    assert_eq!(arena.bytes(large, ARENA_BLOCK_SIZE), vec![1u8; ARENA_BLOCK_SIZE].as_slice());
+    // the block of the small allocations is still used after the large one
+    assert_eq!(after, small + 5);
+}
+
+#[test]
+fn memtable_replays_wal_segment() {
+    let dir = TempDir::new("memtable-replay");
+    let path = dir.join("000001.log");
+    let mut writer = WalWriter::create(&path).unwrap();
+    writer.put(1, b"a", b"1").unwrap();
+    writer.put(2, b"b", b"2").unwrap();
+    writer.delete(3, b"a").unwrap();
+    writer.sync().unwrap();
+    drop(writer);
+
+    let mut memtable = Memtable::new();
+    assert_eq!(memtable.replay(WalReader::open(&path).unwrap()).unwrap(), 3);
+    // replaying the same segment again adds nothing
+    assert_eq!(memtable.replay(WalReader::open(&path).unwrap()).unwrap(), 0);
+
+    assert_eq!(memtable.get(b"a", u64::MAX).unwrap().record_type, RecordType::Delete);
+    assert_eq!(memtable.get(b"b", u64::MAX).unwrap().value, b"2");
+    assert_eq!(memtable.max_sequence(), Some(3));
+}
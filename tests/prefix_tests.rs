@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use stone_kvs::db::{Db, DbOptions};
+use stone_kvs::env::{FileSystem, MemFileSystem};
+use stone_kvs::table::{
+    BlockBasedTableFactory, BloomFilterPolicy, CustomPrefix, DelimiterPrefix, FixedPrefix, PrefixExtractor,
+    TableBuilder, TableOptions, TableReader,
+};
+use stone_kvs::wal::RecordType;
+
+const PATH: &str = "/table/000001.sst";
+
+fn key(tenant: u32, id: u32) -> Vec<u8> {
+    format!("tenant{tenant:03}/order/{id:06}").into_bytes()
+}
+
+fn tenant_options() -> TableOptions {
+    TableOptions {
+        filter_policy: Some(Arc::new(BloomFilterPolicy::new(10.0))),
+        prefix_extractor: Some(Arc::new(DelimiterPrefix::new(b'/', 1))),
+        ..TableOptions::default()
+    }
+}
+
+/// Even tenants only, 50 keys each
+fn build_table(fs: &MemFileSystem, options: &TableOptions) {
+    fs.create_dir_all(Path::new("/table")).unwrap();
+    let mut builder = TableBuilder::new(fs.create_new(Path::new(PATH)).unwrap(), options.clone());
+    for tenant in (0..200).step_by(2) {
+        for id in 0..50 {
+            builder.add(&key(tenant, id), 1, RecordType::Put, b"value").unwrap();
+        }
+    }
+    builder.finish().unwrap();
+}
+
+#[test]
+fn prefix_extractors() {
+    let key = b"acme/orders/42".as_slice();
+
+    assert_eq!(FixedPrefix::new(4).prefix(key), Some(b"acme".as_slice()));
+    assert_eq!(FixedPrefix::new(20).prefix(key), None);
+    assert_eq!(DelimiterPrefix::new(b'/', 1).prefix(key), Some(b"acme/".as_slice()));
+    assert_eq!(DelimiterPrefix::new(b'/', 2).prefix(key), Some(b"acme/orders/".as_slice()));
+    assert_eq!(DelimiterPrefix::new(b'/', 3).prefix(key), None);
+    assert_eq!(DelimiterPrefix::new(b'/', 1).prefix(b"acme"), None);
+    let custom = CustomPrefix::new("app.before_colon", |key: &[u8]| {
+        key.iter().position(|&b| b == b':').map(|end| &key[..end])
+    });
+    assert_eq!(custom.name(), "app.before_colon");
+    assert_eq!(custom.prefix(b"user:42"), Some(b"user".as_slice()));
+    assert_eq!(custom.prefix(b"user42"), None);
+}
+
+#[test]
+fn prefix_filter_rules_out_absent_prefixes() {
+    let fs = MemFileSystem::new();
+    let options = tenant_options();
+    build_table(&fs, &options);
+    let table = TableReader::open(&fs, Path::new(PATH), &options).unwrap();
+
+    let tenant = |tenant: u32| format!("tenant{tenant:03}/").into_bytes();
+    assert!((0..200).step_by(2).all(|n| table.may_contain_prefix(&tenant(n))));
+    let skipped = (1..200).step_by(2).filter(|&n| !table.may_contain_prefix(&tenant(n)));
+    assert!(skipped.count() >= 95);
+    // a longer prefix has the same extracted prefix, a shorter one is out of the domain
+    assert!(table.may_contain_prefix(b"tenant004/order/"));
+    assert!(table.may_contain_prefix(b"tenant00"));
+    let counters = options.filter_stats.snapshot();
+    assert!(counters.prefix_checked >= 200 && counters.prefix_useful >= 95, "{counters:?}");
+    // the whole keys are in the filter too
+    assert!(table.get(&key(4, 7), u64::MAX).unwrap().is_some());
+    assert_eq!(options.filter_stats.snapshot().checked, 1);
+}
+
+#[test]
+fn prefix_only_filter_checks_the_prefix_of_point_lookups() {
+    let whole = MemFileSystem::new();
+    build_table(&whole, &tenant_options());
+    let fs = MemFileSystem::new();
+    let options = TableOptions {
+        whole_key_filtering: false,
+        ..tenant_options()
+    };
+    build_table(&fs, &options);
+    let table = TableReader::open(&fs, Path::new(PATH), &options).unwrap();
+    let whole_table = TableReader::open(&whole, Path::new(PATH), &tenant_options()).unwrap();
+
+    // one prefix per tenant instead of one key per entry
+    assert!(table.properties().filter_size * 20 < whole_table.properties().filter_size);
+    assert!(table.get(&key(4, 7), u64::MAX).unwrap().is_some());
+    // an absent key of a present tenant is let through, one of an absent tenant is not
+    assert!(table.get(&key(4, 70), u64::MAX).unwrap().is_none());
+    assert!((1..200).step_by(2).all(|tenant| table.get(&key(tenant, 1), u64::MAX).unwrap().is_none()));
+    let counters = options.filter_stats.snapshot();
+    assert!(counters.useful >= 95, "{counters:?}");
+    assert!(counters.false_positives >= 1, "{counters:?}");
+}
+
+#[test]
+fn prefixes_of_another_extractor_are_not_used() {
+    let fs = MemFileSystem::new();
+    build_table(&fs, &tenant_options());
+    let options = TableOptions {
+        prefix_extractor: Some(Arc::new(FixedPrefix::new(10))),
+        ..tenant_options()
+    };
+    let table = TableReader::open(&fs, Path::new(PATH), &options).unwrap();
+
+    assert!((1..200).step_by(2).all(|n| table.may_contain_prefix(format!("tenant{n:03}/").as_bytes())));
+    assert_eq!(options.filter_stats.snapshot().prefix_checked, 0);
+    assert!(table.get(&key(4, 7), u64::MAX).unwrap().is_some());
+}
+
+#[test]
+fn db_prefix_scans_skip_tables_of_other_tenants() {
+    let table_options = tenant_options();
+    let stats = Arc::clone(&table_options.filter_stats);
+    let options = DbOptions {
+        table_factory: Arc::new(BlockBasedTableFactory { options: table_options }),
+        disable_auto_compactions: true,
+        ..DbOptions::default()
+    };
+    let db = Db::open_in(Arc::new(MemFileSystem::new()), "/db", options).unwrap();
+    let mut expected = BTreeMap::new();
+    // one table per tenant, more entries than a batch of the iterator
+    for tenant in 0..8 {
+        for id in 0..600 {
+            let value = format!("{tenant}-{id}").into_bytes();
+            db.put(&key(tenant, id), &value).unwrap();
+            expected.insert(key(tenant, id), value);
+        }
+        db.flush().unwrap();
+    }
+    // overwrites and deletes in a newer table and in the memtable
+    for id in (0..600).step_by(7) {
+        db.put(&key(3, id), b"new").unwrap();
+        expected.insert(key(3, id), b"new".to_vec());
+    }
+    db.flush().unwrap();
+    for id in (0..600).step_by(5) {
+        db.delete(&key(3, id)).unwrap();
+        expected.remove(&key(3, id));
+    }
+    db.put(&key(3, 1000), b"memtable").unwrap();
+    expected.insert(key(3, 1000), b"memtable".to_vec());
+
+    let before = stats.snapshot();
+    let scan = db.prefix_iter(b"tenant003/");
+    db.put(&key(3, 2000), b"after the iterator").unwrap();
+    let scanned: Vec<(Vec<u8>, Vec<u8>)> = scan.collect::<std::io::Result<_>>().unwrap();
+
+    let tenant: Vec<(Vec<u8>, Vec<u8>)> = expected
+        .iter()
+        .filter(|(key, _)| key.starts_with(b"tenant003/"))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    assert_eq!(scanned, tenant);
+    let after = stats.snapshot();
+    assert_eq!(after.prefix_checked - before.prefix_checked, 9);
+    assert!(after.prefix_useful - before.prefix_useful >= 6, "{after:?}");
+    assert_eq!(db.prefix_iter(b"tenant100/").count(), 0);
+    assert_eq!(db.prefix_iter(b"tenant00").count(), 8 * 600 - 600 / 5 + 1 + 1);
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use common::TempDir;
+use stone_kvs::wal::WalWriter;
+use stone_kvs::wal::record::{FILE_HEADER_SIZE, RECORD_HEADER_SIZE};
+
+fn stone_wal(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_stone-wal")).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn write_sample(path: &Path) {
+    let mut writer = WalWriter::create(path).unwrap();
+    writer.put(1, b"tenant1/a", b"hello").unwrap();
+    writer.put(2, b"tenant2/b", b"world").unwrap();
+    writer.delete(3, b"tenant1/a").unwrap();
+    writer.sync().unwrap();
+}
+
+#[test]
+fn dump_prints_header_and_every_record() {
+    let dir = TempDir::new("cli-dump");
+    let path = dir.join("000001.log");
+    write_sample(&path);
+
+    let output = stone_wal(&["dump", path.to_str().unwrap()]);
+    let text = stdout(&output);
+
+    assert!(output.status.success());
+    assert!(text.contains("magic: SKVW"));
+    assert!(text.contains("version: 1"));
+    assert!(text.contains("offset=16 seq=1 type=PUT key_size=9 value_size=5 crc=ok"));
+    assert!(text.contains("seq=3 type=DELETE key_size=9 value_size=0 crc=ok"));
+    assert!(text.contains("records=3 corrupted=0 torn_tail_bytes=0"));
+}
+
+#[test]
+fn dump_renders_keys_and_values() {
+    let dir = TempDir::new("cli-render");
+    let path = dir.join("000001.log");
+    write_sample(&path);
+
+    let text = stdout(&stone_wal(&["dump", path.to_str().unwrap(), "--utf8", "--hex", "--to", "1"]));
+
+    assert!(text.contains("  key (utf8): tenant1/a"));
+    assert!(text.contains("  value (utf8): hello"));
+    assert!(text.contains("  value (hex): 68656c6c6f"));
+    assert!(!text.contains("seq=2"));
+}
+
+#[test]
+fn dump_filters_by_sequence_range_and_prefix() {
+    let dir = TempDir::new("cli-filter");
+    let path = dir.join("000001.log");
+    write_sample(&path);
+
+    let by_range = stdout(&stone_wal(&["dump", path.to_str().unwrap(), "--from", "2", "--to", "2"]));
+    let by_prefix = stdout(&stone_wal(&["dump", path.to_str().unwrap(), "--prefix", "tenant1/"]));
+    let by_hex_prefix = stdout(&stone_wal(&["dump", path.to_str().unwrap(), "--prefix", "0x74656e616e7432"]));
+
+    assert!(!by_range.contains("seq=1 ") && by_range.contains("seq=2 ") && !by_range.contains("seq=3 "));
+    assert!(by_prefix.contains("seq=1 ") && !by_prefix.contains("seq=2 ") && by_prefix.contains("seq=3 "));
+    assert!(!by_hex_prefix.contains("seq=1 ") && by_hex_prefix.contains("seq=2 "));
+}
+
+#[test]
+fn dump_reports_crc_mismatch() {
+    let dir = TempDir::new("cli-dump-corrupt");
+    let path = dir.join("000001.log");
+    write_sample(&path);
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[FILE_HEADER_SIZE + RECORD_HEADER_SIZE] ^= 0x20;
+    fs::write(&path, bytes).unwrap();
+
+    let text = stdout(&stone_wal(&["dump", path.to_str().unwrap()]));
+
+    assert!(text.contains("offset=16 seq=1 type=PUT key_size=9 value_size=5 crc=MISMATCH"));
+    assert!(text.contains("records=2 corrupted=1"));
+}
+
+#[test]
+fn verify_succeeds_on_clean_segment() {
+    let dir = TempDir::new("cli-verify-ok");
+    let path = dir.join("000001.log");
+    write_sample(&path);
+
+    let output = stone_wal(&["verify", path.to_str().unwrap()]);
+
+    assert!(output.status.success());
+    assert!(stdout(&output).contains(": OK version=1 records=3 corrupted=0"));
+}
+
+#[test]
+fn verify_exits_non_zero_on_crc_mismatch() {
+    let dir = TempDir::new("cli-verify-bad");
+    let good = dir.join("000001.log");
+    let bad = dir.join("000002.log");
+    write_sample(&good);
+    write_sample(&bad);
+    let mut bytes = fs::read(&bad).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0x01;
+    fs::write(&bad, bytes).unwrap();
+
+    let output = stone_wal(&["verify", good.to_str().unwrap(), bad.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("000002.log: FAILED"));
+}
+
+#[test]
+fn unknown_command_is_a_usage_error() {
+    let output = stone_wal(&["compact"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("usage:"));
+}
@@ -0,0 +1,209 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use stone_kvs::cache::BlockCache;
+use stone_kvs::db::{Db, DbOptions};
+use stone_kvs::env::{FileSystem, MemFileSystem, RandomAccessFile, SequentialFile, WritableFile};
+use stone_kvs::table::{BlockBasedTableFactory, TableOptions};
+
+/// Counts the table files open for random access, like the descriptors of a process
+struct CountingFs {
+    inner: MemFileSystem,
+    open: Arc<AtomicUsize>,
+    max_open: Arc<AtomicUsize>,
+}
+
+struct CountedFile {
+    inner: Arc<dyn RandomAccessFile>,
+    open: Arc<AtomicUsize>,
+}
+
+impl RandomAccessFile for CountedFile {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read_at(offset, buf)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        self.inner.size()
+    }
+}
+
+impl Drop for CountedFile {
+    fn drop(&mut self) {
+        self.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl FileSystem for CountingFs {
+    fn create_new(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        self.inner.create_new(path)
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn WritableFile>> {
+        self.inner.create(path)
+    }
+
+    fn open_sequential(&self, path: &Path) -> io::Result<Box<dyn SequentialFile>> {
+        self.inner.open_sequential(path)
+    }
+
+    fn open_random_access(&self, path: &Path) -> io::Result<Arc<dyn RandomAccessFile>> {
+        let inner = self.inner.open_random_access(path)?;
+        let open = self.open.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_open.fetch_max(open, Ordering::SeqCst);
+        Ok(Arc::new(CountedFile {
+            inner,
+            open: Arc::clone(&self.open),
+        }))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner.exists(path)
+    }
+
+    fn file_size(&self, path: &Path) -> io::Result<u64> {
+        self.inner.file_size(path)
+    }
+
+    fn list_dir(&self, dir: &Path) -> io::Result<Vec<String>> {
+        self.inner.list_dir(dir)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_file(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner.rename(from, to)
+    }
+
+    fn create_dir_all(&self, dir: &Path) -> io::Result<()> {
+        self.inner.create_dir_all(dir)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        self.inner.sync_dir(dir)
+    }
+}
+
+fn counting_fs() -> (Arc<CountingFs>, Arc<AtomicUsize>, Arc<AtomicUsize>) {
+    let open = Arc::new(AtomicUsize::new(0));
+    let max_open = Arc::new(AtomicUsize::new(0));
+    let fs = Arc::new(CountingFs {
+        inner: MemFileSystem::new(),
+        open: Arc::clone(&open),
+        max_open: Arc::clone(&max_open),
+    });
+    (fs, open, max_open)
+}
+
+fn key(table: u32, n: u32) -> Vec<u8> {
+    format!("key{table:03}-{n:04}").into_bytes()
+}
+
+/// 20 tables of 100 keys each
+fn fill_tables(db: &Db) {
+    for table in 0..20 {
+        for n in 0..100 {
+            db.put(&key(table, n), &[table as u8; 100]).unwrap();
+        }
+        db.flush().unwrap();
+    }
+}
+
+/// The 20 tables stay in level 0
+fn uncompacted() -> DbOptions {
+    DbOptions {
+        disable_auto_compactions: true,
+        ..DbOptions::default()
+    }
+}
+
+fn few_open_files() -> DbOptions {
+    DbOptions {
+        max_open_files: 4,
+        ..uncompacted()
+    }
+}
+
+#[test]
+fn table_cache_bounds_the_open_files() {
+    let (fs, open, max_open) = counting_fs();
+    let db = Db::open_in(fs.clone(), "/db", few_open_files()).unwrap();
+    fill_tables(&db);
+    assert_eq!(db.table_numbers().len(), 20);
+
+    for table in 0..20 {
+        for n in (0..100).step_by(10) {
+            assert_eq!(db.get(&key(table, n)).unwrap(), Some(vec![table as u8; 100]));
+        }
+    }
+    assert_eq!(db.get(b"absent").unwrap(), None);
+
+    assert_eq!(db.table_cache().len(), 4);
+    // the 4 cached tables, plus the one being opened before the oldest is closed
+    assert!(open.load(Ordering::SeqCst) <= 4);
+    assert!(max_open.load(Ordering::SeqCst) <= 5, "{max_open:?}");
+    assert!(db.table_cache().stats().evictions > 0);
+    assert_eq!(db.prefix_iter(b"key").count(), 2000);
+    assert!(open.load(Ordering::SeqCst) <= 4);
+
+    drop(db);
+    assert_eq!(open.load(Ordering::SeqCst), 0);
+    // every table is opened on recovery, through the cache too
+    let db = Db::open_in(fs, "/db", few_open_files()).unwrap();
+    assert!(open.load(Ordering::SeqCst) <= 4);
+    assert_eq!(db.get(&key(7, 7)).unwrap(), Some(vec![7; 100]));
+}
+
+#[test]
+fn table_cache_keeps_parsed_tables_open() {
+    let (fs, _, _) = counting_fs();
+    let db = Db::open_in(fs, "/db", uncompacted()).unwrap();
+    fill_tables(&db);
+
+    let before = db.table_cache().stats();
+    for _ in 0..3 {
+        for table in 0..20 {
+            assert!(db.get(&key(table, 1)).unwrap().is_some());
+        }
+    }
+    let after = db.table_cache().stats();
+    // the flush put each new table in the cache, no lookup opens one
+    assert_eq!(after.misses, before.misses);
+    assert_eq!(db.table_cache().len(), 20);
+    assert!(after.hits - before.hits >= 60);
+}
+
+#[test]
+fn reopened_table_finds_its_blocks_in_the_block_cache() {
+    let (fs, _, _) = counting_fs();
+    let block_cache = Arc::new(BlockCache::new(1024 * 1024));
+    let options = DbOptions {
+        table_factory: Arc::new(BlockBasedTableFactory {
+            options: TableOptions {
+                block_cache: Some(Arc::clone(&block_cache)),
+                ..TableOptions::default()
+            },
+        }),
+        ..few_open_files()
+    };
+    let db = Db::open_in(fs, "/db", options).unwrap();
+    fill_tables(&db);
+    assert_eq!(db.get(&key(0, 1)).unwrap(), Some(vec![0; 100]));
+
+    // the other tables push table 0 out of the table cache
+    for table in 1..20 {
+        assert!(db.get(&key(table, 1)).unwrap().is_some());
+    }
+    let tables = db.table_cache().stats();
+    let blocks = block_cache.stats();
+    assert_eq!(db.get(&key(0, 1)).unwrap(), Some(vec![0; 100]));
+
+    // reopened, it reads its data block from the block cache
+    assert_eq!(db.table_cache().stats().misses, tables.misses + 1);
+    assert_eq!(block_cache.stats().hits, blocks.hits + 1);
+    assert_eq!(block_cache.stats().misses, blocks.misses);
+}
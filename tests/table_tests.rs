@@ -0,0 +1,440 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use stone_kvs::cache::BlockCache;
+use stone_kvs::env::{FileSystem, MemFileSystem, read_file};
+use stone_kvs::table::block::{Block, BlockBuilder};
+use stone_kvs::table::format::{FOOTER_SIZE, TABLE_FORMAT_VERSION, compare_internal_keys, internal_key};
+use stone_kvs::table::{DataBlockIndexType, IndexType, Table, TableBuilder, TableEntry, TableOptions, TableReader};
+use stone_kvs::wal::RecordType;
+
+const PATH: &str = "/table/000001.sst";
+
+fn mem_fs() -> MemFileSystem {
+    let fs = MemFileSystem::new();
+    fs.create_dir_all(Path::new("/table")).unwrap();
+    fs
+}
+
+fn build(fs: &MemFileSystem, options: TableOptions, entries: &[TableEntry]) -> u64 {
+    let mut builder = TableBuilder::new(fs.create_new(Path::new(PATH)).unwrap(), options);
+    for entry in entries {
+        builder
+            .add(&entry.key, entry.sequence, entry.record_type, &entry.value)
+            .unwrap();
+    }
+    builder.finish().unwrap()
+}
+
+fn open(fs: &MemFileSystem) -> io::Result<TableReader> {
+    TableReader::open(fs, Path::new(PATH), &TableOptions::default())
+}
+
+fn partitioned(block_cache: Option<Arc<BlockCache>>) -> TableOptions {
+    TableOptions {
+        block_size: 512,
+        index_type: IndexType::Partitioned,
+        index_partition_size: 1024,
+        block_cache,
+        ..TableOptions::default()
+    }
+}
+
+fn entry(key: &[u8], sequence: u64, record_type: RecordType, value: &[u8]) -> TableEntry {
+    TableEntry {
+        key: key.to_vec(),
+        sequence,
+        record_type,
+        value: value.to_vec(),
+    }
+}
+
+/// Versions of 2000 keys sharing a long prefix, in the internal order
+fn sorted_entries() -> Vec<TableEntry> {
+    let mut model = BTreeMap::new();
+    let mut rng = 0x2545_f491_4f6c_dd1du64;
+    for sequence in 1..=5000u64 {
+        rng ^= rng << 13;
+        rng ^= rng >> 7;
+        rng ^= rng << 17;
+        let key = format!("tenant/0042/user/{:06}", rng % 2000).into_bytes();
+        let record_type = if rng.is_multiple_of(7) { RecordType::Delete } else { RecordType::Put };
+        let value = match record_type {
+            RecordType::Put => vec![(rng >> 8) as u8; (rng % 300) as usize],
+            RecordType::Delete => Vec::new(),
+        };
+        let entry = entry(&key, sequence, record_type, &value);
+        model.insert((key, u64::MAX - sequence), entry);
+    }
+    model.into_values().collect()
+}
+
+fn flip_byte(fs: &MemFileSystem, offset: usize) {
+    let mut data = read_file(fs, Path::new(PATH)).unwrap();
+    data[offset] ^= 0x40;
+    fs.set_contents(Path::new(PATH), data).unwrap();
+}
+
+#[test]
+fn block_seek_finds_first_key_not_less_than_target() {
+    for restart_interval in [1, 3, 16] {
+        let mut builder = BlockBuilder::new(restart_interval);
+        for i in (0..200).step_by(2) {
+            builder.add(format!("key{i:04}").as_bytes(), format!("value{i}").as_bytes());
+        }
+        let block = Arc::new(Block::new(builder.finish().to_vec()).unwrap());
+
+        let mut iter = block.iter();
+        iter.seek(b"key0051", |a, b| a.cmp(b));
+        assert_eq!(iter.key(), b"key0052");
+        assert_eq!(iter.value(), b"value52");
+        iter.seek(b"key0000", |a, b| a.cmp(b));
+        assert_eq!(iter.key(), b"key0000");
+        iter.seek(b"key0199", |a, b| a.cmp(b));
+        assert!(!iter.valid());
+        iter.status().unwrap();
+
+        iter.seek_to_first();
+        let mut count = 0;
+        while iter.valid() {
+            count += 1;
+            iter.advance();
+        }
+        assert_eq!(count, 100);
+    }
+}
+
+#[test]
+fn block_hash_index_finds_the_restart_interval_of_a_key() {
+    let mut builder = BlockBuilder::with_hash_index(4, 0.75);
+    let mut plain = BlockBuilder::new(4);
+    for i in (0..200).step_by(2) {
+        // a few keys with versions spanning restart intervals
+        for sequence in (1..=1 + (i % 5) as u64).rev() {
+            let key = internal_key(format!("key{i:04}").as_bytes(), sequence, RecordType::Put);
+            builder.add(&key, format!("value{i}-{sequence}").as_bytes());
+            plain.add(&key, format!("value{i}-{sequence}").as_bytes());
+        }
+    }
+    let hashed = Arc::new(Block::new(builder.finish().to_vec()).unwrap());
+    let plain = Arc::new(Block::new(plain.finish().to_vec()).unwrap());
+    assert!(hashed.has_hash_index() && !plain.has_hash_index());
+    assert!(hashed.size() > plain.size());
+
+    for i in 0..201 {
+        for sequence in [u64::MAX >> 8, 3, 1] {
+            let target = internal_key(format!("key{i:04}").as_bytes(), sequence, RecordType::Put);
+            let mut expected = plain.iter();
+            expected.seek(&target, compare_internal_keys);
+            for block in [&hashed, &plain] {
+                let mut iter = block.iter();
+                iter.seek_for_get(&target, compare_internal_keys);
+                iter.status().unwrap();
+                // an absent key may leave the cursor anywhere past it, or not valid
+                let found = iter.valid() && iter.key()[..7] == target[..7];
+                let present = expected.valid() && expected.key()[..7] == target[..7];
+                assert_eq!(found, present, "key{i:04} at {sequence}");
+                if present {
+                    assert_eq!((iter.key(), iter.value()), (expected.key(), expected.value()));
+                }
+            }
+        }
+    }
+
+    // too many restart points for a byte per bucket
+    let mut builder = BlockBuilder::with_hash_index(1, 0.75);
+    for i in 0..300 {
+        builder.add(&internal_key(format!("key{i:04}").as_bytes(), 1, RecordType::Put), b"v");
+    }
+    let block = Arc::new(Block::new(builder.finish().to_vec()).unwrap());
+    assert!(!block.has_hash_index());
+    let mut iter = block.iter();
+    iter.seek_for_get(&internal_key(b"key0299", 1, RecordType::Put), compare_internal_keys);
+    assert_eq!(iter.value(), b"v");
+}
+
+#[test]
+fn table_get_returns_newest_visible_version() {
+    let fs = mem_fs();
+    let entries = sorted_entries();
+    let options = TableOptions {
+        block_size: 1024,
+        ..TableOptions::default()
+    };
+    build(&fs, options, &entries);
+    let table = open(&fs).unwrap();
+
+    assert!(table.properties().num_data_blocks > 10);
+    for read in [u64::MAX, 4000, 2500, 10] {
+        for key in (0..2000).step_by(7) {
+            let key = format!("tenant/0042/user/{key:06}").into_bytes();
+            let expected = entries
+                .iter()
+                .find(|entry| entry.key == key && entry.sequence <= read)
+                .cloned();
+            assert_eq!(table.get(&key, read).unwrap(), expected, "{key:?} at {read}");
+        }
+    }
+    assert_eq!(table.get(b"tenant/0042/user/999999", u64::MAX).unwrap(), None);
+    assert_eq!(table.get(b"", u64::MAX).unwrap(), None);
+}
+
+#[test]
+fn table_iterates_in_order_and_seeks() {
+    let fs = mem_fs();
+    let entries = sorted_entries();
+    build(
+        &fs,
+        TableOptions {
+            block_size: 2048,
+            block_restart_interval: 4,
+            ..TableOptions::default()
+        },
+        &entries,
+    );
+    let table = open(&fs).unwrap();
+
+    let all: Vec<TableEntry> = table.iter().collect::<io::Result<_>>().unwrap();
+    assert_eq!(all, entries);
+
+    let from: Vec<TableEntry> = table
+        .iter_from(b"tenant/0042/user/001000")
+        .collect::<io::Result<_>>()
+        .unwrap();
+    let expected: Vec<TableEntry> = entries
+        .iter()
+        .filter(|entry| entry.key.as_slice() >= b"tenant/0042/user/001000".as_slice())
+        .cloned()
+        .collect();
+    assert_eq!(from, expected);
+
+    // sequences start at 1, so seeking at 0 skips every version of the key
+    let mut iter = table.iter();
+    let key = &entries[1234].key;
+    iter.seek(key, 0);
+    let next_key = entries.iter().find(|entry| entry.key > *key).unwrap();
+    assert_eq!(iter.next().unwrap().unwrap(), *next_key);
+    assert!(table.iter_from(b"u").next().is_none());
+}
+
+#[test]
+fn table_prefix_compression_shrinks_shared_prefixes() {
+    let fs = mem_fs();
+    let entries: Vec<TableEntry> = (0..5000u64)
+        .map(|i| entry(format!("a/long/shared/key/prefix/{i:08}").as_bytes(), i + 1, RecordType::Put, b"v"))
+        .collect();
+
+    let size = build(&fs, TableOptions::default(), &entries);
+
+    let table = open(&fs).unwrap();
+    let raw = table.properties().raw_key_size + table.properties().raw_value_size;
+    assert!(size < raw / 2, "table of {size} bytes for {raw} raw bytes");
+    assert_eq!(size, fs.file_size(Path::new(PATH)).unwrap());
+}
+
+#[test]
+fn table_properties_describe_the_entries() {
+    let fs = mem_fs();
+    let entries = vec![
+        entry(b"a", 7, RecordType::Put, &[1u8; 16 * 1024]),
+        entry(b"a", 3, RecordType::Delete, b""),
+        entry(b"b", 9, RecordType::Put, b"xyz"),
+    ];
+    build(&fs, TableOptions::default(), &entries);
+    let table = open(&fs).unwrap();
+
+    let properties = table.properties();
+    assert_eq!(properties.num_entries, 3);
+    assert_eq!(properties.num_deletions, 1);
+    assert_eq!(properties.raw_key_size, 3);
+    assert_eq!(properties.raw_value_size, 16 * 1024 + 3);
+    assert_eq!((properties.min_sequence, properties.max_sequence), (3, 9));
+    // the 16KB value fills a block on its own
+    assert_eq!(properties.num_data_blocks, 2);
+    assert_eq!(table.max_sequence(), Some(9));
+    assert_eq!(table.get(b"a", 5).unwrap().unwrap().record_type, RecordType::Delete);
+}
+
+#[test]
+fn empty_table_has_no_entries() {
+    let fs = mem_fs();
+    build(&fs, TableOptions::default(), &[]);
+    let table = open(&fs).unwrap();
+
+    assert_eq!(table.properties().num_entries, 0);
+    assert_eq!(table.max_sequence(), None);
+    assert_eq!(table.get(b"k", u64::MAX).unwrap(), None);
+    assert!(table.iter().next().is_none());
+}
+
+#[test]
+fn table_builder_rejects_entries_out_of_order() {
+    let fs = mem_fs();
+    let mut builder = TableBuilder::new(fs.create_new(Path::new(PATH)).unwrap(), TableOptions::default());
+    builder.add(b"b", 5, RecordType::Put, b"").unwrap();
+
+    for (key, sequence) in [(&b"a"[..], 9), (b"b", 5), (b"b", 6)] {
+        let err = builder.add(key, sequence, RecordType::Put, b"").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+    builder.add(b"b", 4, RecordType::Delete, b"").unwrap();
+    let err = builder.add(b"c", u64::MAX, RecordType::Put, b"").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn table_footer_checks_magic_and_version() {
+    let fs = mem_fs();
+    build(&fs, TableOptions::default(), &[entry(b"k", 1, RecordType::Put, b"v")]);
+    let data = read_file(&fs, Path::new(PATH)).unwrap();
+    let version_at = data.len() - 12;
+    assert_eq!(data[version_at..version_at + 4], TABLE_FORMAT_VERSION.to_le_bytes());
+
+    let mut bad_magic = data.clone();
+    *bad_magic.last_mut().unwrap() ^= 1;
+    fs.set_contents(Path::new(PATH), bad_magic).unwrap();
+    assert_eq!(open(&fs).err().unwrap().kind(), io::ErrorKind::InvalidData);
+
+    let mut next_version = data.clone();
+    next_version[version_at..version_at + 4].copy_from_slice(&(TABLE_FORMAT_VERSION + 1).to_le_bytes());
+    fs.set_contents(Path::new(PATH), next_version).unwrap();
+    assert_eq!(open(&fs).err().unwrap().kind(), io::ErrorKind::Unsupported);
+
+    fs.set_contents(Path::new(PATH), data[data.len() - FOOTER_SIZE + 1..].to_vec()).unwrap();
+    assert_eq!(open(&fs).err().unwrap().kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn table_detects_corrupted_blocks() {
+    let fs = mem_fs();
+    let entries = sorted_entries();
+    let options = TableOptions {
+        block_size: 1024,
+        ..TableOptions::default()
+    };
+    build(&fs, options, &entries);
+
+    // a byte in the first data block: open succeeds, reading that block fails
+    flip_byte(&fs, 10);
+    let table = open(&fs).unwrap();
+    let err = table.get(&entries[0].key, u64::MAX).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let results: Vec<io::Result<TableEntry>> = table.iter().collect();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].as_ref().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    // other blocks are still readable
+    let last = entries.last().unwrap();
+    assert_eq!(table.get(&last.key, u64::MAX).unwrap().as_ref(), Some(last));
+
+    // a byte in the index: the table does not open
+    flip_byte(&fs, 10);
+    let size = fs.file_size(Path::new(PATH)).unwrap() as usize;
+    flip_byte(&fs, size - FOOTER_SIZE - 10);
+    assert_eq!(open(&fs).err().unwrap().kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn partitioned_index_reads_like_a_single_index() {
+    let entries = sorted_entries();
+    let single_fs = mem_fs();
+    build(
+        &single_fs,
+        TableOptions {
+            block_size: 512,
+            ..TableOptions::default()
+        },
+        &entries,
+    );
+    let single = open(&single_fs).unwrap();
+    let fs = mem_fs();
+    build(&fs, partitioned(None), &entries);
+    let table = TableReader::open(&fs, Path::new(PATH), &partitioned(None)).unwrap();
+
+    assert!(table.properties().index_partitions > 10);
+    assert_eq!(single.properties().index_partitions, 0);
+    // only the top level stays in memory
+    assert!(table.index_memory_usage() * 10 < single.index_memory_usage());
+    assert!(table.properties().index_size > single.properties().index_size);
+
+    for key in (0..2000).step_by(3) {
+        let key = format!("tenant/0042/user/{key:06}").into_bytes();
+        for read in [u64::MAX, 2500] {
+            assert_eq!(table.get(&key, read).unwrap(), single.get(&key, read).unwrap());
+        }
+    }
+    assert_eq!(table.get(b"zzz", u64::MAX).unwrap(), None);
+    let all: Vec<TableEntry> = table.iter().collect::<io::Result<_>>().unwrap();
+    assert_eq!(all, entries);
+    let from = table.iter_from(b"tenant/0042/user/001500").next().unwrap().unwrap();
+    assert_eq!(from.key, b"tenant/0042/user/001500");
+}
+
+#[test]
+fn hash_index_reads_like_binary_search() {
+    let entries = sorted_entries();
+    let plain_fs = mem_fs();
+    build(
+        &plain_fs,
+        TableOptions {
+            block_size: 1024,
+            ..TableOptions::default()
+        },
+        &entries,
+    );
+    let plain = open(&plain_fs).unwrap();
+    let fs = mem_fs();
+    build(
+        &fs,
+        TableOptions {
+            block_size: 1024,
+            data_block_index_type: DataBlockIndexType::BinaryAndHash,
+            ..TableOptions::default()
+        },
+        &entries,
+    );
+    // the index is found from the blocks, the reader does not need the option
+    let table = open(&fs).unwrap();
+
+    assert!(table.properties().data_size > plain.properties().data_size);
+    for key in 0..2100 {
+        let key = format!("tenant/0042/user/{key:06}").into_bytes();
+        for read in [u64::MAX, 4000, 2500, 10] {
+            assert_eq!(table.get(&key, read).unwrap(), plain.get(&key, read).unwrap());
+        }
+    }
+    assert_eq!(table.get(b"", u64::MAX).unwrap(), None);
+    let all: Vec<TableEntry> = table.iter().collect::<io::Result<_>>().unwrap();
+    assert_eq!(all, entries);
+}
+
+#[test]
+fn partitioned_index_loads_partitions_through_the_block_cache() {
+    let entries = sorted_entries();
+    let fs = mem_fs();
+    build(&fs, partitioned(None), &entries);
+    let cache = Arc::new(BlockCache::new(64 * 1024));
+    let table = TableReader::open(&fs, Path::new(PATH), &partitioned(Some(Arc::clone(&cache)))).unwrap();
+
+    let keys: Vec<Vec<u8>> = (0..50).map(|key| format!("tenant/0042/user/{key:06}").into_bytes()).collect();
+    for key in &keys {
+        table.get(key, u64::MAX).unwrap();
+    }
+    let first_pass = cache.stats();
+    // the partition and the data block of each key
+    assert!(first_pass.misses >= 2);
+    for key in &keys {
+        table.get(key, u64::MAX).unwrap();
+    }
+    let second_pass = cache.stats();
+    assert_eq!(second_pass.misses, first_pass.misses);
+    assert_eq!(second_pass.hits - first_pass.hits, 2 * keys.len() as u64);
+
+    // a small cache stays within its capacity while the whole table is scanned
+    let small = Arc::new(BlockCache::new(4 * 1024));
+    let table = TableReader::open(&fs, Path::new(PATH), &partitioned(Some(Arc::clone(&small)))).unwrap();
+    assert_eq!(table.iter().count(), entries.len());
+    assert!(small.usage() <= 4 * 1024);
+    assert!(small.stats().evictions > 0);
+}
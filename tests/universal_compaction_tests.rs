@@ -0,0 +1,242 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Arc;
+
+use stone_kvs::db::{CompactionStyle, Db, DbOptions, NUM_LEVELS, UniversalCompactionOptions, sorted_run_count};
+use stone_kvs::env::{FileSystem, MemFileSystem};
+
+fn universal(options: UniversalCompactionOptions) -> DbOptions {
+    DbOptions {
+        compaction_style: CompactionStyle::Universal,
+        level0_file_num_compaction_trigger: 4,
+        compaction_options_universal: options,
+        ..DbOptions::default()
+    }
+}
+
+fn key(n: u64) -> Vec<u8> {
+    format!("key{n:06}").into_bytes()
+}
+
+fn fill(db: &Db, keys: impl Iterator<Item = u64>, value: &[u8]) {
+    for n in keys {
+        db.put(&key(n), value).unwrap();
+    }
+    db.flush().unwrap();
+}
+
+/// Random puts and deletes over 5000 keys, returns what the store holds
+fn random_writes(db: &Db) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    let mut expected = BTreeMap::new();
+    let mut seed = 7u64;
+    for i in 0..20_000u64 {
+        seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+        let n = (seed >> 33) % 5000;
+        if i % 10 == 9 {
+            db.delete(&key(n)).unwrap();
+            expected.remove(&key(n));
+        } else {
+            let value = format!("value{i}-").repeat(8).into_bytes();
+            db.put(&key(n), &value).unwrap();
+            expected.insert(key(n), value);
+        }
+    }
+    db.flush().unwrap();
+    db.wait_for_compactions().unwrap();
+    expected
+}
+
+fn check(db: &Db, expected: &BTreeMap<Vec<u8>, Vec<u8>>) {
+    for n in 0..5000 {
+        assert_eq!(db.get(&key(n)).unwrap(), expected.get(&key(n)).cloned(), "{n}");
+    }
+    let scanned: Vec<(Vec<u8>, Vec<u8>)> = db.prefix_iter(b"key").collect::<io::Result<_>>().unwrap();
+    assert_eq!(scanned, expected.clone().into_iter().collect::<Vec<_>>());
+}
+
+#[test]
+fn universal_compaction_keeps_the_runs_under_the_trigger() {
+    let fs: Arc<dyn FileSystem> = Arc::new(MemFileSystem::new());
+    let options = DbOptions {
+        write_buffer_size: 256 * 1024,
+        ..universal(UniversalCompactionOptions::default())
+    };
+    let db = Db::open_in(Arc::clone(&fs), "/db", options.clone()).unwrap();
+    let expected = random_writes(&db);
+
+    let version = db.current_version();
+    assert!(sorted_run_count(&version) < 4, "{version:?}");
+    assert!(db.compaction_stats().compactions > 0);
+    // only level 0 and the last level are written
+    for level in 1..NUM_LEVELS - 1 {
+        assert!(version.files(level).is_empty(), "{version:?}");
+    }
+    check(&db, &expected);
+
+    drop(db);
+    let db = Db::open_in(fs, "/db", options).unwrap();
+    assert_eq!(db.current_version().table_numbers(), version.table_numbers());
+    check(&db, &expected);
+}
+
+#[test]
+fn size_amplification_merges_every_run_into_the_last_level() {
+    let db = Db::open_in(
+        Arc::new(MemFileSystem::new()),
+        "/db",
+        DbOptions {
+            level0_file_num_compaction_trigger: 3,
+            ..universal(UniversalCompactionOptions {
+                min_merge_width: 10,
+                max_size_amplification_percent: 100,
+                ..UniversalCompactionOptions::default()
+            })
+        },
+    )
+    .unwrap();
+    fill(&db, 0..1000, b"first");
+    fill(&db, 0..1000, b"second");
+    db.delete(&key(7)).unwrap();
+    fill(&db, 0..500, b"third");
+    db.wait_for_compactions().unwrap();
+
+    // the newer runs weigh twice the oldest: one run left, without the shadowed versions
+    let version = db.current_version();
+    assert_eq!(sorted_run_count(&version), 1);
+    let last = version.files(NUM_LEVELS - 1);
+    assert_eq!(last.len(), 1);
+    let table = db.table_cache().get(last[0].number).unwrap();
+    assert_eq!(table.iter().count(), 1000);
+    assert_eq!(db.get(&key(7)).unwrap(), Some(b"third".to_vec()));
+    assert_eq!(db.get(&key(700)).unwrap(), Some(b"second".to_vec()));
+}
+
+#[test]
+fn size_ratio_merges_runs_of_similar_size() {
+    let db = Db::open_in(
+        Arc::new(MemFileSystem::new()),
+        "/db",
+        universal(UniversalCompactionOptions {
+            size_ratio: 10,
+            max_size_amplification_percent: 10_000,
+            ..UniversalCompactionOptions::default()
+        }),
+    )
+    .unwrap();
+    fill(&db, 0..2000, &[0; 100]);
+    let oldest = db.table_numbers()[0];
+    for run in 1..4u64 {
+        for n in 0..10 {
+            db.delete(&key(run * 10 + n)).unwrap();
+        }
+        fill(&db, (0..100).map(|n| 10_000 + run * 100 + n), &[run as u8; 100]);
+    }
+    db.wait_for_compactions().unwrap();
+
+    // the three small runs are merged in level 0, the large one is not rewritten
+    let version = db.current_version();
+    assert_eq!(version.files(0).len(), 2, "{version:?}");
+    assert_eq!(version.files(0)[1].number, oldest);
+    assert_eq!(db.compaction_stats().compactions, 1);
+    // the tombstones still hide the keys of the large run
+    let merged = db.table_cache().get(version.files(0)[0].number).unwrap();
+    assert_eq!(merged.iter().count(), 330);
+    assert_eq!(db.get(&key(15)).unwrap(), None);
+    assert_eq!(db.get(&key(45)).unwrap(), Some(vec![0; 100]));
+    assert_eq!(db.get(&key(10_250)).unwrap(), Some(vec![2; 100]));
+}
+
+#[test]
+fn level0_merge_is_cut_in_tables_forming_one_run() {
+    let fs: Arc<dyn FileSystem> = Arc::new(MemFileSystem::new());
+    let options = DbOptions {
+        target_file_size_base: 8 * 1024,
+        ..universal(UniversalCompactionOptions {
+            size_ratio: 10,
+            max_size_amplification_percent: 10_000,
+            ..UniversalCompactionOptions::default()
+        })
+    };
+    let db = Db::open_in(Arc::clone(&fs), "/db", options.clone()).unwrap();
+    fill(&db, 0..2000, &[0; 100]);
+    let oldest = db.table_numbers()[0];
+    for run in 1..4u64 {
+        for n in 0..10 {
+            db.delete(&key(run * 10 + n)).unwrap();
+        }
+        fill(&db, (0..100).map(|n| 10_000 + run * 100 + n), &[run as u8; 100]);
+    }
+    db.wait_for_compactions().unwrap();
+
+    // the three small runs are merged in several tables of level 0, still two runs
+    let version = db.current_version();
+    let level0 = version.files(0);
+    assert!(level0.len() > 3, "{version:?}");
+    assert_eq!(sorted_run_count(&version), 2);
+    assert_eq!(level0.last().unwrap().number, oldest);
+    let merged = &level0[..level0.len() - 1];
+    assert!(merged.iter().all(|file| file.file_size < 16 * 1024), "{version:?}");
+    let mut ranges: Vec<_> = merged.iter().map(|file| (&file.smallest, &file.largest)).collect();
+    ranges.sort();
+    assert!(ranges.windows(2).all(|pair| pair[0].1 < pair[1].0));
+    assert_eq!(db.compaction_stats().compactions, 1);
+    assert_eq!(db.get(&key(15)).unwrap(), None);
+    assert_eq!(db.get(&key(45)).unwrap(), Some(vec![0; 100]));
+    assert_eq!(db.get(&key(10_250)).unwrap(), Some(vec![2; 100]));
+
+    // a newer run with the keys of the merged one comes first
+    fill(&db, (0..100).map(|n| 10_000 + 100 + n), &[9; 100]);
+    assert_eq!(db.get(&key(10_150)).unwrap(), Some(vec![9; 100]));
+    drop(db);
+    let db = Db::open_in(fs, "/db", options).unwrap();
+    assert_eq!(sorted_run_count(&db.current_version()), 3);
+    assert_eq!(db.get(&key(10_150)).unwrap(), Some(vec![9; 100]));
+    assert_eq!(db.get(&key(10_350)).unwrap(), Some(vec![3; 100]));
+}
+
+#[test]
+fn universal_compaction_writes_less_than_leveled() {
+    let written = |style| {
+        let options = DbOptions {
+            write_buffer_size: 256 * 1024,
+            compaction_style: style,
+            max_bytes_for_level_base: 128 * 1024,
+            max_bytes_for_level_multiplier: 4.0,
+            target_file_size_base: 64 * 1024,
+            ..DbOptions::default()
+        };
+        let db = Db::open_in(Arc::new(MemFileSystem::new()), "/db", options).unwrap();
+        let expected = random_writes(&db);
+        check(&db, &expected);
+        db.compaction_stats().bytes_written
+    };
+    let leveled = written(CompactionStyle::Level);
+    let universal = written(CompactionStyle::Universal);
+    assert!(universal < leveled, "universal {universal}, leveled {leveled}");
+}
+
+#[test]
+fn compaction_style_can_change_on_reopen() {
+    let fs: Arc<dyn FileSystem> = Arc::new(MemFileSystem::new());
+    let leveled = DbOptions {
+        write_buffer_size: 256 * 1024,
+        max_bytes_for_level_base: 128 * 1024,
+        target_file_size_base: 64 * 1024,
+        ..DbOptions::default()
+    };
+    let db = Db::open_in(Arc::clone(&fs), "/db", leveled.clone()).unwrap();
+    let expected = random_writes(&db);
+    drop(db);
+
+    let db = Db::open_in(Arc::clone(&fs), "/db", universal(UniversalCompactionOptions::default())).unwrap();
+    db.wait_for_compactions().unwrap();
+    assert!(sorted_run_count(&db.current_version()) < 4);
+    check(&db, &expected);
+    fill(&db, 0..100, b"universal");
+    drop(db);
+
+    let db = Db::open_in(fs, "/db", leveled).unwrap();
+    db.wait_for_compactions().unwrap();
+    assert_eq!(db.get(&key(50)).unwrap(), Some(b"universal".to_vec()));
+    assert_eq!(db.get(&key(150)).unwrap(), expected.get(&key(150)).cloned());
+}
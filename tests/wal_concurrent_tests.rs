@@ -0,0 +1,133 @@
+mod common;
+
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::sync::Mutex;
+use std::thread;
+
+use common::TempDir;
+use stone_kvs::wal::{ConcurrentWal, ConcurrentWalOptions, RecordType, WalReader, WalRecord};
+
+fn options(buffer_size: usize) -> ConcurrentWalOptions {
+    ConcurrentWalOptions {
+        buffer_size,
+        sync: false,
+    }
+}
+
+fn read_all(path: &std::path::Path) -> Vec<WalRecord> {
+    let mut reader = WalReader::open(path).unwrap();
+    let records = reader.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(reader.stats().corrupted_records, 0);
+    assert_eq!(reader.stats().truncated_tail_bytes, 0);
+    records
+}
+
+#[test]
+fn concurrent_wal_single_writer_produces_a_regular_segment() {
+    let dir = TempDir::new("cwal-single");
+    let path = dir.join("000001.log");
+    let wal = ConcurrentWal::create(&path, 100, options(1024)).unwrap();
+
+    assert_eq!(wal.put(b"a", b"1").unwrap(), 100);
+    assert_eq!(wal.delete(b"a").unwrap(), 101);
+    assert_eq!(wal.next_sequence(), 102);
+    wal.close().unwrap();
+
+    assert_eq!(read_all(&path), vec![WalRecord::put(100, b"a", b"1"), WalRecord::delete(101, b"a")]);
+}
+
+#[test]
+fn concurrent_wal_many_writers_keep_sequence_and_file_order_aligned() {
+    let dir = TempDir::new("cwal-many");
+    let path = dir.join("000001.log");
+    // a small buffer forces writers to wait for the flusher and records to wrap around the ring
+    let wal = ConcurrentWal::create(&path, 1, options(4096)).unwrap();
+    let written = Mutex::new(HashMap::new());
+
+    thread::scope(|scope| {
+        for thread_id in 0..8 {
+            let wal = &wal;
+            let written = &written;
+            scope.spawn(move || {
+                for i in 0..500 {
+                    let key = format!("t{thread_id}-k{i}");
+                    let value = vec![thread_id as u8; i % 97];
+                    let sequence = wal.put(key.as_bytes(), &value).unwrap();
+                    written.lock().unwrap().insert(sequence, (key.into_bytes(), value));
+                }
+            });
+        }
+    });
+    wal.close().unwrap();
+
+    let records = read_all(&path);
+    let written = written.into_inner().unwrap();
+    assert_eq!(records.len(), 4000);
+    for (index, record) in records.iter().enumerate() {
+        assert_eq!(record.sequence, index as u64 + 1);
+        let (key, value) = &written[&record.sequence];
+        assert_eq!((&record.key, &record.value), (key, value));
+    }
+}
+
+#[test]
+fn concurrent_wal_wait_durable_returns_once_record_is_written() {
+    let dir = TempDir::new("cwal-durable");
+    let path = dir.join("000001.log");
+    let wal = ConcurrentWal::create(&path, 1, ConcurrentWalOptions::default()).unwrap();
+
+    let sequence = wal.append(RecordType::Put, b"key", b"value").unwrap();
+    wal.wait_durable(sequence).unwrap();
+
+    assert!(wal.durable_sequence().is_some_and(|durable| durable >= sequence));
+    assert_eq!(read_all(&path), vec![WalRecord::put(1, b"key", b"value")]);
+}
+
+#[test]
+fn concurrent_wal_writers_wait_durable_in_parallel() {
+    let dir = TempDir::new("cwal-group");
+    let path = dir.join("000001.log");
+    let wal = ConcurrentWal::create(&path, 1, ConcurrentWalOptions::default()).unwrap();
+
+    thread::scope(|scope| {
+        for thread_id in 0..4 {
+            let wal = &wal;
+            let path = &path;
+            scope.spawn(move || {
+                for i in 0..50 {
+                    let sequence = wal.put(format!("{thread_id}/{i}").as_bytes(), b"v").unwrap();
+                    wal.wait_durable(sequence).unwrap();
+                    // other writers may be appending, only the records up to ours must be on disk
+                    let on_disk = WalReader::open(path).unwrap().map(|r| r.unwrap().sequence);
+                    assert!(on_disk.take_while(|&s| s <= sequence).any(|s| s == sequence));
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn concurrent_wal_rejects_record_larger_than_buffer() {
+    let dir = TempDir::new("cwal-large");
+    let wal = ConcurrentWal::create(dir.join("000001.log"), 1, options(64)).unwrap();
+
+    let err = wal.put(b"key", &[0u8; 64]).unwrap_err();
+
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert_eq!(wal.next_sequence(), 1);
+}
+
+#[test]
+fn concurrent_wal_record_filling_the_whole_buffer_is_written() {
+    let dir = TempDir::new("cwal-full");
+    let path = dir.join("000001.log");
+    let wal = ConcurrentWal::create(&path, 1, options(64)).unwrap();
+
+    for _ in 0..3 {
+        wal.put(b"key", &[7u8; 40]).unwrap();
+    }
+    wal.close().unwrap();
+
+    assert_eq!(read_all(&path).len(), 3);
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use std::fs::{self, OpenOptions};
+
+use bytes::Bytes;
+use common::TempDir;
+use stone_kvs::wal::mmap::Mmap;
+use stone_kvs::wal::record::{FILE_HEADER_SIZE, RECORD_HEADER_SIZE};
+use stone_kvs::wal::{DecoderRegistry, MmapWalReader, RecordType, WalReader, WalRecord, WalWriter};
+
+fn write_segment(path: &std::path::Path, records: &[WalRecord]) {
+    let mut writer = WalWriter::create(path).unwrap();
+    for record in records {
+        writer
+            .append(record.record_type, record.sequence, &record.key, &record.value)
+            .unwrap();
+    }
+    writer.sync().unwrap();
+}
+
+fn sample_records() -> Vec<WalRecord> {
+    (1..=100)
+        .map(|seq| match seq % 5 {
+            0 => WalRecord::delete(seq, format!("key{}", seq - 1).as_bytes()),
+            _ => WalRecord::put(seq, format!("key{seq}").as_bytes(), &vec![seq as u8; seq as usize * 3]),
+        })
+        .collect()
+}
+
+#[test]
+fn mmap_reader_returns_same_records_as_buffered_reader() {
+    let dir = TempDir::new("mmap-same");
+    let path = dir.join("000001.log");
+    write_segment(&path, &sample_records());
+
+    let buffered: Vec<WalRecord> = WalReader::open(&path).unwrap().map(Result::unwrap).collect();
+    let mapped: Vec<WalRecord> = MmapWalReader::open(&path)
+        .unwrap()
+        .map(|r| WalRecord {
+            record_type: r.record_type,
+            sequence: r.sequence,
+            key: r.key.to_vec(),
+            value: r.value.to_vec(),
+        })
+        .collect();
+
+    assert_eq!(mapped, buffered);
+    assert_eq!(mapped, sample_records());
+}
+
+#[test]
+fn mmap_reader_views_borrow_the_mapping() {
+    let dir = TempDir::new("mmap-view");
+    let path = dir.join("000001.log");
+    write_segment(&path, &[WalRecord::put(1, b"key", b"value")]);
+    let mut reader = MmapWalReader::open(&path).unwrap();
+
+    let view = reader.next_view().unwrap();
+
+    assert_eq!((view.record_type, view.sequence), (RecordType::Put, 1));
+    assert_eq!((view.key, view.value), (&b"key"[..], &b"value"[..]));
+    assert!(reader.next_view().is_none());
+}
+
+#[test]
+fn mmap_records_point_into_the_segment_bytes() {
+    let dir = TempDir::new("mmap-zero-copy");
+    let path = dir.join("000001.log");
+    write_segment(&path, &[WalRecord::put(1, b"key", b"value")]);
+    let data = Bytes::from(fs::read(&path).unwrap());
+
+    let record = MmapWalReader::from_bytes(data.clone(), &DecoderRegistry::default())
+        .unwrap()
+        .next_record()
+        .unwrap();
+
+    let value_offset = FILE_HEADER_SIZE + RECORD_HEADER_SIZE + 3;
+    assert_eq!(record.value.as_ptr(), data[value_offset..].as_ptr());
+}
+
+#[test]
+fn mmap_records_outlive_the_reader() {
+    let dir = TempDir::new("mmap-outlive");
+    let path = dir.join("000001.log");
+    write_segment(&path, &sample_records());
+
+    let records: Vec<_> = MmapWalReader::open(&path).unwrap().collect();
+
+    assert_eq!(records[1].key, Bytes::from_static(b"key2"));
+    assert_eq!(records[1].value.len(), 6);
+}
+
+#[test]
+fn mmap_reader_skips_corrupted_records_and_torn_tail() {
+    let dir = TempDir::new("mmap-corrupt");
+    let path = dir.join("000001.log");
+    write_segment(&path, &sample_records()[..3]);
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[FILE_HEADER_SIZE + RECORD_HEADER_SIZE] ^= 0x01;
+    fs::write(&path, &bytes).unwrap();
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(bytes.len() as u64 - 1)
+        .unwrap();
+
+    let mut reader = MmapWalReader::open(&path).unwrap();
+    let sequences: Vec<u64> = reader.by_ref().map(|r| r.sequence).collect();
+
+    assert_eq!(sequences, vec![2]);
+    assert_eq!(reader.stats().corrupted_records, 1);
+    assert!(reader.stats().truncated_tail_bytes > 0);
+}
+
+#[test]
+fn mmap_of_empty_file_is_empty() {
+    let dir = TempDir::new("mmap-empty");
+    let path = dir.join("empty");
+    fs::write(&path, []).unwrap();
+
+    let mmap = Mmap::map(&fs::File::open(&path).unwrap()).unwrap();
+
+    assert!(mmap.is_empty());
+    assert_eq!(mmap.as_ref(), &[] as &[u8]);
+    assert!(MmapWalReader::open(&path).is_err());
+}
@@ -0,0 +1,206 @@
+mod common;
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::thread;
+use std::time::Duration;
+
+use common::TempDir;
+use stone_kvs::wal::record::{RecordType, encode_record};
+use stone_kvs::wal::segment::{list_segments, parse_segment_file_name, segment_file_name, segment_path};
+use stone_kvs::wal::{ConcurrentWal, ConcurrentWalOptions, DurableSequence, WalTailer, WalWriter};
+
+fn sequences(tailer: &mut WalTailer) -> Vec<u64> {
+    let mut out = Vec::new();
+    while let Some(record) = tailer.poll().unwrap() {
+        out.push(record.sequence);
+    }
+    out
+}
+
+#[test]
+fn segment_file_names_round_trip() {
+    assert_eq!(segment_file_name(42), "000042.log");
+    assert_eq!(parse_segment_file_name("000042.log"), Some(42));
+    assert_eq!(parse_segment_file_name("000042.sst"), None);
+    assert_eq!(parse_segment_file_name(".log"), None);
+    assert_eq!(parse_segment_file_name("CURRENT"), None);
+}
+
+#[test]
+fn tailer_on_empty_directory_has_nothing_to_deliver() {
+    let dir = TempDir::new("tail-empty");
+    let mut tailer = WalTailer::open(dir.path(), 0).unwrap();
+
+    assert!(tailer.poll().unwrap().is_none());
+    assert_eq!(tailer.current_segment(), None);
+}
+
+#[test]
+fn tailer_delivers_records_as_they_are_flushed() {
+    let dir = TempDir::new("tail-flush");
+    let mut writer = WalWriter::create(segment_path(dir.path(), 1)).unwrap();
+    let mut tailer = WalTailer::open(dir.path(), 0).unwrap();
+
+    writer.put(1, b"a", b"1").unwrap();
+    writer.delete(2, b"b").unwrap();
+    writer.sync().unwrap();
+    let first = tailer.poll().unwrap().unwrap();
+    let second = tailer.poll().unwrap().unwrap();
+    assert!(tailer.poll().unwrap().is_none());
+
+    writer.put(3, b"c", b"3").unwrap();
+    writer.sync().unwrap();
+
+    assert_eq!((first.sequence, first.record_type, first.key), (1, RecordType::Put, b"a".to_vec()));
+    assert_eq!((second.sequence, second.record_type), (2, RecordType::Delete));
+    assert_eq!(sequences(&mut tailer), vec![3]);
+    assert_eq!(tailer.next_sequence(), 4);
+}
+
+#[test]
+fn tailer_with_durable_sequence_waits_for_the_sync() {
+    let dir = TempDir::new("tail-durable");
+    let durable = DurableSequence::new();
+    let mut writer = WalWriter::create(segment_path(dir.path(), 1)).unwrap();
+    writer.set_durable_sequence(durable.clone());
+    let mut tailer = WalTailer::open(dir.path(), 0).unwrap();
+    tailer.set_durable_sequence(durable.clone());
+
+    writer.put(1, b"a", b"1").unwrap();
+    writer.sync().unwrap();
+    writer.put(2, b"b", b"2").unwrap();
+    writer.put(3, b"c", b"3").unwrap();
+    writer.flush().unwrap();
+    // flushed but not synced, a power loss would take them back
+    assert_eq!(sequences(&mut tailer), vec![1]);
+    assert_eq!(durable.get(), Some(1));
+
+    writer.sync().unwrap();
+    assert_eq!(sequences(&mut tailer), vec![2, 3]);
+
+    // the same watermark follows the writer of the next segment
+    let mut next = WalWriter::create(segment_path(dir.path(), 2)).unwrap();
+    next.set_durable_sequence(durable.clone());
+    next.put(4, b"d", b"4").unwrap();
+    next.flush().unwrap();
+    assert!(tailer.poll().unwrap().is_none());
+    assert_eq!(tailer.stats().truncated_tail_bytes, 0);
+    next.sync().unwrap();
+    assert_eq!(sequences(&mut tailer), vec![4]);
+}
+
+#[test]
+fn tailer_follows_the_syncs_of_a_concurrent_wal() {
+    let dir = TempDir::new("tail-durable-concurrent");
+    let options = ConcurrentWalOptions {
+        buffer_size: 4096,
+        sync: true,
+    };
+    let wal = ConcurrentWal::create(segment_path(dir.path(), 1), 1, options).unwrap();
+    let durable = DurableSequence::new();
+    wal.set_durable_sequence(durable.clone());
+    let mut tailer = WalTailer::open(dir.path(), 0).unwrap();
+    tailer.set_durable_sequence(durable);
+
+    for _ in 0..10 {
+        wal.put(b"key", b"value").unwrap();
+    }
+    wal.wait_durable(10).unwrap();
+    assert_eq!(sequences(&mut tailer), (1..=10).collect::<Vec<_>>());
+    wal.close().unwrap();
+}
+
+#[test]
+fn tailer_does_not_deliver_a_partially_written_record() {
+    let dir = TempDir::new("tail-partial");
+    let path = segment_path(dir.path(), 1);
+    WalWriter::create(&path).unwrap().sync().unwrap();
+    let mut tailer = WalTailer::open(dir.path(), 0).unwrap();
+    let mut bytes = Vec::new();
+    encode_record(&mut bytes, RecordType::Put, 7, b"key", b"value");
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+
+    file.write_all(&bytes[..10]).unwrap();
+    assert!(tailer.poll().unwrap().is_none());
+    file.write_all(&bytes[10..]).unwrap();
+
+    assert_eq!(tailer.poll().unwrap().unwrap().value, b"value");
+}
+
+#[test]
+fn tailer_rolls_to_the_next_segment() {
+    let dir = TempDir::new("tail-roll");
+    let mut first = WalWriter::create(segment_path(dir.path(), 1)).unwrap();
+    first.put(1, b"a", b"1").unwrap();
+    first.put(2, b"b", b"2").unwrap();
+    first.sync().unwrap();
+    let mut tailer = WalTailer::open(dir.path(), 0).unwrap();
+    assert_eq!(sequences(&mut tailer), vec![1, 2]);
+
+    let mut second = WalWriter::create(segment_path(dir.path(), 2)).unwrap();
+    second.put(3, b"c", b"3").unwrap();
+    second.sync().unwrap();
+
+    assert_eq!(sequences(&mut tailer), vec![3]);
+    assert_eq!(tailer.current_segment(), Some(2));
+    assert_eq!(tailer.stats().segments_completed, 1);
+}
+
+#[test]
+fn tailer_resumes_from_sequence_after_restart() {
+    let dir = TempDir::new("tail-resume");
+    let mut seq = 0;
+    for segment in 1..=3 {
+        let mut writer = WalWriter::create(segment_path(dir.path(), segment)).unwrap();
+        for _ in 0..5 {
+            seq += 1;
+            writer.put(seq, format!("k{seq}").as_bytes(), b"v").unwrap();
+        }
+        writer.sync().unwrap();
+    }
+
+    let mut tailer = WalTailer::open(dir.path(), 8).unwrap();
+
+    assert_eq!(tailer.current_segment(), Some(2));
+    assert_eq!(sequences(&mut tailer), (8..=15).collect::<Vec<_>>());
+}
+
+#[test]
+fn tailer_starts_from_oldest_segment_when_resume_point_was_released() {
+    let dir = TempDir::new("tail-released");
+    let mut writer = WalWriter::create(segment_path(dir.path(), 4)).unwrap();
+    writer.put(20, b"a", b"1").unwrap();
+    writer.sync().unwrap();
+
+    let mut tailer = WalTailer::open(dir.path(), 3).unwrap();
+
+    assert_eq!(sequences(&mut tailer), vec![20]);
+    assert_eq!(list_segments(dir.path()).unwrap(), vec![4]);
+}
+
+#[test]
+fn tailer_waits_for_records_written_by_another_thread() {
+    let dir = TempDir::new("tail-wait");
+    let path = segment_path(dir.path(), 1);
+    let mut writer = WalWriter::create(&path).unwrap();
+    writer.sync().unwrap();
+    let mut tailer = WalTailer::open(dir.path(), 0).unwrap();
+    tailer.set_poll_interval(Duration::from_millis(1));
+
+    let handle = thread::spawn(move || {
+        for seq in 1..=50 {
+            writer.put(seq, b"key", b"value").unwrap();
+            writer.flush().unwrap();
+        }
+        writer.sync().unwrap();
+    });
+    let mut received = Vec::new();
+    while received.len() < 50 {
+        let record = tailer.wait_next(Duration::from_secs(5)).unwrap().expect("record within timeout");
+        received.push(record.sequence);
+    }
+    handle.join().unwrap();
+
+    assert_eq!(received, (1..=50).collect::<Vec<_>>());
+}
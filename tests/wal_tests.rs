@@ -0,0 +1,190 @@
+mod common;
+
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+
+use common::TempDir;
+use stone_kvs::wal::record::{FILE_HEADER_SIZE, FileHeader, RECORD_HEADER_SIZE};
+use stone_kvs::wal::{DecoderRegistry, RecordType, WalReader, WalRecord, WalWriter};
+
+fn write_segment(path: &std::path::Path, records: &[WalRecord]) {
+    let mut writer = WalWriter::create(path).unwrap();
+    for record in records {
+        writer
+            .append(record.record_type, record.sequence, &record.key, &record.value)
+            .unwrap();
+    }
+    writer.sync().unwrap();
+}
+
+fn read_all(path: &std::path::Path) -> (Vec<WalRecord>, stone_kvs::wal::ReadStats) {
+    let mut reader = WalReader::open(path).unwrap();
+    let records = reader.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+    (records, reader.stats())
+}
+
+fn sample_records() -> Vec<WalRecord> {
+    vec![
+        WalRecord::put(1, b"apple", b"red"),
+        WalRecord::put(2, b"banana", b"yellow"),
+        WalRecord::delete(3, b"apple"),
+        WalRecord::put(4, b"", b""),
+    ]
+}
+
+#[test]
+fn wal_written_records_are_read_back_in_order() {
+    let dir = TempDir::new("wal-roundtrip");
+    let path = dir.join("000001.log");
+    write_segment(&path, &sample_records());
+
+    let (records, stats) = read_all(&path);
+
+    assert_eq!(records, sample_records());
+    assert_eq!(stats.records, 4);
+    assert_eq!(stats.corrupted_records, 0);
+}
+
+#[test]
+fn wal_file_size_matches_record_format() {
+    let dir = TempDir::new("wal-size");
+    let path = dir.join("000001.log");
+    write_segment(&path, &[WalRecord::put(1, b"key", b"value")]);
+
+    assert_eq!(
+        fs::metadata(&path).unwrap().len() as usize,
+        FILE_HEADER_SIZE + RECORD_HEADER_SIZE + 3 + 5
+    );
+}
+
+#[test]
+fn wal_header_contains_current_version() {
+    let dir = TempDir::new("wal-header");
+    let path = dir.join("000001.log");
+    write_segment(&path, &[]);
+
+    let bytes = fs::read(&path).unwrap();
+    let header = FileHeader::decode(bytes[..FILE_HEADER_SIZE].try_into().unwrap()).unwrap();
+
+    assert_eq!(header.version, stone_kvs::wal::CURRENT_VERSION);
+    assert_eq!(WalReader::open(&path).unwrap().version(), 1);
+}
+
+#[test]
+fn wal_record_with_flipped_bit_is_skipped() {
+    let dir = TempDir::new("wal-corrupt");
+    let path = dir.join("000001.log");
+    write_segment(&path, &sample_records());
+
+    // flip a bit in the value of the first record
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[FILE_HEADER_SIZE + RECORD_HEADER_SIZE + 5] ^= 0x01;
+    fs::write(&path, bytes).unwrap();
+
+    let (records, stats) = read_all(&path);
+
+    assert_eq!(records, sample_records()[1..]);
+    assert_eq!(stats.corrupted_records, 1);
+}
+
+#[test]
+fn wal_corrupted_sequence_is_detected() {
+    let dir = TempDir::new("wal-corrupt-seq");
+    let path = dir.join("000001.log");
+    write_segment(&path, &sample_records());
+
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[FILE_HEADER_SIZE + 1] ^= 0x80;
+    fs::write(&path, bytes).unwrap();
+
+    let (records, stats) = read_all(&path);
+
+    assert_eq!(records.len(), 3);
+    assert_eq!(stats.corrupted_records, 1);
+}
+
+#[test]
+fn wal_torn_tail_is_ignored() {
+    let dir = TempDir::new("wal-torn");
+    let path = dir.join("000001.log");
+    write_segment(&path, &sample_records());
+
+    let len = fs::metadata(&path).unwrap().len();
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(len - 3).unwrap();
+
+    let (records, stats) = read_all(&path);
+
+    assert_eq!(records, sample_records()[..3]);
+    assert_eq!(stats.truncated_tail_bytes, RECORD_HEADER_SIZE as u64 - 3);
+}
+
+#[test]
+fn wal_large_values_span_read_chunks() {
+    let dir = TempDir::new("wal-large");
+    let path = dir.join("000001.log");
+    let records: Vec<WalRecord> = (1..=20)
+        .map(|seq| WalRecord::put(seq, format!("key{seq}").as_bytes(), &vec![seq as u8; 16 * 1024]))
+        .collect();
+    write_segment(&path, &records);
+
+    assert_eq!(read_all(&path).0, records);
+}
+
+#[test]
+fn wal_writer_rejects_non_increasing_sequence() {
+    let dir = TempDir::new("wal-seq");
+    let mut writer = WalWriter::create(dir.join("000001.log")).unwrap();
+    writer.put(5, b"a", b"1").unwrap();
+
+    let err = writer.put(5, b"b", b"2").unwrap_err();
+
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert_eq!(writer.last_sequence(), Some(5));
+}
+
+#[test]
+fn wal_writer_refuses_to_overwrite_a_segment() {
+    let dir = TempDir::new("wal-exists");
+    let path = dir.join("000001.log");
+    write_segment(&path, &sample_records());
+
+    assert_eq!(WalWriter::create(&path).err().unwrap().kind(), ErrorKind::AlreadyExists);
+}
+
+#[test]
+fn wal_reader_rejects_bad_magic() {
+    let dir = TempDir::new("wal-magic");
+    let path = dir.join("000001.log");
+    fs::write(&path, [0u8; FILE_HEADER_SIZE]).unwrap();
+
+    assert_eq!(WalReader::open(&path).err().unwrap().kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn wal_reader_rejects_unknown_version() {
+    let dir = TempDir::new("wal-version");
+    let path = dir.join("000001.log");
+    let mut file = fs::File::create(&path).unwrap();
+    file.write_all(&FileHeader { version: 99 }.encode()).unwrap();
+
+    assert_eq!(WalReader::open(&path).err().unwrap().kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn wal_reader_without_decoder_for_version_fails() {
+    let dir = TempDir::new("wal-registry");
+    let path = dir.join("000001.log");
+    write_segment(&path, &sample_records());
+
+    let result = WalReader::open_with_registry(&path, &DecoderRegistry::empty());
+
+    assert_eq!(result.err().unwrap().kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn wal_record_type_round_trips_through_byte() {
+    assert_eq!(RecordType::from_byte(0x01), Some(RecordType::Put));
+    assert_eq!(RecordType::from_byte(0x02), Some(RecordType::Delete));
+    assert_eq!(RecordType::from_byte(0x00), None);
+}
@@ -0,0 +1,197 @@
+mod common;
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::Arc;
+
+use common::TempDir;
+use stone_kvs::wal::crc32c::crc32c_hw;
+use stone_kvs::wal::record::FileHeader;
+use stone_kvs::wal::version::{Decoded, RecordView};
+use stone_kvs::wal::{
+    CURRENT_VERSION, DecoderRegistry, RecordDecoder, RecordType, WalReader, WalRecord, WalWriter, upgrade_segment,
+};
+
+/// A made-up legacy layout: `[CRC32C(4B) | Type(1B) | Sequence(8B) | Key_Size(2B) | Value_Size(4B) | Key | Value]`
+struct V0Decoder;
+
+/// A version from the future, reusing the legacy layout
+struct NextDecoder;
+
+impl RecordDecoder for NextDecoder {
+    fn version(&self) -> u32 {
+        CURRENT_VERSION + 1
+    }
+
+    fn decode<'a>(&self, buf: &'a [u8]) -> Decoded<'a> {
+        V0Decoder.decode(buf)
+    }
+}
+
+const V0_HEADER_SIZE: usize = 19;
+
+impl RecordDecoder for V0Decoder {
+    fn version(&self) -> u32 {
+        0
+    }
+
+    fn decode<'a>(&self, buf: &'a [u8]) -> Decoded<'a> {
+        if buf.len() < V0_HEADER_SIZE {
+            return Decoded::Incomplete;
+        }
+        let key_size = u16::from_le_bytes(buf[13..15].try_into().unwrap()) as usize;
+        let value_size = u32::from_le_bytes(buf[15..19].try_into().unwrap()) as usize;
+        let len = V0_HEADER_SIZE + key_size + value_size;
+        if buf.len() < len {
+            return Decoded::Incomplete;
+        }
+        if u32::from_le_bytes(buf[..4].try_into().unwrap()) != crc32c_hw(&buf[4..len]) {
+            return Decoded::Corrupted { len };
+        }
+        Decoded::Record {
+            record: RecordView {
+                record_type: RecordType::from_byte(buf[4]).unwrap(),
+                sequence: u64::from_le_bytes(buf[5..13].try_into().unwrap()),
+                key: &buf[V0_HEADER_SIZE..V0_HEADER_SIZE + key_size],
+                value: &buf[V0_HEADER_SIZE + key_size..len],
+            },
+            len,
+        }
+    }
+}
+
+fn registry_with_v0() -> DecoderRegistry {
+    let mut registry = DecoderRegistry::default();
+    registry.register(Arc::new(V0Decoder));
+    registry
+}
+
+fn write_v0_segment(path: &Path, records: &[WalRecord]) {
+    write_v0_layout(path, 0, records);
+}
+
+fn write_v0_layout(path: &Path, version: u32, records: &[WalRecord]) {
+    let mut bytes = FileHeader { version }.encode().to_vec();
+    for record in records {
+        let mut body = vec![record.record_type as u8];
+        body.extend_from_slice(&record.sequence.to_le_bytes());
+        body.extend_from_slice(&(record.key.len() as u16).to_le_bytes());
+        body.extend_from_slice(&(record.value.len() as u32).to_le_bytes());
+        body.extend_from_slice(&record.key);
+        body.extend_from_slice(&record.value);
+        bytes.extend_from_slice(&crc32c_hw(&body).to_le_bytes());
+        bytes.extend_from_slice(&body);
+    }
+    fs::write(path, bytes).unwrap();
+}
+
+fn legacy_records() -> Vec<WalRecord> {
+    vec![
+        WalRecord::put(10, b"user:1", b"alice"),
+        WalRecord::put(11, b"user:2", b"bob"),
+        WalRecord::delete(15, b"user:1"),
+    ]
+}
+
+#[test]
+fn upgrade_rewrites_old_segment_into_current_version() {
+    let dir = TempDir::new("upgrade-v0");
+    let path = dir.join("000001.log");
+    write_v0_segment(&path, &legacy_records());
+
+    let report = upgrade_segment(&path, &registry_with_v0()).unwrap();
+
+    assert_eq!(report.from_version, 0);
+    assert_eq!(report.to_version, CURRENT_VERSION);
+    assert_eq!(report.records, 3);
+    assert!(report.rewritten);
+
+    // readable with the default registry, which knows nothing about v0
+    let mut reader = WalReader::open(&path).unwrap();
+    assert_eq!(reader.version(), CURRENT_VERSION);
+    let records = reader.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(records, legacy_records());
+}
+
+#[test]
+fn upgrade_preserves_sequence_numbers() {
+    let dir = TempDir::new("upgrade-seq");
+    let path = dir.join("000001.log");
+    write_v0_segment(&path, &legacy_records());
+
+    upgrade_segment(&path, &registry_with_v0()).unwrap();
+
+    let sequences: Vec<u64> = WalReader::open(&path).unwrap().map(|r| r.unwrap().sequence).collect();
+    assert_eq!(sequences, vec![10, 11, 15]);
+}
+
+#[test]
+fn upgrade_refuses_segment_with_crc_mismatch() {
+    let dir = TempDir::new("upgrade-corrupt");
+    let path = dir.join("000001.log");
+    write_v0_segment(&path, &legacy_records());
+    let mut bytes = fs::read(&path).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0xff;
+    fs::write(&path, &bytes).unwrap();
+
+    let err = upgrade_segment(&path, &registry_with_v0()).unwrap_err();
+
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    // the original segment is untouched and no temporary file is left behind
+    assert_eq!(fs::read(&path).unwrap(), bytes);
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn upgrade_of_current_version_leaves_segment_untouched() {
+    let dir = TempDir::new("upgrade-current");
+    let path = dir.join("000001.log");
+    let mut writer = WalWriter::create(&path).unwrap();
+    writer.put(1, b"k", b"v").unwrap();
+    writer.sync().unwrap();
+    drop(writer);
+    let before = fs::read(&path).unwrap();
+
+    let report = upgrade_segment(&path, &DecoderRegistry::default()).unwrap();
+
+    assert!(!report.rewritten);
+    assert_eq!(report.records, 1);
+    assert_eq!(fs::read(&path).unwrap(), before);
+}
+
+#[test]
+fn upgrade_without_decoder_for_old_version_fails() {
+    let dir = TempDir::new("upgrade-unknown");
+    let path = dir.join("000001.log");
+    write_v0_segment(&path, &legacy_records());
+
+    let err = upgrade_segment(&path, &DecoderRegistry::default()).unwrap_err();
+
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn upgrade_refuses_segment_of_newer_version() {
+    let dir = TempDir::new("upgrade-newer");
+    let path = dir.join("000001.log");
+    write_v0_layout(&path, CURRENT_VERSION + 1, &legacy_records());
+    let bytes = fs::read(&path).unwrap();
+    let mut registry = DecoderRegistry::default();
+    registry.register(Arc::new(NextDecoder));
+
+    let err = upgrade_segment(&path, &registry).unwrap_err();
+
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert!(err.to_string().contains("newer"), "{err}");
+    // not downgraded
+    assert_eq!(fs::read(&path).unwrap(), bytes);
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn registry_lists_registered_versions() {
+    assert_eq!(DecoderRegistry::default().versions(), vec![1]);
+    assert_eq!(registry_with_v0().versions(), vec![0, 1]);
+}